@@ -0,0 +1,308 @@
+use crate::link::{self, LinkType};
+use crate::record;
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use pnet::datalink::{self, Channel::Ethernet, DataLinkReceiver, DataLinkSender, NetworkInterface};
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, SyncSender, TrySendError},
+        Arc,
+    },
+    thread,
+};
+
+/// 全てのインターフェイスを表す名前
+pub const ANY: &str = "any";
+
+/**
+ * キャプチャの設定
+ */
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    /// 1フレームあたりに保持する最大バイト数
+    pub snaplen: usize,
+    /// カーネルから読み込む際のバッファサイズ
+    pub buffer_size: usize,
+    /// 解析待ちのフレームを溜めておく数。溢れた分は破棄して数える
+    pub queue_len: usize,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            snaplen: 65535,
+            buffer_size: 65536,
+            queue_len: 4096,
+        }
+    }
+}
+
+/**
+ * 起動したキャプチャ
+ */
+pub struct Capture {
+    pub frames: mpsc::Receiver<Frame>,
+    /// 最初に開いたインターフェイスのリンク層の種類
+    pub link_type: LinkType,
+    /// 解析が追いつかずに破棄したフレーム数
+    dropped: Arc<AtomicU64>,
+    /// キャプチャ開始時点の各インターフェイスのrx_dropped
+    kernel_dropped: Vec<(String, Option<u64>)>,
+}
+
+impl Capture {
+    /**
+     * インターフェイス以外から受け取るフレームのチャネルをキャプチャとして扱う
+     */
+    pub fn from_frames(frames: mpsc::Receiver<Frame>, link_type: LinkType) -> Self {
+        Self {
+            frames,
+            link_type,
+            dropped: Arc::new(AtomicU64::new(0)),
+            kernel_dropped: vec![],
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /**
+     * キャプチャ開始以降にカーネルが破棄したパケット数
+     * pnetのチャネルはソケットの統計を公開していないので、インターフェイスの統計の差分で代用する
+     * 取得できないインターフェイスがあればNoneを返す
+     */
+    pub fn kernel_dropped(&self) -> Option<u64> {
+        self.kernel_dropped
+            .iter()
+            .map(|(name, start)| Some(link::rx_dropped(name)?.saturating_sub((*start)?)))
+            .sum()
+    }
+}
+
+/**
+ * インターフェイスから受信したフレーム
+ */
+pub struct Frame {
+    pub interface: String,
+    pub link_type: LinkType,
+    /// UNIXエポックからの秒数
+    pub timestamp: f64,
+    /// 元のフレーム長
+    pub len: usize,
+    pub data: Vec<u8>,
+}
+
+/**
+ * インターフェイスの一覧を表示する
+ */
+pub fn list_interfaces<W: Write>(w: &mut W) -> Result<()> {
+    for interface in datalink::interfaces() {
+        writeln!(w, "{} (index {})", interface.name, interface.index)?;
+        let mtu = link::mtu(&interface)
+            .map(|m| m.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        writeln!(
+            w,
+            "    link: {}  mtu: {}",
+            LinkType::from_interface(&interface).name(),
+            mtu
+        )?;
+        if let Some(mac) = interface.mac {
+            writeln!(w, "    ether: {}", mac)?;
+        }
+        for ip in &interface.ips {
+            let family = if ip.is_ipv4() { "inet" } else { "inet6" };
+            writeln!(w, "    {}: {}", family, ip)?;
+        }
+        writeln!(w, "    flags: {}", link::flags_string(&interface))?;
+        if !interface.description.is_empty() {
+            writeln!(w, "    description: {}", interface.description)?;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * 名前からインターフェイスを1つ選択する
+ */
+pub fn find_interface(name: &str) -> Result<NetworkInterface> {
+    datalink::interfaces()
+        .into_iter()
+        .find(|iface| iface.name == name)
+        .with_context(|| format!("Failed to get interface {}", name))
+}
+
+/**
+ * 名前からインターフェイスを選択する
+ * "any"は起動しているすべてのインターフェイスを表す
+ */
+fn resolve_interfaces(names: &[String]) -> Result<Vec<NetworkInterface>> {
+    // ネットワークインターフェイス（NICや無線LANアダプタを抽象化したもの）の選択
+    let interfaces = datalink::interfaces();
+    if names.iter().any(|name| name == ANY) {
+        return Ok(interfaces.into_iter().filter(|i| i.is_up()).collect());
+    }
+    names
+        .iter()
+        .map(|name| {
+            interfaces
+                .iter()
+                .find(|iface| iface.name == *name)
+                .cloned()
+                .with_context(|| format!("Failed to get interface {}", name))
+        })
+        .collect()
+}
+
+/**
+ * インターフェイスごとにキャプチャ用のスレッドを起動する
+ * 受信したフレームはインターフェイス名を付けて返り値のチャネルに送られる
+ */
+pub fn start(names: &[String], config: &CaptureConfig) -> Result<Capture> {
+    let any = names.iter().any(|name| name == ANY);
+    let (sender, receiver) = mpsc::sync_channel(config.queue_len);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let mut kernel_dropped = vec![];
+    let mut link_type = None;
+    let datalink_config = datalink::Config {
+        read_buffer_size: config.buffer_size,
+        ..Default::default()
+    };
+    for interface in resolve_interfaces(names)? {
+        match open_with(&interface, &datalink_config) {
+            Ok((_tx, rx)) => {
+                kernel_dropped.push((interface.name.clone(), link::rx_dropped(&interface.name)));
+                let interface_link_type = LinkType::from_interface(&interface);
+                link_type.get_or_insert(interface_link_type);
+                let receiver = Receiver {
+                    interface: interface.name.clone(),
+                    link_type: interface_link_type,
+                    snaplen: config.snaplen,
+                    sender: sender.clone(),
+                    dropped: dropped.clone(),
+                };
+                thread::spawn(move || receiver.run(rx));
+            }
+            // anyの場合はキャプチャできないインターフェイスを飛ばす
+            Err(e) if any => warn!("{}: {}", interface.name, e),
+            Err(e) => return Err(e),
+        }
+    }
+    let link_type = link_type.context("No interface to capture")?;
+    Ok(Capture {
+        frames: receiver,
+        link_type,
+        dropped,
+        kernel_dropped,
+    })
+}
+
+/**
+ * データリンクのチャネルを取得
+ */
+pub fn open(
+    interface: &NetworkInterface,
+) -> Result<(Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>)> {
+    open_with(interface, &Default::default())
+}
+
+fn open_with(
+    interface: &NetworkInterface,
+    config: &datalink::Config,
+) -> Result<(Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>)> {
+    match datalink::channel(interface, *config) {
+        Ok(Ethernet(tx, rx)) => Ok((tx, rx)),
+        Ok(_) => Err(anyhow!("Unhandled channel type")),
+        Err(e) => Err(anyhow!("Failed to create datalink channel {}", e)),
+    }
+}
+
+/**
+ * 1つのインターフェイスからフレームを受信し続ける
+ */
+struct Receiver {
+    interface: String,
+    link_type: LinkType,
+    snaplen: usize,
+    sender: SyncSender<Frame>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Receiver {
+    fn run(self, mut rx: Box<dyn DataLinkReceiver>) {
+        loop {
+            match rx.next() {
+                Ok(data) => {
+                    let frame = Frame {
+                        interface: self.interface.clone(),
+                        link_type: self.link_type,
+                        timestamp: record::now(),
+                        len: data.len(),
+                        data: data[..data.len().min(self.snaplen)].to_vec(),
+                    };
+                    match self.sender.try_send(frame) {
+                        Ok(()) => {}
+                        // 解析が追いつかない場合は破棄する
+                        Err(TrySendError::Full(_)) => {
+                            self.dropped.fetch_add(1, Ordering::Relaxed);
+                        }
+                        // 受信側が終了していたらスレッドも終了する
+                        Err(TrySendError::Disconnected(_)) => return,
+                    }
+                }
+                Err(e) => {
+                    warn!("{}: {}", self.interface, e);
+                }
+            }
+        }
+    }
+}
+
+/**
+ * キャプチャ終了時に表示する統計
+ */
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// 受信したフレーム数
+    pub received: u64,
+    /// 解析して出力したパケット数
+    pub decoded: u64,
+    /// 表示フィルタに一致せず出力しなかったパケット数
+    pub filtered: u64,
+    /// デコーダのないプロトコルのため出力しなかったパケット数
+    pub unsupported: u64,
+    /// 解析に失敗したパケット数
+    pub failed: u64,
+    /// 出力したうち、チェックサムの不一致などの異常があったパケット数
+    pub malformed: u64,
+    /// 解析が追いつかずに破棄したフレーム数（ライブキャプチャのみ）
+    pub dropped: Option<u64>,
+    /// カーネルが破棄したパケット数（取得できる場合のみ）
+    pub kernel_dropped: Option<u64>,
+}
+
+impl Summary {
+    pub fn print<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(w)?;
+        writeln!(w, "{} packets received", self.received)?;
+        writeln!(w, "{} packets decoded", self.decoded)?;
+        writeln!(w, "{} packets filtered", self.filtered)?;
+        writeln!(w, "{} packets of unsupported protocols", self.unsupported)?;
+        writeln!(w, "{} packets failed to parse", self.failed)?;
+        writeln!(w, "{} packets with errors", self.malformed)?;
+        if let Some(dropped) = self.dropped {
+            writeln!(w, "{} packets dropped by capture queue", dropped)?;
+        }
+        match self.kernel_dropped {
+            Some(dropped) => writeln!(w, "{} packets dropped by kernel", dropped)?,
+            None if self.dropped.is_some() => {
+                writeln!(w, "packets dropped by kernel: not available")?
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
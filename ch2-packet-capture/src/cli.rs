@@ -0,0 +1,309 @@
+use crate::capture::CaptureConfig;
+use crate::filter::Filter;
+use crate::hexdump::HexDump;
+use crate::output::{Format, TextOptions};
+use crate::replay::{self, ReplayOptions, Rewrite, Timing};
+use anyhow::{anyhow, Context, Result};
+use std::{
+    io::{self, IsTerminal},
+    time::Duration,
+};
+
+pub const USAGE: &str = "Usage: ch2-packet-capture --list
+       ch2-packet-capture --replay <file.pcap> [--speed X | --pps N] \
+[--rewrite-mac OLD=NEW] [--rewrite-ip OLD=NEW] [--rewrite-port OLD=NEW] <interface>
+       ch2-packet-capture [--format text|json|csv] \
+[-c COUNT] [--duration SECS] [--snaplen BYTES] [--buffer-size BYTES] \
+[(--stats | --tcp-analysis) [--interval SECS] [--top N] | --tui] [--filter EXPR] [--serve ADDR:PORT] \
+[-V] [--no-checksum] [--full-frame] [--hex-width N] [--hex-group N] [--no-color] [--no-utf8] \
+(<interface>[,<interface>...] | any | --read <file.pcap> | --connect HOST:PORT)";
+
+/**
+ * パケットの入力元
+ */
+#[derive(Debug)]
+pub enum Source {
+    /// インターフェイス名のリスト。"any"はすべてのインターフェイス
+    Interfaces(Vec<String>),
+    File(String),
+    /// --serveで配信しているストリームの接続先
+    Remote(String),
+}
+
+/**
+ * 実行するコマンド
+ */
+#[derive(Debug)]
+pub enum Command {
+    /// インターフェイスの一覧を表示する
+    List,
+    Capture(Source),
+    /// pcapファイルのフレームを送信し直す
+    Replay(ReplayOptions),
+}
+
+/**
+ * コマンドライン引数
+ */
+#[derive(Debug)]
+pub struct Options {
+    pub command: Command,
+    pub format: Format,
+    /// パケットを出力する代わりにフローを集計する
+    pub stats: bool,
+    /// パケットを出力する代わりにTCPコネクションの遅延や再送を集計する
+    pub tcp_analysis: bool,
+    /// 集計結果を表示する間隔。Noneの場合は終了時にのみ表示する
+    pub interval: Option<Duration>,
+    /// 集計結果で表示する件数
+    pub top: usize,
+    /// 端末UIで表示する
+    pub tui: bool,
+    /// 表示フィルタの式
+    pub filter: Option<String>,
+    /// キャプチャしたフレームをpcap形式で配信するアドレス
+    pub serve: Option<String>,
+    pub text: TextOptions,
+    /// 指定した数のパケットを出力したら終了する
+    pub count: Option<u64>,
+    /// 指定した時間が経過したら終了する
+    pub duration: Option<Duration>,
+    pub capture: CaptureConfig,
+    /// IP・TCP・UDPのチェックサムを検証する
+    pub verify_checksums: bool,
+}
+
+impl Options {
+    /**
+     * コマンドライン引数を解析する（先頭のプログラム名は含まない）
+     */
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self> {
+        let mut interfaces: Vec<String> = vec![];
+        let mut file = None;
+        let mut list = false;
+        let mut format = Format::Text;
+        let mut stats = false;
+        let mut tcp_analysis = false;
+        let mut interval = None;
+        let mut top = 10;
+        let mut tui = false;
+        let mut filter = None;
+        let mut serve = None;
+        let mut remote = None;
+        // 端末に出力する場合のみ色付けする
+        let mut text = TextOptions {
+            hexdump: HexDump {
+                color: io::stdout().is_terminal(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut count = None;
+        let mut duration = None;
+        let mut capture = CaptureConfig::default();
+        let mut verify_checksums = true;
+        let mut replay_file = None;
+        let mut timing = Timing::Original { speed: 1.0 };
+        let mut rewrite = Rewrite::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-f" | "--format" => {
+                    let value = args.next().context("Missing value for --format")?;
+                    format = value.parse()?;
+                }
+                "-r" | "--read" => {
+                    file = Some(args.next().context("Missing value for --read")?);
+                }
+                "--connect" => {
+                    remote = Some(args.next().context("Missing value for --connect")?);
+                }
+                "--serve" => {
+                    serve = Some(args.next().context("Missing value for --serve")?);
+                }
+                "-l" | "--list" => list = true,
+                "--stats" => stats = true,
+                "--tcp-analysis" => tcp_analysis = true,
+                "--interval" => {
+                    let value = args.next().context("Missing value for --interval")?;
+                    let secs: u64 = value.parse().context("invalid interval")?;
+                    interval = Some(Duration::from_secs(secs));
+                }
+                "--top" => {
+                    let value = args.next().context("Missing value for --top")?;
+                    top = value.parse().context("invalid top count")?;
+                }
+                "--tui" => tui = true,
+                "--filter" => {
+                    let value = args.next().context("Missing value for --filter")?;
+                    // ここで構文を確認しておく
+                    value.parse::<Filter>()?;
+                    filter = Some(value);
+                }
+                "--full-frame" => text.full_frame = true,
+                "-V" | "--verbose" => text.verbose = true,
+                "--no-checksum" => verify_checksums = false,
+                "--hex-width" => {
+                    let value = args.next().context("Missing value for --hex-width")?;
+                    text.hexdump.width = value.parse().context("invalid hex width")?;
+                    if text.hexdump.width == 0 {
+                        return Err(anyhow!("hex width must be positive"));
+                    }
+                }
+                "--hex-group" => {
+                    let value = args.next().context("Missing value for --hex-group")?;
+                    text.hexdump.group = value.parse().context("invalid hex group")?;
+                }
+                "--no-color" => text.hexdump.color = false,
+                "--no-utf8" => text.hexdump.utf8 = false,
+                "-c" | "--count" => {
+                    let value = args.next().context("Missing value for --count")?;
+                    count = Some(value.parse().context("invalid count")?);
+                }
+                "--duration" => {
+                    let value = args.next().context("Missing value for --duration")?;
+                    let secs: u64 = value.parse().context("invalid duration")?;
+                    duration = Some(Duration::from_secs(secs));
+                }
+                "-s" | "--snaplen" => {
+                    let value = args.next().context("Missing value for --snaplen")?;
+                    capture.snaplen = value.parse().context("invalid snaplen")?;
+                    if capture.snaplen == 0 {
+                        return Err(anyhow!("snaplen must be positive"));
+                    }
+                }
+                "-B" | "--buffer-size" => {
+                    let value = args.next().context("Missing value for --buffer-size")?;
+                    capture.buffer_size = value.parse().context("invalid buffer size")?;
+                }
+                "--replay" => {
+                    replay_file = Some(args.next().context("Missing value for --replay")?);
+                }
+                "--speed" => {
+                    let value = args.next().context("Missing value for --speed")?;
+                    let speed: f64 = value.parse().context("invalid speed")?;
+                    if speed.is_nan() || speed <= 0.0 {
+                        return Err(anyhow!("speed must be positive"));
+                    }
+                    timing = Timing::Original { speed };
+                }
+                "--pps" => {
+                    let value = args.next().context("Missing value for --pps")?;
+                    let pps: f64 = value.parse().context("invalid pps")?;
+                    if pps.is_nan() || pps <= 0.0 {
+                        return Err(anyhow!("pps must be positive"));
+                    }
+                    timing = Timing::Rate { pps };
+                }
+                "--rewrite-mac" => {
+                    let value = args.next().context("Missing value for --rewrite-mac")?;
+                    let (old, new) = replay::parse_rule(&value)?;
+                    rewrite.macs.insert(old, new);
+                }
+                "--rewrite-ip" => {
+                    let value = args.next().context("Missing value for --rewrite-ip")?;
+                    let (old, new) = replay::parse_rule(&value)?;
+                    rewrite.ips.insert(old, new);
+                }
+                "--rewrite-port" => {
+                    let value = args.next().context("Missing value for --rewrite-port")?;
+                    let (old, new) = replay::parse_rule(&value)?;
+                    rewrite.ports.insert(old, new);
+                }
+                _ if arg.starts_with('-') => {
+                    return Err(anyhow!("Unknown option {}\n{}", arg, USAGE));
+                }
+                // カンマ区切りでも複数指定できる
+                _ => interfaces.extend(
+                    arg.split(',')
+                        .filter(|name| !name.is_empty())
+                        .map(str::to_string),
+                ),
+            }
+        }
+
+        if let Some(file) = replay_file {
+            if interfaces.len() != 1 {
+                return Err(anyhow!(
+                    "Specify exactly one interface to replay on\n{}",
+                    USAGE
+                ));
+            }
+            let replay = ReplayOptions {
+                file,
+                interface: interfaces.remove(0),
+                timing,
+                rewrite,
+            };
+            return Ok(Self {
+                command: Command::Replay(replay),
+                format,
+                stats,
+                tcp_analysis,
+                interval,
+                top,
+                tui,
+                filter,
+                serve,
+                text,
+                count,
+                duration,
+                capture,
+                verify_checksums,
+            });
+        }
+
+        let source = match (interfaces.is_empty(), file, remote) {
+            _ if list => Source::Interfaces(interfaces),
+            (false, None, None) => Source::Interfaces(interfaces),
+            (true, Some(file), None) => Source::File(file),
+            (true, None, Some(addr)) => Source::Remote(addr),
+            (true, None, None) => {
+                return Err(anyhow!("Please specify target interface name\n{}", USAGE))
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Specify only one of an interface, a file or a remote stream\n{}",
+                    USAGE
+                ))
+            }
+        };
+        if [stats, tcp_analysis, tui]
+            .iter()
+            .filter(|mode| **mode)
+            .count()
+            > 1
+        {
+            return Err(anyhow!(
+                "Only one of --stats, --tcp-analysis and --tui can be used\n{}",
+                USAGE
+            ));
+        }
+        // ライブキャプチャは終わりがないので、既定では5秒ごとに表示する
+        if (stats || tcp_analysis) && interval.is_none() && !matches!(source, Source::File(_)) {
+            interval = Some(Duration::from_secs(5));
+        }
+
+        let command = if list {
+            Command::List
+        } else {
+            Command::Capture(source)
+        };
+
+        Ok(Self {
+            command,
+            format,
+            stats,
+            tcp_analysis,
+            interval,
+            top,
+            tui,
+            filter,
+            serve,
+            text,
+            count,
+            duration,
+            capture,
+            verify_checksums,
+        })
+    }
+}
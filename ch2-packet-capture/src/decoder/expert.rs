@@ -0,0 +1,115 @@
+use super::Header;
+use pnet::{packet::ip::IpNextHeaderProtocol, util};
+use serde::Serialize;
+use std::fmt;
+
+/**
+ * 異常の深刻度
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// 検証できなかったなど、異常とは限らないもの
+    Note,
+    /// プロトコル上ありえるが疑わしいもの
+    Warning,
+    /// チェックサムの不一致や壊れたヘッダ
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Note => "Note",
+            Severity::Warning => "Warning",
+            Severity::Error => "Error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/**
+ * デコード中に見つかった異常（エキスパート情報）
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct ExpertInfo {
+    pub severity: Severity,
+    /// 異常が見つかった層
+    pub layer: &'static str,
+    pub message: String,
+}
+
+impl ExpertInfo {
+    pub fn new<S: Into<String>>(severity: Severity, layer: &'static str, message: S) -> Self {
+        Self {
+            severity,
+            layer,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ExpertInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}: {}", self.severity, self.layer, self.message)
+    }
+}
+
+/**
+ * IPの疑似ヘッダを含めたTCP/UDPのチェックサムを計算する
+ * checksum_wordはセグメント内のチェックサムフィールドの位置（16ビット単位）
+ * IPヘッダが示す長さ分のセグメントがキャプチャされていない場合や、
+ * 断片化されている場合は計算できないのでNoneを返す
+ */
+pub fn pseudo_header_checksum(
+    outer: &Header,
+    protocol: u8,
+    segment: &[u8],
+    checksum_word: usize,
+) -> Option<u16> {
+    let protocol = IpNextHeaderProtocol(protocol);
+    match outer {
+        Header::Ipv4 {
+            src,
+            dst,
+            header_length,
+            total_length,
+            more_fragments,
+            fragment_offset,
+            ..
+        } => {
+            let len = (*total_length as usize).checked_sub(*header_length as usize)?;
+            if *more_fragments || *fragment_offset != 0 || segment.len() < len {
+                return None;
+            }
+            Some(util::ipv4_checksum(
+                &segment[..len],
+                checksum_word,
+                &[],
+                src,
+                dst,
+                protocol,
+            ))
+        }
+        Header::Ipv6 {
+            src,
+            dst,
+            payload_length,
+            ..
+        } => {
+            let len = *payload_length as usize;
+            if segment.len() < len {
+                return None;
+            }
+            Some(util::ipv6_checksum(
+                &segment[..len],
+                checksum_word,
+                &[],
+                src,
+                dst,
+                protocol,
+            ))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,168 @@
+use super::{DecodeContext, Decoded, Decoder, ExpertInfo, Header, Protocol, Severity};
+use anyhow::{anyhow, Context, Result};
+use pnet::packet::{
+    ipv4::{self, Ipv4Packet},
+    ipv6::Ipv6Packet,
+};
+
+const IPV4_MIN_HEADER_SIZE: usize = 20;
+const IPV6_HEADER_SIZE: usize = 40;
+/// IPv4のフラグの予約ビット
+const RESERVED: u8 = 0b100;
+/// IPv4のフラグのDon't Fragmentビット
+const DONT_FRAGMENT: u8 = 0b010;
+/// IPv4のフラグのMore Fragmentsビット
+const MORE_FRAGMENTS: u8 = 0b001;
+
+pub struct Ipv4Decoder;
+
+impl Decoder for Ipv4Decoder {
+    fn name(&self) -> &'static str {
+        "IPv4"
+    }
+
+    fn decode(&self, data: &[u8], context: &DecodeContext) -> Result<Decoded> {
+        let packet = Ipv4Packet::new(data).context("Truncated IPv4 header")?;
+        if packet.get_version() != 4 {
+            return Err(anyhow!("Bogus IP version {}", packet.get_version()));
+        }
+        let header_len = packet.get_header_length() as usize * 4;
+        if header_len < IPV4_MIN_HEADER_SIZE || header_len > data.len() {
+            return Err(anyhow!("Bogus IPv4 header length {}", header_len));
+        }
+        // pnetのpayload()は全長がヘッダ長より短くても黙って空のペイロードを返すので、
+        // 壊れた全長をエラーにできるよう自前で範囲を求める
+        let total_len = packet.get_total_length() as usize;
+        if total_len < header_len {
+            return Err(anyhow!("Bogus IPv4 total length {}", total_len));
+        }
+
+        let mut expert = vec![];
+        let mut note = |severity, message: String| {
+            expert.push(ExpertInfo::new(severity, self.name(), message));
+        };
+        if context.verify_checksums {
+            let expected = ipv4::checksum(&packet);
+            if packet.get_checksum() != expected {
+                note(
+                    Severity::Error,
+                    format!(
+                        "Bad header checksum 0x{:04x} (should be 0x{:04x})",
+                        packet.get_checksum(),
+                        expected
+                    ),
+                );
+            }
+        }
+        if total_len > data.len() {
+            note(
+                Severity::Warning,
+                format!(
+                    "Total length {} exceeds the {} bytes captured",
+                    total_len,
+                    data.len()
+                ),
+            );
+        }
+        let flags = packet.get_flags();
+        if flags & RESERVED != 0 {
+            note(Severity::Warning, "Reserved flag is set".to_string());
+        }
+        if flags & DONT_FRAGMENT != 0 && flags & MORE_FRAGMENTS != 0 {
+            note(
+                Severity::Warning,
+                "Both Don't Fragment and More Fragments are set".to_string(),
+            );
+        }
+        if packet.get_ttl() == 0 {
+            note(Severity::Warning, "Time to live is 0".to_string());
+        }
+        // 先頭以外のフラグメントには上の層のヘッダがない
+        let fragment_offset = packet.get_fragment_offset();
+        let protocol = packet.get_next_level_protocol().0;
+        let next = if fragment_offset == 0 {
+            Some(Protocol::Ip(protocol))
+        } else {
+            note(
+                Severity::Note,
+                format!(
+                    "Fragment at offset {}, upper layers not decoded",
+                    fragment_offset as usize * 8
+                ),
+            );
+            None
+        };
+
+        Ok(Decoded {
+            header: Header::Ipv4 {
+                src: packet.get_source(),
+                dst: packet.get_destination(),
+                header_length: header_len as u8,
+                ttl: packet.get_ttl(),
+                protocol,
+                identification: packet.get_identification(),
+                dont_fragment: flags & DONT_FRAGMENT != 0,
+                more_fragments: flags & MORE_FRAGMENTS != 0,
+                fragment_offset,
+                total_length: packet.get_total_length(),
+                checksum: packet.get_checksum(),
+            },
+            header_len,
+            next,
+            payload: header_len..total_len.min(data.len()),
+            expert,
+        })
+    }
+}
+
+pub struct Ipv6Decoder;
+
+impl Decoder for Ipv6Decoder {
+    fn name(&self) -> &'static str {
+        "IPv6"
+    }
+
+    fn decode(&self, data: &[u8], _context: &DecodeContext) -> Result<Decoded> {
+        let packet = Ipv6Packet::new(data).context("Truncated IPv6 header")?;
+        if packet.get_version() != 6 {
+            return Err(anyhow!("Bogus IP version {}", packet.get_version()));
+        }
+        let payload_len = packet.get_payload_length() as usize;
+        let captured = data.len() - IPV6_HEADER_SIZE;
+
+        let mut expert = vec![];
+        if payload_len > captured {
+            expert.push(ExpertInfo::new(
+                Severity::Warning,
+                self.name(),
+                format!(
+                    "Payload length {} exceeds the {} bytes captured",
+                    payload_len, captured
+                ),
+            ));
+        }
+        if packet.get_hop_limit() == 0 {
+            expert.push(ExpertInfo::new(
+                Severity::Warning,
+                self.name(),
+                "Hop limit is 0",
+            ));
+        }
+
+        let next_header = packet.get_next_header().0;
+        Ok(Decoded {
+            header: Header::Ipv6 {
+                src: packet.get_source(),
+                dst: packet.get_destination(),
+                hop_limit: packet.get_hop_limit(),
+                next_header,
+                flow_label: packet.get_flow_label(),
+                payload_length: packet.get_payload_length(),
+            },
+            header_len: IPV6_HEADER_SIZE,
+            next: Some(Protocol::Ip(next_header)),
+            payload: IPV6_HEADER_SIZE..IPV6_HEADER_SIZE + payload_len.min(captured),
+            expert,
+        })
+    }
+}
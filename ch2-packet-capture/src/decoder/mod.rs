@@ -0,0 +1,799 @@
+use crate::dissectors::{self, Field, ProtoTree, Transport};
+use crate::link::LinkType;
+use anyhow::{Context, Result};
+use pnet::{packet::tcp::TcpFlags, util::MacAddr};
+use reassembly::{Continuation, Reassembler, StreamKey};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::Range,
+};
+
+mod expert;
+mod ip;
+mod reassembly;
+mod transport;
+pub use expert::{ExpertInfo, Severity};
+
+/**
+ * 次の層のプロトコルを識別する値
+ * レジストリはこの値でデコーダを選ぶ
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Protocol {
+    EtherType(u16),
+    Ip(u8),
+    /// トランスポート層のポート。アプリケーション層のディセクタを選ぶのに使う
+    Port {
+        transport: Transport,
+        src: u16,
+        dst: u16,
+    },
+}
+
+/**
+ * 各層のヘッダの要約
+ */
+#[derive(Debug, Clone)]
+pub enum Header {
+    Link {
+        name: &'static str,
+        src: Option<MacAddr>,
+        dst: Option<MacAddr>,
+        ethertype: u16,
+    },
+    Ipv4 {
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        /// ヘッダ長（バイト）
+        header_length: u8,
+        ttl: u8,
+        protocol: u8,
+        identification: u16,
+        dont_fragment: bool,
+        more_fragments: bool,
+        /// フラグメントオフセット（8バイト単位）
+        fragment_offset: u16,
+        total_length: u16,
+        checksum: u16,
+    },
+    Ipv6 {
+        src: Ipv6Addr,
+        dst: Ipv6Addr,
+        hop_limit: u8,
+        next_header: u8,
+        flow_label: u32,
+        payload_length: u16,
+    },
+    Tcp {
+        src: u16,
+        dst: u16,
+        seq: u32,
+        ack: u32,
+        flags: u16,
+        window: u16,
+        checksum: u16,
+    },
+    Udp {
+        src: u16,
+        dst: u16,
+        length: u16,
+        checksum: u16,
+    },
+}
+
+impl Header {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Header::Link { name, .. } => name,
+            Header::Ipv4 { .. } => "IPv4",
+            Header::Ipv6 { .. } => "IPv6",
+            Header::Tcp { .. } => "TCP",
+            Header::Udp { .. } => "UDP",
+        }
+    }
+
+    /**
+     * L3の送信元・宛先アドレス
+     */
+    pub fn addresses(&self) -> Option<(IpAddr, IpAddr)> {
+        match self {
+            Header::Ipv4 { src, dst, .. } => Some(((*src).into(), (*dst).into())),
+            Header::Ipv6 { src, dst, .. } => Some(((*src).into(), (*dst).into())),
+            _ => None,
+        }
+    }
+
+    /**
+     * L4の送信元・宛先ポート
+     */
+    pub fn ports(&self) -> Option<(u16, u16)> {
+        match self {
+            Header::Tcp { src, dst, .. } | Header::Udp { src, dst, .. } => Some((*src, *dst)),
+            _ => None,
+        }
+    }
+
+    /**
+     * プロトコルツリーとして表示するためのフィールド
+     */
+    pub fn fields(&self) -> Vec<Field> {
+        let opt_mac = |mac: &Option<MacAddr>| mac.map(|m| m.to_string()).unwrap_or_default();
+        match self {
+            Header::Link {
+                src,
+                dst,
+                ethertype,
+                ..
+            } => vec![
+                Field::new("Source", opt_mac(src)),
+                Field::new("Destination", opt_mac(dst)),
+                Field::new("Type", format!("0x{:04x}", ethertype)),
+            ],
+            Header::Ipv4 {
+                src,
+                dst,
+                header_length,
+                ttl,
+                protocol,
+                identification,
+                dont_fragment,
+                more_fragments,
+                fragment_offset,
+                total_length,
+                checksum,
+            } => vec![
+                Field::new("Source", src),
+                Field::new("Destination", dst),
+                Field::new("Header length", header_length),
+                Field::new("Total length", total_length),
+                Field::new("Identification", format!("0x{:04x}", identification)),
+                Field::new("Don't fragment", dont_fragment),
+                Field::new("More fragments", more_fragments),
+                Field::new("Fragment offset", fragment_offset),
+                Field::new("Time to live", ttl),
+                Field::new("Protocol", protocol),
+                Field::new("Checksum", format!("0x{:04x}", checksum)),
+            ],
+            Header::Ipv6 {
+                src,
+                dst,
+                hop_limit,
+                next_header,
+                flow_label,
+                payload_length,
+            } => vec![
+                Field::new("Source", src),
+                Field::new("Destination", dst),
+                Field::new("Payload length", payload_length),
+                Field::new("Flow label", format!("0x{:05x}", flow_label)),
+                Field::new("Hop limit", hop_limit),
+                Field::new("Next header", next_header),
+            ],
+            Header::Tcp {
+                src,
+                dst,
+                seq,
+                ack,
+                flags,
+                window,
+                checksum,
+            } => vec![
+                Field::new("Source port", src),
+                Field::new("Destination port", dst),
+                Field::new("Sequence number", seq),
+                Field::new("Acknowledgment number", ack),
+                Field::new("Flags", crate::record::tcp_flags_string(*flags)),
+                Field::new("Window", window),
+                Field::new("Checksum", format!("0x{:04x}", checksum)),
+            ],
+            Header::Udp {
+                src,
+                dst,
+                length,
+                checksum,
+            } => vec![
+                Field::new("Source port", src),
+                Field::new("Destination port", dst),
+                Field::new("Length", length),
+                Field::new("Checksum", format!("0x{:04x}", checksum)),
+            ],
+        }
+    }
+}
+
+/**
+ * 1つの層をデコードした結果
+ * 範囲はデコーダに渡したバッファの先頭からのオフセット
+ */
+pub struct Decoded {
+    pub header: Header,
+    pub header_len: usize,
+    pub next: Option<Protocol>,
+    pub payload: Range<usize>,
+    /// この層で見つかった異常
+    pub expert: Vec<ExpertInfo>,
+}
+
+/**
+ * デコーダに渡す、下の層の情報と設定
+ */
+pub struct DecodeContext<'a> {
+    /// 1つ下の層のヘッダ。TCP/UDPの疑似ヘッダの計算に使う
+    pub outer: &'a Header,
+    pub verify_checksums: bool,
+}
+
+/**
+ * 1つの層のデコーダ
+ * 続きを解析できないほど壊れている場合はエラーを返す
+ */
+pub trait Decoder {
+    /// エキスパート情報に表示する層の名前
+    fn name(&self) -> &'static str;
+
+    fn decode(&self, data: &[u8], context: &DecodeContext) -> Result<Decoded>;
+}
+
+/**
+ * デコード済みの層
+ * rangeはフレームの先頭からのヘッダの範囲
+ */
+#[derive(Debug, Clone)]
+pub struct DecodedLayer {
+    pub header: Header,
+    pub range: Range<usize>,
+}
+
+/**
+ * フレーム全体をデコードした結果
+ */
+#[derive(Debug, Clone)]
+pub struct DecodedPacket {
+    pub layers: Vec<DecodedLayer>,
+    /// 最上位の層のペイロードの範囲
+    pub payload: Range<usize>,
+    pub application: Option<ProtoTree>,
+    /// デコーダが登録されていなかった次の層のプロトコル
+    pub unsupported: Option<Protocol>,
+    /// 各層で見つかった異常
+    pub expert: Vec<ExpertInfo>,
+}
+
+impl DecodedPacket {
+    /**
+     * 指定した層のヘッダを下の層から順に探す
+     */
+    pub fn find<T, F: Fn(&Header) -> Option<T>>(&self, f: F) -> Option<T> {
+        self.layers.iter().find_map(|layer| f(&layer.header))
+    }
+
+    /**
+     * 最も深刻な異常
+     */
+    pub fn worst(&self) -> Option<&ExpertInfo> {
+        self.expert.iter().max_by_key(|info| info.severity)
+    }
+
+    /**
+     * 最上位の層（アプリケーション層を除く）
+     */
+    pub fn top(&self) -> Option<&DecodedLayer> {
+        self.layers.last()
+    }
+}
+
+/**
+ * イーサタイプ・IPのプロトコル番号・ポート番号からデコーダを選ぶ
+ */
+pub struct Registry {
+    ethertypes: HashMap<u16, Box<dyn Decoder>>,
+    ip_protocols: HashMap<u8, Box<dyn Decoder>>,
+    applications: dissectors::Registry,
+    verify_checksums: bool,
+    reassembler: Reassembler,
+}
+
+impl Registry {
+    pub fn new(applications: dissectors::Registry) -> Self {
+        Self {
+            ethertypes: HashMap::new(),
+            ip_protocols: HashMap::new(),
+            applications,
+            verify_checksums: true,
+            reassembler: Reassembler::default(),
+        }
+    }
+
+    /**
+     * チェックサムを検証するかどうか
+     * NICのチェックサムオフロードで送信パケットがすべて不正になる場合に無効にする
+     */
+    pub fn set_verify_checksums(&mut self, verify: bool) {
+        self.verify_checksums = verify;
+    }
+
+    pub fn register_ethertype(&mut self, ethertype: u16, decoder: Box<dyn Decoder>) {
+        self.ethertypes.insert(ethertype, decoder);
+    }
+
+    pub fn register_ip_protocol(&mut self, protocol: u8, decoder: Box<dyn Decoder>) {
+        self.ip_protocols.insert(protocol, decoder);
+    }
+
+    fn lookup(&self, protocol: Protocol) -> Option<&dyn Decoder> {
+        match protocol {
+            Protocol::EtherType(t) => self.ethertypes.get(&t).map(|d| d.as_ref()),
+            Protocol::Ip(p) => self.ip_protocols.get(&p).map(|d| d.as_ref()),
+            Protocol::Port { .. } => None,
+        }
+    }
+
+    /**
+     * リンク層から順に、デコーダがある限り上の層へデコードしていく
+     * リンク層より上の層が壊れていた場合は、そこでデコードをやめて異常として記録する
+     */
+    pub fn decode(&mut self, link_type: LinkType, frame: &[u8]) -> Result<DecodedPacket> {
+        // リンク層ヘッダを剥がす
+        let link = link_type
+            .decode(frame)
+            .context("Failed to decode link layer header")?;
+        let mut layers = vec![DecodedLayer {
+            header: Header::Link {
+                name: link.name,
+                src: link.src_mac,
+                dst: link.dst_mac,
+                ethertype: link.ethertype.0,
+            },
+            range: 0..link.len,
+        }];
+        let mut payload = link.len..frame.len();
+        let mut next = Some(Protocol::EtherType(link.ethertype.0));
+        let mut application = None;
+        let mut unsupported = None;
+        let mut expert = vec![];
+
+        while let Some(protocol) = next.take() {
+            if let Protocol::Port {
+                transport,
+                src,
+                dst,
+            } = protocol
+            {
+                let data = &frame[payload.clone()];
+                application = match transport {
+                    Transport::Tcp => self.dissect_stream(&layers, src, dst, data, &mut expert),
+                    Transport::Udp => self.applications.dissect(transport, src, dst, data),
+                };
+                break;
+            }
+            let decoder = match self.lookup(protocol) {
+                Some(decoder) => decoder,
+                None => {
+                    unsupported = Some(protocol);
+                    break;
+                }
+            };
+            let context = DecodeContext {
+                outer: &layers[layers.len() - 1].header,
+                verify_checksums: self.verify_checksums,
+            };
+            let decoded = match decoder.decode(&frame[payload.clone()], &context) {
+                Ok(decoded) => decoded,
+                Err(e) => {
+                    expert.push(ExpertInfo::new(
+                        Severity::Error,
+                        decoder.name(),
+                        format!("{:#}", e),
+                    ));
+                    break;
+                }
+            };
+            expert.extend(decoded.expert);
+            let base = payload.start;
+            layers.push(DecodedLayer {
+                header: decoded.header,
+                range: base..base + decoded.header_len,
+            });
+            payload = base + decoded.payload.start..base + decoded.payload.end;
+            next = decoded.next;
+        }
+
+        Ok(DecodedPacket {
+            layers,
+            payload,
+            application,
+            unsupported,
+            expert,
+        })
+    }
+}
+
+impl Registry {
+    /**
+     * TCPのペイロードを、前のセグメントから続くPDUを組み立てながら解析する
+     * PDUがまだ揃っていない場合はNoneを返す
+     */
+    fn dissect_stream(
+        &mut self,
+        layers: &[DecodedLayer],
+        src_port: u16,
+        dst_port: u16,
+        payload: &[u8],
+        expert: &mut Vec<ExpertInfo>,
+    ) -> Option<ProtoTree> {
+        let (src, dst) = layers.iter().find_map(|l| l.header.addresses())?;
+        let (seq, flags) = layers.iter().find_map(|l| match l.header {
+            Header::Tcp { seq, flags, .. } => Some((seq, flags)),
+            _ => None,
+        })?;
+        let key = StreamKey {
+            src,
+            dst,
+            src_port,
+            dst_port,
+        };
+        let closing = flags & (TcpFlags::FIN | TcpFlags::RST) != 0;
+        if payload.is_empty() {
+            if closing {
+                self.reassembler.close(&key);
+            }
+            return None;
+        }
+
+        let data = match self.reassembler.take(&key, seq, payload) {
+            Continuation::None => Cow::Borrowed(payload),
+            Continuation::Joined(data) => Cow::Owned(data),
+            Continuation::Retransmission => {
+                expert.push(ExpertInfo::new(
+                    Severity::Note,
+                    "TCP",
+                    "Retransmission of a segment being reassembled",
+                ));
+                return None;
+            }
+        };
+        let transport = Transport::Tcp;
+        let data = if !closing
+            && self
+                .applications
+                .incomplete(transport, src_port, dst_port, &data)
+        {
+            let next_seq = seq.wrapping_add(payload.len() as u32);
+            let len = data.len();
+            match self.reassembler.hold(key, next_seq, data.into_owned()) {
+                Ok(()) => {
+                    expert.push(ExpertInfo::new(
+                        Severity::Note,
+                        "TCP",
+                        format!("Segment of a reassembled PDU ({} bytes buffered)", len),
+                    ));
+                    return None;
+                }
+                Err(data) => {
+                    expert.push(ExpertInfo::new(
+                        Severity::Warning,
+                        "TCP",
+                        "Reassembly buffer limit exceeded",
+                    ));
+                    Cow::Owned(data)
+                }
+            }
+        } else {
+            data
+        };
+        if let Cow::Owned(data) = &data {
+            expert.push(ExpertInfo::new(
+                Severity::Note,
+                "TCP",
+                format!("Reassembled PDU of {} bytes", data.len()),
+            ));
+        }
+        if closing {
+            self.reassembler.close(&key);
+        }
+        self.applications
+            .dissect(transport, src_port, dst_port, &data)
+    }
+}
+
+impl Default for Registry {
+    /**
+     * 組み込みのデコーダを登録したレジストリ
+     */
+    fn default() -> Self {
+        let mut registry = Self::new(dissectors::Registry::default());
+        registry.register_ethertype(0x0800, Box::new(ip::Ipv4Decoder));
+        registry.register_ethertype(0x86dd, Box::new(ip::Ipv6Decoder));
+        registry.register_ip_protocol(6, Box::new(transport::TcpDecoder));
+        registry.register_ip_protocol(17, Box::new(transport::UdpDecoder));
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, Tcp};
+    use pnet::packet::{ipv4, ipv4::MutableIpv4Packet, tcp::TcpFlags::*};
+
+    const IP_OFFSET: usize = 14;
+    const IPV4_L4_OFFSET: usize = IP_OFFSET + 20;
+    const IPV6_L4_OFFSET: usize = IP_OFFSET + 40;
+    const LINK_TYPES: [LinkType; 5] = [
+        LinkType::Ethernet,
+        LinkType::Loopback,
+        LinkType::Null,
+        LinkType::RawIp,
+        LinkType::LinuxSll,
+    ];
+
+    /// DNSのAレコードの問い合わせ（example.com）
+    const DNS_QUERY: &[u8] = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+        \x07example\x03com\x00\x00\x01\x00\x01";
+    /// TLSのレコードヘッダとClientHelloの先頭
+    const TLS_HEAD: &[u8] = b"\x16\x03\x01\x00\x40\x01\x00\x00\x3c\x03\x03";
+
+    /**
+     * 乱数の代わりに使う、シードが同じなら同じ列を返すxorshift
+     */
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    fn seeds() -> Vec<Vec<u8>> {
+        vec![
+            Tcp::new("10.0.0.1:50000", "10.0.0.2:80", SYN).frame(),
+            Tcp::new("10.0.0.1:50000", "10.0.0.2:80", PSH | ACK)
+                .payload(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n")
+                .frame(),
+            Tcp::new("[2001:db8::1]:50000", "[2001:db8::2]:443", PSH | ACK)
+                .payload(TLS_HEAD)
+                .frame(),
+            testutil::udp("10.0.0.1:5353", "10.0.0.2:53", DNS_QUERY),
+            testutil::udp("[2001:db8::1]:68", "[2001:db8::2]:67", &[1; 260]),
+        ]
+    }
+
+    /**
+     * イーサネットフレームを各リンク層の形式に変換する
+     */
+    fn reframe(link_type: LinkType, frame: &[u8]) -> Vec<u8> {
+        let ip = &frame[IP_OFFSET..];
+        let mut out = match link_type {
+            LinkType::Ethernet | LinkType::Loopback => return frame.to_vec(),
+            LinkType::Null => vec![2, 0, 0, 0],
+            LinkType::RawIp => vec![],
+            LinkType::LinuxSll => {
+                let mut header = vec![0, 0, 0, 1, 0, 6];
+                header.extend_from_slice(&frame[6..12]);
+                header.extend_from_slice(&[0, 0]);
+                header.extend_from_slice(&frame[12..14]);
+                header
+            }
+        };
+        out.extend_from_slice(ip);
+        out
+    }
+
+    fn decode(frame: &[u8]) -> DecodedPacket {
+        Registry::default()
+            .decode(LinkType::Ethernet, frame)
+            .unwrap()
+    }
+
+    fn messages(packet: &DecodedPacket, severity: Severity) -> Vec<String> {
+        packet
+            .expert
+            .iter()
+            .filter(|info| info.severity == severity)
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    fn ipv4_header(frame: &mut [u8]) -> MutableIpv4Packet<'_> {
+        MutableIpv4Packet::new(&mut frame[IP_OFFSET..]).unwrap()
+    }
+
+    fn fix_ipv4_checksum(frame: &mut [u8]) {
+        let mut ip = ipv4_header(frame);
+        let checksum = ipv4::checksum(&ip.to_immutable());
+        ip.set_checksum(checksum);
+    }
+
+    #[test]
+    fn mangled_frames_never_panic() {
+        let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+        for link_type in LINK_TYPES {
+            let mut registry = Registry::default();
+            for seed in seeds() {
+                let frame = reframe(link_type, &seed);
+                assert!(registry.decode(link_type, &frame).is_ok());
+                for len in 0..frame.len() {
+                    let _ = registry.decode(link_type, &frame[..len]);
+                }
+                for _ in 0..2000 {
+                    let mut mutated = frame.clone();
+                    for _ in 0..=rng.below(4) {
+                        let i = rng.below(mutated.len());
+                        mutated[i] = rng.next() as u8;
+                    }
+                    mutated.truncate(rng.below(frame.len()) + 1);
+                    let _ = registry.decode(link_type, &mutated);
+                }
+            }
+            for _ in 0..2000 {
+                let len = rng.below(128);
+                let random: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+                let _ = registry.decode(link_type, &random);
+            }
+        }
+    }
+
+    #[test]
+    fn valid_checksums_produce_no_errors() {
+        for seed in seeds() {
+            let packet = decode(&seed);
+            assert_eq!(messages(&packet, Severity::Error), Vec::<String>::new());
+        }
+    }
+
+    #[test]
+    fn bad_ipv4_header_checksum() {
+        let mut frame = Tcp::new("10.0.0.1:50000", "10.0.0.2:80", SYN).frame();
+        let checksum = ipv4_header(&mut frame).get_checksum();
+        ipv4_header(&mut frame).set_checksum(checksum ^ 0x0101);
+        let packet = decode(&frame);
+        assert_eq!(
+            messages(&packet, Severity::Error),
+            [format!(
+                "[Error] IPv4: Bad header checksum 0x{:04x} (should be 0x{:04x})",
+                checksum ^ 0x0101,
+                checksum
+            )]
+        );
+        // ヘッダのチェックサムが不正でも上の層はデコードする
+        assert_eq!(packet.top().unwrap().header.name(), "TCP");
+    }
+
+    /**
+     * L4のチェックサムを壊し、期待するメッセージが付くことを確かめる
+     */
+    fn assert_bad_l4_checksum(mut frame: Vec<u8>, offset: usize, layer: &str) {
+        let expected = u16::from_be_bytes([frame[offset], frame[offset + 1]]);
+        frame[offset] ^= 0xff;
+        let bad = u16::from_be_bytes([frame[offset], frame[offset + 1]]);
+        assert_eq!(
+            messages(&decode(&frame), Severity::Error),
+            [format!(
+                "[Error] {}: Bad checksum 0x{:04x} (should be 0x{:04x})",
+                layer, bad, expected
+            )]
+        );
+
+        let mut registry = Registry::default();
+        registry.set_verify_checksums(false);
+        let packet = registry.decode(LinkType::Ethernet, &frame).unwrap();
+        assert!(packet.expert.is_empty());
+    }
+
+    #[test]
+    fn bad_tcp_and_udp_checksums() {
+        let tcp4 = Tcp::new("10.0.0.1:50000", "10.0.0.2:80", SYN).frame();
+        assert_bad_l4_checksum(tcp4, IPV4_L4_OFFSET + 16, "TCP");
+        let udp4 = testutil::udp("10.0.0.1:5353", "10.0.0.2:53", DNS_QUERY);
+        assert_bad_l4_checksum(udp4, IPV4_L4_OFFSET + 6, "UDP");
+    }
+
+    #[test]
+    fn bad_checksums_over_ipv6_use_the_pseudo_header() {
+        let tcp6 = Tcp::new("[2001:db8::1]:50000", "[2001:db8::2]:443", SYN).frame();
+        assert_bad_l4_checksum(tcp6.clone(), IPV6_L4_OFFSET + 16, "TCP");
+        let udp6 = testutil::udp("[2001:db8::1]:5353", "[2001:db8::2]:53", DNS_QUERY);
+        assert_bad_l4_checksum(udp6.clone(), IPV6_L4_OFFSET + 6, "UDP");
+
+        // 疑似ヘッダのアドレスを変えると、セグメントが同じでもチェックサムが合わなくなる
+        let mut moved = tcp6;
+        moved[IP_OFFSET + 8 + 15] ^= 1;
+        let errors = messages(&decode(&moved), Severity::Error);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("[Error] TCP: Bad checksum"));
+
+        let mut zero = udp6;
+        zero[IPV6_L4_OFFSET + 6..IPV6_L4_OFFSET + 8].copy_from_slice(&[0, 0]);
+        assert_eq!(
+            messages(&decode(&zero), Severity::Error),
+            ["[Error] UDP: Zero checksum is not allowed over IPv6"]
+        );
+    }
+
+    #[test]
+    fn bogus_ipv4_lengths() {
+        let frame = Tcp::new("10.0.0.1:50000", "10.0.0.2:80", SYN).frame();
+
+        let mut short = frame.clone();
+        ipv4_header(&mut short).set_header_length(4);
+        fix_ipv4_checksum(&mut short);
+        let packet = decode(&short);
+        assert_eq!(
+            messages(&packet, Severity::Error),
+            ["[Error] IPv4: Bogus IPv4 header length 16"]
+        );
+        assert_eq!(packet.top().unwrap().header.name(), "Ethernet");
+
+        let mut long = frame.clone();
+        ipv4_header(&mut long).set_header_length(15);
+        let data = long[..IP_OFFSET + 40].to_vec();
+        assert_eq!(
+            messages(&decode(&data), Severity::Error),
+            ["[Error] IPv4: Bogus IPv4 header length 60"]
+        );
+
+        let mut total = frame.clone();
+        ipv4_header(&mut total).set_total_length(12);
+        fix_ipv4_checksum(&mut total);
+        assert_eq!(
+            messages(&decode(&total), Severity::Error),
+            ["[Error] IPv4: Bogus IPv4 total length 12"]
+        );
+
+        let mut truncated = frame;
+        truncated.truncate(IPV4_L4_OFFSET + 10);
+        let packet = decode(&truncated);
+        assert_eq!(
+            messages(&packet, Severity::Warning),
+            ["[Warning] IPv4: Total length 40 exceeds the 30 bytes captured"]
+        );
+        assert_eq!(
+            messages(&packet, Severity::Error),
+            ["[Error] TCP: Truncated TCP header"]
+        );
+    }
+
+    #[test]
+    fn bogus_transport_lengths() {
+        let mut tcp = Tcp::new("10.0.0.1:50000", "10.0.0.2:80", SYN).frame();
+        tcp[IPV4_L4_OFFSET + 12] = 3 << 4;
+        assert_eq!(
+            messages(&decode(&tcp), Severity::Error),
+            ["[Error] TCP: Bogus TCP header length 12"]
+        );
+        tcp[IPV4_L4_OFFSET + 12] = 15 << 4;
+        assert_eq!(
+            messages(&decode(&tcp), Severity::Error),
+            ["[Error] TCP: Bogus TCP header length 60"]
+        );
+
+        let udp = testutil::udp("10.0.0.1:5353", "10.0.0.2:53", DNS_QUERY);
+        let mut short = udp.clone();
+        short[IPV4_L4_OFFSET + 4..IPV4_L4_OFFSET + 6].copy_from_slice(&4u16.to_be_bytes());
+        assert_eq!(
+            messages(&decode(&short), Severity::Error),
+            ["[Error] UDP: Bogus UDP length 4"]
+        );
+
+        let mut long = udp;
+        long[IPV4_L4_OFFSET + 4..IPV4_L4_OFFSET + 6].copy_from_slice(&1000u16.to_be_bytes());
+        let packet = decode(&long);
+        assert_eq!(
+            messages(&packet, Severity::Warning),
+            ["[Warning] UDP: Length 1000 exceeds the 37 bytes captured"]
+        );
+        // 長さのフィールドもチェックサムの対象なので、書き換えると一致しなくなる
+        let errors = messages(&packet, Severity::Error);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("[Error] UDP: Bad checksum"));
+    }
+}
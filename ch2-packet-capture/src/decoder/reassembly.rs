@@ -0,0 +1,87 @@
+use std::{collections::HashMap, net::IpAddr};
+
+/// 1つのストリームに溜めておく最大バイト数
+const MAX_BUFFER: usize = 256 * 1024;
+/// 同時に溜めておくストリーム数の上限
+const MAX_STREAMS: usize = 1024;
+
+/**
+ * TCPのストリームを方向ごとに区別するキー
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StreamKey {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+}
+
+struct Stream {
+    /// 次に来るはずのシーケンス番号
+    next_seq: u32,
+    data: Vec<u8>,
+}
+
+/**
+ * 溜めていたデータに今回のセグメントを続けた結果
+ */
+pub enum Continuation {
+    /// 溜めているデータはない
+    None,
+    /// 溜めていたデータに今回のペイロードを連結したもの
+    Joined(Vec<u8>),
+    /// 溜めているデータに含まれる範囲の再送
+    Retransmission,
+}
+
+/**
+ * 複数のセグメントにまたがるアプリケーション層のPDUを組み立てる
+ * 順序通りに届いたセグメントだけを連結し、抜けがあった場合は諦める
+ */
+#[derive(Default)]
+pub struct Reassembler {
+    streams: HashMap<StreamKey, Stream>,
+}
+
+impl Reassembler {
+    /**
+     * 溜めていたデータがあれば取り出し、今回のペイロードを連結して返す
+     */
+    pub fn take(&mut self, key: &StreamKey, seq: u32, payload: &[u8]) -> Continuation {
+        let stream = match self.streams.remove(key) {
+            Some(stream) => stream,
+            None => return Continuation::None,
+        };
+        if seq == stream.next_seq {
+            let mut data = stream.data;
+            data.extend_from_slice(payload);
+            return Continuation::Joined(data);
+        }
+        // next_seqより前で終わるセグメントは再送とみなして待ち続ける
+        let end = seq.wrapping_add(payload.len() as u32);
+        if (stream.next_seq.wrapping_sub(end) as i32) >= 0 {
+            self.streams.insert(*key, stream);
+            return Continuation::Retransmission;
+        }
+        Continuation::None
+    }
+
+    /**
+     * 続きのセグメントを待つためにデータを溜める
+     * 上限を超える場合は溜めずにデータを返す
+     */
+    pub fn hold(&mut self, key: StreamKey, next_seq: u32, data: Vec<u8>) -> Result<(), Vec<u8>> {
+        if data.len() > MAX_BUFFER || self.streams.len() >= MAX_STREAMS {
+            return Err(data);
+        }
+        self.streams.insert(key, Stream { next_seq, data });
+        Ok(())
+    }
+
+    /**
+     * FINやRSTでストリームが終わったときに溜めていたデータを捨てる
+     */
+    pub fn close(&mut self, key: &StreamKey) {
+        self.streams.remove(key);
+    }
+}
@@ -0,0 +1,188 @@
+use super::{expert, DecodeContext, Decoded, Decoder, ExpertInfo, Header, Protocol, Severity};
+use crate::dissectors::Transport;
+use anyhow::{anyhow, Context, Result};
+use pnet::packet::{
+    tcp::{TcpFlags, TcpPacket},
+    udp::UdpPacket,
+};
+
+const TCP_MIN_HEADER_SIZE: usize = 20;
+const UDP_HEADER_SIZE: usize = 8;
+/// チェックサムフィールドの位置（16ビット単位）
+const TCP_CHECKSUM_WORD: usize = 8;
+const UDP_CHECKSUM_WORD: usize = 3;
+const TCP: u8 = 6;
+const UDP: u8 = 17;
+
+/**
+ * 疑似ヘッダを含めたチェックサムを検証する
+ * UDPでは計算結果が0のとき0xffffとして送られる
+ */
+fn verify_checksum(
+    name: &'static str,
+    context: &DecodeContext,
+    protocol: u8,
+    segment: &[u8],
+    checksum: u16,
+) -> Option<ExpertInfo> {
+    if !context.verify_checksums {
+        return None;
+    }
+    let word = if protocol == TCP {
+        TCP_CHECKSUM_WORD
+    } else {
+        UDP_CHECKSUM_WORD
+    };
+    match expert::pseudo_header_checksum(context.outer, protocol, segment, word) {
+        Some(expected) if expected == checksum => None,
+        Some(0) if protocol == UDP && checksum == 0xffff => None,
+        Some(expected) => Some(ExpertInfo::new(
+            Severity::Error,
+            name,
+            format!(
+                "Bad checksum 0x{:04x} (should be 0x{:04x})",
+                checksum, expected
+            ),
+        )),
+        None => Some(ExpertInfo::new(
+            Severity::Note,
+            name,
+            "Checksum not verified, segment is truncated or fragmented",
+        )),
+    }
+}
+
+/**
+ * ありえないフラグの組み合わせを調べる
+ */
+fn check_tcp_flags(flags: u16) -> Option<&'static str> {
+    let has = |bit: u16| flags & bit != 0;
+    if flags == 0 {
+        Some("No flags set")
+    } else if has(TcpFlags::SYN) && has(TcpFlags::FIN) {
+        Some("SYN and FIN are both set")
+    } else if has(TcpFlags::SYN) && has(TcpFlags::RST) {
+        Some("SYN and RST are both set")
+    } else if !has(TcpFlags::ACK) && !has(TcpFlags::SYN) && !has(TcpFlags::RST) {
+        Some("ACK is not set")
+    } else {
+        None
+    }
+}
+
+pub struct TcpDecoder;
+
+impl Decoder for TcpDecoder {
+    fn name(&self) -> &'static str {
+        "TCP"
+    }
+
+    fn decode(&self, data: &[u8], context: &DecodeContext) -> Result<Decoded> {
+        let tcp = TcpPacket::new(data).context("Truncated TCP header")?;
+        let header_len = tcp.get_data_offset() as usize * 4;
+        if header_len < TCP_MIN_HEADER_SIZE || header_len > data.len() {
+            return Err(anyhow!("Bogus TCP header length {}", header_len));
+        }
+
+        let mut expert = vec![];
+        expert.extend(verify_checksum(
+            self.name(),
+            context,
+            TCP,
+            data,
+            tcp.get_checksum(),
+        ));
+        if let Some(message) = check_tcp_flags(tcp.get_flags()) {
+            expert.push(ExpertInfo::new(Severity::Warning, self.name(), message));
+        }
+
+        Ok(Decoded {
+            header: Header::Tcp {
+                src: tcp.get_source(),
+                dst: tcp.get_destination(),
+                seq: tcp.get_sequence(),
+                ack: tcp.get_acknowledgement(),
+                flags: tcp.get_flags(),
+                window: tcp.get_window(),
+                checksum: tcp.get_checksum(),
+            },
+            header_len,
+            next: Some(Protocol::Port {
+                transport: Transport::Tcp,
+                src: tcp.get_source(),
+                dst: tcp.get_destination(),
+            }),
+            payload: header_len..data.len(),
+            expert,
+        })
+    }
+}
+
+pub struct UdpDecoder;
+
+impl Decoder for UdpDecoder {
+    fn name(&self) -> &'static str {
+        "UDP"
+    }
+
+    fn decode(&self, data: &[u8], context: &DecodeContext) -> Result<Decoded> {
+        let udp = UdpPacket::new(data).context("Truncated UDP header")?;
+        let length = udp.get_length() as usize;
+        if length < UDP_HEADER_SIZE {
+            return Err(anyhow!("Bogus UDP length {}", length));
+        }
+
+        let mut expert = vec![];
+        let mut note = |severity, message: String| {
+            expert.push(ExpertInfo::new(severity, self.name(), message));
+        };
+        if length > data.len() {
+            note(
+                Severity::Warning,
+                format!(
+                    "Length {} exceeds the {} bytes captured",
+                    length,
+                    data.len()
+                ),
+            );
+        } else if length < data.len() {
+            note(
+                Severity::Warning,
+                format!(
+                    "Length {} is shorter than the IP payload of {} bytes",
+                    length,
+                    data.len()
+                ),
+            );
+        }
+        // IPv4ではチェックサム0は「計算していない」を意味するが、IPv6では必須
+        let checksum = udp.get_checksum();
+        if checksum == 0 {
+            if let Header::Ipv6 { .. } = context.outer {
+                note(
+                    Severity::Error,
+                    "Zero checksum is not allowed over IPv6".to_string(),
+                );
+            }
+        } else if let Some(info) = verify_checksum(self.name(), context, UDP, data, checksum) {
+            expert.push(info);
+        }
+
+        Ok(Decoded {
+            header: Header::Udp {
+                src: udp.get_source(),
+                dst: udp.get_destination(),
+                length: udp.get_length(),
+                checksum,
+            },
+            header_len: UDP_HEADER_SIZE,
+            next: Some(Protocol::Port {
+                transport: Transport::Udp,
+                src: udp.get_source(),
+                dst: udp.get_destination(),
+            }),
+            payload: UDP_HEADER_SIZE..length.min(data.len()),
+            expert,
+        })
+    }
+}
@@ -0,0 +1,421 @@
+use super::{hex_string, read_u16, read_u32, Dissector, Field, ProtoTree, Transport};
+use pnet::util::MacAddr;
+use std::net::Ipv4Addr;
+
+// BOOTPの固定長フィールドのオフセット（RFC 2131）
+const OP: usize = 0;
+const HTYPE: usize = 1;
+const HLEN: usize = 2;
+const HOPS: usize = 3;
+const XID: usize = 4;
+const SECS: usize = 8;
+const FLAGS: usize = 10;
+const CIADDR: usize = 12;
+const YIADDR: usize = 16;
+const SIADDR: usize = 20;
+const GIADDR: usize = 24;
+const CHADDR: usize = 28;
+const SNAME: usize = 44;
+const FILE: usize = 108;
+const MAGIC_COOKIE: usize = 236;
+const OPTIONS: usize = 240;
+
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const OPTION_PAD: u8 = 0;
+const OPTION_END: u8 = 255;
+
+pub struct DhcpDissector;
+
+impl Dissector for DhcpDissector {
+    fn ports(&self, transport: Transport) -> &'static [u16] {
+        match transport {
+            Transport::Udp => &[67, 68],
+            Transport::Tcp => &[],
+        }
+    }
+
+    fn heuristic(&self, transport: Transport, payload: &[u8]) -> bool {
+        transport == Transport::Udp
+            && payload.get(MAGIC_COOKIE..OPTIONS) == Some(&DHCP_MAGIC_COOKIE[..])
+    }
+
+    fn dissect(&self, _transport: Transport, payload: &[u8]) -> Option<ProtoTree> {
+        if payload.len() < MAGIC_COOKIE {
+            return None;
+        }
+        let op = payload[OP];
+        let htype = payload[HTYPE];
+        let hlen = payload[HLEN];
+        let flags = read_u16(payload, FLAGS)?;
+
+        let mut fields = vec![
+            Field::new(
+                "Message type",
+                match op {
+                    1 => "Boot Request (1)".to_string(),
+                    2 => "Boot Reply (2)".to_string(),
+                    o => o.to_string(),
+                },
+            ),
+            Field::new("Hardware type", htype),
+            Field::new("Hardware address length", hlen),
+            Field::new("Hops", payload[HOPS]),
+            Field::new(
+                "Transaction ID",
+                format!("0x{:08x}", read_u32(payload, XID)?),
+            ),
+            Field::new("Seconds elapsed", read_u16(payload, SECS)?),
+            Field::with_children(
+                "Bootp flags",
+                format!("0x{:04x}", flags),
+                vec![Field::new("Broadcast", flags & 0x8000 != 0)],
+            ),
+            Field::new("Client IP address", read_ipv4(payload, CIADDR)?),
+            Field::new("Your IP address", read_ipv4(payload, YIADDR)?),
+            Field::new("Next server IP address", read_ipv4(payload, SIADDR)?),
+            Field::new("Relay agent IP address", read_ipv4(payload, GIADDR)?),
+            Field::new("Client hardware address", read_chaddr(payload, htype, hlen)),
+        ];
+        let sname = read_cstring(&payload[SNAME..FILE]);
+        if !sname.is_empty() {
+            fields.push(Field::new("Server host name", sname));
+        }
+        let file = read_cstring(&payload[FILE..MAGIC_COOKIE]);
+        if !file.is_empty() {
+            fields.push(Field::new("Boot file name", file));
+        }
+
+        // マジッククッキーがなければ純粋なBOOTP
+        if payload.get(MAGIC_COOKIE..OPTIONS) != Some(&DHCP_MAGIC_COOKIE[..]) {
+            return Some(ProtoTree {
+                protocol: "BOOTP",
+                fields,
+            });
+        }
+        fields.push(Field::new("Magic cookie", "DHCP"));
+        fields.extend(dissect_options(&payload[OPTIONS..]));
+
+        Some(ProtoTree {
+            protocol: "DHCP",
+            fields,
+        })
+    }
+}
+
+/**
+ * オプション領域を解析する
+ * 途中で途切れていた場合は解析できたところまでを返す
+ */
+fn dissect_options(options: &[u8]) -> Vec<Field> {
+    let mut fields = vec![];
+    let mut pos = 0;
+    while pos < options.len() {
+        let code = options[pos];
+        match code {
+            OPTION_PAD => {
+                pos += 1;
+                continue;
+            }
+            OPTION_END => {
+                fields.push(Field::new("Option", "(255) End"));
+                break;
+            }
+            _ => {}
+        }
+        let len = match options.get(pos + 1) {
+            Some(&len) => len as usize,
+            None => break,
+        };
+        let value = match options.get(pos + 2..pos + 2 + len) {
+            Some(value) => value,
+            None => {
+                fields.push(Field::new(
+                    "Option",
+                    format!("({}) {} [truncated]", code, option_name(code)),
+                ));
+                break;
+            }
+        };
+        fields.push(Field::with_children(
+            "Option",
+            format!("({}) {}", code, option_name(code)),
+            vec![
+                Field::new("Length", len),
+                Field::new("Value", option_value(code, value)),
+            ],
+        ));
+        pos += 2 + len;
+    }
+    fields
+}
+
+fn option_name(code: u8) -> &'static str {
+    match code {
+        1 => "Subnet Mask",
+        2 => "Time Offset",
+        3 => "Router",
+        4 => "Time Server",
+        6 => "Domain Name Server",
+        12 => "Host Name",
+        15 => "Domain Name",
+        26 => "Interface MTU",
+        28 => "Broadcast Address",
+        42 => "Network Time Protocol Servers",
+        43 => "Vendor-Specific Information",
+        44 => "NetBIOS over TCP/IP Name Server",
+        50 => "Requested IP Address",
+        51 => "IP Address Lease Time",
+        52 => "Option Overload",
+        53 => "DHCP Message Type",
+        54 => "DHCP Server Identifier",
+        55 => "Parameter Request List",
+        56 => "Message",
+        57 => "Maximum DHCP Message Size",
+        58 => "Renewal Time Value",
+        59 => "Rebinding Time Value",
+        60 => "Vendor class identifier",
+        61 => "Client identifier",
+        66 => "TFTP Server Name",
+        67 => "Bootfile name",
+        81 => "Client Fully Qualified Domain Name",
+        119 => "Domain Search",
+        121 => "Classless Static Route",
+        _ => "Unknown",
+    }
+}
+
+/**
+ * オプションの種類に応じて値を整形する
+ */
+fn option_value(code: u8, value: &[u8]) -> String {
+    match code {
+        // IPアドレスのリスト
+        1 | 3 | 4 | 6 | 28 | 42 | 44 | 50 | 54 if value.len().is_multiple_of(4) => value
+            .chunks(4)
+            .map(|b| Ipv4Addr::new(b[0], b[1], b[2], b[3]).to_string())
+            .collect::<Vec<_>>()
+            .join(", "),
+        // 秒数
+        2 | 51 | 58 | 59 if value.len() == 4 => {
+            let secs = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
+            format!("{}s", secs)
+        }
+        26 | 57 if value.len() == 2 => u16::from_be_bytes([value[0], value[1]]).to_string(),
+        53 if value.len() == 1 => message_type_name(value[0]),
+        55 => value
+            .iter()
+            .map(|c| format!("({}) {}", c, option_name(*c)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        // 文字列
+        12 | 15 | 56 | 60 | 66 | 67 => read_cstring(value),
+        61 if value.len() == 7 && value[0] == 1 => {
+            format!("Ethernet {}", read_mac(&value[1..]))
+        }
+        _ => hex_string(value),
+    }
+}
+
+fn message_type_name(t: u8) -> String {
+    let name = match t {
+        1 => "Discover",
+        2 => "Offer",
+        3 => "Request",
+        4 => "Decline",
+        5 => "ACK",
+        6 => "NAK",
+        7 => "Release",
+        8 => "Inform",
+        _ => return t.to_string(),
+    };
+    format!("DHCP{} ({})", name.to_uppercase(), t)
+}
+
+fn read_ipv4(buf: &[u8], offset: usize) -> Option<Ipv4Addr> {
+    let b = buf.get(offset..offset + 4)?;
+    Some(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+}
+
+fn read_mac(b: &[u8]) -> MacAddr {
+    MacAddr::new(b[0], b[1], b[2], b[3], b[4], b[5])
+}
+
+fn read_chaddr(payload: &[u8], htype: u8, hlen: u8) -> String {
+    // イーサネットの場合はMACアドレスとして表示する
+    if htype == 1 && hlen == 6 {
+        read_mac(&payload[CHADDR..CHADDR + 6]).to_string()
+    } else {
+        let len = (hlen as usize).min(SNAME - CHADDR);
+        hex_string(&payload[CHADDR..CHADDR + len])
+    }
+}
+
+fn read_cstring(buf: &[u8]) -> String {
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..end]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * オプションの前までのDHCPDISCOVER（マジッククッキーまで）
+     */
+    fn discover() -> Vec<u8> {
+        let mut payload = vec![0u8; OPTIONS];
+        payload[OP] = 1;
+        payload[HTYPE] = 1;
+        payload[HLEN] = 6;
+        payload[XID..XID + 4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        payload[SECS + 1] = 3;
+        payload[FLAGS] = 0x80;
+        payload[CHADDR..CHADDR + 6].copy_from_slice(&[0x02, 0, 0, 0, 0, 0x01]);
+        payload[MAGIC_COOKIE..OPTIONS].copy_from_slice(&DHCP_MAGIC_COOKIE);
+        payload
+    }
+
+    fn dissect(payload: &[u8]) -> Option<ProtoTree> {
+        DhcpDissector.dissect(Transport::Udp, payload)
+    }
+
+    /**
+     * オプションの部分だけを"値"の一覧にする
+     */
+    fn options(options: &[u8]) -> Vec<String> {
+        dissect_options(options)
+            .iter()
+            .map(|field| match field.children.get(1) {
+                Some(value) => format!("{} = {}", field.value, value.value),
+                None => field.value.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn discover_header() {
+        let mut payload = discover();
+        payload.extend_from_slice(&[53, 1, 1, 255]);
+        let tree = dissect(&payload).unwrap();
+        assert_eq!(
+            tree.to_string(),
+            "DHCP
+    Message type: Boot Request (1)
+    Hardware type: 1
+    Hardware address length: 6
+    Hops: 0
+    Transaction ID: 0xdeadbeef
+    Seconds elapsed: 3
+    Bootp flags: 0x8000
+        Broadcast: true
+    Client IP address: 0.0.0.0
+    Your IP address: 0.0.0.0
+    Next server IP address: 0.0.0.0
+    Relay agent IP address: 0.0.0.0
+    Client hardware address: 02:00:00:00:00:01
+    Magic cookie: DHCP
+    Option: (53) DHCP Message Type
+        Length: 1
+        Value: DHCPDISCOVER (1)
+    Option: (255) End
+"
+        );
+        assert!(DhcpDissector.heuristic(Transport::Udp, &payload));
+        assert!(!DhcpDissector.heuristic(Transport::Tcp, &payload));
+    }
+
+    #[test]
+    fn option_values() {
+        let mut bytes = vec![0, 0];
+        bytes.extend_from_slice(&[1, 4, 255, 255, 255, 0]);
+        bytes.extend_from_slice(&[3, 8, 192, 0, 2, 1, 192, 0, 2, 2]);
+        bytes.extend_from_slice(&[2, 4, 0, 0, 0x0e, 0x10]);
+        bytes.extend_from_slice(&[51, 4, 0, 1, 0x51, 0x80]);
+        bytes.extend_from_slice(&[57, 2, 0x05, 0xdc]);
+        bytes.extend_from_slice(&[53, 1, 5]);
+        bytes.extend_from_slice(&[53, 1, 42]);
+        bytes.extend_from_slice(&[55, 3, 1, 3, 200]);
+        bytes.extend_from_slice(&[12, 5, b'h', b'o', b's', b't', 0]);
+        bytes.extend_from_slice(&[61, 7, 1, 0x02, 0, 0, 0, 0, 0x01]);
+        bytes.extend_from_slice(&[61, 3, 0, b'i', b'd']);
+        // 長さが種類に合わないものと、知らないオプションは16進数で表示する
+        bytes.extend_from_slice(&[1, 3, 255, 255, 255]);
+        bytes.extend_from_slice(&[224, 2, 0xab, 0xcd]);
+        bytes.extend_from_slice(&[255, 1, 2, 3]);
+        assert_eq!(
+            options(&bytes),
+            [
+                "(1) Subnet Mask = 255.255.255.0",
+                "(3) Router = 192.0.2.1, 192.0.2.2",
+                "(2) Time Offset = 3600s",
+                "(51) IP Address Lease Time = 86400s",
+                "(57) Maximum DHCP Message Size = 1500",
+                "(53) DHCP Message Type = DHCPACK (5)",
+                "(53) DHCP Message Type = 42",
+                "(55) Parameter Request List = (1) Subnet Mask, (3) Router, (200) Unknown",
+                "(12) Host Name = host",
+                "(61) Client identifier = Ethernet 02:00:00:00:00:01",
+                "(61) Client identifier = 00:69:64",
+                "(1) Subnet Mask = ff:ff:ff",
+                "(224) Unknown = ab:cd",
+                // Endより後ろは読まない
+                "(255) End",
+            ]
+        );
+    }
+
+    #[test]
+    fn truncated_options() {
+        // 値が途中で切れている
+        assert_eq!(
+            options(&[53, 1, 3, 50, 4, 192, 0]),
+            [
+                "(53) DHCP Message Type = DHCPREQUEST (3)",
+                "(50) Requested IP Address [truncated]"
+            ]
+        );
+        // 長さのオクテットがない
+        assert_eq!(options(&[0, 0, 6]), Vec::<String>::new());
+        // Endがなくても、読めたところまでを返す
+        assert_eq!(
+            options(&[54, 4, 192, 0, 2, 1]),
+            ["(54) DHCP Server Identifier = 192.0.2.1"]
+        );
+        assert_eq!(options(&[]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn bootp_without_magic_cookie() {
+        let mut payload = discover();
+        payload[OP] = 2;
+        payload[YIADDR..YIADDR + 4].copy_from_slice(&[192, 0, 2, 10]);
+        payload[SNAME..SNAME + 4].copy_from_slice(b"boot");
+        payload[FILE..FILE + 8].copy_from_slice(b"pxelinux");
+        payload.truncate(MAGIC_COOKIE);
+        let tree = dissect(&payload).unwrap();
+        assert_eq!(tree.protocol, "BOOTP");
+        let text = tree.to_string();
+        assert!(text.contains("Message type: Boot Reply (2)\n"));
+        assert!(text.contains("Your IP address: 192.0.2.10\n"));
+        assert!(text.contains("Server host name: boot\n"));
+        assert!(text.contains("Boot file name: pxelinux\n"));
+        assert!(!text.contains("Magic cookie"));
+        assert!(!DhcpDissector.heuristic(Transport::Udp, &payload));
+
+        assert!(dissect(&payload[..MAGIC_COOKIE - 1]).is_none());
+    }
+
+    #[test]
+    fn other_hardware_addresses_are_hex() {
+        let mut payload = discover();
+        payload[HTYPE] = 6;
+        payload[HLEN] = 3;
+        assert_eq!(read_chaddr(&payload, 6, 3), "02:00:00");
+        // 長すぎる長さはchaddrの大きさで切る
+        assert_eq!(read_chaddr(&payload, 6, 255).split(':').count(), 16);
+        let tree = dissect(&payload).unwrap();
+        assert!(tree
+            .to_string()
+            .contains("Client hardware address: 02:00:00\n"));
+    }
+}
@@ -0,0 +1,489 @@
+use super::{hex_string, read_u16, read_u32, Dissector, Field, ProtoTree, Transport};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+const HEADER_SIZE: usize = 12;
+/// 名前圧縮のポインタを辿る回数の上限（ループ対策）
+const MAX_POINTER_JUMPS: usize = 16;
+
+pub struct DnsDissector;
+
+impl Dissector for DnsDissector {
+    fn ports(&self, _transport: Transport) -> &'static [u16] {
+        &[53, 5353]
+    }
+
+    fn heuristic(&self, _transport: Transport, _payload: &[u8]) -> bool {
+        // DNSはバイナリプロトコルで誤検出しやすいため、ポートでのみ判定する
+        false
+    }
+
+    fn dissect(&self, transport: Transport, payload: &[u8]) -> Option<ProtoTree> {
+        // TCP上のDNSは先頭2オクテットがメッセージ長
+        let msg = match transport {
+            Transport::Tcp => {
+                let len = read_u16(payload, 0)? as usize;
+                payload.get(2..2 + len)?
+            }
+            Transport::Udp => payload,
+        };
+        dissect_message(msg)
+    }
+}
+
+fn dissect_message(msg: &[u8]) -> Option<ProtoTree> {
+    if msg.len() < HEADER_SIZE {
+        return None;
+    }
+    let id = read_u16(msg, 0)?;
+    let flags = read_u16(msg, 2)?;
+    let qdcount = read_u16(msg, 4)?;
+    let ancount = read_u16(msg, 6)?;
+    let nscount = read_u16(msg, 8)?;
+    let arcount = read_u16(msg, 10)?;
+
+    let is_response = flags & 0x8000 != 0;
+    let mut fields = vec![
+        Field::new("Transaction ID", format!("0x{:04x}", id)),
+        Field::with_children(
+            "Flags",
+            format!("0x{:04x}", flags),
+            vec![
+                Field::new("Response", is_response),
+                Field::new("Opcode", opcode_name((flags >> 11) & 0x0f)),
+                Field::new("Authoritative", flags & 0x0400 != 0),
+                Field::new("Truncated", flags & 0x0200 != 0),
+                Field::new("Recursion desired", flags & 0x0100 != 0),
+                Field::new("Recursion available", flags & 0x0080 != 0),
+                Field::new("Reply code", rcode_name(flags & 0x000f)),
+            ],
+        ),
+        Field::new("Questions", qdcount),
+        Field::new("Answer RRs", ancount),
+        Field::new("Authority RRs", nscount),
+        Field::new("Additional RRs", arcount),
+    ];
+
+    let mut offset = HEADER_SIZE;
+    let mut queries = vec![];
+    for _ in 0..qdcount {
+        let (name, next) = read_name(msg, offset)?;
+        let qtype = read_u16(msg, next)?;
+        let qclass = read_u16(msg, next + 2)?;
+        offset = next + 4;
+        queries.push(Field::new(
+            &name,
+            format!("type {}, class {}", type_name(qtype), class_name(qclass)),
+        ));
+    }
+    if !queries.is_empty() {
+        fields.push(Field::with_children("Queries", "", queries));
+    }
+
+    for (label, count) in [
+        ("Answers", ancount),
+        ("Authoritative nameservers", nscount),
+        ("Additional records", arcount),
+    ] {
+        let mut records = vec![];
+        for _ in 0..count {
+            let (record, next) = read_record(msg, offset)?;
+            records.push(record);
+            offset = next;
+        }
+        if !records.is_empty() {
+            fields.push(Field::with_children(label, "", records));
+        }
+    }
+
+    Some(ProtoTree {
+        protocol: "DNS",
+        fields,
+    })
+}
+
+/**
+ * リソースレコードを1つ読み、次のレコードのオフセットと共に返す
+ */
+fn read_record(msg: &[u8], offset: usize) -> Option<(Field, usize)> {
+    let (name, next) = read_name(msg, offset)?;
+    let rtype = read_u16(msg, next)?;
+    let rclass = read_u16(msg, next + 2)?;
+    let ttl = read_u32(msg, next + 4)?;
+    let rdlength = read_u16(msg, next + 8)? as usize;
+    let rdata_start = next + 10;
+    let rdata = msg.get(rdata_start..rdata_start + rdlength)?;
+
+    let data = match rtype {
+        1 if rdata.len() == 4 => Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3]).to_string(),
+        28 if rdata.len() == 16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(rdata);
+            Ipv6Addr::from(octets).to_string()
+        }
+        // NS, CNAME, PTR は圧縮されたドメイン名を持つ
+        2 | 5 | 12 => read_name(msg, rdata_start)
+            .map(|(n, _)| n)
+            .unwrap_or_else(|| hex_string(rdata)),
+        15 => {
+            let preference = read_u16(rdata, 0)?;
+            let (exchange, _) = read_name(msg, rdata_start + 2)?;
+            format!("{} {}", preference, exchange)
+        }
+        16 => read_character_strings(rdata),
+        _ => hex_string(rdata),
+    };
+
+    let field = Field::with_children(
+        &name,
+        format!("type {}, class {}", type_name(rtype), class_name(rclass)),
+        vec![Field::new("TTL", ttl), Field::new("Data", data)],
+    );
+    Some((field, rdata_start + rdlength))
+}
+
+/**
+ * ドメイン名を読み、名前の直後のオフセットと共に返す
+ * 名前圧縮（RFC 1035 4.1.4）に対応する
+ */
+fn read_name(msg: &[u8], offset: usize) -> Option<(String, usize)> {
+    let mut labels = vec![];
+    let mut pos = offset;
+    let mut end = None;
+    let mut jumps = 0;
+    loop {
+        let len = *msg.get(pos)? as usize;
+        match len & 0xc0 {
+            0x00 => {
+                if len == 0 {
+                    pos += 1;
+                    break;
+                }
+                let label = msg.get(pos + 1..pos + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            }
+            0xc0 => {
+                jumps += 1;
+                if jumps > MAX_POINTER_JUMPS {
+                    return None;
+                }
+                let pointer = (read_u16(msg, pos)? & 0x3fff) as usize;
+                if end.is_none() {
+                    end = Some(pos + 2);
+                }
+                pos = pointer;
+            }
+            _ => return None,
+        }
+    }
+    let name = if labels.is_empty() {
+        "<Root>".to_string()
+    } else {
+        labels.join(".")
+    };
+    Some((name, end.unwrap_or(pos)))
+}
+
+fn read_character_strings(rdata: &[u8]) -> String {
+    let mut strings = vec![];
+    let mut pos = 0;
+    while pos < rdata.len() {
+        let len = rdata[pos] as usize;
+        let end = (pos + 1 + len).min(rdata.len());
+        strings.push(format!(
+            "\"{}\"",
+            String::from_utf8_lossy(&rdata[pos + 1..end])
+        ));
+        pos = end;
+    }
+    strings.join(" ")
+}
+
+fn type_name(rtype: u16) -> String {
+    let name = match rtype {
+        1 => "A",
+        2 => "NS",
+        5 => "CNAME",
+        6 => "SOA",
+        12 => "PTR",
+        15 => "MX",
+        16 => "TXT",
+        28 => "AAAA",
+        33 => "SRV",
+        41 => "OPT",
+        65 => "HTTPS",
+        255 => "ANY",
+        _ => return rtype.to_string(),
+    };
+    name.to_string()
+}
+
+fn class_name(class: u16) -> String {
+    // mDNSでは最上位ビットがunicast-response/cache-flushに使われる
+    match class & 0x7fff {
+        1 => "IN".to_string(),
+        3 => "CH".to_string(),
+        255 => "ANY".to_string(),
+        c => c.to_string(),
+    }
+}
+
+fn opcode_name(opcode: u16) -> String {
+    match opcode {
+        0 => "Standard query".to_string(),
+        1 => "Inverse query".to_string(),
+        2 => "Server status".to_string(),
+        4 => "Notify".to_string(),
+        5 => "Update".to_string(),
+        o => o.to_string(),
+    }
+}
+
+fn rcode_name(rcode: u16) -> String {
+    match rcode {
+        0 => "No error".to_string(),
+        1 => "Format error".to_string(),
+        2 => "Server failure".to_string(),
+        3 => "No such name".to_string(),
+        4 => "Not implemented".to_string(),
+        5 => "Refused".to_string(),
+        r => r.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * ヘッダと、その後ろに続くバイト列からメッセージを作る
+     */
+    fn message(flags: u16, counts: [u16; 4], body: &[u8]) -> Vec<u8> {
+        let mut msg = vec![0x12, 0x34];
+        msg.extend_from_slice(&flags.to_be_bytes());
+        for count in counts {
+            msg.extend_from_slice(&count.to_be_bytes());
+        }
+        msg.extend_from_slice(body);
+        msg
+    }
+
+    /**
+     * 圧縮していないドメイン名
+     */
+    fn name(name: &str) -> Vec<u8> {
+        let mut bytes = vec![];
+        for label in name.split('.') {
+            bytes.push(label.len() as u8);
+            bytes.extend_from_slice(label.as_bytes());
+        }
+        bytes.push(0);
+        bytes
+    }
+
+    fn record(name: &[u8], rtype: u16, class: u16, ttl: u32, rdata: &[u8]) -> Vec<u8> {
+        let mut bytes = name.to_vec();
+        bytes.extend_from_slice(&rtype.to_be_bytes());
+        bytes.extend_from_slice(&class.to_be_bytes());
+        bytes.extend_from_slice(&ttl.to_be_bytes());
+        bytes.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(rdata);
+        bytes
+    }
+
+    fn dissect(transport: Transport, payload: &[u8]) -> Option<String> {
+        DnsDissector
+            .dissect(transport, payload)
+            .map(|tree| tree.to_string())
+    }
+
+    #[test]
+    fn query() {
+        let mut body = name("www.example.com");
+        body.extend_from_slice(&[0, 1, 0, 1]);
+        let msg = message(0x0100, [1, 0, 0, 0], &body);
+        assert_eq!(
+            dissect(Transport::Udp, &msg).unwrap(),
+            "DNS
+    Transaction ID: 0x1234
+    Flags: 0x0100
+        Response: false
+        Opcode: Standard query
+        Authoritative: false
+        Truncated: false
+        Recursion desired: true
+        Recursion available: false
+        Reply code: No error
+    Questions: 1
+    Answer RRs: 0
+    Authority RRs: 0
+    Additional RRs: 0
+    Queries
+        www.example.com: type A, class IN
+"
+        );
+    }
+
+    #[test]
+    fn response_with_compressed_names() {
+        // 質問のwww.example.comは12オクテット目、example.comは16オクテット目から始まる
+        let mut body = name("www.example.com");
+        body.extend_from_slice(&[0, 5, 0, 1]);
+        let mut cname = name("cdn");
+        cname.pop();
+        cname.extend_from_slice(&[0xc0, 16]);
+        body.extend(record(&[0xc0, 12], 5, 1, 3600, &cname));
+        // CNAMEのデータ（cdn.example.com）を指すポインタ
+        let cdn = (12 + 17 + 4 + 12) as u8;
+        body.extend(record(&[0xc0, cdn], 1, 1, 300, &[93, 184, 216, 34]));
+        let mut aaaa = [0u8; 16];
+        aaaa[..2].copy_from_slice(&[0x20, 0x01]);
+        aaaa[2..4].copy_from_slice(&[0x0d, 0xb8]);
+        aaaa[15] = 1;
+        body.extend(record(&[0xc0, cdn], 28, 1, 300, &aaaa));
+        let mut mx = vec![0, 10, 4];
+        mx.extend_from_slice(b"mail");
+        mx.extend_from_slice(&[0xc0, 16]);
+        body.extend(record(&[0xc0, 16], 15, 1, 60, &mx));
+        body.extend(record(
+            &[0xc0, 16],
+            16,
+            0x8001,
+            0,
+            b"\x05hello\x0bwor\"ld=1 2",
+        ));
+        body.extend(record(&[0], 41, 1232, 0, &[]));
+        let msg = message(0x8580, [1, 3, 1, 2], &body);
+
+        assert_eq!(
+            dissect(Transport::Udp, &msg).unwrap(),
+            "DNS
+    Transaction ID: 0x1234
+    Flags: 0x8580
+        Response: true
+        Opcode: Standard query
+        Authoritative: true
+        Truncated: false
+        Recursion desired: true
+        Recursion available: true
+        Reply code: No error
+    Questions: 1
+    Answer RRs: 3
+    Authority RRs: 1
+    Additional RRs: 2
+    Queries
+        www.example.com: type CNAME, class IN
+    Answers
+        www.example.com: type CNAME, class IN
+            TTL: 3600
+            Data: cdn.example.com
+        cdn.example.com: type A, class IN
+            TTL: 300
+            Data: 93.184.216.34
+        cdn.example.com: type AAAA, class IN
+            TTL: 300
+            Data: 2001:db8::1
+    Authoritative nameservers
+        example.com: type MX, class IN
+            TTL: 60
+            Data: 10 mail.example.com
+    Additional records
+        example.com: type TXT, class IN
+            TTL: 0
+            Data: \"hello\" \"wor\"ld=1 2\"
+        <Root>: type OPT, class 1232
+            TTL: 0
+            Data
+"
+        );
+    }
+
+    #[test]
+    fn malformed_rdata_is_shown_as_hex() {
+        let mut body = name("example.com");
+        body.extend_from_slice(&[0, 1, 0, 1]);
+        // 長さの合わないAと、名前として読めないCNAME
+        body.extend(record(&[0xc0, 12], 1, 1, 1, &[10, 0, 0]));
+        body.extend(record(&[0xc0, 12], 5, 1, 1, &[0x80]));
+        body.extend(record(&[0xc0, 12], 99, 3, 1, &[0xde, 0xad]));
+        let msg = message(0x8183, [1, 3, 0, 0], &body);
+        let text = dissect(Transport::Udp, &msg).unwrap();
+        assert!(text.contains("Reply code: No such name\n"), "{}", text);
+        assert!(text.contains(
+            "        example.com: type A, class IN
+            TTL: 1
+            Data: 0a:00:00
+        example.com: type CNAME, class IN
+            TTL: 1
+            Data: 80
+        example.com: type 99, class CH
+            TTL: 1
+            Data: de:ad
+"
+        ));
+    }
+
+    #[test]
+    fn tcp_messages_have_a_length_prefix() {
+        let mut body = name("example.com");
+        body.extend_from_slice(&[0, 255, 0, 255]);
+        let msg = message(0x2800, [1, 0, 0, 0], &body);
+        let mut payload = (msg.len() as u16).to_be_bytes().to_vec();
+        payload.extend_from_slice(&msg);
+        let text = dissect(Transport::Tcp, &payload).unwrap();
+        assert!(text.contains("Opcode: Update\n"));
+        assert!(text.contains("example.com: type ANY, class ANY\n"));
+
+        // 長さの分だけ届いていない
+        assert_eq!(dissect(Transport::Tcp, &payload[..payload.len() - 1]), None);
+        // UDPとしては長さのオクテットがヘッダにずれ込む
+        assert_eq!(dissect(Transport::Udp, &payload[..11]), None);
+    }
+
+    #[test]
+    fn truncated_messages() {
+        let mut body = name("www.example.com");
+        body.extend_from_slice(&[0, 1, 0, 1]);
+        body.extend(record(&[0xc0, 12], 1, 1, 300, &[192, 0, 2, 1]));
+        let msg = message(0x8180, [1, 1, 0, 0], &body);
+        assert!(dissect(Transport::Udp, &msg).is_some());
+        for len in [0, 11, 12, 20, 30, 33, msg.len() - 1] {
+            assert_eq!(dissect(Transport::Udp, &msg[..len]), None, "{}", len);
+        }
+        // ラベルの長さの上位2ビットが01と10のものは使われていない
+        let msg = message(0, [1, 0, 0, 0], &[0x40, 0, 0, 1, 0, 1]);
+        assert_eq!(dissect(Transport::Udp, &msg), None);
+    }
+
+    /**
+     * 12オクテット目から、次のポインタを指すポインタをjumps個並べ、最後に"a"を置く
+     */
+    fn pointer_chain(jumps: usize) -> Vec<u8> {
+        let mut msg = message(0, [0; 4], &[]);
+        for i in 1..jumps {
+            msg.extend_from_slice(&[0xc0, (12 + 2 * i) as u8]);
+        }
+        let label = 12 + 2 * jumps as u8;
+        msg.extend_from_slice(&[0xc0, label, 1, b'a', 0]);
+        msg
+    }
+
+    #[test]
+    fn pointer_jumps_are_limited() {
+        let msg = pointer_chain(MAX_POINTER_JUMPS);
+        assert_eq!(read_name(&msg, 12), Some(("a".to_string(), 14)));
+        let msg = pointer_chain(MAX_POINTER_JUMPS + 1);
+        assert_eq!(read_name(&msg, 12), None);
+
+        // 自分自身を指すポインタと、互いに指し合うポインタ
+        let mut msg = message(0, [1, 0, 0, 0], &[0xc0, 12, 0, 1, 0, 1]);
+        assert_eq!(read_name(&msg, 12), None);
+        assert_eq!(dissect(Transport::Udp, &msg), None);
+        msg.extend_from_slice(&[0xc0, 12]);
+        msg[13] = 18;
+        assert_eq!(read_name(&msg, 12), None);
+        // メッセージの外を指すポインタ
+        let msg = message(0, [1, 0, 0, 0], &[0xc0, 200, 0, 1, 0, 1]);
+        assert_eq!(read_name(&msg, 12), None);
+    }
+}
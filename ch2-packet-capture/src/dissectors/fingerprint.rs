@@ -0,0 +1,268 @@
+use md5::{Digest, Md5};
+use sha2::Sha256;
+
+/**
+ * JA3/JA4の計算に使うClientHello・ServerHelloの内容
+ */
+#[derive(Debug, Default)]
+pub struct Hello {
+    pub version: u16,
+    /// ServerHelloでは選択された1つだけ
+    pub ciphers: Vec<u16>,
+    /// 出現順の拡張の種類
+    pub extensions: Vec<u16>,
+    pub groups: Vec<u16>,
+    pub point_formats: Vec<u8>,
+    pub signature_algorithms: Vec<u16>,
+    pub supported_versions: Vec<u16>,
+    pub server_name: Option<String>,
+    pub alpn: Vec<String>,
+}
+
+/// JA4の空のハッシュ
+const EMPTY_HASH: &str = "000000000000";
+
+/**
+ * GREASE（RFC 8701）の値か
+ * 実装ごとにランダムに選ばれるので、フィンガープリントからは除く
+ */
+pub fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn join<T: ToString>(values: impl Iterator<Item = T>, sep: &str) -> String {
+    values.map(|v| v.to_string()).collect::<Vec<_>>().join(sep)
+}
+
+fn md5_hex(s: &str) -> String {
+    Md5::digest(s.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/**
+ * SHA-256の先頭12桁
+ */
+fn sha256_prefix(s: &str) -> String {
+    Sha256::digest(s.as_bytes())
+        .iter()
+        .take(6)
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/**
+ * ClientHelloのJA3文字列
+ * "バージョン,暗号スイート,拡張,楕円曲線,点の形式"を10進数で並べる
+ */
+pub fn ja3(hello: &Hello) -> String {
+    let non_grease = |values: &[u16]| join(values.iter().filter(|v| !is_grease(**v)), "-");
+    format!(
+        "{},{},{},{},{}",
+        hello.version,
+        non_grease(&hello.ciphers),
+        non_grease(&hello.extensions),
+        non_grease(&hello.groups),
+        join(hello.point_formats.iter(), "-"),
+    )
+}
+
+/**
+ * ServerHelloのJA3S文字列
+ */
+pub fn ja3s(hello: &Hello) -> String {
+    format!(
+        "{},{},{}",
+        hello.version,
+        join(hello.ciphers.iter(), "-"),
+        join(hello.extensions.iter().filter(|v| !is_grease(**v)), "-"),
+    )
+}
+
+/**
+ * JA3・JA3S文字列のハッシュ
+ */
+pub fn ja3_hash(ja3: &str) -> String {
+    md5_hex(ja3)
+}
+
+/**
+ * ClientHelloのJA4フィンガープリント
+ * "t13d1516h2_暗号スイートのハッシュ_拡張のハッシュ"の形式
+ */
+pub fn ja4(hello: &Hello) -> String {
+    let ciphers: Vec<u16> = hello
+        .ciphers
+        .iter()
+        .copied()
+        .filter(|v| !is_grease(*v))
+        .collect();
+    let extensions: Vec<u16> = hello
+        .extensions
+        .iter()
+        .copied()
+        .filter(|v| !is_grease(*v))
+        .collect();
+
+    let version = hello
+        .supported_versions
+        .iter()
+        .copied()
+        .filter(|v| !is_grease(*v))
+        .max()
+        .unwrap_or(hello.version);
+    let sni = if hello.server_name.is_some() {
+        'd'
+    } else {
+        'i'
+    };
+    let a = format!(
+        "t{}{}{:02}{:02}{}",
+        ja4_version(version),
+        sni,
+        ciphers.len().min(99),
+        extensions.len().min(99),
+        ja4_alpn(hello.alpn.first()),
+    );
+
+    let mut sorted_ciphers = ciphers;
+    sorted_ciphers.sort_unstable();
+    let b = if sorted_ciphers.is_empty() {
+        EMPTY_HASH.to_string()
+    } else {
+        sha256_prefix(&join(
+            sorted_ciphers.iter().map(|v| format!("{:04x}", v)),
+            ",",
+        ))
+    };
+
+    // SNIとALPNは先頭の部分に含まれているので除く
+    let mut sorted_extensions: Vec<u16> = extensions
+        .into_iter()
+        .filter(|v| *v != 0x0000 && *v != 0x0010)
+        .collect();
+    sorted_extensions.sort_unstable();
+    let c = if sorted_extensions.is_empty() {
+        EMPTY_HASH.to_string()
+    } else {
+        let mut s = join(sorted_extensions.iter().map(|v| format!("{:04x}", v)), ",");
+        if !hello.signature_algorithms.is_empty() {
+            s.push('_');
+            s.push_str(&join(
+                hello
+                    .signature_algorithms
+                    .iter()
+                    .map(|v| format!("{:04x}", v)),
+                ",",
+            ));
+        }
+        sha256_prefix(&s)
+    };
+    format!("{}_{}_{}", a, b, c)
+}
+
+fn ja4_version(version: u16) -> &'static str {
+    match version {
+        0x0304 => "13",
+        0x0303 => "12",
+        0x0302 => "11",
+        0x0301 => "10",
+        0x0300 => "s3",
+        0x0002 => "s2",
+        0xfeff => "d1",
+        0xfefd => "d2",
+        0xfefc => "d3",
+        _ => "00",
+    }
+}
+
+/**
+ * 最初のALPNの値の先頭と末尾の文字
+ * 英数字以外の場合は16進表記の先頭と末尾を使う
+ */
+fn ja4_alpn(alpn: Option<&String>) -> String {
+    let alpn = match alpn {
+        Some(alpn) if !alpn.is_empty() => alpn,
+        _ => return "00".to_string(),
+    };
+    let first = alpn.chars().next().unwrap_or('0');
+    let last = alpn.chars().last().unwrap_or('0');
+    if first.is_ascii_alphanumeric() && last.is_ascii_alphanumeric() {
+        format!("{}{}", first, last)
+    } else {
+        let hex: String = alpn.bytes().map(|b| format!("{:02x}", b)).collect();
+        format!("{}{}", &hex[..1], &hex[hex.len() - 1..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * SNIなし、GREASEを含むClientHello
+     */
+    fn hello() -> Hello {
+        Hello {
+            version: 0x0303,
+            ciphers: vec![0x0a0a, 0x1301, 0xc02f],
+            extensions: vec![0x2a2a, 16, 10, 13, 43],
+            groups: vec![0x3a3a, 29],
+            point_formats: vec![0],
+            signature_algorithms: vec![0x0403, 0x0804],
+            supported_versions: vec![0x4a4a, 0x0304, 0x0303],
+            server_name: None,
+            alpn: vec!["h2".to_string()],
+        }
+    }
+
+    #[test]
+    fn recognizes_grease_values() {
+        let grease: Vec<u16> = (0..16).map(|i| 0x0a0a + i * 0x1010).collect();
+        assert!(grease.iter().all(|v| is_grease(*v)));
+        assert!(!is_grease(0x0a1a));
+        assert!(!is_grease(0x1301));
+        assert!(!is_grease(0x0000));
+    }
+
+    #[test]
+    fn ja3_skips_grease() {
+        let ja3 = ja3(&hello());
+        assert_eq!(ja3, "771,4865-49199,16-10-13-43,29,0");
+        assert_eq!(ja3_hash(&ja3), "1b6873549bed98fc912db4d0c811220e");
+    }
+
+    #[test]
+    fn ja3s_keeps_the_selected_cipher() {
+        let server = Hello {
+            version: 0x0303,
+            ciphers: vec![0xc02f],
+            extensions: vec![0xff01, 0x2a2a, 11],
+            ..Default::default()
+        };
+        assert_eq!(ja3s(&server), "771,49199,65281-11");
+    }
+
+    #[test]
+    fn ja4_uses_the_highest_supported_version() {
+        assert_eq!(ja4(&hello()), "t13i0204h2_c1929292aa6b_fbabbea27ee8");
+    }
+
+    #[test]
+    fn ja4_of_an_empty_hello() {
+        let hello = Hello {
+            version: 0x0303,
+            ..Default::default()
+        };
+        assert_eq!(ja4(&hello), "t12i000000_000000000000_000000000000");
+    }
+
+    #[test]
+    fn ja4_alpn_falls_back_to_hex() {
+        assert_eq!(ja4_alpn(Some(&"http/1.1".to_string())), "h1");
+        assert_eq!(ja4_alpn(Some(&"\u{7f}ab".to_string())), "72");
+        assert_eq!(ja4_alpn(Some(&String::new())), "00");
+        assert_eq!(ja4_alpn(None), "00");
+    }
+}
@@ -0,0 +1,165 @@
+use super::{Dissector, Field, ProtoTree, Transport};
+
+const METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+pub struct HttpDissector;
+
+impl Dissector for HttpDissector {
+    fn ports(&self, transport: Transport) -> &'static [u16] {
+        match transport {
+            Transport::Tcp => &[80, 8000, 8080],
+            Transport::Udp => &[],
+        }
+    }
+
+    fn heuristic(&self, transport: Transport, payload: &[u8]) -> bool {
+        transport == Transport::Tcp && (is_request_line(payload) || payload.starts_with(b"HTTP/1."))
+    }
+
+    fn dissect(&self, _transport: Transport, payload: &[u8]) -> Option<ProtoTree> {
+        // ヘッダ部は1セグメントに収まっている前提で、先頭から行単位で読む
+        let head_len = find_subslice(payload, b"\r\n\r\n")
+            .map(|i| i + 4)
+            .unwrap_or(payload.len());
+        let head = std::str::from_utf8(&payload[..head_len]).ok()?;
+        let mut lines = head.split("\r\n");
+        let first = lines.next()?;
+
+        let mut fields = if first.starts_with("HTTP/1.") {
+            let mut parts = first.splitn(3, ' ');
+            let version = parts.next()?;
+            let code = parts.next()?;
+            code.parse::<u16>().ok()?;
+            vec![Field::with_children(
+                "Status line",
+                first,
+                vec![
+                    Field::new("Version", version),
+                    Field::new("Status code", code),
+                    Field::new("Reason phrase", parts.next().unwrap_or("")),
+                ],
+            )]
+        } else if is_request_line(first.as_bytes()) {
+            let mut parts = first.splitn(3, ' ');
+            let method = parts.next()?;
+            let target = parts.next()?;
+            let version = parts.next()?;
+            if !version.starts_with("HTTP/1.") {
+                return None;
+            }
+            vec![Field::with_children(
+                "Request line",
+                first,
+                vec![
+                    Field::new("Method", method),
+                    Field::new("Request URI", target),
+                    Field::new("Version", version),
+                ],
+            )]
+        } else {
+            return None;
+        };
+
+        let headers: Vec<_> = lines
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                Some(Field::new(name.trim(), value.trim()))
+            })
+            .collect();
+        if !headers.is_empty() {
+            fields.push(Field::with_children("Headers", "", headers));
+        }
+        let body_len = payload.len() - head_len;
+        if body_len > 0 {
+            fields.push(Field::new("Body", format!("{} bytes", body_len)));
+        }
+
+        Some(ProtoTree {
+            protocol: "HTTP",
+            fields,
+        })
+    }
+}
+
+fn is_request_line(payload: &[u8]) -> bool {
+    METHODS
+        .iter()
+        .any(|m| payload.starts_with(m.as_bytes()) && payload.get(m.len()) == Some(&b' '))
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dissect(payload: &[u8]) -> Option<String> {
+        HttpDissector
+            .dissect(Transport::Tcp, payload)
+            .map(|tree| tree.to_string())
+    }
+
+    #[test]
+    fn request() {
+        let payload = b"POST /api?q=1 HTTP/1.1\r\nHost: example.com\r\nContent-Type:text/plain\r\nbroken header\r\n\r\nhello";
+        assert_eq!(
+            dissect(payload).unwrap(),
+            "HTTP
+    Request line: POST /api?q=1 HTTP/1.1
+        Method: POST
+        Request URI: /api?q=1
+        Version: HTTP/1.1
+    Headers
+        Host: example.com
+        Content-Type: text/plain
+    Body: 5 bytes
+"
+        );
+        assert!(HttpDissector.heuristic(Transport::Tcp, payload));
+        assert!(!HttpDissector.heuristic(Transport::Udp, payload));
+    }
+
+    #[test]
+    fn status_line() {
+        let payload = b"HTTP/1.0 404 Not Found\r\nServer: mio webserver\r\n\r\n";
+        assert_eq!(
+            dissect(payload).unwrap(),
+            "HTTP
+    Status line: HTTP/1.0 404 Not Found
+        Version: HTTP/1.0
+        Status code: 404
+        Reason phrase: Not Found
+    Headers
+        Server: mio webserver
+"
+        );
+        assert!(HttpDissector.heuristic(Transport::Tcp, payload));
+
+        // 理由句は省略できる
+        let text = dissect(b"HTTP/1.1 204\r\n\r\n").unwrap();
+        assert!(text.contains("Reason phrase\n"));
+        // ヘッダが途中で切れていても、届いた行までを読む
+        let text = dissect(b"HTTP/1.1 200 OK\r\nDate: today\r\nConte").unwrap();
+        assert!(text.contains("Date: today\n"));
+        assert!(!text.contains("Body"));
+    }
+
+    #[test]
+    fn not_http() {
+        assert_eq!(dissect(b"HTTP/1.1 abc OK\r\n\r\n"), None);
+        assert_eq!(dissect(b"GET / SPDY/3\r\n\r\n"), None);
+        assert_eq!(dissect(b"GET /\r\n\r\n"), None);
+        assert_eq!(dissect(b"GETS / HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(dissect(b"\x16\x03\x01\x00\x05hello"), None);
+        assert_eq!(dissect(b"GET /\xff HTTP/1.1\r\n\r\n"), None);
+        assert!(!HttpDissector.heuristic(Transport::Tcp, b"GETS / HTTP/1.1"));
+        assert!(!HttpDissector.heuristic(Transport::Tcp, b"SSH-2.0-OpenSSH"));
+    }
+}
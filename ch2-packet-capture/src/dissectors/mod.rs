@@ -0,0 +1,236 @@
+use serde::Serialize;
+use std::fmt;
+
+mod dhcp;
+mod dns;
+mod fingerprint;
+mod http;
+mod tls;
+mod x509;
+
+/**
+ * ディセクタが解析したフィールド
+ * 子要素を持つことで木構造を表現する
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct Field {
+    pub name: String,
+    pub value: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Field>,
+}
+
+impl Field {
+    pub fn new(name: &str, value: impl ToString) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            children: vec![],
+        }
+    }
+
+    pub fn with_children(name: &str, value: impl ToString, children: Vec<Field>) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            children,
+        }
+    }
+}
+
+/**
+ * アプリケーション層のプロトコルを解析した結果
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct ProtoTree {
+    pub protocol: &'static str,
+    pub fields: Vec<Field>,
+}
+
+impl fmt::Display for ProtoTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.protocol)?;
+        for field in &self.fields {
+            write_field(f, field, 1)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_field(f: &mut fmt::Formatter<'_>, field: &Field, depth: usize) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+    if field.value.is_empty() {
+        writeln!(f, "{}{}", indent, field.name)?;
+    } else {
+        writeln!(f, "{}{}: {}", indent, field.name, field.value)?;
+    }
+    for child in &field.children {
+        write_field(f, child, depth + 1)?;
+    }
+    Ok(())
+}
+
+/**
+ * トランスポート層のプロトコル
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+/**
+ * アプリケーション層のディセクタ
+ */
+pub trait Dissector {
+    /// このディセクタが担当するウェルノウンポート
+    fn ports(&self, transport: Transport) -> &'static [u16];
+    /// ポートで判定できない場合にペイロードの中身から推測する
+    fn heuristic(&self, transport: Transport, payload: &[u8]) -> bool;
+    fn dissect(&self, transport: Transport, payload: &[u8]) -> Option<ProtoTree>;
+    /// TCPでPDUが次のセグメントに続いていて、解析するにはまだデータが足りないか
+    fn incomplete(&self, _transport: Transport, _payload: &[u8]) -> bool {
+        false
+    }
+}
+
+/**
+ * ポート番号とヒューリスティクスでディセクタを選択する
+ */
+pub struct Registry {
+    dissectors: Vec<Box<dyn Dissector>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self { dissectors: vec![] }
+    }
+
+    pub fn register(&mut self, dissector: Box<dyn Dissector>) {
+        self.dissectors.push(dissector);
+    }
+
+    /**
+     * ペイロードを解析する
+     * 該当するディセクタがない、または解析に失敗した場合はNoneを返す
+     */
+    pub fn dissect(
+        &self,
+        transport: Transport,
+        src_port: u16,
+        dst_port: u16,
+        payload: &[u8],
+    ) -> Option<ProtoTree> {
+        if payload.is_empty() {
+            return None;
+        }
+        // まずはウェルノウンポートで選択し、失敗したらヒューリスティクスで選択する
+        let by_port = self.dissectors.iter().filter(|d| {
+            let ports = d.ports(transport);
+            ports.contains(&src_port) || ports.contains(&dst_port)
+        });
+        for dissector in by_port {
+            if let Some(tree) = dissector.dissect(transport, payload) {
+                return Some(tree);
+            }
+        }
+        self.dissectors
+            .iter()
+            .filter(|d| d.heuristic(transport, payload))
+            .find_map(|d| d.dissect(transport, payload))
+    }
+
+    /**
+     * ポート番号かヒューリスティクスで選ばれるディセクタが、続きのセグメントを待っているか
+     */
+    pub fn incomplete(
+        &self,
+        transport: Transport,
+        src_port: u16,
+        dst_port: u16,
+        payload: &[u8],
+    ) -> bool {
+        self.dissectors.iter().any(|d| {
+            let ports = d.ports(transport);
+            (ports.contains(&src_port)
+                || ports.contains(&dst_port)
+                || d.heuristic(transport, payload))
+                && d.incomplete(transport, payload)
+        })
+    }
+}
+
+impl Default for Registry {
+    /**
+     * 組み込みのディセクタを登録したレジストリ
+     */
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register(Box::new(dns::DnsDissector));
+        registry.register(Box::new(dhcp::DhcpDissector));
+        registry.register(Box::new(http::HttpDissector));
+        registry.register(Box::new(tls::TlsDissector));
+        registry
+    }
+}
+
+/**
+ * ビッグエンディアンで読み出す補助関数
+ */
+fn read_u16(buf: &[u8], offset: usize) -> Option<u16> {
+    let b = buf.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let b = buf.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn hex_string(buf: &[u8]) -> String {
+    buf.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{Sink, TextOptions, TextSink};
+    use crate::testutil;
+
+    #[test]
+    fn ports_are_tried_before_heuristics() {
+        let registry = Registry::default();
+        let http = b"GET / HTTP/1.1\r\n\r\n";
+        // DNSのポートでもDNSとして読めなければ、ヒューリスティクスでHTTPとする
+        let tree = registry.dissect(Transport::Tcp, 53, 40000, http).unwrap();
+        assert_eq!(tree.protocol, "HTTP");
+        assert!(registry.dissect(Transport::Tcp, 80, 40000, &[]).is_none());
+        assert!(registry
+            .dissect(Transport::Udp, 9999, 40000, b"\x00\x01binary")
+            .is_none());
+    }
+
+    #[test]
+    fn unknown_payloads_fall_back_to_hexdump() {
+        let frame = testutil::udp("10.0.0.1:5000", "10.0.0.2:6000", b"\x00\x01binary?");
+        let records = testutil::records(&testutil::pcap(&[(1.0, frame)]));
+        assert!(records[0].application.is_none());
+
+        let mut out = vec![];
+        TextSink::new(&mut out, TextOptions::default())
+            .write(&records[0])
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "Captured a UDP packet from 10.0.0.1|5000 to 10.0.0.2|6000\n\n\
+                 00000000  00 01 62 69 6e 61 72 79  3f                       |..binary?|\n\
+                 {}\n\n",
+                "=".repeat(60)
+            )
+        );
+    }
+}
@@ -0,0 +1,713 @@
+use super::fingerprint::{self, Hello};
+use super::{hex_string, read_u16, x509, Dissector, Field, ProtoTree, Transport};
+
+const RECORD_HEADER_SIZE: usize = 5;
+const HANDSHAKE_HEADER_SIZE: usize = 4;
+const RANDOM_SIZE: usize = 32;
+
+/// レコードのContentType
+const CHANGE_CIPHER_SPEC: u8 = 20;
+const ALERT: u8 = 21;
+const HANDSHAKE: u8 = 22;
+const APPLICATION_DATA: u8 = 23;
+
+/// HandshakeType
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const CERTIFICATE: u8 = 11;
+
+/// 拡張の種類
+const EXT_SERVER_NAME: u16 = 0;
+const EXT_SUPPORTED_GROUPS: u16 = 10;
+const EXT_EC_POINT_FORMATS: u16 = 11;
+const EXT_SIGNATURE_ALGORITHMS: u16 = 13;
+const EXT_ALPN: u16 = 16;
+const EXT_SUPPORTED_VERSIONS: u16 = 43;
+
+pub struct TlsDissector;
+
+impl Dissector for TlsDissector {
+    fn ports(&self, transport: Transport) -> &'static [u16] {
+        match transport {
+            Transport::Tcp => &[443, 465, 636, 853, 993, 995, 8443],
+            Transport::Udp => &[],
+        }
+    }
+
+    fn heuristic(&self, transport: Transport, payload: &[u8]) -> bool {
+        transport == Transport::Tcp && is_record_header(payload)
+    }
+
+    fn dissect(&self, _transport: Transport, payload: &[u8]) -> Option<ProtoTree> {
+        if !is_record_header(payload) {
+            return None;
+        }
+        let records = split_records(payload);
+        let mut fields = vec![];
+        // 1つのハンドシェイクメッセージが複数のレコードにまたがることがあるので連結してから読む
+        let mut handshake = vec![];
+        let mut encrypted = false;
+        for record in &records {
+            if record.content_type != HANDSHAKE || encrypted {
+                fields.extend(dissect_handshake(&handshake));
+                handshake.clear();
+            }
+            fields.push(record.field());
+            match record.content_type {
+                HANDSHAKE if !encrypted => handshake.extend_from_slice(record.body),
+                HANDSHAKE => fields.push(Field::new("Handshake", "Encrypted handshake message")),
+                // ChangeCipherSpec以降のハンドシェイクは暗号化されている
+                CHANGE_CIPHER_SPEC => encrypted = true,
+                _ => {}
+            }
+        }
+        fields.extend(dissect_handshake(&handshake));
+        Some(ProtoTree {
+            protocol: "TLS",
+            fields,
+        })
+    }
+
+    fn incomplete(&self, _transport: Transport, payload: &[u8]) -> bool {
+        if !is_record_header(payload) {
+            return false;
+        }
+        // アプリケーションデータは中身を読まないので、途中で切れていても待たない
+        let records = split_records(payload);
+        let consumed: usize = records
+            .iter()
+            .map(|r| RECORD_HEADER_SIZE + r.body.len())
+            .sum();
+        if let Some(rest) = payload.get(consumed..) {
+            if rest.first() == Some(&HANDSHAKE) {
+                return true;
+            }
+        }
+        // 最後のハンドシェイクメッセージが次のレコードに続いている
+        let mut handshake = vec![];
+        for record in &records {
+            match record.content_type {
+                HANDSHAKE => handshake.extend_from_slice(record.body),
+                CHANGE_CIPHER_SPEC => return false,
+                _ => {}
+            }
+        }
+        let mut offset = 0;
+        while let Some((msg_type, len)) = read_handshake_header(&handshake, offset) {
+            let end = offset + HANDSHAKE_HEADER_SIZE + len;
+            if end > handshake.len() {
+                return matches!(msg_type, CLIENT_HELLO | SERVER_HELLO | CERTIFICATE);
+            }
+            offset = end;
+        }
+        false
+    }
+}
+
+/**
+ * TLSのレコードヘッダで始まっているか
+ */
+fn is_record_header(payload: &[u8]) -> bool {
+    payload.len() >= RECORD_HEADER_SIZE
+        && (CHANGE_CIPHER_SPEC..=APPLICATION_DATA).contains(&payload[0])
+        && payload[1] == 3
+        && payload[2] <= 4
+}
+
+struct Record<'a> {
+    content_type: u8,
+    version: u16,
+    /// 宣言された長さ。bodyはキャプチャされている範囲だけ
+    length: usize,
+    body: &'a [u8],
+}
+
+impl Record<'_> {
+    fn field(&self) -> Field {
+        let name = match self.content_type {
+            CHANGE_CIPHER_SPEC => "Change Cipher Spec",
+            ALERT => "Alert",
+            HANDSHAKE => "Handshake",
+            _ => "Application Data",
+        };
+        let partial = if self.body.len() < self.length {
+            " (partial)"
+        } else {
+            ""
+        };
+        let mut children = vec![];
+        if let (ALERT, [level, description]) = (self.content_type, self.body) {
+            children.push(Field::new("Level", alert_level(*level)));
+            children.push(Field::new("Description", alert_description(*description)));
+        }
+        Field::with_children(
+            "Record",
+            format!(
+                "{}, {}, {} bytes{}",
+                name,
+                version_name(self.version),
+                self.length,
+                partial
+            ),
+            children,
+        )
+    }
+}
+
+/**
+ * ペイロードをレコードに分ける
+ * 最後のレコードは、ハンドシェイク以外なら途中で切れていても含める
+ */
+fn split_records(payload: &[u8]) -> Vec<Record<'_>> {
+    let mut records = vec![];
+    let mut offset = 0;
+    while is_record_header(&payload[offset..]) {
+        let content_type = payload[offset];
+        let version = read_u16(payload, offset + 1).unwrap_or_default();
+        let length = read_u16(payload, offset + 3).unwrap_or_default() as usize;
+        let start = offset + RECORD_HEADER_SIZE;
+        let end = start + length;
+        if end > payload.len() {
+            if content_type != HANDSHAKE {
+                records.push(Record {
+                    content_type,
+                    version,
+                    length,
+                    body: &payload[start..],
+                });
+            }
+            break;
+        }
+        records.push(Record {
+            content_type,
+            version,
+            length,
+            body: &payload[start..end],
+        });
+        offset = end;
+    }
+    records
+}
+
+fn read_u24(buf: &[u8], offset: usize) -> Option<usize> {
+    let b = buf.get(offset..offset + 3)?;
+    Some((b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize)
+}
+
+fn read_handshake_header(buf: &[u8], offset: usize) -> Option<(u8, usize)> {
+    let msg_type = *buf.get(offset)?;
+    let len = read_u24(buf, offset + 1)?;
+    Some((msg_type, len))
+}
+
+/**
+ * 連結したハンドシェイクメッセージを順に解析する
+ */
+fn dissect_handshake(buf: &[u8]) -> Vec<Field> {
+    let mut fields = vec![];
+    let mut offset = 0;
+    while let Some((msg_type, len)) = read_handshake_header(buf, offset) {
+        let start = offset + HANDSHAKE_HEADER_SIZE;
+        let body = match buf.get(start..start + len) {
+            Some(body) => body,
+            None => break,
+        };
+        let field = match msg_type {
+            CLIENT_HELLO => dissect_hello(body, true),
+            SERVER_HELLO => dissect_hello(body, false),
+            CERTIFICATE => dissect_certificates(body),
+            _ => None,
+        };
+        fields.push(field.unwrap_or_else(|| {
+            Field::new(
+                "Handshake",
+                format!("{} ({} bytes)", handshake_name(msg_type), len),
+            )
+        }));
+        offset = start + len;
+    }
+    fields
+}
+
+/**
+ * ClientHello・ServerHelloを解析する
+ */
+fn dissect_hello(body: &[u8], client: bool) -> Option<Field> {
+    let mut hello = Hello {
+        version: read_u16(body, 0)?,
+        ..Default::default()
+    };
+    let mut offset = 2 + RANDOM_SIZE;
+    let session_id_len = *body.get(offset)? as usize;
+    let session_id = body.get(offset + 1..offset + 1 + session_id_len)?;
+    offset += 1 + session_id_len;
+
+    if client {
+        let len = read_u16(body, offset)? as usize;
+        hello.ciphers = read_u16_list(body.get(offset + 2..offset + 2 + len)?);
+        offset += 2 + len;
+        let len = *body.get(offset)? as usize;
+        offset += 1 + len;
+    } else {
+        hello.ciphers = vec![read_u16(body, offset)?];
+        // 圧縮方式
+        offset += 3;
+    }
+
+    let mut extension_fields = vec![];
+    if let Some(len) = read_u16(body, offset) {
+        let extensions = body.get(offset + 2..offset + 2 + len as usize)?;
+        let mut ext_offset = 0;
+        while ext_offset < extensions.len() {
+            let ext_type = read_u16(extensions, ext_offset)?;
+            let ext_len = read_u16(extensions, ext_offset + 2)? as usize;
+            let data = extensions.get(ext_offset + 4..ext_offset + 4 + ext_len)?;
+            hello.extensions.push(ext_type);
+            read_extension(&mut hello, ext_type, data, client);
+            extension_fields.push(Field::new(
+                &extension_name(ext_type),
+                format!("{} bytes", ext_len),
+            ));
+            ext_offset += 4 + ext_len;
+        }
+    }
+
+    let mut fields = vec![Field::new(
+        "Version",
+        format!("{} (0x{:04x})", version_name(hello.version), hello.version),
+    )];
+    if !session_id.is_empty() {
+        fields.push(Field::new("Session ID", hex_string(session_id)));
+    }
+    if client {
+        fields.push(Field::with_children(
+            "Cipher suites",
+            format!("{} suites", hello.ciphers.len()),
+            hello
+                .ciphers
+                .iter()
+                .map(|c| Field::new(&cipher_name(*c), format!("0x{:04x}", c)))
+                .collect(),
+        ));
+    } else {
+        fields.push(Field::new("Cipher suite", cipher_name(hello.ciphers[0])));
+    }
+    if let Some(name) = &hello.server_name {
+        fields.push(Field::new("Server name", name));
+    }
+    if !hello.alpn.is_empty() {
+        fields.push(Field::new("ALPN", hello.alpn.join(", ")));
+    }
+    if client {
+        if !hello.supported_versions.is_empty() {
+            fields.push(Field::new(
+                "Supported versions",
+                version_list(&hello.supported_versions),
+            ));
+        }
+    } else {
+        // TLS 1.3ではsupported_versionsで実際のバージョンを示す
+        let selected = hello
+            .supported_versions
+            .first()
+            .copied()
+            .unwrap_or(hello.version);
+        fields.push(Field::new("Selected version", version_name(selected)));
+    }
+    fields.push(Field::with_children(
+        "Extensions",
+        format!("{} extensions", extension_fields.len()),
+        extension_fields,
+    ));
+    if client {
+        let ja3 = fingerprint::ja3(&hello);
+        fields.push(Field::new("JA3", fingerprint::ja3_hash(&ja3)));
+        fields.push(Field::new("JA3 string", ja3));
+        fields.push(Field::new("JA4", fingerprint::ja4(&hello)));
+    } else {
+        let ja3s = fingerprint::ja3s(&hello);
+        fields.push(Field::new("JA3S", fingerprint::ja3_hash(&ja3s)));
+        fields.push(Field::new("JA3S string", ja3s));
+    }
+
+    let name = if client {
+        "Client Hello"
+    } else {
+        "Server Hello"
+    };
+    Some(Field::with_children("Handshake", name, fields))
+}
+
+fn read_extension(hello: &mut Hello, ext_type: u16, data: &[u8], client: bool) {
+    match ext_type {
+        EXT_SERVER_NAME => {
+            // server_name_list: 種類(1) + 長さ(2) + 名前
+            if let (Some(0), Some(len)) = (data.get(2), read_u16(data, 3)) {
+                if let Some(name) = data.get(5..5 + len as usize) {
+                    hello.server_name = Some(String::from_utf8_lossy(name).to_string());
+                }
+            }
+        }
+        EXT_ALPN => {
+            let mut offset = 2;
+            while let Some(len) = data.get(offset) {
+                let start = offset + 1;
+                match data.get(start..start + *len as usize) {
+                    Some(protocol) => hello
+                        .alpn
+                        .push(String::from_utf8_lossy(protocol).to_string()),
+                    None => break,
+                }
+                offset = start + *len as usize;
+            }
+        }
+        EXT_SUPPORTED_VERSIONS if client => {
+            hello.supported_versions = read_u16_list(data.get(1..).unwrap_or_default());
+        }
+        EXT_SUPPORTED_VERSIONS => hello.supported_versions = read_u16_list(data),
+        EXT_SUPPORTED_GROUPS => {
+            hello.groups = read_u16_list(data.get(2..).unwrap_or_default());
+        }
+        EXT_EC_POINT_FORMATS => {
+            hello.point_formats = data.get(1..).unwrap_or_default().to_vec();
+        }
+        EXT_SIGNATURE_ALGORITHMS => {
+            hello.signature_algorithms = read_u16_list(data.get(2..).unwrap_or_default());
+        }
+        _ => {}
+    }
+}
+
+fn read_u16_list(buf: &[u8]) -> Vec<u16> {
+    buf.chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .collect()
+}
+
+/**
+ * Certificateメッセージ（TLS 1.2まで）の証明書チェーンを解析する
+ * TLS 1.3では暗号化されているので読めない
+ */
+fn dissect_certificates(body: &[u8]) -> Option<Field> {
+    let total = read_u24(body, 0)?;
+    let list = body.get(3..3 + total)?;
+    let mut certificates = vec![];
+    let mut offset = 0;
+    while offset < list.len() {
+        let len = read_u24(list, offset)?;
+        let der = list.get(offset + 3..offset + 3 + len)?;
+        certificates.push(
+            x509::dissect_certificate(der)
+                .unwrap_or_else(|| Field::new("Certificate", format!("{} bytes (unparsed)", len))),
+        );
+        offset += 3 + len;
+    }
+    Some(Field::with_children(
+        "Handshake",
+        format!("Certificate ({} certificates)", certificates.len()),
+        certificates,
+    ))
+}
+
+fn version_name(version: u16) -> String {
+    match version {
+        0x0300 => "SSL 3.0".to_string(),
+        0x0301 => "TLS 1.0".to_string(),
+        0x0302 => "TLS 1.1".to_string(),
+        0x0303 => "TLS 1.2".to_string(),
+        0x0304 => "TLS 1.3".to_string(),
+        v if fingerprint::is_grease(v) => "GREASE".to_string(),
+        v => format!("0x{:04x}", v),
+    }
+}
+
+fn version_list(versions: &[u16]) -> String {
+    versions
+        .iter()
+        .map(|v| version_name(*v))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn handshake_name(msg_type: u8) -> String {
+    match msg_type {
+        0 => "Hello Request".to_string(),
+        CLIENT_HELLO => "Client Hello".to_string(),
+        SERVER_HELLO => "Server Hello".to_string(),
+        4 => "New Session Ticket".to_string(),
+        8 => "Encrypted Extensions".to_string(),
+        CERTIFICATE => "Certificate".to_string(),
+        12 => "Server Key Exchange".to_string(),
+        13 => "Certificate Request".to_string(),
+        14 => "Server Hello Done".to_string(),
+        15 => "Certificate Verify".to_string(),
+        16 => "Client Key Exchange".to_string(),
+        20 => "Finished".to_string(),
+        _ => format!("Unknown ({})", msg_type),
+    }
+}
+
+fn cipher_name(cipher: u16) -> String {
+    let name = match cipher {
+        0x1301 => "TLS_AES_128_GCM_SHA256",
+        0x1302 => "TLS_AES_256_GCM_SHA384",
+        0x1303 => "TLS_CHACHA20_POLY1305_SHA256",
+        0xc02b => "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+        0xc02f => "TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256",
+        0xc02c => "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384",
+        0xc030 => "TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+        0xcca9 => "TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xcca8 => "TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256",
+        0xc009 => "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA",
+        0xc013 => "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA",
+        0xc00a => "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA",
+        0xc014 => "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA",
+        0xc023 => "TLS_ECDHE_ECDSA_WITH_AES_128_CBC_SHA256",
+        0xc027 => "TLS_ECDHE_RSA_WITH_AES_128_CBC_SHA256",
+        0xc024 => "TLS_ECDHE_ECDSA_WITH_AES_256_CBC_SHA384",
+        0xc028 => "TLS_ECDHE_RSA_WITH_AES_256_CBC_SHA384",
+        0x009c => "TLS_RSA_WITH_AES_128_GCM_SHA256",
+        0x009d => "TLS_RSA_WITH_AES_256_GCM_SHA384",
+        0x009e => "TLS_DHE_RSA_WITH_AES_128_GCM_SHA256",
+        0x009f => "TLS_DHE_RSA_WITH_AES_256_GCM_SHA384",
+        0x0067 => "TLS_DHE_RSA_WITH_AES_128_CBC_SHA256",
+        0x006b => "TLS_DHE_RSA_WITH_AES_256_CBC_SHA256",
+        0x002f => "TLS_RSA_WITH_AES_128_CBC_SHA",
+        0x0035 => "TLS_RSA_WITH_AES_256_CBC_SHA",
+        0x003c => "TLS_RSA_WITH_AES_128_CBC_SHA256",
+        0x003d => "TLS_RSA_WITH_AES_256_CBC_SHA256",
+        0x000a => "TLS_RSA_WITH_3DES_EDE_CBC_SHA",
+        0x00ff => "TLS_EMPTY_RENEGOTIATION_INFO_SCSV",
+        c if fingerprint::is_grease(c) => "GREASE",
+        c => return format!("Unknown (0x{:04x})", c),
+    };
+    name.to_string()
+}
+
+fn extension_name(ext_type: u16) -> String {
+    let name = match ext_type {
+        EXT_SERVER_NAME => "server_name",
+        5 => "status_request",
+        EXT_SUPPORTED_GROUPS => "supported_groups",
+        EXT_EC_POINT_FORMATS => "ec_point_formats",
+        EXT_SIGNATURE_ALGORITHMS => "signature_algorithms",
+        EXT_ALPN => "application_layer_protocol_negotiation",
+        18 => "signed_certificate_timestamp",
+        21 => "padding",
+        22 => "encrypt_then_mac",
+        23 => "extended_master_secret",
+        27 => "compress_certificate",
+        35 => "session_ticket",
+        41 => "pre_shared_key",
+        42 => "early_data",
+        EXT_SUPPORTED_VERSIONS => "supported_versions",
+        45 => "psk_key_exchange_modes",
+        49 => "post_handshake_auth",
+        50 => "signature_algorithms_cert",
+        51 => "key_share",
+        0xff01 => "renegotiation_info",
+        t if fingerprint::is_grease(t) => "GREASE",
+        t => return format!("unknown ({})", t),
+    };
+    format!("{} ({})", name, ext_type)
+}
+
+fn alert_level(level: u8) -> String {
+    match level {
+        1 => "warning".to_string(),
+        2 => "fatal".to_string(),
+        l => l.to_string(),
+    }
+}
+
+fn alert_description(description: u8) -> String {
+    let name = match description {
+        0 => "close_notify",
+        10 => "unexpected_message",
+        20 => "bad_record_mac",
+        40 => "handshake_failure",
+        42 => "bad_certificate",
+        45 => "certificate_expired",
+        46 => "certificate_unknown",
+        48 => "unknown_ca",
+        50 => "decode_error",
+        70 => "protocol_version",
+        80 => "internal_error",
+        112 => "unrecognized_name",
+        120 => "no_application_protocol",
+        d => return d.to_string(),
+    };
+    name.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, Tcp};
+    use pnet::packet::tcp::TcpFlags::{ACK, PSH};
+
+    // openssl s_clientとs_serverの間で記録したレコード（fixtures/tls/README.md）
+    const CLIENT_HELLO: &[u8] = include_bytes!("../../fixtures/tls/client_hello.bin");
+    const CLIENT_HELLO_GREASE: &[u8] = include_bytes!("../../fixtures/tls/client_hello_grease.bin");
+    const SERVER_HELLO: &[u8] = include_bytes!("../../fixtures/tls/server_hello.bin");
+    const CERTIFICATE: &[u8] = include_bytes!("../../fixtures/tls/certificate.bin");
+
+    const JA3: &str = "3cccd0410cd2a6eb87bab586a6f51e73";
+    const JA3_STRING: &str =
+        "771,4866-4867-4865-49196-49200-159-52393-52392-52394-49195-49199-158-\
+        49188-49192-107-49187-49191-103-49162-49172-57-49161-49171-51-157-156-61-60-53-47,\
+        65281-0-11-10-35-16-22-23-13-43-45-51,29-23,0-1-2";
+    const JA4: &str = "t13d3012h2_1d37bd780c83_8e6e362c5eac";
+    const JA3S: &str = "e195432758194ce2d482396b82e0d48e";
+
+    /**
+     * 名前が一致する最初のフィールドを深さ優先で探す
+     */
+    fn find<'a>(fields: &'a [Field], name: &str) -> Option<&'a Field> {
+        fields.iter().find_map(|field| {
+            if field.name == name {
+                Some(field)
+            } else {
+                find(&field.children, name)
+            }
+        })
+    }
+
+    fn value(tree: &ProtoTree, name: &str) -> String {
+        find(&tree.fields, name)
+            .unwrap_or_else(|| panic!("{} not found in\n{}", name, tree))
+            .value
+            .clone()
+    }
+
+    fn dissect(payload: &[u8]) -> ProtoTree {
+        TlsDissector.dissect(Transport::Tcp, payload).unwrap()
+    }
+
+    #[test]
+    fn client_hello() {
+        let tree = dissect(CLIENT_HELLO);
+        assert_eq!(value(&tree, "Record"), "Handshake, TLS 1.0, 329 bytes");
+        assert_eq!(value(&tree, "Handshake"), "Client Hello");
+        assert_eq!(value(&tree, "Version"), "TLS 1.2 (0x0303)");
+        assert_eq!(value(&tree, "Server name"), "www.example.com");
+        assert_eq!(value(&tree, "ALPN"), "h2, http/1.1");
+        assert_eq!(value(&tree, "Supported versions"), "TLS 1.3, TLS 1.2");
+
+        let suites = find(&tree.fields, "Cipher suites").unwrap();
+        assert_eq!(suites.value, "30 suites");
+        assert_eq!(suites.children[0].name, "TLS_AES_256_GCM_SHA384");
+        assert_eq!(suites.children[0].value, "0x1302");
+        assert_eq!(suites.children[29].name, "TLS_RSA_WITH_AES_128_CBC_SHA");
+        assert_eq!(value(&tree, "Extensions"), "12 extensions");
+
+        assert_eq!(value(&tree, "JA3"), JA3);
+        assert_eq!(value(&tree, "JA3 string"), JA3_STRING);
+        assert_eq!(value(&tree, "JA4"), JA4);
+    }
+
+    #[test]
+    fn grease_values_do_not_change_fingerprints() {
+        // 暗号スイート・拡張・グループ・バージョンにGREASEを加えたもの
+        let tree = dissect(CLIENT_HELLO_GREASE);
+        let suites = find(&tree.fields, "Cipher suites").unwrap();
+        assert_eq!(suites.value, "31 suites");
+        assert_eq!(suites.children[0].name, "GREASE");
+        assert_eq!(value(&tree, "Extensions"), "14 extensions");
+        assert_eq!(value(&tree, "GREASE (6682)"), "0 bytes");
+        assert_eq!(
+            value(&tree, "Supported versions"),
+            "GREASE, TLS 1.3, TLS 1.2"
+        );
+
+        assert_eq!(value(&tree, "JA3"), JA3);
+        assert_eq!(value(&tree, "JA3 string"), JA3_STRING);
+        assert_eq!(value(&tree, "JA4"), JA4);
+    }
+
+    #[test]
+    fn server_hello() {
+        let tree = dissect(SERVER_HELLO);
+        assert_eq!(value(&tree, "Handshake"), "Server Hello");
+        assert_eq!(value(&tree, "Version"), "TLS 1.2 (0x0303)");
+        assert_eq!(
+            value(&tree, "Cipher suite"),
+            "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384"
+        );
+        assert_eq!(value(&tree, "ALPN"), "h2");
+        assert_eq!(value(&tree, "Selected version"), "TLS 1.2");
+        assert_eq!(value(&tree, "JA3S"), JA3S);
+        assert_eq!(value(&tree, "JA3S string"), "771,49196,65281-11-35-16-23");
+    }
+
+    #[test]
+    fn certificate_chain() {
+        let tree = dissect(CERTIFICATE);
+        assert_eq!(value(&tree, "Handshake"), "Certificate (2 certificates)");
+        let chain = &find(&tree.fields, "Handshake").unwrap().children;
+        let leaf = &chain[0].children;
+        assert_eq!(chain[0].value, "C=JP, O=Example, CN=www.example.com");
+        assert_eq!(leaf[0].value, "C=JP, O=Example, CN=www.example.com");
+        assert_eq!(leaf[1].value, "C=JP, O=Example CA, CN=Example Root CA");
+        assert_eq!(
+            leaf[2].value,
+            "20:97:f8:d3:16:4d:da:72:46:3a:cd:a1:98:db:88:5b:16:92:7b:03"
+        );
+        assert_eq!(leaf[3].value, "2026-10-18 22:24:49 UTC");
+        assert_eq!(leaf[4].value, "2036-10-15 22:24:49 UTC");
+        // ルート証明書は自己署名
+        let root = &chain[1].children;
+        assert_eq!(root[0].value, "C=JP, O=Example CA, CN=Example Root CA");
+        assert_eq!(root[1].value, root[0].value);
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_handshake() {
+        assert!(!TlsDissector.incomplete(Transport::Tcp, CERTIFICATE));
+        assert!(TlsDissector.incomplete(Transport::Tcp, &CERTIFICATE[..500]));
+        // ServerHelloは揃っていて、Certificateのレコードが途中
+        let flight = [SERVER_HELLO, &CERTIFICATE[..100]].concat();
+        assert!(TlsDissector.incomplete(Transport::Tcp, &flight));
+    }
+
+    #[test]
+    fn reassembles_a_flight_split_across_segments() {
+        let flight = [SERVER_HELLO, CERTIFICATE].concat();
+        let mut seq = 1000;
+        let frames: Vec<_> = [&flight[..40], &flight[40..600], &flight[600..]]
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let frame = Tcp::new("10.0.0.1:443", "10.0.0.2:50000", PSH | ACK)
+                    .seq(seq)
+                    .payload(chunk)
+                    .frame();
+                seq += chunk.len() as u32;
+                (i as f64, frame)
+            })
+            .collect();
+        let records = testutil::records(&testutil::pcap(&frames));
+        assert_eq!(records.len(), 3);
+
+        for record in &records[..2] {
+            assert!(record.application.is_none());
+            assert!(record.expert[0]
+                .message
+                .starts_with("Segment of a reassembled PDU"));
+        }
+        let last = &records[2];
+        assert_eq!(
+            last.expert[0].message,
+            format!("Reassembled PDU of {} bytes", flight.len())
+        );
+        let tree = last.application.as_ref().unwrap();
+        let handshakes: Vec<_> = tree
+            .fields
+            .iter()
+            .filter(|f| f.name == "Handshake")
+            .map(|f| f.value.as_str())
+            .collect();
+        assert_eq!(handshakes, ["Server Hello", "Certificate (2 certificates)"]);
+        assert_eq!(value(tree, "JA3S"), JA3S);
+    }
+}
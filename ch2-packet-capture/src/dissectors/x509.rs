@@ -0,0 +1,190 @@
+use super::{hex_string, Field};
+
+/// DERのタグ
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_SET: u8 = 0x31;
+const TAG_BMP_STRING: u8 = 0x1e;
+const TAG_UTC_TIME: u8 = 0x17;
+const TAG_GENERALIZED_TIME: u8 = 0x18;
+/// TBSCertificateのversion（[0] EXPLICIT）
+const TAG_VERSION: u8 = 0xa0;
+
+/**
+ * DERのTLVを1つ読み、タグと値と次のオフセットを返す
+ */
+fn read_tlv(buf: &[u8], offset: usize) -> Option<(u8, &[u8], usize)> {
+    let tag = *buf.get(offset)?;
+    let first = *buf.get(offset + 1)? as usize;
+    let (len, start) = if first < 0x80 {
+        (first, offset + 2)
+    } else {
+        // 長形式。下位7ビットが長さのオクテット数
+        let n = first & 0x7f;
+        if n == 0 || n > 4 {
+            return None;
+        }
+        let bytes = buf.get(offset + 2..offset + 2 + n)?;
+        let len = bytes.iter().fold(0usize, |acc, b| acc << 8 | *b as usize);
+        (len, offset + 2 + n)
+    };
+    let value = buf.get(start..start.checked_add(len)?)?;
+    Some((tag, value, start + len))
+}
+
+/**
+ * 指定したタグのTLVを読む
+ */
+fn expect_tlv(buf: &[u8], offset: usize, tag: u8) -> Option<(&[u8], usize)> {
+    let (actual, value, next) = read_tlv(buf, offset)?;
+    (actual == tag).then_some((value, next))
+}
+
+/**
+ * 証明書のサブジェクトと発行者などを解析する
+ * 解析できない形式の場合はNoneを返す
+ */
+pub fn dissect_certificate(der: &[u8]) -> Option<Field> {
+    let (cert, _) = expect_tlv(der, 0, TAG_SEQUENCE)?;
+    let (tbs, _) = expect_tlv(cert, 0, TAG_SEQUENCE)?;
+
+    let mut offset = 0;
+    if tbs.first() == Some(&TAG_VERSION) {
+        offset = read_tlv(tbs, offset)?.2;
+    }
+    let (serial, next) = expect_tlv(tbs, offset, TAG_INTEGER)?;
+    // signature
+    let (_, next) = expect_tlv(tbs, next, TAG_SEQUENCE)?;
+    let (issuer, next) = expect_tlv(tbs, next, TAG_SEQUENCE)?;
+    let (validity, next) = expect_tlv(tbs, next, TAG_SEQUENCE)?;
+    let (subject, _) = expect_tlv(tbs, next, TAG_SEQUENCE)?;
+    let (not_before, next) = read_time(validity, 0)?;
+    let (not_after, _) = read_time(validity, next)?;
+
+    let subject = format_name(subject)?;
+    let issuer = format_name(issuer)?;
+    Some(Field::with_children(
+        "Certificate",
+        &subject,
+        vec![
+            Field::new("Subject", &subject),
+            Field::new("Issuer", issuer),
+            Field::new("Serial number", hex_string(serial)),
+            Field::new("Not before", not_before),
+            Field::new("Not after", not_after),
+        ],
+    ))
+}
+
+/**
+ * Nameを"CN=example.com, O=Example"のような文字列にする
+ */
+fn format_name(name: &[u8]) -> Option<String> {
+    let mut parts = vec![];
+    let mut offset = 0;
+    while offset < name.len() {
+        let (rdn, next) = expect_tlv(name, offset, TAG_SET)?;
+        let mut rdn_offset = 0;
+        while rdn_offset < rdn.len() {
+            let (attribute, rdn_next) = expect_tlv(rdn, rdn_offset, TAG_SEQUENCE)?;
+            let (oid, value_offset) = expect_tlv(attribute, 0, TAG_OID)?;
+            let (tag, value, _) = read_tlv(attribute, value_offset)?;
+            parts.push(format!(
+                "{}={}",
+                attribute_name(oid),
+                decode_string(tag, value)
+            ));
+            rdn_offset = rdn_next;
+        }
+        offset = next;
+    }
+    Some(parts.join(", "))
+}
+
+fn attribute_name(oid: &[u8]) -> String {
+    match oid {
+        [0x55, 0x04, 0x03] => "CN".to_string(),
+        [0x55, 0x04, 0x06] => "C".to_string(),
+        [0x55, 0x04, 0x07] => "L".to_string(),
+        [0x55, 0x04, 0x08] => "ST".to_string(),
+        [0x55, 0x04, 0x0a] => "O".to_string(),
+        [0x55, 0x04, 0x0b] => "OU".to_string(),
+        [0x55, 0x04, 0x05] => "serialNumber".to_string(),
+        [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01] => "emailAddress".to_string(),
+        _ => format_oid(oid),
+    }
+}
+
+/**
+ * OIDをドット区切りの文字列にする
+ */
+fn format_oid(oid: &[u8]) -> String {
+    let mut arcs = vec![];
+    let mut value: u64 = 0;
+    for b in oid {
+        value = value.wrapping_shl(7) | (b & 0x7f) as u64;
+        if b & 0x80 != 0 {
+            continue;
+        }
+        if arcs.is_empty() {
+            // 先頭の2つのアークは1オクテットにまとめられている
+            let first = (value / 40).min(2);
+            arcs.push(first);
+            arcs.push(value - first * 40);
+        } else {
+            arcs.push(value);
+        }
+        value = 0;
+    }
+    arcs.iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn decode_string(tag: u8, value: &[u8]) -> String {
+    match tag {
+        TAG_BMP_STRING => {
+            let units: Vec<u16> = value
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        // PrintableString・IA5StringなどはASCIIの範囲なのでUTF8Stringと同様に扱う
+        _ => String::from_utf8_lossy(value).to_string(),
+    }
+}
+
+/**
+ * UTCTime・GeneralizedTimeを"2024-01-01 00:00:00 UTC"の形式で読む
+ */
+fn read_time(buf: &[u8], offset: usize) -> Option<(String, usize)> {
+    let (tag, value, next) = read_tlv(buf, offset)?;
+    let s = std::str::from_utf8(value).ok()?;
+    let digits = s.strip_suffix('Z')?;
+    let full = match tag {
+        TAG_UTC_TIME => {
+            // 2桁の年は50以上なら19xx年、それ以外は20xx年
+            let year: u32 = digits.get(..2)?.parse().ok()?;
+            let century = if year >= 50 { "19" } else { "20" };
+            format!("{}{}", century, digits)
+        }
+        TAG_GENERALIZED_TIME => digits.to_string(),
+        _ => return None,
+    };
+    if full.len() < 14 || !full.is_ascii() {
+        return None;
+    }
+    let time = format!(
+        "{}-{}-{} {}:{}:{} UTC",
+        &full[0..4],
+        &full[4..6],
+        &full[6..8],
+        &full[8..10],
+        &full[10..12],
+        &full[12..14]
+    );
+    Some((time, next))
+}
@@ -0,0 +1,177 @@
+use crate::decoder::Severity;
+use crate::record::PacketRecord;
+use anyhow::{anyhow, Context, Result};
+
+/**
+ * 表示フィルタ
+ * "tcp and port 443"、"not host 10.0.0.1"、"dns or (udp and port 5353)"のような式で、
+ * andは省略できる。プロトコル名は各層とアプリケーション層の名前に大文字小文字を区別せずに一致する
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    /// 送信元か宛先のIPアドレス
+    Host(String),
+    Src(String),
+    Dst(String),
+    /// 送信元か宛先のポート
+    Port(u16),
+    Protocol(String),
+    /// エラーの異常があるパケット
+    Malformed,
+}
+
+impl std::str::FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let tokens = tokenize(s);
+        let mut parser = Parser { tokens, pos: 0 };
+        let filter = parser.parse_or()?;
+        match parser.peek() {
+            None => Ok(filter),
+            Some(token) => Err(anyhow!("Unexpected '{}' in filter", token)),
+        }
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, record: &PacketRecord) -> bool {
+        match self {
+            Filter::And(a, b) => a.matches(record) && b.matches(record),
+            Filter::Or(a, b) => a.matches(record) || b.matches(record),
+            Filter::Not(f) => !f.matches(record),
+            Filter::Host(addr) => record.src_ip == *addr || record.dst_ip == *addr,
+            Filter::Src(addr) => record.src_ip == *addr,
+            Filter::Dst(addr) => record.dst_ip == *addr,
+            Filter::Port(port) => record.src_port == *port || record.dst_port == *port,
+            Filter::Protocol(name) => {
+                record
+                    .decoded
+                    .layers
+                    .iter()
+                    .any(|layer| layer.header.name().eq_ignore_ascii_case(name))
+                    || record
+                        .application
+                        .as_ref()
+                        .is_some_and(|tree| tree.protocol.eq_ignore_ascii_case(name))
+                    // "ip"はIPv4とIPv6の両方に一致させる
+                    || (name == "ip" && record.decoded.find(|h| h.addresses()).is_some())
+            }
+            Filter::Malformed => record
+                .expert
+                .iter()
+                .any(|info| info.severity == Severity::Error),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    for c in s.chars() {
+        if c.is_whitespace() || c == '(' || c == ')' || c == '!' {
+            if !current.is_empty() {
+                tokens.push(std::mem::take(&mut current));
+            }
+            if !c.is_whitespace() {
+                tokens.push(c.to_string());
+            }
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+/**
+ * 再帰下降パーサ
+ * 優先順位は not > and > or
+ */
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+
+    fn next(&mut self) -> Option<String> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Filter> {
+        let mut filter = self.parse_and()?;
+        while matches!(self.peek(), Some("or" | "||")) {
+            self.next();
+            filter = Filter::Or(Box::new(filter), Box::new(self.parse_and()?));
+        }
+        Ok(filter)
+    }
+
+    fn parse_and(&mut self) -> Result<Filter> {
+        let mut filter = self.parse_not()?;
+        loop {
+            match self.peek() {
+                Some("and" | "&&") => {
+                    self.next();
+                }
+                // andの省略
+                Some(token) if token != "or" && token != "||" && token != ")" => {}
+                _ => break,
+            }
+            filter = Filter::And(Box::new(filter), Box::new(self.parse_not()?));
+        }
+        Ok(filter)
+    }
+
+    fn parse_not(&mut self) -> Result<Filter> {
+        match self.peek() {
+            Some("not" | "!") => {
+                self.next();
+                Ok(Filter::Not(Box::new(self.parse_not()?)))
+            }
+            Some("(") => {
+                self.next();
+                let filter = self.parse_or()?;
+                match self.next().as_deref() {
+                    Some(")") => Ok(filter),
+                    _ => Err(anyhow!("Missing ')' in filter")),
+                }
+            }
+            _ => self.parse_primitive(),
+        }
+    }
+
+    fn parse_primitive(&mut self) -> Result<Filter> {
+        let token = self.next().context("Incomplete filter")?.to_lowercase();
+        let mut value = |name: &str| {
+            self.next()
+                .with_context(|| format!("Missing value for '{}' in filter", name))
+        };
+        match token.as_str() {
+            "host" => Ok(Filter::Host(value("host")?)),
+            "src" => Ok(Filter::Src(value("src")?)),
+            "dst" => Ok(Filter::Dst(value("dst")?)),
+            "port" => {
+                let port = value("port")?;
+                Ok(Filter::Port(port.parse().with_context(|| {
+                    format!("Invalid port '{}' in filter", port)
+                })?))
+            }
+            "malformed" => Ok(Filter::Malformed),
+            "and" | "or" | "&&" | "||" | ")" => Err(anyhow!("Unexpected '{}' in filter", token)),
+            _ if token.chars().all(|c| c.is_ascii_alphanumeric()) => Ok(Filter::Protocol(token)),
+            _ => Err(anyhow!("Unknown filter '{}'", token)),
+        }
+    }
+}
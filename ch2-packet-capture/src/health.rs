@@ -0,0 +1,1035 @@
+use crate::decoder::Header;
+use crate::output::Report;
+use crate::record::PacketRecord;
+use crate::stats::{endpoint, FlowKey};
+use anyhow::Result;
+use pnet::packet::tcp::TcpFlags;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    io::Write,
+};
+
+/// RTTが分からないときに、穴を埋めたセグメントを順序の入れ替わりとみなす時間（秒）
+const DEFAULT_REORDER_WINDOW: f64 = 0.003;
+/// 1方向あたりに覚えておくACK待ちのセグメント数
+const MAX_UNACKED: usize = 1024;
+/// 1方向あたりに覚えておくシーケンス番号の抜けの数
+const MAX_HOLES: usize = 64;
+/// 高速再送とみなす重複ACKの数
+const FAST_RETRANSMIT_DUP_ACKS: u32 = 2;
+/// この時間（秒）パケットのないコネクションは解析を終える
+const IDLE_TIMEOUT: f64 = 300.0;
+/// 解析中のコネクションからアイドルのものを探す間隔（秒）
+const EXPIRE_INTERVAL: f64 = 10.0;
+/// 解析中のコネクション数の上限。超えたら最後にパケットを見たのが古いものから解析を終える
+const MAX_CONNECTIONS: usize = 100_000;
+/// 解析を終えたコネクションを個別に覚えておく数。超えたら異常の少ないものは全体の集計にだけ残す
+const MAX_CLOSED: usize = 1000;
+
+/**
+ * シーケンス番号の比較（a < b）
+ * 32ビットで一周するので差の符号で判断する
+ */
+fn before(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/**
+ * TCPセグメントの解析に使う値
+ */
+struct Segment {
+    timestamp: f64,
+    seq: u32,
+    ack: u32,
+    flags: u16,
+    window: u16,
+    /// ペイロードのバイト数。スナップ長で切り詰められていてもIPヘッダの長さから求める
+    len: u32,
+}
+
+impl Segment {
+    fn from_record(record: &PacketRecord) -> Option<Self> {
+        let (seq, ack, flags, window) = record.decoded.find(|header| match header {
+            Header::Tcp {
+                seq,
+                ack,
+                flags,
+                window,
+                ..
+            } => Some((*seq, *ack, *flags, *window)),
+            _ => None,
+        })?;
+        let ip_payload = record.decoded.find(|header| match header {
+            Header::Ipv4 {
+                header_length,
+                total_length,
+                ..
+            } => Some(total_length.saturating_sub(*header_length as u16) as usize),
+            Header::Ipv6 { payload_length, .. } => Some(*payload_length as usize),
+            _ => None,
+        });
+        let tcp_header = record
+            .decoded
+            .layers
+            .iter()
+            .find(|layer| matches!(layer.header, Header::Tcp { .. }))
+            .map(|layer| layer.range.len())
+            .unwrap_or_default();
+        let len = match ip_payload {
+            Some(ip_payload) => ip_payload.saturating_sub(tcp_header),
+            None => record.payload_len,
+        };
+        Some(Self {
+            timestamp: record.timestamp,
+            seq,
+            ack,
+            flags,
+            window,
+            len: len as u32,
+        })
+    }
+
+    fn has(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+
+    /**
+     * シーケンス番号を消費する長さ（SYNとFINは1つずつ数える）
+     */
+    fn seq_len(&self) -> u32 {
+        self.len + self.has(TcpFlags::SYN) as u32 + self.has(TcpFlags::FIN) as u32
+    }
+}
+
+/**
+ * ACK待ちのセグメント
+ */
+struct Unacked {
+    end: u32,
+    timestamp: f64,
+    /// 再送したセグメントはどちらへのACKか区別できないのでRTTの計算に使わない（Karnのアルゴリズム）
+    retransmitted: bool,
+}
+
+/**
+ * 1方向の送信側の状態
+ */
+#[derive(Default)]
+struct Half {
+    /// 次に来るはずのシーケンス番号（これまでに見た最大の終端）
+    next_seq: Option<u32>,
+    /// 最後に送ったACK番号とウィンドウ
+    last_ack: Option<(u32, u16)>,
+    /// 同じACK番号が続いた回数
+    dup_acks: u32,
+    zero_window: bool,
+    unacked: VecDeque<Unacked>,
+    /// 取りこぼしたか順序が入れ替わったシーケンス番号の範囲と、気づいた時刻
+    holes: Vec<(u32, u32, f64)>,
+}
+
+/**
+ * 1つのTCPコネクションの健全性
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    pub packets: u64,
+    /// SYNからSYN/ACKへのACKまでの時間（秒）
+    pub handshake_rtt: Option<f64>,
+    /// データとそのACKの組から求めたRTT（秒）
+    pub rtt_samples: RttStats,
+    pub retransmissions: u64,
+    pub fast_retransmissions: u64,
+    pub duplicate_acks: u64,
+    pub out_of_order: u64,
+    /// ウィンドウが0になった回数
+    pub zero_windows: u64,
+    pub resets: u64,
+    pub first_seen: f64,
+    pub last_seen: f64,
+}
+
+impl Health {
+    /**
+     * 異常の合計。表示の順番に使う
+     */
+    pub fn problems(&self) -> u64 {
+        self.retransmissions
+            + self.duplicate_acks
+            + self.out_of_order
+            + self.zero_windows
+            + self.resets
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.last_seen - self.first_seen
+    }
+
+    /**
+     * RTTの最小・平均・最大
+     */
+    pub fn rtt(&self) -> Option<(f64, f64, f64)> {
+        self.rtt_samples.summary()
+    }
+}
+
+/**
+ * RTTのサンプルの集計
+ * 長いコネクションではサンプルが増え続けるので、最小・最大・合計だけを持つ
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RttStats {
+    pub count: u64,
+    min: f64,
+    max: f64,
+    sum: f64,
+}
+
+impl RttStats {
+    pub fn add(&mut self, rtt: f64) {
+        self.merge(&RttStats {
+            count: 1,
+            min: rtt,
+            max: rtt,
+            sum: rtt,
+        });
+    }
+
+    fn merge(&mut self, other: &RttStats) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = *other;
+            return;
+        }
+        self.count += other.count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+    }
+
+    /**
+     * 最小・平均・最大
+     */
+    pub fn summary(&self) -> Option<(f64, f64, f64)> {
+        (self.count > 0).then(|| (self.min, self.sum / self.count as f64, self.max))
+    }
+}
+
+/**
+ * コネクション全体の集計
+ */
+#[derive(Debug, Clone, Default)]
+struct Totals {
+    connections: u64,
+    packets: u64,
+    handshakes: RttStats,
+    rtt_samples: RttStats,
+    retransmissions: u64,
+    fast_retransmissions: u64,
+    duplicate_acks: u64,
+    out_of_order: u64,
+    zero_windows: u64,
+    resets: u64,
+    /// RSTのあったコネクション数
+    reset_connections: u64,
+}
+
+impl Totals {
+    fn add(&mut self, health: &Health) {
+        self.connections += 1;
+        self.packets += health.packets;
+        if let Some(rtt) = health.handshake_rtt {
+            self.handshakes.add(rtt);
+        }
+        self.rtt_samples.merge(&health.rtt_samples);
+        self.retransmissions += health.retransmissions;
+        self.fast_retransmissions += health.fast_retransmissions;
+        self.duplicate_acks += health.duplicate_acks;
+        self.out_of_order += health.out_of_order;
+        self.zero_windows += health.zero_windows;
+        self.resets += health.resets;
+        self.reset_connections += (health.resets > 0) as u64;
+    }
+}
+
+/**
+ * コネクションの解析状態
+ */
+#[derive(Default)]
+struct Connection {
+    health: Health,
+    /// a→b、b→aの送信側
+    halves: [Half; 2],
+    /// SYNを送った側とその時刻
+    syn: Option<(usize, f64)>,
+    /// SYN/ACKを受け取った時刻
+    syn_ack: Option<f64>,
+    /// FINかRSTを見たか
+    closed: bool,
+}
+
+impl Connection {
+    fn add(&mut self, forward: bool, segment: &Segment) {
+        let (me, peer) = if forward { (0, 1) } else { (1, 0) };
+        self.health.packets += 1;
+        self.health.last_seen = self.health.last_seen.max(segment.timestamp);
+
+        if segment.has(TcpFlags::RST) {
+            self.health.resets += 1;
+            self.closed = true;
+            return;
+        }
+        if segment.has(TcpFlags::FIN) {
+            self.closed = true;
+        }
+        self.handshake(me, segment);
+
+        // 相手のデータへのACKを処理する
+        if segment.has(TcpFlags::ACK) {
+            self.acknowledge(me, peer, segment);
+        }
+        self.send(me, segment);
+    }
+
+    /**
+     * 3ウェイハンドシェイクの時刻を記録する
+     */
+    fn handshake(&mut self, me: usize, segment: &Segment) {
+        let syn = segment.has(TcpFlags::SYN);
+        let ack = segment.has(TcpFlags::ACK);
+        match (syn, ack, self.syn, self.syn_ack) {
+            (true, false, None, _) => self.syn = Some((me, segment.timestamp)),
+            // SYNを再送した場合はどちらへの応答か分からないので、最後のSYNから測る
+            (true, false, Some((client, _)), None) if client == me => {
+                self.syn = Some((me, segment.timestamp))
+            }
+            (true, true, Some((client, _)), None) if client != me => {
+                self.syn_ack = Some(segment.timestamp)
+            }
+            (false, true, Some((client, syn_time)), Some(_))
+                if client == me && self.health.handshake_rtt.is_none() =>
+            {
+                self.health.handshake_rtt = Some(segment.timestamp - syn_time);
+            }
+            _ => {}
+        }
+    }
+
+    /**
+     * ACKでACK待ちのセグメントを取り除き、RTTと重複ACKを数える
+     */
+    fn acknowledge(&mut self, me: usize, peer: usize, segment: &Segment) {
+        // 相手に送信中のデータがある場合だけ重複ACKとみなす
+        let peer_sending = !self.halves[peer].unacked.is_empty();
+        let health = &mut self.health;
+        let half = &mut self.halves[me];
+        let pure_ack =
+            segment.len == 0 && !segment.has(TcpFlags::SYN) && !segment.has(TcpFlags::FIN);
+        match half.last_ack {
+            Some((ack, window)) if ack == segment.ack && window == segment.window && pure_ack => {
+                if peer_sending {
+                    half.dup_acks += 1;
+                    health.duplicate_acks += 1;
+                }
+            }
+            Some((ack, _)) if ack == segment.ack => {}
+            _ => half.dup_acks = 0,
+        }
+        half.last_ack = Some((segment.ack, segment.window));
+
+        let was_zero = half.zero_window;
+        half.zero_window = segment.window == 0 && !segment.has(TcpFlags::SYN);
+        if half.zero_window && !was_zero {
+            health.zero_windows += 1;
+        }
+
+        let unacked = &mut self.halves[peer].unacked;
+        let mut newest = None;
+        while let Some(front) = unacked.front() {
+            if before(segment.ack, front.end) {
+                break;
+            }
+            newest = unacked.pop_front();
+        }
+        if let Some(acked) = newest {
+            if !acked.retransmitted {
+                health.rtt_samples.add(segment.timestamp - acked.timestamp);
+            }
+        }
+    }
+
+    /**
+     * 送信したセグメントが新しいデータか、再送か、順序の入れ替わりかを判断する
+     */
+    fn send(&mut self, me: usize, segment: &Segment) {
+        let seq_len = segment.seq_len();
+        let end = segment.seq.wrapping_add(seq_len);
+        // 再送か入れ替わりの判断に使うRTTの目安
+        let reorder_window = self
+            .health
+            .handshake_rtt
+            .or_else(|| self.health.rtt().map(|(min, _, _)| min))
+            .unwrap_or(DEFAULT_REORDER_WINDOW);
+        let dup_acks = self.halves[1 - me].dup_acks;
+        let health = &mut self.health;
+        let half = &mut self.halves[me];
+        let next_seq = match half.next_seq {
+            Some(next_seq) => next_seq,
+            None => {
+                half.next_seq = Some(end);
+                if seq_len > 0 {
+                    push_unacked(half, end, segment.timestamp, false);
+                }
+                return;
+            }
+        };
+        if seq_len == 0 {
+            return;
+        }
+        // キープアライブは1つ前のシーケンス番号で0か1バイト送る
+        if segment.len <= 1
+            && segment.seq == next_seq.wrapping_sub(1)
+            && !segment.has(TcpFlags::SYN | TcpFlags::FIN)
+        {
+            return;
+        }
+
+        if !before(segment.seq, next_seq) {
+            // 途中が抜けていたら、後から埋まるかもしれないので覚えておく
+            if before(next_seq, segment.seq) && half.holes.len() < MAX_HOLES {
+                half.holes.push((next_seq, segment.seq, segment.timestamp));
+            }
+            half.next_seq = Some(end);
+            push_unacked(half, end, segment.timestamp, false);
+            return;
+        }
+
+        // 既に見た範囲より前のセグメント
+        let hole = half.holes.iter().position(|(start, hole_end, _)| {
+            !before(segment.seq, *start) && !before(*hole_end, end)
+        });
+        match hole {
+            Some(i) if segment.timestamp - half.holes[i].2 < reorder_window => {
+                health.out_of_order += 1;
+                fill_hole(&mut half.holes, i, segment.seq, end);
+            }
+            hole => {
+                health.retransmissions += 1;
+                if dup_acks >= FAST_RETRANSMIT_DUP_ACKS {
+                    health.fast_retransmissions += 1;
+                }
+                if let Some(i) = hole {
+                    fill_hole(&mut half.holes, i, segment.seq, end);
+                }
+                if !before(next_seq, end) {
+                    for unacked in half.unacked.iter_mut() {
+                        if before(segment.seq, unacked.end) && !before(end, unacked.end) {
+                            unacked.retransmitted = true;
+                        }
+                    }
+                }
+            }
+        }
+        if before(next_seq, end) {
+            half.next_seq = Some(end);
+            push_unacked(half, end, segment.timestamp, true);
+        }
+    }
+}
+
+fn push_unacked(half: &mut Half, end: u32, timestamp: f64, retransmitted: bool) {
+    if half.unacked.len() >= MAX_UNACKED {
+        half.unacked.pop_front();
+    }
+    half.unacked.push_back(Unacked {
+        end,
+        timestamp,
+        retransmitted,
+    });
+}
+
+/**
+ * 抜けていた範囲からセグメントの分を取り除く
+ */
+fn fill_hole(holes: &mut Vec<(u32, u32, f64)>, i: usize, start: u32, end: u32) {
+    let (hole_start, hole_end, timestamp) = holes.remove(i);
+    if before(hole_start, start) {
+        holes.push((hole_start, start, timestamp));
+    }
+    if before(end, hole_end) {
+        holes.push((end, hole_end, timestamp));
+    }
+}
+
+/**
+ * TCPコネクションごとの健全性の集計
+ */
+#[derive(Default)]
+pub struct HealthTable {
+    connections: HashMap<FlowKey, Connection>,
+    /// 解析を終えたコネクション
+    /// 同じ4タプルで新しいコネクションが始まったものと、しばらくパケットのないもの
+    closed: Vec<(FlowKey, Health)>,
+    /// closedから溢れたコネクションの集計
+    evicted: Totals,
+    /// 次にアイドルのコネクションを探すタイムスタンプ
+    next_expire: f64,
+}
+
+impl HealthTable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /**
+     * コネクションの解析を終え、closedに移す
+     */
+    fn close(&mut self, key: &FlowKey) {
+        if let Some(connection) = self.connections.remove(key) {
+            self.closed.push((key.clone(), connection.health));
+        }
+        if self.closed.len() > MAX_CLOSED {
+            // 1件ずつ並べ替えないよう、1割まとめて全体の集計に移す
+            self.closed
+                .sort_by(|x, y| worse((&x.0, &x.1), (&y.0, &y.1)));
+            for (_, health) in self.closed.drain(MAX_CLOSED - MAX_CLOSED / 10..) {
+                self.evicted.add(&health);
+            }
+        }
+    }
+
+    /**
+     * しばらくパケットのないコネクションと、上限を超えた分の古いコネクションの解析を終える
+     */
+    fn expire(&mut self, now: f64) {
+        let mut idle: Vec<(f64, FlowKey)> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| now - connection.health.last_seen >= IDLE_TIMEOUT)
+            .map(|(key, connection)| (connection.health.last_seen, key.clone()))
+            .collect();
+        let excess = (self.connections.len() - idle.len()).saturating_sub(MAX_CONNECTIONS);
+        if excess > 0 {
+            let mut active: Vec<(f64, FlowKey)> = self
+                .connections
+                .iter()
+                .filter(|(_, connection)| now - connection.health.last_seen < IDLE_TIMEOUT)
+                .map(|(key, connection)| (connection.health.last_seen, key.clone()))
+                .collect();
+            // 上限を超えるたびに探さないよう、1割多めに終える
+            let cut = (excess + MAX_CONNECTIONS / 10).min(active.len());
+            active.select_nth_unstable_by(cut - 1, |x, y| x.0.total_cmp(&y.0));
+            idle.extend(active.drain(..cut));
+        }
+        for (_, key) in idle {
+            self.close(&key);
+        }
+    }
+
+    /**
+     * 入れ替えた古いものを含む、すべてのコネクション
+     */
+    fn healths(&self) -> impl Iterator<Item = (&FlowKey, &Health)> {
+        self.connections
+            .iter()
+            .map(|(key, connection)| (key, &connection.health))
+            .chain(self.closed.iter().map(|(key, health)| (key, health)))
+    }
+
+    /**
+     * 異常の多い順にコネクションを返す
+     */
+    pub fn worst(&self, top: usize) -> Vec<(&FlowKey, &Health)> {
+        let mut connections: Vec<_> = self.healths().collect();
+        connections.sort_by(|x, y| worse(*x, *y));
+        connections.truncate(top);
+        connections
+    }
+}
+
+/**
+ * 異常の多い順、パケット数の多い順に並べるための比較
+ */
+fn worse(x: (&FlowKey, &Health), y: (&FlowKey, &Health)) -> Ordering {
+    y.1.problems()
+        .cmp(&x.1.problems())
+        .then(y.1.packets.cmp(&x.1.packets))
+        .then(x.0.cmp(y.0))
+}
+
+impl Report for HealthTable {
+    fn add(&mut self, record: &PacketRecord) {
+        let segment = match Segment::from_record(record) {
+            Some(segment) => segment,
+            None => return,
+        };
+        let key = FlowKey::from_record(record);
+        let forward = key.a == (record.src_ip.clone(), record.src_port);
+        // 閉じたコネクションと同じ4タプルでSYNが来たら、ポートを再利用した新しいコネクションとみなす
+        let reused = segment.has(TcpFlags::SYN)
+            && !segment.has(TcpFlags::ACK)
+            && self.connections.get(&key).is_some_and(|c| c.closed);
+        if reused {
+            self.close(&key);
+        }
+        let connection = self.connections.entry(key).or_insert_with(|| Connection {
+            health: Health {
+                first_seen: record.timestamp,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        connection.add(forward, &segment);
+        if record.timestamp >= self.next_expire || self.connections.len() > MAX_CONNECTIONS {
+            self.expire(record.timestamp);
+            self.next_expire = record.timestamp + EXPIRE_INTERVAL;
+        }
+    }
+
+    /**
+     * コネクションごとと全体の集計結果を表示する
+     * 個別に覚えておけなかったコネクションは全体の集計にだけ含める
+     */
+    fn report<W: Write>(&self, w: &mut W, top: usize) -> Result<()> {
+        let mut totals = self.evicted.clone();
+        for (_, health) in self.healths() {
+            totals.add(health);
+        }
+        writeln!(w, "=== TCP connections ({} total) ===", totals.connections)?;
+        writeln!(
+            w,
+            "{:<24}     {:<24} {:>7} {:>10} {:>9} {:>23} {:>6} {:>6} {:>6} {:>6} {:>5}",
+            "Endpoint A",
+            "Endpoint B",
+            "Packets",
+            "Duration",
+            "iRTT(ms)",
+            "RTT min/avg/max(ms)",
+            "Retx",
+            "DupAck",
+            "OOO",
+            "ZeroW",
+            "RST"
+        )?;
+        for (key, health) in self.worst(top) {
+            writeln!(
+                w,
+                "{:<24} <-> {:<24} {:>7} {:>9.3}s {:>9} {:>23} {:>6} {:>6} {:>6} {:>6} {:>5}",
+                endpoint(&key.a),
+                endpoint(&key.b),
+                health.packets,
+                health.duration(),
+                health
+                    .handshake_rtt
+                    .map(|rtt| format!("{:.3}", rtt * 1e3))
+                    .unwrap_or_else(|| "-".to_string()),
+                rtt_string(health.rtt()),
+                retransmission_string(health),
+                health.duplicate_acks,
+                health.out_of_order,
+                health.zero_windows,
+                health.resets,
+            )?;
+        }
+        writeln!(w)?;
+
+        writeln!(w, "=== TCP summary ===")?;
+        writeln!(w, "{} packets", totals.packets)?;
+        writeln!(
+            w,
+            "{} handshakes, RTT min/avg/max {} ms",
+            totals.handshakes.count,
+            rtt_string(totals.handshakes.summary())
+        )?;
+        writeln!(
+            w,
+            "{} RTT samples, RTT min/avg/max {} ms",
+            totals.rtt_samples.count,
+            rtt_string(totals.rtt_samples.summary())
+        )?;
+        writeln!(
+            w,
+            "{} retransmissions ({} fast)",
+            totals.retransmissions, totals.fast_retransmissions
+        )?;
+        writeln!(w, "{} duplicate ACKs", totals.duplicate_acks)?;
+        writeln!(w, "{} out-of-order segments", totals.out_of_order)?;
+        writeln!(w, "{} zero window events", totals.zero_windows)?;
+        writeln!(
+            w,
+            "{} resets in {} connections",
+            totals.resets, totals.reset_connections
+        )?;
+        writeln!(w)?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+fn rtt_string(rtt: Option<(f64, f64, f64)>) -> String {
+    match rtt {
+        Some((min, avg, max)) => format!("{:.3}/{:.3}/{:.3}", min * 1e3, avg * 1e3, max * 1e3),
+        None => "-".to_string(),
+    }
+}
+
+fn retransmission_string(health: &Health) -> String {
+    if health.fast_retransmissions > 0 {
+        format!(
+            "{}({}f)",
+            health.retransmissions, health.fast_retransmissions
+        )
+    } else {
+        health.retransmissions.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, Tcp};
+    use pnet::packet::tcp::TcpFlags::{ACK, FIN, PSH, RST, SYN};
+
+    const CLIENT: &str = "10.0.0.2:50000";
+    const SERVER: &str = "10.0.0.1:80";
+    const DATA: &[u8] = &[0x55; 100];
+    /// ハンドシェイク後のクライアントとサーバの次のシーケンス番号
+    const C: u32 = 101;
+    const S: u32 = 501;
+
+    fn client(flags: u16, seq: u32, ack: u32) -> Tcp {
+        Tcp::new(CLIENT, SERVER, flags).seq(seq).ack(ack)
+    }
+
+    fn server(flags: u16, seq: u32, ack: u32) -> Tcp {
+        Tcp::new(SERVER, CLIENT, flags).seq(seq).ack(ack)
+    }
+
+    /**
+     * t秒から始まる、SYNからACKまで12msの3ウェイハンドシェイク
+     */
+    fn handshake(t: f64) -> Vec<(f64, Tcp)> {
+        vec![
+            (t, client(SYN, C - 1, 0)),
+            (t + 0.010, server(SYN | ACK, S - 1, C)),
+            (t + 0.012, client(ACK, C, S)),
+        ]
+    }
+
+    /**
+     * セグメントの列をpcapにし、読み込んだレコードを集計する
+     */
+    fn table(segments: Vec<(f64, Tcp)>) -> HealthTable {
+        let frames: Vec<_> = segments
+            .into_iter()
+            .map(|(t, segment)| (t, segment.frame()))
+            .collect();
+        let mut table = HealthTable::new();
+        for record in testutil::records(&testutil::pcap(&frames)) {
+            table.add(&record);
+        }
+        table
+    }
+
+    fn health(table: &HealthTable) -> Health {
+        let connections = table.worst(10);
+        assert_eq!(connections.len(), 1);
+        connections[0].1.clone()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-6,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn assert_samples(health: &Health, expected: &[f64]) {
+        assert_eq!(
+            health.rtt_samples.count,
+            expected.len() as u64,
+            "{:?}",
+            health
+        );
+        let (min, avg, max) = health.rtt().unwrap();
+        assert_close(min, expected.iter().copied().fold(f64::MAX, f64::min));
+        assert_close(avg, expected.iter().sum::<f64>() / expected.len() as f64);
+        assert_close(max, expected.iter().copied().fold(f64::MIN, f64::max));
+    }
+
+    #[test]
+    fn handshake_and_data_rtt() {
+        let mut segments = handshake(0.0);
+        segments.extend([
+            (0.020, client(PSH | ACK, C, S).payload(DATA)),
+            (0.050, server(ACK, S, C + 100)),
+        ]);
+        let health = health(&table(segments));
+        assert_eq!(health.packets, 5);
+        assert_close(health.handshake_rtt.unwrap(), 0.012);
+        // SYN→SYN/ACK、SYN/ACK→ACK、データ→ACK
+        assert_samples(&health, &[0.010, 0.002, 0.030]);
+        assert_eq!(health.problems(), 0);
+        assert_close(health.duration(), 0.050);
+    }
+
+    #[test]
+    fn retransmission_is_not_used_for_rtt() {
+        let mut segments = handshake(0.0);
+        segments.extend([
+            (1.0, client(PSH | ACK, C, S).payload(DATA)),
+            (1.5, client(PSH | ACK, C, S).payload(DATA)),
+            (1.6, server(ACK, S, C + 100)),
+        ]);
+        let health = health(&table(segments));
+        assert_eq!(health.retransmissions, 1);
+        assert_eq!(health.fast_retransmissions, 0);
+        assert_eq!(health.out_of_order, 0);
+        // 再送したデータへのACKはRTTに数えない
+        assert_samples(&health, &[0.010, 0.002]);
+    }
+
+    #[test]
+    fn fast_retransmission_after_duplicate_acks() {
+        let mut segments = handshake(0.0);
+        segments.extend([
+            (1.000, client(PSH | ACK, C, S).payload(DATA)),
+            (1.001, client(PSH | ACK, C + 100, S).payload(DATA)),
+            (1.002, client(PSH | ACK, C + 200, S).payload(DATA)),
+            (1.030, server(ACK, S, C + 100)),
+            (1.031, server(ACK, S, C + 100)),
+            (1.032, server(ACK, S, C + 100)),
+            (1.033, client(PSH | ACK, C + 100, S).payload(DATA)),
+            (1.060, server(ACK, S, C + 300)),
+        ]);
+        let table = table(segments);
+        let health = health(&table);
+        assert_eq!(health.duplicate_acks, 2);
+        assert_eq!(health.retransmissions, 1);
+        assert_eq!(health.fast_retransmissions, 1);
+        assert_eq!(health.out_of_order, 0);
+
+        let mut out = vec![];
+        table.report(&mut out, 10).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\n1 retransmissions (1 fast)\n2 duplicate ACKs\n"));
+        assert!(out.contains("1(1f)"));
+    }
+
+    #[test]
+    fn out_of_order_within_the_rtt() {
+        let mut segments = handshake(0.0);
+        segments.extend([
+            (1.000, client(PSH | ACK, C + 100, S).payload(DATA)),
+            (1.001, client(PSH | ACK, C, S).payload(DATA)),
+            (1.030, server(ACK, S, C + 200)),
+        ]);
+        let health = health(&table(segments));
+        assert_eq!(health.out_of_order, 1);
+        assert_eq!(health.retransmissions, 0);
+    }
+
+    #[test]
+    fn late_fill_is_a_retransmission() {
+        let mut segments = handshake(0.0);
+        segments.extend([
+            (1.000, client(PSH | ACK, C + 100, S).payload(DATA)),
+            // ハンドシェイクのRTT（12ms）より後に抜けが埋まった
+            (1.100, client(PSH | ACK, C, S).payload(DATA)),
+        ]);
+        let health = health(&table(segments));
+        assert_eq!(health.out_of_order, 0);
+        assert_eq!(health.retransmissions, 1);
+    }
+
+    #[test]
+    fn zero_window_events() {
+        let mut segments = handshake(0.0);
+        segments.extend([
+            (1.0, client(PSH | ACK, C, S).payload(DATA)),
+            (1.1, server(ACK, S, C + 100).window(0)),
+            (1.2, server(ACK, S, C + 100).window(0)),
+            (1.3, server(ACK, S, C + 100).window(4096)),
+            (1.4, server(ACK, S, C + 100).window(0)),
+        ]);
+        let health = health(&table(segments));
+        assert_eq!(health.zero_windows, 2);
+        // ウィンドウが変わったACKは重複ACKではない
+        assert_eq!(health.duplicate_acks, 0);
+    }
+
+    #[test]
+    fn resets_are_counted() {
+        let mut segments = handshake(0.0);
+        segments.push((1.0, server(RST | ACK, S, C)));
+        let table = table(segments);
+        let health = health(&table);
+        assert_eq!(health.resets, 1);
+
+        let mut out = vec![];
+        table.report(&mut out, 10).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\n1 resets in 1 connections\n"));
+    }
+
+    #[test]
+    fn syn_after_fin_starts_a_new_connection() {
+        let mut segments = handshake(0.0);
+        segments.extend([
+            (1.0, client(FIN | ACK, C, S)),
+            (1.1, server(FIN | ACK, S, C + 1)),
+            (1.2, client(ACK, C + 1, S + 1)),
+        ]);
+        // 同じ4タプルを再利用したコネクション。シーケンス番号は前より小さくてもよい
+        segments.extend([
+            (5.0, client(SYN, 9, 0)),
+            (5.1, server(SYN | ACK, 99, 10)),
+            (5.3, client(ACK, 10, 100)),
+        ]);
+        let table = table(segments);
+        let connections = table.worst(10);
+        assert_eq!(connections.len(), 2);
+        let mut healths: Vec<_> = connections.into_iter().map(|(_, h)| h).collect();
+        healths.sort_by(|x, y| x.first_seen.total_cmp(&y.first_seen));
+        assert_eq!(healths[0].packets, 6);
+        assert_close(healths[0].handshake_rtt.unwrap(), 0.012);
+        assert_eq!(healths[1].packets, 3);
+        assert_close(healths[1].first_seen, 5.0);
+        assert_close(healths[1].handshake_rtt.unwrap(), 0.3);
+        assert!(healths.iter().all(|h| h.problems() == 0));
+
+        let mut out = vec![];
+        table.report(&mut out, 10).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("=== TCP connections (2 total) ==="));
+        assert!(out.contains("\n2 handshakes, "));
+    }
+
+    #[test]
+    fn syn_after_rst_starts_a_new_connection() {
+        let mut segments = handshake(0.0);
+        segments.push((1.0, server(RST | ACK, S, C)));
+        segments.extend(handshake(2.0));
+        let table = table(segments);
+        let connections = table.worst(10);
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0].1.resets, 1);
+        assert_eq!(connections[1].1.resets, 0);
+        assert_eq!(connections[1].1.retransmissions, 0);
+        assert_close(connections[1].1.first_seen, 2.0);
+    }
+
+    #[test]
+    fn syn_retransmission_keeps_the_connection() {
+        let segments = vec![
+            (0.0, client(SYN, C - 1, 0)),
+            (1.0, client(SYN, C - 1, 0)),
+            (1.010, server(SYN | ACK, S - 1, C)),
+            (1.012, client(ACK, C, S)),
+        ];
+        let health = health(&table(segments));
+        assert_eq!(health.packets, 4);
+        assert_eq!(health.retransmissions, 1);
+        // 最後のSYNから測る
+        assert_close(health.handshake_rtt.unwrap(), 0.012);
+    }
+
+    fn report(table: &HealthTable) -> String {
+        let mut out = vec![];
+        table.report(&mut out, 10).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn idle_connections_are_closed() {
+        let mut segments = handshake(0.0);
+        segments.push((1.0, client(PSH | ACK, C, S).payload(DATA)));
+        segments.push((1.1, server(ACK, S, C + 100)));
+        let later = Tcp::new("10.0.0.3:40000", SERVER, SYN);
+        segments.push((1.1 + IDLE_TIMEOUT, later.clone()));
+        let table = table(segments);
+        assert_eq!(table.connections.len(), 1);
+        assert_eq!(table.closed.len(), 1);
+        assert_eq!(table.closed[0].1.packets, 5);
+        assert_eq!(table.worst(10).len(), 2);
+        assert!(report(&table).starts_with("=== TCP connections (2 total) ==="));
+    }
+
+    #[test]
+    fn closed_connections_are_bounded() {
+        // 1つだけRSTを受けたコネクションを含む、ポートを変えたSYNだけのコネクション
+        let mut segments: Vec<(f64, Tcp)> = (0..=MAX_CLOSED as u16)
+            .map(|i| {
+                let client = format!("10.0.0.2:{}", 10000 + i);
+                (i as f64 * 0.001, Tcp::new(&client, SERVER, SYN).seq(1))
+            })
+            .collect();
+        segments.push((0.5, Tcp::new(SERVER, "10.0.0.2:10500", RST | ACK).ack(2)));
+        segments.push((IDLE_TIMEOUT + 1.0, client(SYN, C - 1, 0)));
+        let table = table(segments);
+        assert_eq!(table.connections.len(), 1);
+        assert_eq!(table.closed.len(), MAX_CLOSED - MAX_CLOSED / 10);
+        assert_eq!(table.evicted.connections, (MAX_CLOSED / 10 + 1) as u64);
+        // 異常のあるコネクションは個別に残る
+        assert_eq!(table.worst(1)[0].1.resets, 1);
+
+        let out = report(&table);
+        assert!(out.starts_with(&format!(
+            "=== TCP connections ({} total) ===",
+            MAX_CLOSED + 2
+        )));
+        assert!(out.contains(&format!("\n{} packets\n", MAX_CLOSED + 3)));
+        assert!(out.contains("\n1 resets in 1 connections\n"));
+    }
+
+    #[test]
+    fn active_connections_are_bounded() {
+        let syn =
+            testutil::records(&testutil::pcap(&[(0.0, client(SYN, C - 1, 0).frame())])).remove(0);
+        let mut table = HealthTable::new();
+        for i in 0..=MAX_CONNECTIONS {
+            let mut record = syn.clone();
+            record.src_ip = format!("10.{}.{}.{}", i >> 16, (i >> 8) & 0xff, i & 0xff);
+            record.timestamp = i as f64 * 1e-6;
+            table.add(&record);
+        }
+        assert_eq!(
+            table.connections.len(),
+            MAX_CONNECTIONS - MAX_CONNECTIONS / 10
+        );
+        // 最後にパケットを見たのが古いものから終える
+        let oldest = table
+            .connections
+            .values()
+            .map(|c| c.health.last_seen)
+            .fold(f64::MAX, f64::min);
+        assert_close(oldest, (MAX_CONNECTIONS / 10 + 1) as f64 * 1e-6);
+        assert!(report(&table).starts_with(&format!(
+            "=== TCP connections ({} total) ===",
+            MAX_CONNECTIONS + 1
+        )));
+    }
+
+    #[test]
+    fn rtt_stats_keep_min_avg_max() {
+        let mut stats = RttStats::default();
+        assert_eq!(stats.summary(), None);
+        for rtt in [0.03, 0.01, 0.02] {
+            stats.add(rtt);
+        }
+        let mut other = RttStats::default();
+        other.add(0.1);
+        stats.merge(&other);
+        stats.merge(&RttStats::default());
+        assert_eq!(stats.count, 4);
+        let (min, avg, max) = stats.summary().unwrap();
+        assert_close(min, 0.01);
+        assert_close(avg, 0.04);
+        assert_close(max, 0.1);
+    }
+}
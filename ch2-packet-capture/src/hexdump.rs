@@ -0,0 +1,348 @@
+use std::{fmt::Write, ops::Range};
+
+/**
+ * フレーム中のあるプロトコル層が占めるバイト範囲
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layer {
+    pub name: &'static str,
+    pub range: Range<usize>,
+}
+
+/// 層ごとの色（ANSIエスケープシーケンスの前景色）
+const COLORS: [&str; 5] = ["\x1b[34m", "\x1b[32m", "\x1b[33m", "\x1b[35m", "\x1b[36m"];
+const RESET: &str = "\x1b[0m";
+
+/**
+ * 16進数とテキストを並べて表示するダンプの設定
+ */
+#[derive(Debug, Clone)]
+pub struct HexDump {
+    /// 1行に表示するバイト数
+    pub width: usize,
+    /// 何バイトごとに区切りの空白を入れるか（0なら区切らない）
+    pub group: usize,
+    /// UTF-8の複数バイト文字を文字として表示するか
+    pub utf8: bool,
+    /// 層ごとに色分けするか
+    pub color: bool,
+}
+
+impl Default for HexDump {
+    fn default() -> Self {
+        Self {
+            width: 16,
+            group: 8,
+            utf8: true,
+            color: false,
+        }
+    }
+}
+
+impl HexDump {
+    /**
+     * dataをダンプした文字列を返す
+     * layersを渡すと、各層のバイトを色分けし、凡例を付ける
+     */
+    pub fn render(&self, data: &[u8], layers: &[Layer]) -> String {
+        let width = self.width.max(1);
+        let text = self.text_cells(data);
+        let mut out = String::new();
+
+        if self.color && !layers.is_empty() {
+            let legend: Vec<_> = layers
+                .iter()
+                .enumerate()
+                .map(|(i, l)| format!("{}{}{}", COLORS[i % COLORS.len()], l.name, RESET))
+                .collect();
+            let _ = writeln!(out, "{}", legend.join(" "));
+        }
+
+        for (row, chunk) in data.chunks(width).enumerate() {
+            let start = row * width;
+            let _ = write!(out, "{:08x}  ", start);
+
+            // 16進数部
+            let mut painter = Painter::default();
+            for i in 0..width {
+                if i > 0 && self.group > 0 && i % self.group == 0 {
+                    out.push(' ');
+                }
+                match chunk.get(i) {
+                    Some(b) => {
+                        painter.paint(&mut out, self.color_of(start + i, layers));
+                        let _ = write!(out, "{:02x} ", b);
+                    }
+                    None => {
+                        painter.reset(&mut out);
+                        out.push_str("   ");
+                    }
+                }
+            }
+            painter.reset(&mut out);
+
+            // テキスト部
+            out.push_str(" |");
+            for i in 0..chunk.len() {
+                painter.paint(&mut out, self.color_of(start + i, layers));
+                out.push_str(&text[start + i]);
+            }
+            painter.reset(&mut out);
+            out.push_str("|\n");
+        }
+        out
+    }
+
+    /**
+     * offsetのバイトを含む層の色を返す
+     */
+    fn color_of(&self, offset: usize, layers: &[Layer]) -> Option<&'static str> {
+        if !self.color {
+            return None;
+        }
+        layers
+            .iter()
+            .position(|l| l.range.contains(&offset))
+            .map(|i| COLORS[i % COLORS.len()])
+    }
+
+    /**
+     * 各バイトに対応するテキスト部の表示を返す
+     * 表示幅が揃うように、複数バイト文字は先頭バイトに文字を、続くバイトに空白を割り当てる
+     */
+    fn text_cells(&self, data: &[u8]) -> Vec<String> {
+        let width = self.width.max(1);
+        let mut cells = Vec::with_capacity(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            let b = data[i];
+            if is_printable_ascii(b) {
+                cells.push((b as char).to_string());
+                i += 1;
+                continue;
+            }
+            if self.utf8 {
+                if let Some((c, len)) = decode_utf8(&data[i..]) {
+                    // 行をまたぐ文字は崩れるので表示しない
+                    let same_row = i / width == (i + len - 1) / width;
+                    let w = char_width(c);
+                    if same_row && !c.is_control() && w <= len {
+                        cells.push(c.to_string());
+                        cells.extend((0..len - 1).map(|k| {
+                            if k < len - w {
+                                " ".to_string()
+                            } else {
+                                String::new()
+                            }
+                        }));
+                        i += len;
+                        continue;
+                    }
+                }
+            }
+            // 表示できない文字は.で表示
+            cells.push(".".to_string());
+            i += 1;
+        }
+        cells
+    }
+}
+
+/**
+ * 色が変わるときだけエスケープシーケンスを出力する
+ */
+#[derive(Default)]
+struct Painter {
+    current: Option<&'static str>,
+}
+
+impl Painter {
+    fn paint(&mut self, out: &mut String, color: Option<&'static str>) {
+        if self.current == color {
+            return;
+        }
+        match color {
+            Some(color) => out.push_str(color),
+            None => out.push_str(RESET),
+        }
+        self.current = color;
+    }
+
+    fn reset(&mut self, out: &mut String) {
+        self.paint(out, None);
+    }
+}
+
+fn is_printable_ascii(b: u8) -> bool {
+    (0x20..=0x7e).contains(&b)
+}
+
+/**
+ * 先頭の1文字をUTF-8として解釈し、文字とバイト数を返す
+ * ASCIIは扱わない
+ */
+fn decode_utf8(buf: &[u8]) -> Option<(char, usize)> {
+    let len = match buf.first()? {
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return None,
+    };
+    let s = std::str::from_utf8(buf.get(..len)?).ok()?;
+    Some((s.chars().next()?, len))
+}
+
+/**
+ * 端末上での表示幅を大まかに求める
+ * 東アジアの全角文字と絵文字を2、それ以外を1とする
+ */
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Options;
+    use crate::testutil::{self, Tcp};
+    use pnet::packet::tcp::TcpFlags::{ACK, PSH};
+
+    #[test]
+    fn offsets_continue_across_rows() {
+        let data: Vec<u8> = (0..40).collect();
+        assert_eq!(
+            HexDump::default().render(&data, &[]),
+            "00000000  00 01 02 03 04 05 06 07  08 09 0a 0b 0c 0d 0e 0f  |................|\n\
+             00000010  10 11 12 13 14 15 16 17  18 19 1a 1b 1c 1d 1e 1f  |................|\n\
+             00000020  20 21 22 23 24 25 26 27                           | !\"#$%&'|\n"
+        );
+    }
+
+    #[test]
+    fn honours_width_and_grouping() {
+        let dump = HexDump {
+            width: 6,
+            group: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            dump.render(b"0123 ab, c!?~\x7f", &[]),
+            "00000000  30 31  32 33  20 61  |0123 a|\n\
+             00000006  62 2c  20 63  21 3f  |b, c!?|\n\
+             0000000c  7e 7f                |~.|\n"
+        );
+
+        let ungrouped = HexDump {
+            width: 4,
+            group: 0,
+            ..Default::default()
+        };
+        assert_eq!(
+            ungrouped.render(b"abcdef", &[]),
+            "00000000  61 62 63 64  |abcd|\n00000004  65 66        |ef|\n"
+        );
+    }
+
+    #[test]
+    fn utf8_characters_keep_columns_aligned() {
+        let dump = HexDump {
+            width: 8,
+            group: 4,
+            ..Default::default()
+        };
+        // 「漢」は2行目と3行目にまたがるので.で表示する
+        assert_eq!(
+            dump.render("abcdeあいう漢字".as_bytes(), &[]),
+            "00000000  61 62 63 64  65 e3 81 82  |abcdeあ |\n\
+             00000008  e3 81 84 e3  81 86 e6 bc  |い う ..|\n\
+             00000010  a2 e5 ad 97               |.字 |\n"
+        );
+
+        let ascii_only = HexDump {
+            width: 8,
+            group: 4,
+            utf8: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            ascii_only.render("abcdeあ".as_bytes(), &[]),
+            "00000000  61 62 63 64  65 e3 81 82  |abcde...|\n"
+        );
+    }
+
+    fn http_record() -> crate::record::PacketRecord {
+        let frame = Tcp::new("10.0.0.2:50000", "10.0.0.1:80", PSH | ACK)
+            .payload(b"GET / HTTP/1.1\r\n\r\n")
+            .frame();
+        testutil::records(&testutil::pcap(&[(0.0, frame)])).remove(0)
+    }
+
+    #[test]
+    fn colors_each_layer_of_the_full_frame() {
+        let record = http_record();
+        let layers = record.layers();
+        let names: Vec<_> = layers.iter().map(|l| l.name).collect();
+        assert_eq!(names, ["Ethernet", "IPv4", "TCP", "Payload"]);
+
+        let dump = HexDump {
+            color: true,
+            ..Default::default()
+        };
+        let out = dump.render(&record.frame, &layers);
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(
+            lines[0],
+            "\x1b[34mEthernet\x1b[0m \x1b[32mIPv4\x1b[0m \x1b[33mTCP\x1b[0m \x1b[35mPayload\x1b[0m"
+        );
+        // イーサネットヘッダの14バイトの後でIPv4の色に変わる
+        assert_eq!(
+            lines[1],
+            "00000000  \x1b[34m02 00 00 00 00 02 02 00  00 00 00 01 08 00 \
+             \x1b[32m45 00 \x1b[0m |\x1b[34m..............\x1b[32mE.\x1b[0m|"
+        );
+        // TCPヘッダのウィンドウ・チェックサム・緊急ポインタの後にペイロードが続く
+        assert_eq!(
+            lines[4],
+            "00000030  \x1b[33mff ff f9 76 00 00 \x1b[35m47 45  54 20 2f 20 48 54 54 50 \
+             \x1b[0m |\x1b[33m...v..\x1b[35mGET / HTTP\x1b[0m|"
+        );
+        assert_eq!(lines.len(), 6);
+    }
+
+    #[test]
+    fn no_color_omits_legend_and_escapes() {
+        let record = http_record();
+        let args = ["--full-frame", "--no-color", "lo"].map(String::from);
+        let dump = Options::parse(args.into_iter()).unwrap().text.hexdump;
+        assert!(!dump.color);
+        let out = dump.render(&record.frame, &record.layers());
+        assert!(!out.contains('\x1b'));
+        assert_eq!(out, dump.render(&record.frame, &[]));
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[0],
+            "00000000  02 00 00 00 00 02 02 00  00 00 00 01 08 00 45 00  |..............E.|"
+        );
+        assert_eq!(
+            lines[4],
+            "00000040  2f 31 2e 31 0d 0a 0d 0a                           |/1.1....|"
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use pnet::{
+    datalink::NetworkInterface,
+    packet::ethernet::{EtherType, EtherTypes, EthernetPacket},
+    util::MacAddr,
+};
+use std::fs;
+
+const ETHERNET_HEADER_SIZE: usize = 14;
+const NULL_HEADER_SIZE: usize = 4;
+const SLL_HEADER_SIZE: usize = 16;
+
+// pcapのリンク層ヘッダの種類（https://www.tcpdump.org/linktypes.html）
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+// Linuxの/sys/class/net/<name>/typeの値（ARPHRD_*）
+const ARPHRD_ETHER: u32 = 1;
+const ARPHRD_LOOPBACK: u32 = 772;
+const ARPHRD_NONE: u32 = 65534;
+
+/**
+ * リンク層の種類
+ * ループバックやtunはイーサネットのフレームで届くとは限らない
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkType {
+    Ethernet,
+    /// BSDのループバック。pnetは4オクテットのヘッダを0で埋めたイーサネットヘッダに置き換えて渡してくる
+    Loopback,
+    /// 先頭4オクテットがアドレスファミリ（pcapのDLT_NULL）
+    Null,
+    /// リンク層ヘッダのないIPパケット（Linuxのtunなど）
+    RawIp,
+    /// Linuxのcooked capture（tcpdump -i anyの形式）
+    LinuxSll,
+}
+
+/**
+ * リンク層ヘッダを解析した結果
+ */
+#[derive(Debug, Clone)]
+pub struct LinkHeader {
+    pub name: &'static str,
+    pub len: usize,
+    pub src_mac: Option<MacAddr>,
+    pub dst_mac: Option<MacAddr>,
+    pub ethertype: EtherType,
+}
+
+impl LinkType {
+    /**
+     * インターフェイスから受信するフレームのリンク層の種類を推測する
+     */
+    pub fn from_interface(interface: &NetworkInterface) -> Self {
+        // Linuxではsysfsからデバイスの種類が分かる
+        if let Some(arphrd) = read_sysfs::<u32>(&interface.name, "type") {
+            return match arphrd {
+                ARPHRD_ETHER | ARPHRD_LOOPBACK => LinkType::Ethernet,
+                ARPHRD_NONE => LinkType::RawIp,
+                _ if interface.mac.is_none() => LinkType::RawIp,
+                _ => LinkType::Ethernet,
+            };
+        }
+        if interface.is_loopback() {
+            LinkType::Loopback
+        } else {
+            LinkType::Ethernet
+        }
+    }
+
+    /**
+     * pcapファイルのヘッダのリンク層の種類から変換する
+     */
+    pub fn from_pcap(link_type: u32) -> Option<Self> {
+        match link_type {
+            LINKTYPE_NULL => Some(LinkType::Null),
+            LINKTYPE_ETHERNET => Some(LinkType::Ethernet),
+            LINKTYPE_RAW => Some(LinkType::RawIp),
+            LINKTYPE_LINUX_SLL => Some(LinkType::LinuxSll),
+            _ => None,
+        }
+    }
+
+    /**
+     * pcapファイルのヘッダに書くリンク層の種類
+     */
+    pub fn to_pcap(self) -> u32 {
+        match self {
+            // pnetはループバックもイーサネットヘッダを付けて渡してくる
+            LinkType::Ethernet | LinkType::Loopback => LINKTYPE_ETHERNET,
+            LinkType::Null => LINKTYPE_NULL,
+            LinkType::RawIp => LINKTYPE_RAW,
+            LinkType::LinuxSll => LINKTYPE_LINUX_SLL,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LinkType::Ethernet => "Ethernet",
+            LinkType::Loopback => "Loopback",
+            LinkType::Null => "Null/Loopback",
+            LinkType::RawIp => "Raw IP",
+            LinkType::LinuxSll => "Linux cooked",
+        }
+    }
+
+    /**
+     * リンク層ヘッダを解析する。ヘッダが途中で切れている場合はNoneを返す
+     */
+    pub fn decode(&self, frame: &[u8]) -> Option<LinkHeader> {
+        match self {
+            LinkType::Ethernet | LinkType::Loopback => {
+                let eth = EthernetPacket::new(frame)?;
+                let mut ethertype = eth.get_ethertype();
+                // BSDのループバックはイーサタイプが0になっているので中身から推測する
+                if ethertype == EtherType(0) {
+                    ethertype = guess_ethertype(frame.get(ETHERNET_HEADER_SIZE..)?)?;
+                }
+                Some(LinkHeader {
+                    name: "Ethernet",
+                    len: ETHERNET_HEADER_SIZE,
+                    src_mac: Some(eth.get_source()),
+                    dst_mac: Some(eth.get_destination()),
+                    ethertype,
+                })
+            }
+            LinkType::Null => Some(LinkHeader {
+                name: "Null",
+                len: NULL_HEADER_SIZE,
+                src_mac: None,
+                dst_mac: None,
+                // アドレスファミリの値はOSによって異なるので、IPのバージョンから判断する
+                ethertype: guess_ethertype(frame.get(NULL_HEADER_SIZE..)?)?,
+            }),
+            LinkType::RawIp => Some(LinkHeader {
+                name: "Raw",
+                len: 0,
+                src_mac: None,
+                dst_mac: None,
+                ethertype: guess_ethertype(frame)?,
+            }),
+            LinkType::LinuxSll => {
+                let header = frame.get(..SLL_HEADER_SIZE)?;
+                let addr_len = u16::from_be_bytes([header[4], header[5]]);
+                let src_mac = if addr_len == 6 {
+                    let a = &header[6..12];
+                    Some(MacAddr::new(a[0], a[1], a[2], a[3], a[4], a[5]))
+                } else {
+                    None
+                };
+                Some(LinkHeader {
+                    name: "Linux SLL",
+                    len: SLL_HEADER_SIZE,
+                    src_mac,
+                    dst_mac: None,
+                    ethertype: EtherType(u16::from_be_bytes([header[14], header[15]])),
+                })
+            }
+        }
+    }
+}
+
+/**
+ * IPヘッダのバージョンからイーサタイプを推測する
+ */
+fn guess_ethertype(packet: &[u8]) -> Option<EtherType> {
+    match packet.first()? >> 4 {
+        4 => Some(EtherTypes::Ipv4),
+        6 => Some(EtherTypes::Ipv6),
+        _ => None,
+    }
+}
+
+/**
+ * インターフェイスのMTUを返す（Linuxのみ）
+ */
+pub fn mtu(interface: &NetworkInterface) -> Option<u32> {
+    read_sysfs(&interface.name, "mtu")
+}
+
+/**
+ * インターフェイスが受信時に破棄したパケット数の累計を返す（Linuxのみ）
+ */
+pub fn rx_dropped(name: &str) -> Option<u64> {
+    read_sysfs(name, "statistics/rx_dropped")
+}
+
+fn read_sysfs<T: std::str::FromStr>(name: &str, attr: &str) -> Option<T> {
+    let path = format!("/sys/class/net/{}/{}", name, attr);
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/**
+ * インターフェイスのフラグを"UP,BROADCAST"のような文字列にする
+ */
+pub fn flags_string(interface: &NetworkInterface) -> String {
+    let flags = [
+        (interface.is_up(), "UP"),
+        (interface.is_broadcast(), "BROADCAST"),
+        (interface.is_loopback(), "LOOPBACK"),
+        (interface.is_point_to_point(), "POINTOPOINT"),
+        (interface.is_multicast(), "MULTICAST"),
+    ];
+    flags
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(",")
+}
@@ -1,173 +1,398 @@
 use anyhow::{anyhow, Context, Result};
-use env_logger;
-use log::info;
-use pnet::{
-    datalink::{self, Channel::Ethernet},
-    packet::{
-        ethernet::{EtherTypes, EthernetPacket},
-        ip::IpNextHeaderProtocols,
-        ipv4::Ipv4Packet,
-        ipv6::Ipv6Packet,
-        tcp::TcpPacket,
-        udp::UdpPacket,
-        Packet,
+use log::{debug, info};
+use std::{
+    env,
+    fs::File,
+    io::{self, BufReader},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::RecvTimeoutError,
+        Arc,
     },
+    time::{Duration, Instant},
 };
-use std::env;
 
-mod packets;
-use packets::GettableEndPoints;
+mod capture;
+mod cli;
+mod decoder;
+mod dissectors;
+mod filter;
+mod health;
+mod hexdump;
+mod link;
+mod output;
+mod pcap;
+mod record;
+mod remote;
+mod replay;
+mod stats;
+#[cfg(test)]
+mod testutil;
+mod tui;
+use capture::{Capture, Frame, Summary};
+use cli::{Command, Options, Source};
+use decoder::{Registry, Severity};
+use filter::Filter;
+use health::HealthTable;
+use link::LinkType;
+use output::{ReportSink, Sink};
+use pcap::PcapReader;
+use record::PacketRecord;
+use remote::Server;
+use stats::FlowTable;
+use tui::{App, TuiSink};
 
 fn main() -> Result<()> {
     env::set_var("RUST_LOG", "debug");
     env_logger::init();
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        return Err(anyhow!("Please specify target interface name"));
+    let options = Options::parse(env::args().skip(1))?;
+    if options.tui {
+        // ログが画面を崩すので出さない
+        log::set_max_level(log::LevelFilter::Off);
     }
 
-    let interface_name = &args[1];
-    // ネットワークインターフェイス（NICや無線LANアダプタを抽象化したもの）の選択
-    let interfaces = datalink::interfaces();
-    let interface = interfaces
-        .into_iter()
-        .find(|iface| iface.name == *interface_name)
-        .context("Failed to get interface")?;
-
-    // データリンクのチャネルを取得
-    let (_tx, mut rx) = match datalink::channel(&interface, Default::default()) {
-        Ok(Ethernet(tx, rx)) => (tx, rx),
-        Ok(_) => return Err(anyhow!("Unhandled channel type")),
-        Err(e) => {
-            let msg = format!("Failed to create datalink channel {}", e);
-            return Err(anyhow!(msg));
-        }
+    let source = match &options.command {
+        Command::List => return capture::list_interfaces(&mut io::stdout()),
+        Command::Capture(source) => source,
+        Command::Replay(replay) => return replay::run(replay),
     };
 
-    loop {
-        match rx.next() {
-            Ok(frame) => {
-                // 受信パケットからイーサネットフレームの構築
-                let frame =
-                    EthernetPacket::new(frame).context("Failed to make a EthernetPacket")?;
-                match frame.get_ethertype() {
-                    EtherTypes::Ipv4 => {
-                        ipv4_handler(&frame);
-                    }
-                    EtherTypes::Ipv6 => {
-                        ipv6_handler(&frame);
-                    }
-                    _ => {
-                        info!("Not an IPv4 or IPv6");
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("{:?}", e);
-            }
+    // Ctrl-Cで止めたときも統計を表示できるよう、フラグを立てるだけにする
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst))
+        .context("Failed to set Ctrl-C handler")?;
+    let limits = Limits {
+        count: options.count,
+        deadline: options.duration.map(|d| Instant::now() + d),
+        stop,
+    };
+
+    let mut registry = Registry::default();
+    registry.set_verify_checksums(options.verify_checksums);
+    // TUIでは表示中にフィルタを変えられるので、TUIの側で絞り込む
+    let filter: Option<Filter> = match &options.filter {
+        Some(filter) if !options.tui => Some(filter.parse()?),
+        _ => None,
+    };
+    let mut sink: Box<dyn Sink> = if options.tui {
+        let app = App::new(options.filter.clone())?;
+        Box::new(TuiSink::start(app, limits.stop.clone())?)
+    } else if options.tcp_analysis {
+        Box::new(ReportSink::new(
+            HealthTable::new(),
+            io::stdout(),
+            options.interval,
+            options.top,
+        ))
+    } else if options.stats {
+        Box::new(ReportSink::new(
+            FlowTable::new(),
+            io::stdout(),
+            options.interval,
+            options.top,
+        ))
+    } else {
+        output::make_sink(options.format, io::stdout(), options.text.clone())
+    };
+    let input = match source {
+        Source::Interfaces(names) => Input::Interfaces(capture::start(names, &options.capture)?),
+        Source::File(path) => {
+            let file = File::open(path).with_context(|| format!("Failed to open {}", path))?;
+            let reader = PcapReader::new(BufReader::new(file))?;
+            let link_type = LinkType::from_pcap(reader.link_type)
+                .with_context(|| format!("Unhandled link type {}", reader.link_type))?;
+            Input::File(PcapFile {
+                path,
+                reader,
+                link_type,
+            })
+        }
+        Source::Remote(addr) => {
+            // TUIで表示中にフィルタを広げられるよう、その場合は全部送ってもらう
+            let remote_filter = options.filter.as_deref().filter(|_| !options.tui);
+            Input::Remote(remote::connect(addr, remote_filter)?)
+        }
+    };
+    // ストリームのヘッダを接続時に送れるよう、入力元のリンク層の種類で配信する
+    let link_type = match &input {
+        Input::Interfaces(capture) | Input::Remote(capture) => capture.link_type,
+        Input::File(file) => file.link_type,
+    };
+    let mut server = options
+        .serve
+        .as_deref()
+        .map(|addr| Server::start(addr, link_type))
+        .transpose()?;
+    let mut summary = Summary::default();
+    match input {
+        Input::Interfaces(capture) => {
+            capture_interfaces(
+                &capture,
+                &limits,
+                &mut registry,
+                filter.as_ref(),
+                server.as_mut(),
+                sink.as_mut(),
+                &mut summary,
+            )?;
+            summary.dropped = Some(capture.dropped());
+            summary.kernel_dropped = capture.kernel_dropped();
+        }
+        Input::File(file) => read_file(
+            file,
+            &limits,
+            &mut registry,
+            filter.as_ref(),
+            server.as_mut(),
+            sink.as_mut(),
+            &mut summary,
+        )?,
+        Input::Remote(capture) => {
+            capture_interfaces(
+                &capture,
+                &limits,
+                &mut registry,
+                filter.as_ref(),
+                server.as_mut(),
+                sink.as_mut(),
+                &mut summary,
+            )?;
         }
     }
+    if let Some(server) = server.as_mut() {
+        server.finish();
+    }
+    sink.finish()?;
+    // TUIの場合は端末を元に戻してから表示する
+    drop(sink);
+    summary.print(&mut io::stderr())
 }
 
 /**
- * IPv4パケットを構築し次のレイヤのハンドラを呼び出す
+ * 開いた入力元
  */
-fn ipv4_handler(frame: &EthernetPacket) {
-    // フレームを剥いてパケットを取り出す
-    if let Some(packet) = Ipv4Packet::new(frame.payload()) {
-        match packet.get_next_level_protocol() {
-            IpNextHeaderProtocols::Tcp => {
-                tcp_handler(&packet);
-            }
-            IpNextHeaderProtocols::Udp => {
-                udp_handler(&packet);
-            }
-            _ => {
-                info!("Not a TCP or UDP packet");
-            }
-        }
+enum Input<'a> {
+    Interfaces(Capture),
+    File(PcapFile<'a>),
+    Remote(Capture),
+}
+
+/**
+ * グローバルヘッダまで読み込んだpcapファイル
+ */
+struct PcapFile<'a> {
+    path: &'a str,
+    reader: PcapReader<BufReader<File>>,
+    link_type: LinkType,
+}
+
+/**
+ * キャプチャを止める条件
+ */
+struct Limits {
+    /// 出力するパケット数の上限
+    count: Option<u64>,
+    deadline: Option<Instant>,
+    /// Ctrl-Cが押されたか
+    stop: Arc<AtomicBool>,
+}
+
+impl Limits {
+    fn reached(&self, summary: &Summary) -> bool {
+        self.stop.load(Ordering::SeqCst)
+            || self.count.is_some_and(|count| summary.decoded >= count)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
     }
 }
 
+/// 停止条件を確認する間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 /**
- * IPv6パケットを構築し次のレイヤのハンドラを呼び出す
+ * インターフェイスからパケットをキャプチャする
  */
-fn ipv6_handler(frame: &EthernetPacket) {
-    // フレームを剥いてパケットを取り出す
-    if let Some(packet) = Ipv6Packet::new(frame.payload()) {
-        match packet.get_next_header() {
-            IpNextHeaderProtocols::Tcp => {
-                tcp_handler(&packet);
-            }
-            IpNextHeaderProtocols::Udp => {
-                udp_handler(&packet);
-            }
-            _ => {
-                info!("Not a TCP or UDP packet");
-            }
+fn capture_interfaces(
+    capture: &Capture,
+    limits: &Limits,
+    registry: &mut Registry,
+    filter: Option<&Filter>,
+    mut server: Option<&mut Server>,
+    sink: &mut dyn Sink,
+    summary: &mut Summary,
+) -> Result<()> {
+    while !limits.reached(summary) {
+        match capture.frames.recv_timeout(POLL_INTERVAL) {
+            Ok(frame) => process_frame(
+                &frame,
+                registry,
+                filter,
+                server.as_deref_mut(),
+                sink,
+                summary,
+            )?,
+            Err(RecvTimeoutError::Timeout) => sink.tick()?,
+            Err(RecvTimeoutError::Disconnected) => break,
         }
     }
+    Ok(())
 }
 
 /**
- * TCPパケットを構築する
+ * pcapファイルからパケットを読み込む
  */
-fn tcp_handler<T: GettableEndPoints>(packet: &T) {
-    let tcp = TcpPacket::new(packet.get_payload());
-    if let Some(tcp) = tcp {
-        print_packet_info(packet, &tcp, "TCP");
+fn read_file(
+    mut file: PcapFile,
+    limits: &Limits,
+    registry: &mut Registry,
+    filter: Option<&Filter>,
+    mut server: Option<&mut Server>,
+    sink: &mut dyn Sink,
+    summary: &mut Summary,
+) -> Result<()> {
+    while !limits.reached(summary) {
+        let pcap_record = match file.reader.next_record()? {
+            Some(pcap_record) => pcap_record,
+            None => break,
+        };
+        let frame = Frame {
+            interface: file.path.to_string(),
+            link_type: file.link_type,
+            timestamp: pcap_record.timestamp,
+            len: pcap_record.orig_len,
+            data: pcap_record.data,
+        };
+        process_frame(
+            &frame,
+            registry,
+            filter,
+            server.as_deref_mut(),
+            sink,
+            summary,
+        )?;
     }
+    Ok(())
 }
 
 /**
- * UDPパケットを構築する
+ * フレームを解析して出力し、統計を更新する
+ * 配信中の場合は表示フィルタとは関係なく、クライアントごとのフィルタで配る
  */
-fn udp_handler<T: GettableEndPoints>(packet: &T) {
-    let udp = UdpPacket::new(packet.get_payload());
-    if let Some(udp) = udp {
-        print_packet_info(packet, &udp, "UDP");
+fn process_frame(
+    frame: &Frame,
+    registry: &mut Registry,
+    filter: Option<&Filter>,
+    server: Option<&mut Server>,
+    sink: &mut dyn Sink,
+    summary: &mut Summary,
+) -> Result<()> {
+    summary.received += 1;
+    let result = frame_handler(frame, registry);
+    if let Some(server) = server {
+        server.publish(frame, result.as_ref().ok().and_then(Option::as_ref));
     }
+    match result {
+        Ok(Some(record)) if filter.is_some_and(|f| !f.matches(&record)) => summary.filtered += 1,
+        Ok(Some(record)) => {
+            summary.decoded += 1;
+            if record.decoded.worst().map(|info| info.severity) == Some(Severity::Error) {
+                summary.malformed += 1;
+            }
+            sink.write(&record)?;
+        }
+        Ok(None) => summary.unsupported += 1,
+        Err(e) => {
+            summary.failed += 1;
+            debug!("{}: {:#}", frame.interface, e);
+        }
+    }
+    Ok(())
 }
 
-const WIDTH: usize = 20;
-
 /**
- * アプリケーション層のデータをバイナリで表示する
+ * 受信したフレームを解析してレコードを作る
+ * デコーダのないプロトコルはNoneを、IP層まで解析できなかった場合はエラーを返す
  */
-fn print_packet_info<T: GettableEndPoints, S: GettableEndPoints>(l3: &T, l4: &S, proto: &str) {
-    println!(
-        "Captured a {} packet from {}|{} to {}|{}\n",
-        proto,
-        l3.get_source(),
-        l4.get_source(),
-        l3.get_destination(),
-        l4.get_destination(),
-    );
-
-    let payload = l4.get_payload();
-    let len = payload.len();
-    // ペイロード部の表示
-    for i in 0..len {
-        // 16進数で表示
-        print!("{:<02X} ", payload[i]);
-
-        // asciiで表示
-        if i % WIDTH == WIDTH - 1 || i == len - 1 {
-            for _ in 0..WIDTH - 1 - (i % WIDTH) {
-                print!("    ");
-            }
-            print!("|  ");
-            for j in i - i % WIDTH..=i {
-                if payload[j].is_ascii_alphabetic() {
-                    print!("{}", payload[j] as char);
-                } else {
-                    // 非ascii文字は.で表示
-                    print!(".");
-                }
-            }
-            println!();
+fn frame_handler(frame: &Frame, registry: &mut Registry) -> Result<Option<PacketRecord>> {
+    let decoded = registry.decode(frame.link_type, &frame.data)?;
+    if let Some(protocol) = decoded.unsupported {
+        info!("Unsupported protocol {:?}", protocol);
+    }
+    let error = decoded
+        .worst()
+        .filter(|info| info.severity == Severity::Error)
+        .map(|info| info.to_string());
+    match PacketRecord::new(
+        &frame.interface,
+        &frame.data,
+        decoded,
+        frame.timestamp,
+        frame.len,
+    ) {
+        Some(record) => Ok(Some(record)),
+        None => error.map_or(Ok(None), |error| Err(anyhow!(error))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::{Format, TextOptions};
+    use crate::testutil;
+
+    fn frame(data: Vec<u8>) -> Frame {
+        Frame {
+            interface: "test".to_string(),
+            link_type: LinkType::Ethernet,
+            timestamp: 0.0,
+            len: data.len(),
+            data,
         }
     }
-    println!("{}", "=".repeat(WIDTH * 3));
-    println!();
+
+    #[test]
+    fn counts_unsupported_protocols_separately_from_filtered() {
+        let udp = testutil::udp("10.0.0.1:5000", "10.0.0.2:9999", b"data");
+        // イーサタイプをARPに書き換えると、IPv4のデコーダは呼ばれない
+        let mut arp = udp.clone();
+        arp[12..14].copy_from_slice(&[0x08, 0x06]);
+
+        let mut registry = Registry::default();
+        let filter: Filter = "port 53".parse().unwrap();
+        let mut sink = output::make_sink(Format::Csv, io::sink(), TextOptions::default());
+        let mut summary = Summary::default();
+        for data in [udp.clone(), arp.clone(), arp] {
+            process_frame(
+                &frame(data),
+                &mut registry,
+                Some(&filter),
+                None,
+                sink.as_mut(),
+                &mut summary,
+            )
+            .unwrap();
+        }
+        process_frame(
+            &frame(udp),
+            &mut registry,
+            None,
+            None,
+            sink.as_mut(),
+            &mut summary,
+        )
+        .unwrap();
+
+        assert_eq!(summary.received, 4);
+        assert_eq!(summary.decoded, 1);
+        assert_eq!(summary.filtered, 1);
+        assert_eq!(summary.unsupported, 2);
+        assert_eq!(summary.failed, 0);
+
+        let mut out = vec![];
+        summary.print(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\n1 packets filtered\n2 packets of unsupported protocols\n"));
+    }
 }
@@ -0,0 +1,529 @@
+use crate::decoder::ExpertInfo;
+use crate::dissectors::{Field, ProtoTree};
+use crate::hexdump::HexDump;
+use crate::record::PacketRecord;
+use anyhow::{anyhow, Result};
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/**
+ * 出力形式
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            _ => Err(anyhow!(
+                "Undefined output format, only accept [text|json|csv]."
+            )),
+        }
+    }
+}
+
+/**
+ * キャプチャしたパケットの出力先
+ */
+pub trait Sink {
+    fn write(&mut self, record: &PacketRecord) -> Result<()>;
+
+    /**
+     * パケットが届かない間も定期的に呼ばれる
+     */
+    fn tick(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /**
+     * キャプチャ終了時に呼ばれる
+     */
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/**
+ * テキスト出力の設定
+ */
+#[derive(Debug, Clone, Default)]
+pub struct TextOptions {
+    pub hexdump: HexDump,
+    /// ヘッダを含むフレーム全体をダンプする
+    pub full_frame: bool,
+    /// 各層のヘッダをプロトコルツリーとして表示する
+    pub verbose: bool,
+}
+
+/**
+ * 形式に応じた出力先を作る
+ * textはテキスト出力でのみ使う
+ */
+pub fn make_sink<W: Write + 'static>(
+    format: Format,
+    writer: W,
+    text: TextOptions,
+) -> Box<dyn Sink> {
+    match format {
+        Format::Text => Box::new(TextSink::new(writer, text)),
+        Format::Json => Box::new(JsonSink::new(writer)),
+        Format::Csv => Box::new(CsvSink::new(writer)),
+    }
+}
+
+const SEPARATOR_WIDTH: usize = 60;
+
+/**
+ * 人が読むためのテキスト出力
+ * ディセクタで解析できた場合はプロトコルツリーを、できなかった場合はバイナリを表示する
+ * full_frameの場合は、ヘッダを含むフレーム全体を層ごとに色分けして表示する
+ */
+pub struct TextSink<W: Write> {
+    writer: W,
+    options: TextOptions,
+}
+
+impl<W: Write> TextSink<W> {
+    pub fn new(writer: W, options: TextOptions) -> Self {
+        Self { writer, options }
+    }
+}
+
+impl<W: Write> Sink for TextSink<W> {
+    fn write(&mut self, record: &PacketRecord) -> Result<()> {
+        let w = &mut self.writer;
+        writeln!(
+            w,
+            "Captured a {} packet from {}|{} to {}|{}\n",
+            record.protocol, record.src_ip, record.src_port, record.dst_ip, record.dst_port,
+        )?;
+
+        for info in &record.expert {
+            writeln!(w, "{}", info)?;
+        }
+        if !record.expert.is_empty() {
+            writeln!(w)?;
+        }
+        if self.options.verbose {
+            for layer in &record.decoded.layers {
+                let tree = ProtoTree {
+                    protocol: layer.header.name(),
+                    fields: layer.header.fields(),
+                };
+                write!(w, "{}", tree)?;
+            }
+        }
+        if let Some(tree) = &record.application {
+            write!(w, "{}", tree)?;
+        }
+        let hexdump = &self.options.hexdump;
+        if self.options.full_frame {
+            write!(w, "{}", hexdump.render(&record.frame, &record.layers()))?;
+        } else if record.application.is_none() {
+            // ペイロード部の表示
+            write!(w, "{}", hexdump.render(record.payload(), &[]))?;
+        }
+        writeln!(w, "{}", "=".repeat(SEPARATOR_WIDTH))?;
+        writeln!(w)?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+/**
+ * 1行1レコードのJSON出力（JSON Lines）
+ * jqなどにパイプで渡すことを想定している
+ */
+pub struct JsonSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> Sink for JsonSink<W> {
+    fn write(&mut self, record: &PacketRecord) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        writeln!(self.writer)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+const CSV_HEADER: [&str; 17] = [
+    "timestamp",
+    "interface",
+    "src_mac",
+    "dst_mac",
+    "src_ip",
+    "dst_ip",
+    "src_port",
+    "dst_port",
+    "protocol",
+    "frame_len",
+    "payload_len",
+    "ttl",
+    "tcp_flags",
+    "app_protocol",
+    "app_fields",
+    "app_summary",
+    "expert",
+];
+
+/**
+ * CSV出力
+ * プロトコルツリーは"親.子=値"の形に平坦化して1列にまとめる
+ */
+pub struct CsvSink<W: Write> {
+    writer: W,
+    header_written: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            header_written: false,
+        }
+    }
+}
+
+impl<W: Write> Sink for CsvSink<W> {
+    fn write(&mut self, record: &PacketRecord) -> Result<()> {
+        if !self.header_written {
+            writeln!(self.writer, "{}", CSV_HEADER.join(","))?;
+            self.header_written = true;
+        }
+
+        let (app_protocol, app_fields, app_summary) = match &record.application {
+            Some(tree) => {
+                let mut flat = vec![];
+                for field in &tree.fields {
+                    flatten_field(field, "", &mut flat);
+                }
+                let summary = tree
+                    .fields
+                    .first()
+                    .map(|f| f.value.clone())
+                    .unwrap_or_default();
+                (tree.protocol.to_string(), flat.join("; "), summary)
+            }
+            None => Default::default(),
+        };
+        let columns = [
+            format!("{:.6}", record.timestamp),
+            record.interface.clone(),
+            record.src_mac.clone(),
+            record.dst_mac.clone(),
+            record.src_ip.clone(),
+            record.dst_ip.clone(),
+            record.src_port.to_string(),
+            record.dst_port.to_string(),
+            record.protocol.to_string(),
+            record.frame_len.to_string(),
+            record.payload_len.to_string(),
+            record.ttl.to_string(),
+            record.tcp_flags.clone().unwrap_or_default(),
+            app_protocol,
+            app_fields,
+            app_summary,
+            record
+                .expert
+                .iter()
+                .map(ExpertInfo::to_string)
+                .collect::<Vec<_>>()
+                .join("; "),
+        ];
+        let line = columns
+            .iter()
+            .map(|c| csv_escape(c))
+            .collect::<Vec<_>>()
+            .join(",");
+        writeln!(self.writer, "{}", line)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn flatten_field(field: &Field, prefix: &str, out: &mut Vec<String>) {
+    let name = if prefix.is_empty() {
+        field.name.clone()
+    } else {
+        format!("{}.{}", prefix, field.name)
+    };
+    if !field.value.is_empty() {
+        out.push(format!("{}={}", name, field.value));
+    }
+    for child in &field.children {
+        flatten_field(child, &name, out);
+    }
+}
+
+/**
+ * RFC 4180に従ってエスケープする
+ */
+fn csv_escape(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/**
+ * パケットを出力する代わりに集計し、その結果を表示するもの
+ */
+pub trait Report {
+    fn add(&mut self, record: &PacketRecord);
+
+    /**
+     * 集計結果を多い順にtop件まで表示する
+     */
+    fn report<W: Write>(&self, w: &mut W, top: usize) -> Result<()>;
+}
+
+/**
+ * パケットをReportで集計するSink
+ * intervalごと、および終了時に集計結果を表示する
+ * intervalはパケットのタイムスタンプで測るので、pcapファイルを読む場合も記録時の間隔で表示される
+ */
+pub struct ReportSink<R: Report, W: Write> {
+    report: R,
+    writer: W,
+    interval: Option<Duration>,
+    /// 最後に受け取ったパケットのタイムスタンプと、それを受け取った時刻
+    clock: Option<(f64, Instant)>,
+    /// 次に集計結果を表示するタイムスタンプ
+    next_report: f64,
+    top: usize,
+}
+
+impl<R: Report, W: Write> ReportSink<R, W> {
+    pub fn new(report: R, writer: W, interval: Option<Duration>, top: usize) -> Self {
+        Self {
+            report,
+            writer,
+            interval,
+            clock: None,
+            next_report: 0.0,
+            top,
+        }
+    }
+}
+
+impl<R: Report, W: Write> Sink for ReportSink<R, W> {
+    fn write(&mut self, record: &PacketRecord) -> Result<()> {
+        self.report.add(record);
+        let timestamp = match self.clock {
+            Some((last, _)) => last.max(record.timestamp),
+            None => {
+                let interval = self.interval.unwrap_or_default();
+                self.next_report = record.timestamp + interval.as_secs_f64();
+                record.timestamp
+            }
+        };
+        self.clock = Some((timestamp, Instant::now()));
+        self.tick()
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        let (Some(interval), Some((timestamp, received))) = (self.interval, self.clock) else {
+            return Ok(());
+        };
+        // パケットが届かない間は、最後のタイムスタンプから実際の経過時間だけ進める
+        let now = timestamp + received.elapsed().as_secs_f64();
+        if now >= self.next_report {
+            self.report.report(&mut self.writer, self.top)?;
+            self.next_report = now + interval.as_secs_f64();
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.report.report(&mut self.writer, self.top)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, Tcp};
+    use pnet::packet::tcp::TcpFlags::{ACK, PSH};
+
+    /**
+     * カンマと引用符を含むHTTPのリクエストと、チェックサムの壊れたUDPのレコード
+     */
+    fn records() -> Vec<PacketRecord> {
+        let http = Tcp::new("10.0.0.1:40000", "10.0.0.2:80", PSH | ACK)
+            .seq(1)
+            .ack(1)
+            .payload(b"GET /a,b HTTP/1.1\r\nUser-Agent: \"curl\"\r\n\r\n")
+            .frame();
+        let mut udp = testutil::udp("[2001:db8::1]:5000", "[2001:db8::2]:6000", b"x");
+        // UDPのチェックサム（イーサネット、IPv6、UDPヘッダの7オクテット目）
+        udp[14 + 40 + 6] ^= 0xff;
+        testutil::records(&testutil::pcap(&[
+            (1700000000.25, http),
+            (1700000001.5, udp),
+        ]))
+    }
+
+    fn write(sink: &mut dyn Sink) {
+        for record in records() {
+            sink.write(&record).unwrap();
+        }
+    }
+
+    #[test]
+    fn json_lines_keys() {
+        let mut out = vec![];
+        write(&mut JsonSink::new(&mut out));
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "{\"timestamp\":1700000000.25,\"interface\":\"test\",\
+                 \"src_mac\":\"02:00:00:00:00:01\",\"dst_mac\":\"02:00:00:00:00:02\",\
+                 \"src_ip\":\"10.0.0.1\",\"dst_ip\":\"10.0.0.2\",\"src_port\":40000,\"dst_port\":80,\
+                 \"protocol\":\"TCP\",\"frame_len\":95,\"payload_len\":41,\"ttl\":64,\
+                 \"tcp_flags\":\"ACK|PSH\",\"application\":{\"protocol\":\"HTTP\",\"fields\":[\
+                 {\"name\":\"Request line\",\"value\":\"GET /a,b HTTP/1.1\",\"children\":[\
+                 {\"name\":\"Method\",\"value\":\"GET\"},\
+                 {\"name\":\"Request URI\",\"value\":\"/a,b\"},\
+                 {\"name\":\"Version\",\"value\":\"HTTP/1.1\"}]},\
+                 {\"name\":\"Headers\",\"value\":\"\",\"children\":[\
+                 {\"name\":\"User-Agent\",\"value\":\"\\\"curl\\\"\"}]}]},\"expert\":[]}",
+                "{\"timestamp\":1700000001.5,\"interface\":\"test\",\
+                 \"src_mac\":\"02:00:00:00:00:01\",\"dst_mac\":\"02:00:00:00:00:02\",\
+                 \"src_ip\":\"2001:db8::1\",\"dst_ip\":\"2001:db8::2\",\"src_port\":5000,\
+                 \"dst_port\":6000,\"protocol\":\"UDP\",\"frame_len\":63,\"payload_len\":1,\
+                 \"ttl\":64,\"tcp_flags\":null,\"application\":null,\"expert\":[\
+                 {\"severity\":\"error\",\"layer\":\"UDP\",\
+                 \"message\":\"Bad checksum 0xfe6f (should be 0x016f)\"}]}",
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_columns_and_quoting() {
+        let mut out = vec![];
+        write(&mut CsvSink::new(&mut out));
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "timestamp,interface,src_mac,dst_mac,src_ip,dst_ip,src_port,dst_port,protocol,\
+                 frame_len,payload_len,ttl,tcp_flags,app_protocol,app_fields,app_summary,expert",
+                "1700000000.250000,test,02:00:00:00:00:01,02:00:00:00:00:02,10.0.0.1,10.0.0.2,\
+                 40000,80,TCP,95,41,64,ACK|PSH,HTTP,\
+                 \"Request line=GET /a,b HTTP/1.1; Request line.Method=GET; \
+                 Request line.Request URI=/a,b; Request line.Version=HTTP/1.1; \
+                 Headers.User-Agent=\"\"curl\"\"\",\"GET /a,b HTTP/1.1\",",
+                "1700000001.500000,test,02:00:00:00:00:01,02:00:00:00:00:02,2001:db8::1,2001:db8::2,\
+                 5000,6000,UDP,63,1,64,,,,,[Error] UDP: Bad checksum 0xfe6f (should be 0x016f)",
+            ]
+        );
+        assert_eq!(lines[0].split(',').count(), CSV_HEADER.len());
+    }
+
+    #[test]
+    fn csv_escape_follows_rfc4180() {
+        assert_eq!(csv_escape("plain text"), "plain text");
+        assert_eq!(csv_escape("a,b"), "\"a,b\"");
+        assert_eq!(csv_escape("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_escape("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_escape("cr\r"), "\"cr\r\"");
+        assert_eq!(csv_escape(""), "");
+    }
+
+    /**
+     * 集計したパケット数を表示するReport
+     */
+    struct Count(usize);
+
+    impl Report for Count {
+        fn add(&mut self, _record: &PacketRecord) {
+            self.0 += 1;
+        }
+
+        fn report<W: Write>(&self, w: &mut W, _top: usize) -> Result<()> {
+            writeln!(w, "{}", self.0)?;
+            Ok(())
+        }
+    }
+
+    /**
+     * 指定のタイムスタンプのUDPのレコード
+     */
+    fn at(timestamps: &[f64]) -> Vec<PacketRecord> {
+        let frames: Vec<_> = timestamps
+            .iter()
+            .map(|t| (*t, testutil::udp("10.0.0.1:5000", "10.0.0.2:6000", b"x")))
+            .collect();
+        testutil::records(&testutil::pcap(&frames))
+    }
+
+    fn reports(out: Vec<u8>) -> Vec<String> {
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn interval_follows_record_timestamps() {
+        let mut out = vec![];
+        let mut sink = ReportSink::new(Count(0), &mut out, Some(Duration::from_secs(1)), 5);
+        // 一瞬で読み込んでも、記録時の間隔で1秒経つごとに表示する
+        for record in at(&[100.0, 100.5, 101.0, 101.2, 102.5, 102.6]) {
+            sink.write(&record).unwrap();
+        }
+        sink.tick().unwrap();
+        sink.finish().unwrap();
+        assert_eq!(reports(out), ["3", "5", "6"]);
+    }
+
+    #[test]
+    fn tick_reports_while_idle() {
+        let mut out = vec![];
+        let interval = Duration::from_millis(50);
+        let mut sink = ReportSink::new(Count(0), &mut out, Some(interval), 5);
+        // パケットを受け取る前は表示しない
+        sink.tick().unwrap();
+        sink.write(&at(&[100.0])[0]).unwrap();
+        sink.tick().unwrap();
+        std::thread::sleep(interval * 2);
+        sink.tick().unwrap();
+        sink.tick().unwrap();
+        assert_eq!(reports(out), ["1"]);
+    }
+
+    #[test]
+    fn no_interval_reports_only_on_finish() {
+        let mut out = vec![];
+        let mut sink = ReportSink::new(Count(0), &mut out, None, 5);
+        for record in at(&[100.0, 200.0]) {
+            sink.write(&record).unwrap();
+        }
+        sink.tick().unwrap();
+        sink.finish().unwrap();
+        assert_eq!(reports(out), ["2"]);
+    }
+}
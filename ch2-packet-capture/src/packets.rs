@@ -1,63 +0,0 @@
-use pnet::packet::{ipv4::Ipv4Packet, ipv6::Ipv6Packet, tcp::TcpPacket, udp::UdpPacket, Packet};
-
-pub trait GettableEndPoints {
-    fn get_source(&self) -> String;
-    fn get_destination(&self) -> String;
-    fn get_payload(&self) -> &[u8];
-}
-
-impl<'a> GettableEndPoints for Ipv4Packet<'a> {
-    fn get_source(&self) -> String {
-        self.get_source().to_string()
-    }
-
-    fn get_destination(&self) -> String {
-        self.get_destination().to_string()
-    }
-
-    fn get_payload(&self) -> &[u8] {
-        self.payload()
-    }
-}
-
-impl<'a> GettableEndPoints for Ipv6Packet<'a> {
-    fn get_source(&self) -> String {
-        self.get_source().to_string()
-    }
-
-    fn get_destination(&self) -> String {
-        self.get_destination().to_string()
-    }
-
-    fn get_payload(&self) -> &[u8] {
-        self.payload()
-    }
-}
-
-impl<'a> GettableEndPoints for TcpPacket<'a> {
-    fn get_source(&self) -> String {
-        self.get_source().to_string()
-    }
-
-    fn get_destination(&self) -> String {
-        self.get_destination().to_string()
-    }
-
-    fn get_payload(&self) -> &[u8] {
-        self.payload()
-    }
-}
-
-impl<'a> GettableEndPoints for UdpPacket<'a> {
-    fn get_source(&self) -> String {
-        self.get_source().to_string()
-    }
-
-    fn get_destination(&self) -> String {
-        self.get_destination().to_string()
-    }
-
-    fn get_payload(&self) -> &[u8] {
-        self.payload()
-    }
-}
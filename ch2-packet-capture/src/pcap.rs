@@ -0,0 +1,153 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::{Read, Write};
+
+/// マイクロ秒精度のpcapのマジックナンバー
+const MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+/// ナノ秒精度のpcapのマジックナンバー
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+
+/**
+ * pcapファイルの1レコード
+ */
+pub struct PcapRecord {
+    /// UNIXエポックからの秒数
+    pub timestamp: f64,
+    /// キャプチャ時の元のフレーム長
+    pub orig_len: usize,
+    pub data: Vec<u8>,
+}
+
+/**
+ * pcap形式（libpcapのクラシックな形式）のリーダー
+ */
+pub struct PcapReader<R: Read> {
+    reader: R,
+    swapped: bool,
+    nanos: bool,
+    pub link_type: u32,
+    pub snaplen: u32,
+}
+
+impl<R: Read> PcapReader<R> {
+    /**
+     * グローバルヘッダを読み込む
+     */
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0u8; 24];
+        reader
+            .read_exact(&mut header)
+            .context("Failed to read pcap global header")?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        // ファイルを書いたマシンのエンディアンでマジックナンバーが格納されている
+        let (swapped, nanos) = match magic {
+            MAGIC_MICROS => (false, false),
+            MAGIC_NANOS => (false, true),
+            m if m.swap_bytes() == MAGIC_MICROS => (true, false),
+            m if m.swap_bytes() == MAGIC_NANOS => (true, true),
+            _ => return Err(anyhow!("Not a pcap file (magic 0x{:08x})", magic)),
+        };
+        let mut res = Self {
+            reader,
+            swapped,
+            nanos,
+            link_type: 0,
+            snaplen: 0,
+        };
+        res.snaplen = res.u32_at(&header, 16);
+        res.link_type = res.u32_at(&header, 20);
+        Ok(res)
+    }
+
+    /**
+     * 次のレコードを読む。ファイルの終端に達したらNoneを返す
+     */
+    pub fn next_record(&mut self) -> Result<Option<PcapRecord>> {
+        let mut header = [0u8; 16];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let secs = self.u32_at(&header, 0);
+        let frac = self.u32_at(&header, 4);
+        let incl_len = self.u32_at(&header, 8) as usize;
+        let orig_len = self.u32_at(&header, 12) as usize;
+        // 壊れたファイルで巨大な領域を確保しないように上限を設ける
+        if incl_len > (self.snaplen.max(65535) as usize) {
+            return Err(anyhow!("Invalid pcap record length {}", incl_len));
+        }
+        let mut data = vec![0u8; incl_len];
+        self.reader
+            .read_exact(&mut data)
+            .context("Truncated pcap record")?;
+
+        let divisor = if self.nanos { 1e9 } else { 1e6 };
+        Ok(Some(PcapRecord {
+            timestamp: secs as f64 + frac as f64 / divisor,
+            orig_len,
+            data,
+        }))
+    }
+
+    fn u32_at(&self, buf: &[u8], offset: usize) -> u32 {
+        let b = [
+            buf[offset],
+            buf[offset + 1],
+            buf[offset + 2],
+            buf[offset + 3],
+        ];
+        if self.swapped {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        }
+    }
+}
+
+/**
+ * pcap形式のライター
+ * マイクロ秒精度・リトルエンディアンで書き出す
+ */
+pub struct PcapWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /**
+     * グローバルヘッダを書き込む
+     */
+    pub fn new(mut writer: W, link_type: u32, snaplen: u32) -> Result<Self> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&MAGIC_MICROS.to_le_bytes());
+        // バージョン2.4
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        // タイムゾーンと精度は使われていないので0
+        header.extend_from_slice(&[0u8; 8]);
+        header.extend_from_slice(&snaplen.to_le_bytes());
+        header.extend_from_slice(&link_type.to_le_bytes());
+        writer.write_all(&header)?;
+        Ok(Self { writer })
+    }
+
+    /**
+     * レコードを1つ書き込む
+     */
+    pub fn write_record(&mut self, timestamp: f64, orig_len: usize, data: &[u8]) -> Result<()> {
+        let secs = timestamp.trunc() as u32;
+        let micros = ((timestamp.fract() * 1e6).round() as u32).min(999_999);
+        let mut header = Vec::with_capacity(16);
+        header.extend_from_slice(&secs.to_le_bytes());
+        header.extend_from_slice(&micros.to_le_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(orig_len as u32).to_le_bytes());
+        self.writer.write_all(&header)?;
+        self.writer.write_all(data)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
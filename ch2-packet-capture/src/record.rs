@@ -0,0 +1,162 @@
+use crate::decoder::{DecodedPacket, ExpertInfo, Header};
+use crate::dissectors::ProtoTree;
+use crate::hexdump::Layer;
+use pnet::{packet::tcp::TcpFlags, util::MacAddr};
+use serde::Serialize;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/**
+ * キャプチャした1パケット分の情報
+ * 各出力形式はこの構造体をもとに出力する
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct PacketRecord {
+    /// UNIXエポックからの秒数
+    pub timestamp: f64,
+    pub interface: String,
+    pub src_mac: String,
+    pub dst_mac: String,
+    pub src_ip: String,
+    pub dst_ip: String,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub protocol: &'static str,
+    pub frame_len: usize,
+    pub payload_len: usize,
+    /// IPv4のTTL、またはIPv6のホップリミット
+    pub ttl: u8,
+    pub tcp_flags: Option<String>,
+    pub application: Option<ProtoTree>,
+    /// チェックサムの不一致など、デコード中に見つかった異常
+    pub expert: Vec<ExpertInfo>,
+    /// ヘッダを含むフレーム全体。テキスト出力でのみ使う
+    #[serde(skip)]
+    pub frame: Vec<u8>,
+    /// 各層のヘッダ
+    #[serde(skip)]
+    pub decoded: DecodedPacket,
+}
+
+impl PacketRecord {
+    /**
+     * デコードしたパケットからレコードを作る
+     * 次の層のデコーダがなかったパケットはNoneを返す
+     * frame_lenはスナップ長で切り詰められる前の元のフレーム長
+     */
+    pub fn new(
+        interface: &str,
+        frame: &[u8],
+        decoded: DecodedPacket,
+        timestamp: f64,
+        frame_len: usize,
+    ) -> Option<Self> {
+        if decoded.unsupported.is_some() {
+            return None;
+        }
+        let (src_mac, dst_mac) = decoded
+            .find(|header| match header {
+                Header::Link { src, dst, .. } => Some((*src, *dst)),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let (src_ip, dst_ip) = decoded.find(Header::addresses)?;
+        let (src_port, dst_port) = decoded.find(Header::ports).unwrap_or_default();
+        let ttl = decoded
+            .find(|header| match header {
+                Header::Ipv4 { ttl, .. } => Some(*ttl),
+                Header::Ipv6 { hop_limit, .. } => Some(*hop_limit),
+                _ => None,
+            })
+            .unwrap_or_default();
+        let tcp_flags = decoded.find(|header| match header {
+            Header::Tcp { flags, .. } => Some(tcp_flags_string(*flags)),
+            _ => None,
+        });
+        let mac_string = |mac: Option<MacAddr>| mac.map(|m| m.to_string()).unwrap_or_default();
+
+        Some(Self {
+            timestamp,
+            interface: interface.to_string(),
+            src_mac: mac_string(src_mac),
+            dst_mac: mac_string(dst_mac),
+            src_ip: src_ip.to_string(),
+            dst_ip: dst_ip.to_string(),
+            src_port,
+            dst_port,
+            protocol: decoded.top()?.header.name(),
+            frame_len,
+            payload_len: decoded.payload.len(),
+            ttl,
+            tcp_flags,
+            application: decoded.application.clone(),
+            expert: decoded.expert.clone(),
+            frame: frame.to_vec(),
+            decoded,
+        })
+    }
+
+    /**
+     * 最上位の層のペイロード（アプリケーション層のデータ）
+     */
+    pub fn payload(&self) -> &[u8] {
+        self.frame
+            .get(self.decoded.payload.clone())
+            .unwrap_or_default()
+    }
+
+    /**
+     * ダンプで色分けするための各層の範囲
+     */
+    pub fn layers(&self) -> Vec<Layer> {
+        let mut layers: Vec<_> = self
+            .decoded
+            .layers
+            .iter()
+            .filter(|layer| !layer.range.is_empty())
+            .map(|layer| Layer {
+                name: layer.header.name(),
+                range: layer.range.clone(),
+            })
+            .collect();
+        if !self.decoded.payload.is_empty() {
+            layers.push(Layer {
+                name: "Payload",
+                range: self.decoded.payload.clone(),
+            });
+        }
+        layers
+    }
+}
+
+/**
+ * TCPのフラグを"SYN|ACK"のような文字列にする
+ */
+pub fn tcp_flags_string(flags: u16) -> String {
+    const NAMES: [(u16, &str); 9] = [
+        (TcpFlags::NS, "NS"),
+        (TcpFlags::CWR, "CWR"),
+        (TcpFlags::ECE, "ECE"),
+        (TcpFlags::URG, "URG"),
+        (TcpFlags::ACK, "ACK"),
+        (TcpFlags::PSH, "PSH"),
+        (TcpFlags::RST, "RST"),
+        (TcpFlags::SYN, "SYN"),
+        (TcpFlags::FIN, "FIN"),
+    ];
+    NAMES
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/**
+ * 現在時刻をUNIXエポックからの秒数で返す
+ */
+pub fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default()
+}
@@ -0,0 +1,416 @@
+use crate::capture::{Capture, Frame};
+use crate::filter::Filter;
+use crate::link::LinkType;
+use crate::pcap::{PcapReader, PcapWriter};
+use crate::record::PacketRecord;
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use std::{
+    io::{BufRead, BufReader, BufWriter, ErrorKind, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// クライアントごとに送信待ちのフレームを溜めておく数。溢れた分は破棄して数える
+const CLIENT_QUEUE_LEN: usize = 4096;
+/// 接続直後にフィルタの行を待つ時間。Wiresharkなどは何も送ってこない
+const FILTER_TIMEOUT: Duration = Duration::from_millis(500);
+/// 書き込みがこの時間進まないクライアントは切断する
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+/// 終了時に、全クライアントが送信待ちのフレームを送り切るのを待つ時間
+const FINISH_TIMEOUT: Duration = Duration::from_secs(5);
+/// ストリームのpcapヘッダに書くスナップ長
+const SNAPLEN: u32 = 65535;
+/// クライアントモードで受信したフレームを溜めておく数
+const RECEIVE_QUEUE_LEN: usize = 4096;
+
+/**
+ * クライアントに送るフレーム
+ * 全クライアントで共有するので参照カウントで渡す
+ */
+struct Packet {
+    timestamp: f64,
+    len: usize,
+    data: Vec<u8>,
+}
+
+/**
+ * 接続中のクライアント
+ */
+struct Client {
+    addr: SocketAddr,
+    filter: Option<Filter>,
+    sender: SyncSender<Arc<Packet>>,
+    /// 送信が追いつかずに破棄したフレーム数
+    dropped: Arc<AtomicU64>,
+    /// 送信側のスレッドが終わると切断される
+    done: Receiver<()>,
+}
+
+/**
+ * キャプチャしたフレームをTCPでpcap形式のストリームとして配信する
+ * 接続直後に1行送るとそのクライアントだけの表示フィルタになる
+ */
+pub struct Server {
+    clients: Arc<Mutex<Vec<Client>>>,
+    /// ストリームのリンク層の種類
+    link_type: LinkType,
+    local_addr: SocketAddr,
+}
+
+impl Server {
+    /**
+     * 待ち受けを開始し、接続の受け付けは別スレッドで行う
+     * pcapのストリームには1種類のリンク層しか書けないので、link_typeのフレームだけを配信する
+     */
+    pub fn start(addr: &str, link_type: LinkType) -> Result<Self> {
+        let listener =
+            TcpListener::bind(addr).with_context(|| format!("Failed to listen on {}", addr))?;
+        let server = Self {
+            clients: Arc::new(Mutex::new(vec![])),
+            link_type,
+            local_addr: listener.local_addr()?,
+        };
+        info!(
+            "Serving {} capture on {}",
+            link_type.name(),
+            server.local_addr()
+        );
+        let accept_clients = server.clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let clients = accept_clients.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = serve_client(stream, link_type, clients) {
+                                warn!("{:#}", e);
+                            }
+                        });
+                    }
+                    Err(e) => warn!("Failed to accept: {}", e),
+                }
+            }
+        });
+        Ok(server)
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /**
+     * フレームをフィルタに一致するクライアントに配る
+     * レコードのないフレームはフィルタのないクライアントにだけ送る
+     * 送信待ちが溢れたクライアントの分は破棄し、キャプチャは止めない
+     */
+    pub fn publish(&mut self, frame: &Frame, record: Option<&PacketRecord>) {
+        if frame.link_type != self.link_type {
+            debug!(
+                "{}: skip {} frame for {} stream",
+                frame.interface,
+                frame.link_type.name(),
+                self.link_type.name()
+            );
+            return;
+        }
+        let mut clients = self.clients.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+        let packet = Arc::new(Packet {
+            timestamp: frame.timestamp,
+            len: frame.len,
+            data: frame.data.clone(),
+        });
+        clients.retain(|client| {
+            let matches = match &client.filter {
+                Some(filter) => record.is_some_and(|record| filter.matches(record)),
+                None => true,
+            };
+            if !matches {
+                return true;
+            }
+            match client.sender.try_send(packet.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    client.dropped.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                // 書き込みに失敗して切断したクライアントは外す
+                Err(TrySendError::Disconnected(_)) => {
+                    debug!("{}: removed", client.addr);
+                    false
+                }
+            }
+        });
+    }
+
+    /**
+     * キャプチャの終了時に、送信待ちのフレームを各クライアントに送り切るまで待つ
+     * 受信の遅いクライアントがいても、全体でFINISH_TIMEOUTまでしか待たない
+     */
+    pub fn finish(&mut self) {
+        let clients = std::mem::take(&mut *self.clients.lock().unwrap());
+        // 送信側を閉じると、溜まっている分を書いてからスレッドが終わる
+        let waiting: Vec<_> = clients
+            .into_iter()
+            .map(|client| (client.addr, client.done))
+            .collect();
+        let deadline = Instant::now() + FINISH_TIMEOUT;
+        for (addr, done) in waiting {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if let Err(RecvTimeoutError::Timeout) = done.recv_timeout(timeout) {
+                warn!("{}: gave up sending queued packets", addr);
+            }
+        }
+    }
+}
+
+/**
+ * 1つのクライアントにストリームを送り続ける
+ */
+fn serve_client(
+    stream: TcpStream,
+    link_type: LinkType,
+    clients: Arc<Mutex<Vec<Client>>>,
+) -> Result<()> {
+    let addr = stream.peer_addr()?;
+    let filter = read_filter(&stream).with_context(|| format!("{}", addr))?;
+    match &filter {
+        Some(filter) => info!("{}: connected with filter {:?}", addr, filter),
+        None => info!("{}: connected", addr),
+    }
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+
+    let (sender, receiver) = mpsc::sync_channel(CLIENT_QUEUE_LEN);
+    let dropped = Arc::new(AtomicU64::new(0));
+    let (_done, done) = mpsc::channel();
+    clients.lock().unwrap().push(Client {
+        addr,
+        filter,
+        sender,
+        dropped: dropped.clone(),
+        done,
+    });
+    let mut sent = 0;
+    let result = write_stream(BufWriter::new(stream), link_type, &receiver, &mut sent);
+    // 受信側を閉じると、次に配信するときにクライアントの一覧から外れる
+    drop(receiver);
+    info!(
+        "{}: disconnected, {} packets sent, {} dropped",
+        addr,
+        sent,
+        dropped.load(Ordering::Relaxed)
+    );
+    result.with_context(|| format!("{}", addr))
+}
+
+/**
+ * 接続直後に送られてくる表示フィルタの行を読む
+ * 一定時間内に何も送られてこなければフィルタなしとする
+ */
+fn read_filter(stream: &TcpStream) -> Result<Option<Filter>> {
+    stream.set_read_timeout(Some(FILTER_TIMEOUT))?;
+    let mut line = String::new();
+    match BufReader::new(stream).read_line(&mut line) {
+        Ok(_) => {}
+        Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+        Err(e) => return Err(e.into()),
+    }
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(line.parse()?))
+}
+
+fn write_stream<W: Write>(
+    writer: W,
+    link_type: LinkType,
+    receiver: &Receiver<Arc<Packet>>,
+    sent: &mut u64,
+) -> Result<()> {
+    // フィルタに一致するフレームが来なくても読み始められるよう、ヘッダはすぐに送る
+    let mut writer = PcapWriter::new(writer, link_type.to_pcap(), SNAPLEN)?;
+    writer.flush()?;
+    let mut next = receiver.recv().ok();
+    while let Some(packet) = next {
+        writer.write_record(packet.timestamp, packet.len, &packet.data)?;
+        *sent += 1;
+        // 溜まっている分をまとめて書いてから送り出す
+        next = match receiver.try_recv() {
+            Ok(packet) => Some(packet),
+            Err(_) => {
+                writer.flush()?;
+                receiver.recv().ok()
+            }
+        };
+    }
+    writer.flush()
+}
+
+/**
+ * --serveで配信しているストリームに接続し、キャプチャの入力元にする
+ * フィルタを指定した場合はサーバ側で絞り込んでもらう
+ */
+pub fn connect(addr: &str, filter: Option<&str>) -> Result<Capture> {
+    let mut stream =
+        TcpStream::connect(addr).with_context(|| format!("Failed to connect to {}", addr))?;
+    writeln!(stream, "{}", filter.unwrap_or_default())?;
+    // サーバは接続するとすぐにヘッダを送ってくる
+    let reader = PcapReader::new(BufReader::new(stream)).context("Failed to read stream")?;
+    let link_type = LinkType::from_pcap(reader.link_type)
+        .with_context(|| format!("Unhandled link type {}", reader.link_type))?;
+
+    let (sender, receiver) = mpsc::sync_channel(RECEIVE_QUEUE_LEN);
+    let interface = addr.to_string();
+    thread::spawn(move || {
+        if let Err(e) = receive_stream(reader, link_type, &interface, sender) {
+            warn!("{}: {:#}", interface, e);
+        }
+    });
+    Ok(Capture::from_frames(receiver, link_type))
+}
+
+/**
+ * 受信したストリームのレコードをフレームにしてチャネルに送る
+ * 解析が遅い場合は送信側に待ってもらい、サーバのキューで破棄される
+ */
+fn receive_stream(
+    mut reader: PcapReader<BufReader<TcpStream>>,
+    link_type: LinkType,
+    interface: &str,
+    sender: SyncSender<Frame>,
+) -> Result<()> {
+    while let Some(pcap_record) = reader.next_record()? {
+        let frame = Frame {
+            interface: interface.to_string(),
+            link_type,
+            timestamp: pcap_record.timestamp,
+            len: pcap_record.orig_len,
+            data: pcap_record.data,
+        };
+        // 解析側が終了していたら受信もやめる
+        if sender.send(frame).is_err() {
+            return Ok(());
+        }
+    }
+    info!("{}: stream closed", interface);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, Tcp};
+    use pnet::packet::tcp::TcpFlags::SYN;
+
+    fn frame(timestamp: f64, data: Vec<u8>) -> Frame {
+        Frame {
+            interface: "test".to_string(),
+            link_type: LinkType::Ethernet,
+            timestamp,
+            len: data.len(),
+            data,
+        }
+    }
+
+    /**
+     * 接続してフィルタの行を送り、サーバがクライアントとして登録するまで待つ
+     */
+    fn connect_raw(server: &Server, filter: &str) -> TcpStream {
+        let clients = server.clients.lock().unwrap().len();
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        writeln!(stream, "{}", filter).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.clients.lock().unwrap().len() == clients {
+            assert!(Instant::now() < deadline, "client was not registered");
+            thread::sleep(Duration::from_millis(10));
+        }
+        stream
+    }
+
+    #[test]
+    fn streams_matching_frames() {
+        let mut server = Server::start("127.0.0.1:0", LinkType::Ethernet).unwrap();
+        let stream = connect_raw(&server, "udp");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        // フレームを配信する前からヘッダを読める
+        let mut reader = PcapReader::new(BufReader::new(stream)).unwrap();
+        assert_eq!(reader.link_type, LinkType::Ethernet.to_pcap());
+        assert_eq!(reader.snaplen, SNAPLEN);
+
+        let tcp = Tcp::new("10.0.0.1:40000", "10.0.0.2:80", SYN).frame();
+        let udp = testutil::udp("10.0.0.1:5000", "10.0.0.2:53", b"query");
+        let records = testutil::records(&testutil::pcap(&[(1.0, tcp.clone()), (2.5, udp.clone())]));
+        server.publish(&frame(1.0, tcp), Some(&records[0]));
+        server.publish(&frame(2.5, udp.clone()), Some(&records[1]));
+        // レコードのないフレームと、リンク層の異なるフレームはフィルタのあるクライアントに送らない
+        server.publish(&frame(3.0, udp.clone()), None);
+        let mut raw = frame(4.0, udp[14..].to_vec());
+        raw.link_type = LinkType::RawIp;
+        server.publish(&raw, Some(&records[1]));
+        server.finish();
+
+        let record = reader.next_record().unwrap().unwrap();
+        assert_eq!(record.timestamp, 2.5);
+        assert_eq!(record.orig_len, udp.len());
+        assert_eq!(record.data, udp);
+        assert!(reader.next_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn connect_reads_the_header_before_any_frame() {
+        let mut server = Server::start("127.0.0.1:0", LinkType::Ethernet).unwrap();
+        let capture = connect(&server.local_addr().to_string(), None).unwrap();
+        assert_eq!(capture.link_type, LinkType::Ethernet);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while server.clients.lock().unwrap().is_empty() {
+            assert!(Instant::now() < deadline, "client was not registered");
+            thread::sleep(Duration::from_millis(10));
+        }
+        let data = Tcp::new("10.0.0.1:40000", "10.0.0.2:80", SYN).frame();
+        server.publish(&frame(1.0, data.clone()), None);
+        let received = capture.frames.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(received.data, data);
+        assert_eq!(received.link_type, LinkType::Ethernet);
+        server.finish();
+    }
+
+    #[test]
+    fn drops_frames_for_a_slow_client() {
+        let mut server = Server::start("127.0.0.1:0", LinkType::Ethernet).unwrap();
+        let stream = connect_raw(&server, "");
+        // ソケットのバッファとキューが溢れるまで、読み込まずに配信する
+        let data = testutil::udp("10.0.0.1:5000", "10.0.0.2:9", &[0; 1400]);
+        let published = 20_000;
+        for i in 0..published {
+            server.publish(&frame(i as f64, data.clone()), None);
+        }
+        let dropped = server.clients.lock().unwrap()[0].dropped.clone();
+        assert!(dropped.load(Ordering::Relaxed) > 0);
+
+        let reading = thread::spawn(move || {
+            let mut reader = PcapReader::new(BufReader::new(stream)).unwrap();
+            let mut received = 0;
+            while reader.next_record().unwrap().is_some() {
+                received += 1;
+            }
+            received
+        });
+        server.finish();
+        let received = reading.join().unwrap();
+        assert_eq!(received + dropped.load(Ordering::Relaxed), published);
+    }
+}
@@ -0,0 +1,364 @@
+use crate::capture;
+use crate::link::LinkType;
+use crate::pcap::PcapReader;
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use pnet::{
+    packet::{
+        ethernet::{EtherTypes, MutableEthernetPacket},
+        ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+        ipv4::{self, MutableIpv4Packet},
+        ipv6::MutableIpv6Packet,
+        tcp::{self, MutableTcpPacket},
+        udp::{self, MutableUdpPacket},
+        MutablePacket,
+    },
+    util::MacAddr,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    net::IpAddr,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+/**
+ * 送信間隔の決め方
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timing {
+    /// キャプチャ時の間隔をspeed倍速で再現する
+    Original { speed: f64 },
+    /// 1秒あたりのパケット数で送信する
+    Rate { pps: f64 },
+}
+
+/**
+ * 送信前にフレームを書き換える規則
+ * 送信元・宛先のどちらであっても、キーに一致したものを値に置き換える
+ */
+#[derive(Debug, Default)]
+pub struct Rewrite {
+    pub macs: HashMap<MacAddr, MacAddr>,
+    pub ips: HashMap<IpAddr, IpAddr>,
+    pub ports: HashMap<u16, u16>,
+}
+
+#[derive(Debug)]
+pub struct ReplayOptions {
+    pub file: String,
+    pub interface: String,
+    pub timing: Timing,
+    pub rewrite: Rewrite,
+}
+
+/**
+ * "OLD=NEW"の形の書き換え規則を解析する
+ */
+pub fn parse_rule<T: FromStr>(rule: &str) -> Result<(T, T)> {
+    let (old, new) = rule
+        .split_once('=')
+        .with_context(|| format!("Rewrite rule must be OLD=NEW: {}", rule))?;
+    let parse = |s: &str| {
+        s.parse::<T>()
+            .map_err(|_| anyhow!("invalid value in rewrite rule: {}", s))
+    };
+    Ok((parse(old)?, parse(new)?))
+}
+
+/**
+ * pcapファイルのフレームをインターフェイスから送信し直す
+ */
+pub fn run(options: &ReplayOptions) -> Result<()> {
+    let file =
+        File::open(&options.file).with_context(|| format!("Failed to open {}", options.file))?;
+    let mut reader = PcapReader::new(BufReader::new(file))?;
+    let file_link = LinkType::from_pcap(reader.link_type)
+        .with_context(|| format!("Unhandled link type {}", reader.link_type))?;
+
+    let interface = capture::find_interface(&options.interface)?;
+    let link_type = LinkType::from_interface(&interface);
+    // リンク層ヘッダを付け替えることはしないので、種類が一致している必要がある
+    if file_link != link_type {
+        return Err(anyhow!(
+            "Link type mismatch: file is {} but {} is {}",
+            file_link.name(),
+            interface.name,
+            link_type.name()
+        ));
+    }
+    let (mut tx, _rx) = capture::open(&interface)?;
+
+    let start = Instant::now();
+    let mut first_timestamp = None;
+    let mut sent = 0u64;
+    let mut bytes = 0u64;
+    let mut failed = 0u64;
+    while let Some(mut record) = reader.next_record()? {
+        // 送信時刻まで待つ
+        let first = *first_timestamp.get_or_insert(record.timestamp);
+        let due = send_time(options.timing, record.timestamp - first, sent + failed)?;
+        if let Some(wait) = due.checked_sub(start.elapsed()) {
+            thread::sleep(wait);
+        }
+
+        options.rewrite.apply(link_type, &mut record.data);
+        match tx.send_to(&record.data, None) {
+            Some(Ok(())) => {
+                sent += 1;
+                bytes += record.data.len() as u64;
+            }
+            Some(Err(e)) => {
+                failed += 1;
+                warn!("Failed to send frame {}: {}", sent + failed, e);
+            }
+            None => return Err(anyhow!("Failed to send frame: buffer unavailable")),
+        }
+    }
+
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "Replayed {} frames ({} bytes) on {} in {:.3}s, {} failed",
+        sent, bytes, interface.name, elapsed, failed
+    );
+    Ok(())
+}
+
+/**
+ * 送信を始めてからフレームを送るまでの時間を求める
+ * elapsedは先頭のフレームからのキャプチャ時刻の差
+ */
+fn send_time(timing: Timing, elapsed: f64, sent: u64) -> Result<Duration> {
+    let secs = match timing {
+        Timing::Original { speed } => elapsed.max(0.0) / speed,
+        Timing::Rate { pps } => sent as f64 / pps,
+    };
+    // 極端に遅い速度や壊れたタイムスタンプではDurationに収まらない
+    Duration::try_from_secs_f64(secs).map_err(|_| {
+        anyhow!(
+            "Send time of frame {} is out of range ({}s)",
+            sent + 1,
+            secs
+        )
+    })
+}
+
+impl Rewrite {
+    pub fn is_empty(&self) -> bool {
+        self.macs.is_empty() && self.ips.is_empty() && self.ports.is_empty()
+    }
+
+    /**
+     * フレームを書き換え、チェックサムを計算し直す
+     * 書き換えがなければフレームには触れない
+     */
+    pub fn apply(&self, link_type: LinkType, frame: &mut [u8]) {
+        if self.is_empty() {
+            return;
+        }
+        match link_type {
+            LinkType::Ethernet | LinkType::Loopback => {
+                if let Some(mut eth) = MutableEthernetPacket::new(frame) {
+                    if let Some(mac) = self.macs.get(&eth.get_source()) {
+                        eth.set_source(*mac);
+                    }
+                    if let Some(mac) = self.macs.get(&eth.get_destination()) {
+                        eth.set_destination(*mac);
+                    }
+                    match eth.get_ethertype() {
+                        EtherTypes::Ipv4 => self.apply_ipv4(eth.payload_mut()),
+                        EtherTypes::Ipv6 => self.apply_ipv6(eth.payload_mut()),
+                        _ => {}
+                    }
+                }
+            }
+            LinkType::RawIp => match frame.first().map(|b| b >> 4) {
+                Some(4) => self.apply_ipv4(frame),
+                Some(6) => self.apply_ipv6(frame),
+                _ => {}
+            },
+            LinkType::Null | LinkType::LinuxSll => {}
+        }
+    }
+
+    fn apply_ipv4(&self, buf: &mut [u8]) {
+        let mut packet = match MutableIpv4Packet::new(buf) {
+            Some(packet) => packet,
+            None => return,
+        };
+        if let Some(IpAddr::V4(ip)) = self.ips.get(&IpAddr::V4(packet.get_source())) {
+            packet.set_source(*ip);
+        }
+        if let Some(IpAddr::V4(ip)) = self.ips.get(&IpAddr::V4(packet.get_destination())) {
+            packet.set_destination(*ip);
+        }
+        // 先頭以外のフラグメントにはL4ヘッダがない
+        if packet.get_fragment_offset() == 0 {
+            let src = IpAddr::V4(packet.get_source());
+            let dst = IpAddr::V4(packet.get_destination());
+            let protocol = packet.get_next_level_protocol();
+            self.apply_transport(protocol, packet.payload_mut(), src, dst);
+        }
+        let checksum = ipv4::checksum(&packet.to_immutable());
+        packet.set_checksum(checksum);
+    }
+
+    fn apply_ipv6(&self, buf: &mut [u8]) {
+        let mut packet = match MutableIpv6Packet::new(buf) {
+            Some(packet) => packet,
+            None => return,
+        };
+        if let Some(IpAddr::V6(ip)) = self.ips.get(&IpAddr::V6(packet.get_source())) {
+            packet.set_source(*ip);
+        }
+        if let Some(IpAddr::V6(ip)) = self.ips.get(&IpAddr::V6(packet.get_destination())) {
+            packet.set_destination(*ip);
+        }
+        let src = IpAddr::V6(packet.get_source());
+        let dst = IpAddr::V6(packet.get_destination());
+        let protocol = packet.get_next_header();
+        self.apply_transport(protocol, packet.payload_mut(), src, dst);
+    }
+
+    /**
+     * ポート番号を書き換え、疑似ヘッダを含めたチェックサムを計算し直す
+     */
+    fn apply_transport(
+        &self,
+        protocol: IpNextHeaderProtocol,
+        buf: &mut [u8],
+        src: IpAddr,
+        dst: IpAddr,
+    ) {
+        match protocol {
+            IpNextHeaderProtocols::Tcp => {
+                if let Some(mut tcp) = MutableTcpPacket::new(buf) {
+                    if let Some(port) = self.ports.get(&tcp.get_source()) {
+                        tcp.set_source(*port);
+                    }
+                    if let Some(port) = self.ports.get(&tcp.get_destination()) {
+                        tcp.set_destination(*port);
+                    }
+                    let checksum = match (src, dst) {
+                        (IpAddr::V4(s), IpAddr::V4(d)) => {
+                            tcp::ipv4_checksum(&tcp.to_immutable(), &s, &d)
+                        }
+                        (IpAddr::V6(s), IpAddr::V6(d)) => {
+                            tcp::ipv6_checksum(&tcp.to_immutable(), &s, &d)
+                        }
+                        _ => return,
+                    };
+                    tcp.set_checksum(checksum);
+                }
+            }
+            IpNextHeaderProtocols::Udp => {
+                if let Some(mut udp) = MutableUdpPacket::new(buf) {
+                    if let Some(port) = self.ports.get(&udp.get_source()) {
+                        udp.set_source(*port);
+                    }
+                    if let Some(port) = self.ports.get(&udp.get_destination()) {
+                        udp.set_destination(*port);
+                    }
+                    // IPv4ではチェックサム0は「計算していない」を意味するので、そのままにする
+                    let checksum = match (src, dst) {
+                        (IpAddr::V4(_), IpAddr::V4(_)) if udp.get_checksum() == 0 => return,
+                        (IpAddr::V4(s), IpAddr::V4(d)) => {
+                            udp::ipv4_checksum(&udp.to_immutable(), &s, &d)
+                        }
+                        (IpAddr::V6(s), IpAddr::V6(d)) => {
+                            udp::ipv6_checksum(&udp.to_immutable(), &s, &d)
+                        }
+                        _ => return,
+                    };
+                    udp.set_checksum(checksum);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decoder::Severity;
+    use crate::testutil::{self, Tcp};
+    use pnet::packet::{ethernet::EthernetPacket, ipv4::Ipv4Packet, tcp::TcpFlags, Packet};
+
+    const IP_OFFSET: usize = 14;
+    const L4_OFFSET: usize = IP_OFFSET + 20;
+
+    fn rewrite() -> Rewrite {
+        let mut rewrite = Rewrite::default();
+        rewrite
+            .ips
+            .insert("10.0.0.1".parse().unwrap(), "192.168.0.1".parse().unwrap());
+        rewrite.ports.insert(80, 8080);
+        rewrite
+    }
+
+    #[test]
+    fn scales_original_timing() {
+        let original = Timing::Original { speed: 2.0 };
+        assert_eq!(
+            send_time(original, 3.0, 5).unwrap(),
+            Duration::from_millis(1500)
+        );
+        // タイムスタンプが戻った場合はすぐに送る
+        assert_eq!(send_time(original, -1.0, 5).unwrap(), Duration::ZERO);
+        let rate = Timing::Rate { pps: 4.0 };
+        assert_eq!(
+            send_time(rate, 100.0, 2).unwrap(),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn out_of_range_send_time_is_an_error() {
+        let slow = Timing::Original { speed: 1e-300 };
+        assert!(send_time(slow, 1.0, 1).is_err());
+        assert!(send_time(Timing::Rate { pps: 1e-300 }, 0.0, 1).is_err());
+    }
+
+    #[test]
+    fn rewrites_addresses_ports_and_checksums() {
+        let mut frame = Tcp::new("10.0.0.2:50000", "10.0.0.1:80", TcpFlags::SYN).frame();
+        rewrite().apply(LinkType::Ethernet, &mut frame);
+
+        let records = testutil::records(&testutil::pcap(&[(0.0, frame)]));
+        let record = &records[0];
+        assert_eq!(record.dst_ip, "192.168.0.1");
+        assert_eq!(record.dst_port, 8080);
+        assert_eq!(record.src_port, 50000);
+        assert!(record
+            .expert
+            .iter()
+            .all(|info| info.severity != Severity::Error));
+    }
+
+    #[test]
+    fn leaves_non_first_fragments_transport_alone() {
+        let mut frame = Tcp::new("10.0.0.2:50000", "10.0.0.1:80", TcpFlags::SYN)
+            .payload(b"fragment data")
+            .frame();
+        {
+            let mut ip = MutableIpv4Packet::new(&mut frame[IP_OFFSET..]).unwrap();
+            ip.set_flags(0);
+            ip.set_fragment_offset(185);
+            let checksum = ipv4::checksum(&ip.to_immutable());
+            ip.set_checksum(checksum);
+        }
+        let original = frame.clone();
+        rewrite().apply(LinkType::Ethernet, &mut frame);
+
+        // ポートに見える位置のバイトもチェックサムも書き換えない
+        assert_eq!(frame[L4_OFFSET..], original[L4_OFFSET..]);
+        let eth = EthernetPacket::new(&frame).unwrap();
+        let ip = Ipv4Packet::new(eth.payload()).unwrap();
+        assert_eq!(ip.get_destination().to_string(), "192.168.0.1");
+        assert_eq!(ip.get_checksum(), ipv4::checksum(&ip));
+    }
+}
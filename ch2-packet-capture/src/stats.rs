@@ -0,0 +1,438 @@
+use crate::output::Report;
+use crate::record::PacketRecord;
+use anyhow::Result;
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Write,
+};
+
+/**
+ * 双方向のフローを識別するキー
+ * 送信元と宛先を入れ替えても同じキーになるように、小さい方をaに置く
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FlowKey {
+    pub protocol: &'static str,
+    pub a: (String, u16),
+    pub b: (String, u16),
+}
+
+impl FlowKey {
+    pub fn from_record(record: &PacketRecord) -> Self {
+        let src = (record.src_ip.clone(), record.src_port);
+        let dst = (record.dst_ip.clone(), record.dst_port);
+        let (a, b) = if src <= dst { (src, dst) } else { (dst, src) };
+        Self {
+            protocol: record.protocol,
+            a,
+            b,
+        }
+    }
+}
+
+/**
+ * フローごとの集計
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Flow {
+    /// a→b方向のパケット数とバイト数
+    pub a_to_b: (u64, u64),
+    /// b→a方向のパケット数とバイト数
+    pub b_to_a: (u64, u64),
+    pub first_seen: f64,
+    pub last_seen: f64,
+    pub tcp_flags: BTreeSet<String>,
+}
+
+impl Flow {
+    pub fn packets(&self) -> u64 {
+        self.a_to_b.0 + self.b_to_a.0
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.a_to_b.1 + self.b_to_a.1
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.last_seen - self.first_seen
+    }
+
+    /**
+     * 観測したTCPフラグを"ACK|SYN"のような文字列にする
+     */
+    pub fn flags_string(&self) -> String {
+        self.tcp_flags.iter().cloned().collect::<Vec<_>>().join("|")
+    }
+}
+
+/**
+ * ヒストグラムの1要素（パケット数とバイト数）
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Counter {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl Counter {
+    fn add(&mut self, bytes: usize) {
+        self.packets += 1;
+        self.bytes += bytes as u64;
+    }
+}
+
+/**
+ * フローテーブルと各種ヒストグラム
+ */
+#[derive(Debug, Default)]
+pub struct FlowTable {
+    pub flows: HashMap<FlowKey, Flow>,
+    pub hosts: HashMap<String, Counter>,
+    pub protocols: HashMap<String, Counter>,
+    pub ports: HashMap<(&'static str, u16), Counter>,
+}
+
+impl FlowTable {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /**
+     * フロー・ホスト・ポートのうち最も多いものの数
+     */
+    pub fn len(&self) -> usize {
+        self.flows.len().max(self.hosts.len()).max(self.ports.len())
+    }
+
+    /**
+     * それぞれkeep件まで減らす
+     * フローは最後に見た時刻の新しいものを、ホストとポートはバイト数の多いものを残す
+     */
+    pub fn shrink(&mut self, keep: usize) {
+        if self.flows.len() > keep {
+            let mut last_seen: Vec<f64> = self.flows.values().map(|f| f.last_seen).collect();
+            let cut = self.flows.len() - keep;
+            last_seen.select_nth_unstable_by(cut - 1, f64::total_cmp);
+            let threshold = last_seen[cut - 1];
+            // 同じ時刻のフローが境目をまたぐ場合は、それらも含めて捨てる
+            self.flows.retain(|_, flow| flow.last_seen > threshold);
+        }
+        shrink_counters(&mut self.hosts, keep);
+        shrink_counters(&mut self.ports, keep);
+    }
+
+    /**
+     * バイト数の多い順にフローを返す
+     */
+    pub fn top_flows(&self, top: usize) -> Vec<(&FlowKey, &Flow)> {
+        let mut flows: Vec<_> = self.flows.iter().collect();
+        flows.sort_by(|x, y| y.1.bytes().cmp(&x.1.bytes()).then(x.0.cmp(y.0)));
+        flows.truncate(top);
+        flows
+    }
+}
+
+impl Report for FlowTable {
+    fn add(&mut self, record: &PacketRecord) {
+        let bytes = record.frame_len;
+        let key = FlowKey::from_record(record);
+        let forward = key.a == (record.src_ip.clone(), record.src_port);
+        let flow = self.flows.entry(key).or_insert_with(|| Flow {
+            first_seen: record.timestamp,
+            ..Default::default()
+        });
+        let direction = if forward {
+            &mut flow.a_to_b
+        } else {
+            &mut flow.b_to_a
+        };
+        direction.0 += 1;
+        direction.1 += bytes as u64;
+        flow.last_seen = flow.last_seen.max(record.timestamp);
+        if let Some(flags) = &record.tcp_flags {
+            flow.tcp_flags.extend(
+                flags
+                    .split('|')
+                    .filter(|f| !f.is_empty())
+                    .map(str::to_string),
+            );
+        }
+
+        self.hosts
+            .entry(record.src_ip.clone())
+            .or_default()
+            .add(bytes);
+        self.hosts
+            .entry(record.dst_ip.clone())
+            .or_default()
+            .add(bytes);
+
+        // アプリケーション層まで解析できた場合はそのプロトコル名で集計する
+        let protocol = match &record.application {
+            Some(tree) => format!("{}/{}", record.protocol, tree.protocol),
+            None => record.protocol.to_string(),
+        };
+        self.protocols.entry(protocol).or_default().add(bytes);
+
+        // エフェメラルポートではない側（小さい方）をサービスのポートとみなす
+        let port = record.src_port.min(record.dst_port);
+        self.ports
+            .entry((record.protocol, port))
+            .or_default()
+            .add(bytes);
+    }
+
+    fn report<W: Write>(&self, w: &mut W, top: usize) -> Result<()> {
+        let flows = self.top_flows(top);
+        writeln!(w, "=== Flows ({} total) ===", self.flows.len())?;
+        writeln!(
+            w,
+            "{:<5} {:<24}     {:<24} {:>8} {:>10} {:>10}  Flags",
+            "Proto", "Endpoint A", "Endpoint B", "Packets", "Bytes", "Duration"
+        )?;
+        for (key, flow) in flows {
+            writeln!(
+                w,
+                "{:<5} {:<24} <-> {:<24} {:>8} {:>10} {:>9.3}s  {}",
+                key.protocol,
+                endpoint(&key.a),
+                endpoint(&key.b),
+                flow.packets(),
+                flow.bytes(),
+                flow.duration(),
+                flow.flags_string(),
+            )?;
+        }
+        writeln!(w)?;
+
+        writeln!(w, "=== Top talkers ===")?;
+        write_histogram(w, sorted(&self.hosts, top), |host| host.to_string())?;
+        writeln!(w)?;
+
+        writeln!(w, "=== Protocols ===")?;
+        write_histogram(w, sorted(&self.protocols, top), |p| p.to_string())?;
+        writeln!(w)?;
+
+        writeln!(w, "=== Ports ===")?;
+        write_histogram(w, sorted(&self.ports, top), |(proto, port)| {
+            format!("{}/{}", proto, port)
+        })?;
+        writeln!(w)?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+pub fn endpoint((ip, port): &(String, u16)) -> String {
+    // IPv6アドレスは:を含むので[]で囲む
+    if ip.contains(':') {
+        format!("[{}]:{}", ip, port)
+    } else {
+        format!("{}:{}", ip, port)
+    }
+}
+
+/**
+ * バイト数の多い順にtop件を返す
+ */
+fn sorted<K: Ord>(map: &HashMap<K, Counter>, top: usize) -> Vec<(&K, &Counter)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by(|x, y| y.1.bytes.cmp(&x.1.bytes).then(x.0.cmp(y.0)));
+    entries.truncate(top);
+    entries
+}
+
+/**
+ * バイト数の多いkeep件だけを残す
+ */
+fn shrink_counters<K: Ord + Clone + std::hash::Hash>(map: &mut HashMap<K, Counter>, keep: usize) {
+    if map.len() > keep {
+        let kept: Vec<K> = sorted(map, keep)
+            .into_iter()
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut old = std::mem::take(map);
+        map.extend(kept.into_iter().filter_map(|key| old.remove_entry(&key)));
+    }
+}
+
+const BAR_WIDTH: usize = 40;
+
+fn write_histogram<W: Write, K, F: Fn(&K) -> String>(
+    w: &mut W,
+    entries: Vec<(&K, &Counter)>,
+    label: F,
+) -> Result<()> {
+    let max = entries
+        .iter()
+        .map(|(_, c)| c.bytes)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    for (key, counter) in entries {
+        let bar = (counter.bytes * BAR_WIDTH as u64 / max) as usize;
+        writeln!(
+            w,
+            "{:<24} {:>8} pkts {:>10} bytes  {}",
+            label(key),
+            counter.packets,
+            counter.bytes,
+            "#".repeat(bar.max(1)),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, Tcp};
+    use pnet::packet::tcp::TcpFlags::{ACK, FIN, PSH, SYN};
+
+    const CLIENT: &str = "10.0.0.2:50000";
+    const SERVER: &str = "10.0.0.1:80";
+    const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+    const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+
+    /**
+     * HTTPの1往復とUDPの1パケット、IPv6のSYNからなるpcap
+     */
+    fn fixture() -> Vec<(f64, Vec<u8>)> {
+        vec![
+            (100.0, Tcp::new(CLIENT, SERVER, SYN).seq(1000).frame()),
+            (
+                100.1,
+                Tcp::new(SERVER, CLIENT, SYN | ACK)
+                    .seq(5000)
+                    .ack(1001)
+                    .frame(),
+            ),
+            (
+                100.2,
+                Tcp::new(CLIENT, SERVER, ACK).seq(1001).ack(5001).frame(),
+            ),
+            (
+                100.3,
+                Tcp::new(CLIENT, SERVER, PSH | ACK)
+                    .seq(1001)
+                    .ack(5001)
+                    .payload(REQUEST)
+                    .frame(),
+            ),
+            (
+                100.4,
+                testutil::udp("10.0.0.3:40000", "10.0.0.2:9999", b"ping"),
+            ),
+            (
+                100.5,
+                Tcp::new(SERVER, CLIENT, PSH | ACK)
+                    .seq(5001)
+                    .ack(1001 + REQUEST.len() as u32)
+                    .payload(RESPONSE)
+                    .frame(),
+            ),
+            (
+                101.5,
+                Tcp::new(CLIENT, SERVER, FIN | ACK)
+                    .seq(1001 + REQUEST.len() as u32)
+                    .ack(5001 + RESPONSE.len() as u32)
+                    .frame(),
+            ),
+            (
+                102.0,
+                Tcp::new("[2001:db8::1]:40000", "[2001:db8::2]:443", SYN).frame(),
+            ),
+        ]
+    }
+
+    fn table() -> FlowTable {
+        let mut table = FlowTable::new();
+        for record in testutil::records(&testutil::pcap(&fixture())) {
+            table.add(&record);
+        }
+        table
+    }
+
+    fn http_key() -> FlowKey {
+        FlowKey {
+            protocol: "TCP",
+            a: ("10.0.0.1".to_string(), 80),
+            b: ("10.0.0.2".to_string(), 50000),
+        }
+    }
+
+    #[test]
+    fn merges_both_directions_into_one_flow() {
+        let table = table();
+        assert_eq!(table.flows.len(), 3);
+        let flow = &table.flows[&http_key()];
+        // aはアドレスの小さいサーバ側なので、a→bはサーバからの応答
+        let empty = 54;
+        assert_eq!(flow.a_to_b, (2, (empty * 2 + RESPONSE.len()) as u64));
+        assert_eq!(flow.b_to_a, (4, (empty * 4 + REQUEST.len()) as u64));
+        assert_eq!(flow.packets(), 6);
+        assert!((flow.duration() - 1.5).abs() < 1e-6);
+        assert_eq!(flow.flags_string(), "ACK|FIN|PSH|SYN");
+    }
+
+    #[test]
+    fn flow_key_is_independent_of_direction() {
+        let records = testutil::records(&testutil::pcap(&fixture()));
+        assert_eq!(FlowKey::from_record(&records[0]), http_key());
+        assert_eq!(FlowKey::from_record(&records[1]), http_key());
+
+        let udp = FlowKey::from_record(&records[4]);
+        assert_eq!(udp.protocol, "UDP");
+        assert_eq!(udp.a, ("10.0.0.2".to_string(), 9999));
+        assert_eq!(udp.b, ("10.0.0.3".to_string(), 40000));
+    }
+
+    #[test]
+    fn counts_hosts_protocols_and_ports() {
+        let table = table();
+        let http_bytes = table.flows[&http_key()].bytes();
+        let udp_bytes = 14 + 20 + 8 + 4;
+        let ipv6_bytes = 14 + 40 + 20;
+
+        // 10.0.0.2はHTTPとUDPの両方に現れる
+        let talkers = sorted(&table.hosts, 2);
+        assert_eq!(talkers[0].0, "10.0.0.2");
+        assert_eq!(talkers[0].1.packets, 7);
+        assert_eq!(talkers[0].1.bytes, http_bytes + udp_bytes);
+        assert_eq!(talkers[1].0, "10.0.0.1");
+        assert_eq!(talkers[1].1.bytes, http_bytes);
+
+        let protocols = &table.protocols;
+        assert_eq!(protocols["TCP/HTTP"].packets, 2);
+        assert_eq!(protocols["TCP"].packets, 5);
+        assert_eq!(protocols["UDP"].packets, 1);
+        assert_eq!(protocols["UDP"].bytes, udp_bytes);
+
+        assert_eq!(table.ports[&("TCP", 80)].packets, 6);
+        assert_eq!(table.ports[&("TCP", 80)].bytes, http_bytes);
+        assert_eq!(table.ports[&("UDP", 9999)].packets, 1);
+        assert_eq!(table.ports[&("TCP", 443)].bytes, ipv6_bytes);
+    }
+
+    #[test]
+    fn reports_top_entries() {
+        let table = table();
+        let mut out = vec![];
+        table.report(&mut out, 1).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+
+        assert_eq!(lines[0], "=== Flows (3 total) ===");
+        assert!(lines[2].starts_with("TCP   10.0.0.1:80"));
+        assert!(lines[2].contains("<-> 10.0.0.2:50000"));
+        assert!(lines[2].ends_with("1.500s  ACK|FIN|PSH|SYN"));
+        assert_eq!(lines[3], "");
+        assert_eq!(lines[4], "=== Top talkers ===");
+        assert!(lines[5].starts_with("10.0.0.2"));
+        assert!(lines[5].ends_with(&"#".repeat(BAR_WIDTH)));
+        assert_eq!(lines[7], "=== Protocols ===");
+        assert!(lines[8].starts_with("TCP "));
+        assert_eq!(lines[10], "=== Ports ===");
+        assert!(lines[11].starts_with("TCP/80 "));
+    }
+}
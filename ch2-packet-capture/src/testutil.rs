@@ -0,0 +1,226 @@
+use crate::capture::Frame;
+use crate::decoder::Registry;
+use crate::link::LinkType;
+use crate::pcap::{PcapReader, PcapWriter};
+use crate::record::PacketRecord;
+use pnet::packet::{
+    ethernet::{EtherTypes, MutableEthernetPacket},
+    ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+    ipv4::{self, MutableIpv4Packet},
+    ipv6::MutableIpv6Packet,
+    tcp::{self, MutableTcpPacket, TcpPacket},
+    udp::{self, MutableUdpPacket, UdpPacket},
+};
+use pnet::util::MacAddr;
+use std::net::{IpAddr, SocketAddr};
+
+const ETHERNET_HEADER_SIZE: usize = 14;
+const IPV4_HEADER_SIZE: usize = 20;
+const IPV6_HEADER_SIZE: usize = 40;
+const TCP_HEADER_SIZE: usize = 20;
+const UDP_HEADER_SIZE: usize = 8;
+pub const SRC_MAC: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 0x01);
+pub const DST_MAC: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 0x02);
+
+/**
+ * テスト用のTCPセグメント
+ */
+#[derive(Debug, Clone)]
+pub struct Tcp {
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u16,
+    pub window: u16,
+    pub payload: Vec<u8>,
+}
+
+impl Tcp {
+    /**
+     * "10.0.0.1:80"のような文字列で両端を指定する
+     */
+    pub fn new(src: &str, dst: &str, flags: u16) -> Self {
+        Self {
+            src: src.parse().unwrap(),
+            dst: dst.parse().unwrap(),
+            seq: 0,
+            ack: 0,
+            flags,
+            window: 65535,
+            payload: vec![],
+        }
+    }
+
+    pub fn seq(mut self, seq: u32) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    pub fn ack(mut self, ack: u32) -> Self {
+        self.ack = ack;
+        self
+    }
+
+    pub fn window(mut self, window: u16) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn payload(mut self, payload: &[u8]) -> Self {
+        self.payload = payload.to_vec();
+        self
+    }
+
+    /**
+     * 正しいチェックサムを持つイーサネットフレームにする
+     */
+    pub fn frame(&self) -> Vec<u8> {
+        let header_len = TCP_HEADER_SIZE;
+        let mut buf = vec![0u8; header_len + self.payload.len()];
+        let mut packet = MutableTcpPacket::new(&mut buf).unwrap();
+        packet.set_source(self.src.port());
+        packet.set_destination(self.dst.port());
+        packet.set_sequence(self.seq);
+        packet.set_acknowledgement(self.ack);
+        packet.set_data_offset((header_len / 4) as u8);
+        packet.set_flags(self.flags);
+        packet.set_window(self.window);
+        buf[header_len..].copy_from_slice(&self.payload);
+        let checksum = {
+            let packet = TcpPacket::new(&buf).unwrap();
+            match (self.src.ip(), self.dst.ip()) {
+                (IpAddr::V4(src), IpAddr::V4(dst)) => tcp::ipv4_checksum(&packet, &src, &dst),
+                (IpAddr::V6(src), IpAddr::V6(dst)) => tcp::ipv6_checksum(&packet, &src, &dst),
+                _ => panic!("Mixed address families"),
+            }
+        };
+        MutableTcpPacket::new(&mut buf)
+            .unwrap()
+            .set_checksum(checksum);
+        ethernet(
+            self.src.ip(),
+            self.dst.ip(),
+            IpNextHeaderProtocols::Tcp,
+            &buf,
+        )
+    }
+}
+
+/**
+ * 正しいチェックサムを持つUDPのイーサネットフレームを作る
+ */
+pub fn udp(src: &str, dst: &str, payload: &[u8]) -> Vec<u8> {
+    let src: SocketAddr = src.parse().unwrap();
+    let dst: SocketAddr = dst.parse().unwrap();
+    let mut buf = vec![0u8; UDP_HEADER_SIZE + payload.len()];
+    let mut packet = MutableUdpPacket::new(&mut buf).unwrap();
+    packet.set_source(src.port());
+    packet.set_destination(dst.port());
+    packet.set_length((UDP_HEADER_SIZE + payload.len()) as u16);
+    packet.set_payload(payload);
+    let checksum = {
+        let packet = UdpPacket::new(&buf).unwrap();
+        match (src.ip(), dst.ip()) {
+            (IpAddr::V4(s), IpAddr::V4(d)) => udp::ipv4_checksum(&packet, &s, &d),
+            (IpAddr::V6(s), IpAddr::V6(d)) => udp::ipv6_checksum(&packet, &s, &d),
+            _ => panic!("Mixed address families"),
+        }
+    };
+    MutableUdpPacket::new(&mut buf)
+        .unwrap()
+        .set_checksum(checksum);
+    ethernet(src.ip(), dst.ip(), IpNextHeaderProtocols::Udp, &buf)
+}
+
+/**
+ * L4のセグメントをIPヘッダとイーサネットヘッダで包む
+ */
+pub fn ethernet(
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: IpNextHeaderProtocol,
+    segment: &[u8],
+) -> Vec<u8> {
+    let ip = match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let mut buf = vec![0u8; IPV4_HEADER_SIZE + segment.len()];
+            let mut packet = MutableIpv4Packet::new(&mut buf).unwrap();
+            packet.set_version(4);
+            packet.set_header_length((IPV4_HEADER_SIZE / 4) as u8);
+            packet.set_total_length((IPV4_HEADER_SIZE + segment.len()) as u16);
+            packet.set_identification(0x1234);
+            packet.set_flags(0b010);
+            packet.set_ttl(64);
+            packet.set_next_level_protocol(protocol);
+            packet.set_source(src);
+            packet.set_destination(dst);
+            packet.set_payload(segment);
+            let checksum = ipv4::checksum(&packet.to_immutable());
+            packet.set_checksum(checksum);
+            buf
+        }
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            let mut buf = vec![0u8; IPV6_HEADER_SIZE + segment.len()];
+            let mut packet = MutableIpv6Packet::new(&mut buf).unwrap();
+            packet.set_version(6);
+            packet.set_payload_length(segment.len() as u16);
+            packet.set_next_header(protocol);
+            packet.set_hop_limit(64);
+            packet.set_source(src);
+            packet.set_destination(dst);
+            packet.set_payload(segment);
+            buf
+        }
+        _ => panic!("Mixed address families"),
+    };
+    let ethertype = if src.is_ipv4() {
+        EtherTypes::Ipv4
+    } else {
+        EtherTypes::Ipv6
+    };
+    let mut buf = vec![0u8; ETHERNET_HEADER_SIZE + ip.len()];
+    let mut packet = MutableEthernetPacket::new(&mut buf).unwrap();
+    packet.set_source(SRC_MAC);
+    packet.set_destination(DST_MAC);
+    packet.set_ethertype(ethertype);
+    packet.set_payload(&ip);
+    buf
+}
+
+/**
+ * (タイムスタンプ, イーサネットフレーム)の列をpcapファイルにする
+ */
+pub fn pcap(frames: &[(f64, Vec<u8>)]) -> Vec<u8> {
+    let mut buf = vec![];
+    let mut writer = PcapWriter::new(&mut buf, 1, 65535).unwrap();
+    for (timestamp, data) in frames {
+        writer.write_record(*timestamp, data.len(), data).unwrap();
+    }
+    writer.flush().unwrap();
+    buf
+}
+
+/**
+ * pcapファイルを読み込み、コマンドと同じ手順でレコードにする
+ * レコードにならなかったフレームは読み飛ばす
+ */
+pub fn records(pcap: &[u8]) -> Vec<PacketRecord> {
+    let mut reader = PcapReader::new(pcap).unwrap();
+    let link_type = LinkType::from_pcap(reader.link_type).unwrap();
+    let mut registry = Registry::default();
+    let mut records = vec![];
+    while let Some(record) = reader.next_record().unwrap() {
+        let frame = Frame {
+            interface: "test".to_string(),
+            link_type,
+            timestamp: record.timestamp,
+            len: record.orig_len,
+            data: record.data,
+        };
+        if let Ok(Some(record)) = crate::frame_handler(&frame, &mut registry) {
+            records.push(record);
+        }
+    }
+    records
+}
@@ -0,0 +1,879 @@
+use crate::decoder::Severity;
+use crate::dissectors::ProtoTree;
+use crate::filter::Filter;
+use crate::hexdump::HexDump;
+use crate::output::{Report, Sink};
+use crate::record::PacketRecord;
+use crate::stats::{self, FlowTable};
+use anyhow::Result;
+use ratatui::{
+    backend::{Backend, CrosstermBackend},
+    crossterm::{
+        event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+        execute,
+        terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Paragraph, Row, Table, TableState},
+    Frame, Terminal,
+};
+use std::{
+    io::{self, Stdout},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// 保持しておくパケット数の上限。超えたら古いものから捨てる
+const MAX_PACKETS: usize = 100_000;
+/// 保持しておくフロー・ホスト・ポートの数の上限。超えたらしばらく見ていないものから捨てる
+const MAX_FLOWS: usize = 10_000;
+/// 画面を描き直す最小間隔
+const DRAW_INTERVAL: Duration = Duration::from_millis(50);
+/// フロー一覧に表示する件数
+const TOP_FLOWS: usize = 100;
+
+/**
+ * 画面の上半分に表示する一覧
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Packets,
+    Flows,
+}
+
+struct Entry {
+    /// 受信した順の通し番号（1から）
+    number: u64,
+    record: PacketRecord,
+}
+
+/**
+ * TUIの状態
+ * 端末に依存しないので、TestBackendで描画すれば端末なしでも動かせる
+ */
+pub struct App {
+    packets: Vec<Entry>,
+    /// 一時停止中に届いたパケット
+    pending: Vec<Entry>,
+    /// フィルタに一致したパケットのpacketsでの位置
+    visible: Vec<usize>,
+    flows: FlowTable,
+    filter: Option<Filter>,
+    filter_text: String,
+    /// フィルタを編集中ならその文字列
+    input: Option<String>,
+    /// フィルタの構文エラーなど
+    message: Option<String>,
+    /// 選択中のパケットのvisibleでの位置
+    selected: usize,
+    /// 一覧の先頭に表示しているvisibleでの位置
+    offset: usize,
+    /// 最新のパケットを選択し続けるか
+    follow: bool,
+    detail_scroll: u16,
+    paused: bool,
+    view: View,
+    received: u64,
+    first_timestamp: Option<f64>,
+    /// 入力元を読み終えたか
+    finished: bool,
+    quit: bool,
+    hexdump: HexDump,
+}
+
+impl App {
+    pub fn new(filter_text: Option<String>) -> Result<Self> {
+        let filter = filter_text.as_deref().map(str::parse).transpose()?;
+        Ok(Self {
+            packets: vec![],
+            pending: vec![],
+            visible: vec![],
+            flows: FlowTable::new(),
+            filter,
+            filter_text: filter_text.unwrap_or_default(),
+            input: None,
+            message: None,
+            selected: 0,
+            offset: 0,
+            follow: true,
+            detail_scroll: 0,
+            paused: false,
+            view: View::Packets,
+            received: 0,
+            first_timestamp: None,
+            finished: false,
+            quit: false,
+            // 色は層ごとではなく画面の側で付けるので使わない。複数バイト文字は桁がずれるので表示しない
+            hexdump: HexDump {
+                color: false,
+                utf8: false,
+                ..Default::default()
+            },
+        })
+    }
+
+    pub fn push(&mut self, record: PacketRecord) {
+        self.received += 1;
+        let entry = Entry {
+            number: self.received,
+            record,
+        };
+        if self.paused {
+            self.pending.push(entry);
+            // 一時停止が長引いてもメモリを使い切らないよう、packetsと同じように古いものから捨てる
+            if self.pending.len() > MAX_PACKETS {
+                self.pending.drain(..MAX_PACKETS / 10);
+            }
+        } else {
+            self.append(entry);
+        }
+    }
+
+    fn append(&mut self, entry: Entry) {
+        self.first_timestamp.get_or_insert(entry.record.timestamp);
+        self.flows.add(&entry.record);
+        if self.flows.len() > MAX_FLOWS {
+            self.flows.shrink(MAX_FLOWS - MAX_FLOWS / 10);
+        }
+        if self.matches(&entry.record) {
+            self.visible.push(self.packets.len());
+        }
+        self.packets.push(entry);
+        if self.packets.len() > MAX_PACKETS {
+            // visibleは捨てる前の位置を指しているので、先に選択中のパケットを調べておく
+            let selected_number = self.selected_entry().map(|e| e.number);
+            // 1件ずつ捨てると毎回ずらすことになるので、1割まとめて捨てる
+            self.packets.drain(..MAX_PACKETS / 10);
+            self.refilter(selected_number);
+        }
+    }
+
+    fn matches(&self, record: &PacketRecord) -> bool {
+        self.filter.as_ref().is_none_or(|f| f.matches(record))
+    }
+
+    /**
+     * フィルタを変えたときに表示するパケットを選び直す
+     * 選択していた番号selected_numberのパケットが残っていれば選択し続ける
+     */
+    fn refilter(&mut self, selected_number: Option<u64>) {
+        self.visible = (0..self.packets.len())
+            .filter(|i| self.matches(&self.packets[*i].record))
+            .collect();
+        self.selected = selected_number
+            .and_then(|n| {
+                self.visible
+                    .iter()
+                    .position(|i| self.packets[*i].number == n)
+            })
+            .unwrap_or(0);
+        self.offset = self.offset.min(self.selected);
+    }
+
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    fn selected_entry(&self) -> Option<&Entry> {
+        let index = if self.follow {
+            self.visible.last()?
+        } else {
+            self.visible.get(self.selected)?
+        };
+        self.packets.get(*index)
+    }
+
+    fn select(&mut self, selected: usize) {
+        self.selected = selected.min(self.visible.len().saturating_sub(1));
+        self.follow = false;
+        self.detail_scroll = 0;
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+        if let Some(input) = &mut self.input {
+            match key.code {
+                KeyCode::Enter => {
+                    let text = input.trim().to_string();
+                    self.input = None;
+                    self.set_filter(text);
+                }
+                KeyCode::Esc => self.input = None,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            }
+            return;
+        }
+
+        // 追従中は末尾を選択しているものとして移動する
+        let current = if self.follow {
+            self.visible.len().saturating_sub(1)
+        } else {
+            self.selected
+        };
+        let page = 20;
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Char(' ') | KeyCode::Char('p') => self.toggle_pause(),
+            KeyCode::Char('/') => self.input = Some(self.filter_text.clone()),
+            KeyCode::Tab => {
+                self.view = match self.view {
+                    View::Packets => View::Flows,
+                    View::Flows => View::Packets,
+                }
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.select(current.saturating_sub(1)),
+            KeyCode::Down | KeyCode::Char('j') => self.select(current + 1),
+            KeyCode::PageUp => self.select(current.saturating_sub(page)),
+            KeyCode::PageDown => self.select(current + page),
+            KeyCode::Home | KeyCode::Char('g') => self.select(0),
+            KeyCode::End | KeyCode::Char('G') => {
+                self.follow = true;
+                self.detail_scroll = 0;
+            }
+            KeyCode::Char('K') => self.detail_scroll = self.detail_scroll.saturating_sub(1),
+            KeyCode::Char('J') => self.detail_scroll = self.detail_scroll.saturating_add(1),
+            _ => {}
+        }
+    }
+
+    fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        if !self.paused {
+            for entry in std::mem::take(&mut self.pending) {
+                self.append(entry);
+            }
+        }
+    }
+
+    /**
+     * フィルタを変える。空文字列の場合はフィルタを外す
+     */
+    pub fn set_filter(&mut self, text: String) {
+        let filter = if text.is_empty() {
+            None
+        } else {
+            match text.parse() {
+                Ok(filter) => Some(filter),
+                Err(e) => {
+                    self.message = Some(format!("{:#}", e));
+                    return;
+                }
+            }
+        };
+        self.filter = filter;
+        self.filter_text = text;
+        self.message = None;
+        self.refilter(self.selected_entry().map(|e| e.number));
+    }
+
+    pub fn draw(&mut self, frame: &mut Frame) {
+        let [top, middle, status] = Layout::vertical([
+            Constraint::Percentage(45),
+            Constraint::Min(5),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [detail, hex] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(middle);
+
+        match self.view {
+            View::Packets => self.draw_packets(frame, top),
+            View::Flows => self.draw_flows(frame, top),
+        }
+        self.draw_detail(frame, detail);
+        self.draw_hex(frame, hex);
+        self.draw_status(frame, status);
+    }
+
+    fn draw_packets(&mut self, frame: &mut Frame, area: Rect) {
+        // 枠と見出しの行を除いた高さ
+        let height = area.height.saturating_sub(3).max(1) as usize;
+        let selected = if self.follow {
+            self.visible.len().saturating_sub(1)
+        } else {
+            self.selected
+        };
+        if selected < self.offset {
+            self.offset = selected;
+        } else if selected >= self.offset + height {
+            self.offset = selected + 1 - height;
+        }
+
+        let start = self.first_timestamp.unwrap_or_default();
+        let rows: Vec<Row> = self
+            .visible
+            .iter()
+            .skip(self.offset)
+            .take(height)
+            .map(|i| {
+                let entry = &self.packets[*i];
+                let record = &entry.record;
+                let protocol = record
+                    .application
+                    .as_ref()
+                    .map(|tree| tree.protocol)
+                    .unwrap_or(record.protocol);
+                let style = match record.decoded.worst().map(|info| info.severity) {
+                    Some(Severity::Error) => Style::new().fg(Color::Red),
+                    Some(Severity::Warning) => Style::new().fg(Color::Yellow),
+                    _ => Style::new(),
+                };
+                Row::new(vec![
+                    entry.number.to_string(),
+                    format!("{:.6}", record.timestamp - start),
+                    endpoint(&record.src_ip, record.src_port),
+                    endpoint(&record.dst_ip, record.dst_port),
+                    protocol.to_string(),
+                    record.frame_len.to_string(),
+                    info(record),
+                ])
+                .style(style)
+            })
+            .collect();
+        let widths = [
+            Constraint::Length(7),
+            Constraint::Length(12),
+            Constraint::Length(24),
+            Constraint::Length(24),
+            Constraint::Length(6),
+            Constraint::Length(6),
+            Constraint::Fill(1),
+        ];
+        let header = Row::new([
+            "No.",
+            "Time",
+            "Source",
+            "Destination",
+            "Proto",
+            "Length",
+            "Info",
+        ])
+        .style(Style::new().add_modifier(Modifier::BOLD));
+        let title = format!(" Packets ({}/{}) ", self.visible.len(), self.packets.len());
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(title))
+            .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        let mut state = TableState::default();
+        if !self.visible.is_empty() {
+            state.select(Some(selected - self.offset));
+        }
+        frame.render_stateful_widget(table, area, &mut state);
+    }
+
+    fn draw_flows(&self, frame: &mut Frame, area: Rect) {
+        let rows: Vec<Row> = self
+            .flows
+            .top_flows(TOP_FLOWS)
+            .into_iter()
+            .map(|(key, flow)| {
+                Row::new(vec![
+                    key.protocol.to_string(),
+                    stats::endpoint(&key.a),
+                    stats::endpoint(&key.b),
+                    flow.packets().to_string(),
+                    flow.bytes().to_string(),
+                    format!("{:.3}s", flow.duration()),
+                    flow.flags_string(),
+                ])
+            })
+            .collect();
+        let widths = [
+            Constraint::Length(5),
+            Constraint::Length(24),
+            Constraint::Length(24),
+            Constraint::Length(8),
+            Constraint::Length(10),
+            Constraint::Length(10),
+            Constraint::Fill(1),
+        ];
+        let header = Row::new([
+            "Proto",
+            "Endpoint A",
+            "Endpoint B",
+            "Packets",
+            "Bytes",
+            "Duration",
+            "Flags",
+        ])
+        .style(Style::new().add_modifier(Modifier::BOLD));
+        let title = format!(" Top flows ({} total) ", self.flows.flows.len());
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(title));
+        frame.render_widget(table, area);
+    }
+
+    fn draw_detail(&self, frame: &mut Frame, area: Rect) {
+        let mut lines = vec![];
+        if let Some(entry) = self.selected_entry() {
+            let record = &entry.record;
+            for info in &record.expert {
+                let color = match info.severity {
+                    Severity::Error => Color::Red,
+                    Severity::Warning => Color::Yellow,
+                    Severity::Note => Color::Cyan,
+                };
+                lines.push(Line::styled(info.to_string(), Style::new().fg(color)));
+            }
+            let trees = record
+                .decoded
+                .layers
+                .iter()
+                .map(|layer| ProtoTree {
+                    protocol: layer.header.name(),
+                    fields: layer.header.fields(),
+                })
+                .chain(record.application.clone());
+            for tree in trees {
+                lines.extend(tree.to_string().lines().map(|l| Line::raw(l.to_string())));
+            }
+        }
+        let paragraph = Paragraph::new(lines)
+            .block(Block::bordered().title(" Protocol tree "))
+            .scroll((self.detail_scroll, 0));
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw_hex(&self, frame: &mut Frame, area: Rect) {
+        let text = self
+            .selected_entry()
+            .map(|entry| self.hexdump.render(&entry.record.frame, &[]))
+            .unwrap_or_default();
+        let lines: Vec<Line> = text.lines().map(|l| Line::raw(l.to_string())).collect();
+        let paragraph = Paragraph::new(lines)
+            .block(Block::bordered().title(" Hex "))
+            .scroll((self.detail_scroll, 0));
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw_status(&self, frame: &mut Frame, area: Rect) {
+        let line = if let Some(input) = &self.input {
+            Line::raw(format!("Filter: {}_", input))
+        } else if let Some(message) = &self.message {
+            Line::styled(message.clone(), Style::new().fg(Color::Red))
+        } else {
+            let state = if self.paused {
+                format!("PAUSED (+{})", self.pending.len())
+            } else if self.finished {
+                "DONE".to_string()
+            } else {
+                "CAPTURING".to_string()
+            };
+            let filter = if self.filter_text.is_empty() {
+                "none"
+            } else {
+                &self.filter_text
+            };
+            Line::raw(format!(
+                "[{}] {} packets | filter: {} | q:quit space:pause /:filter tab:flows \
+                 j/k:select J/K:scroll G:follow",
+                state, self.received, filter
+            ))
+        };
+        frame.render_widget(Paragraph::new(line), area);
+    }
+}
+
+fn endpoint(ip: &str, port: u16) -> String {
+    stats::endpoint(&(ip.to_string(), port))
+}
+
+/**
+ * 一覧のInfo列
+ * アプリケーション層を解析できた場合はその要約を、できなかった場合はTCPフラグとペイロード長を表示する
+ */
+fn info(record: &PacketRecord) -> String {
+    if let Some(field) = record.application.as_ref().and_then(|t| t.fields.first()) {
+        return format!("{}: {}", field.name, field.value);
+    }
+    match &record.tcp_flags {
+        Some(flags) => format!("[{}] Len={}", flags, record.payload_len),
+        None => format!("Len={}", record.payload_len),
+    }
+}
+
+/**
+ * パケットをTUIに表示するSink
+ * キャプチャ中もキー入力を受け付けるため、パケットを受け取るたびとtickのたびに入力を確認する
+ * 入力元を読み終えた後も、qが押されるまで表示し続ける
+ */
+pub struct TuiSink<B: Backend> {
+    terminal: Terminal<B>,
+    app: App,
+    /// qが押されたらキャプチャを止めるためのフラグ
+    stop: Arc<AtomicBool>,
+    last_draw: Option<Instant>,
+}
+
+impl TuiSink<CrosstermBackend<Stdout>> {
+    /**
+     * 端末を代替画面・rawモードに切り替えてTUIを始める
+     */
+    pub fn start(app: App, stop: Arc<AtomicBool>) -> Result<Self> {
+        enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen)?;
+        let terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        Ok(Self::new(terminal, app, stop))
+    }
+}
+
+impl<B: Backend> TuiSink<B> {
+    pub fn new(terminal: Terminal<B>, app: App, stop: Arc<AtomicBool>) -> Self {
+        Self {
+            terminal,
+            app,
+            stop,
+            last_draw: None,
+        }
+    }
+
+    /**
+     * 溜まっているキー入力を処理し、間隔が空いていれば描き直す
+     */
+    fn update(&mut self, timeout: Duration) -> Result<()> {
+        let mut wait = timeout;
+        while event::poll(wait)? {
+            if let Event::Key(key) = event::read()? {
+                self.app.handle_key(key);
+            }
+            // 入力にはすぐ反応する
+            self.last_draw = None;
+            wait = Duration::ZERO;
+        }
+        if self.app.should_quit() {
+            self.stop.store(true, Ordering::SeqCst);
+        }
+        if self.last_draw.is_none_or(|t| t.elapsed() >= DRAW_INTERVAL) {
+            self.terminal.draw(|frame| self.app.draw(frame))?;
+            self.last_draw = Some(Instant::now());
+        }
+        Ok(())
+    }
+}
+
+impl<B: Backend> Sink for TuiSink<B> {
+    fn write(&mut self, record: &PacketRecord) -> Result<()> {
+        self.app.push(record.clone());
+        self.update(Duration::ZERO)
+    }
+
+    fn tick(&mut self) -> Result<()> {
+        self.update(Duration::ZERO)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.app.finish();
+        self.last_draw = None;
+        while !self.app.should_quit() {
+            self.update(DRAW_INTERVAL)?;
+        }
+        Ok(())
+    }
+}
+
+impl<B: Backend> Drop for TuiSink<B> {
+    fn drop(&mut self) {
+        // エラーで抜けた場合も端末を元に戻す
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stdout(), LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{self, Tcp};
+    use pnet::packet::tcp::TcpFlags::{ACK, PSH, SYN};
+    use ratatui::backend::TestBackend;
+
+    const WIDTH: u16 = 140;
+    const HEIGHT: u16 = 40;
+
+    /**
+     * TCPの3パケットとUDPの1パケットのpcapをレコードにする
+     */
+    fn fixture() -> Vec<PacketRecord> {
+        let client = "10.0.0.2:50000";
+        let server = "10.0.0.1:80";
+        testutil::records(&testutil::pcap(&[
+            (10.0, Tcp::new(client, server, SYN).seq(100).frame()),
+            (
+                10.25,
+                Tcp::new(server, client, SYN | ACK)
+                    .seq(700)
+                    .ack(101)
+                    .frame(),
+            ),
+            (
+                10.5,
+                Tcp::new(client, server, PSH | ACK)
+                    .seq(101)
+                    .ack(701)
+                    .payload(b"GET /index.html HTTP/1.1\r\nHost: example.com\r\n\r\n")
+                    .frame(),
+            ),
+            (
+                11.0,
+                testutil::udp("10.0.0.3:40000", "10.0.0.2:9999", b"hello"),
+            ),
+        ]))
+    }
+
+    fn press(app: &mut App, code: KeyCode) {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+
+    fn type_text(app: &mut App, text: &str) {
+        for c in text.chars() {
+            press(app, KeyCode::Char(c));
+        }
+    }
+
+    /**
+     * TestBackendに描画し、画面を1行ずつの文字列にする
+     */
+    fn render(app: &mut App) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(WIDTH, HEIGHT)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content
+            .chunks(WIDTH as usize)
+            .map(|cells| cells.iter().map(|cell| cell.symbol()).collect())
+            .collect()
+    }
+
+    fn screen(app: &mut App) -> String {
+        render(app).join("\n")
+    }
+
+    fn screen_contains(app: &mut App, text: &str) -> bool {
+        screen(app).contains(text)
+    }
+
+    fn selected(app: &App) -> u64 {
+        app.selected_entry().unwrap().number
+    }
+
+    fn app_with_fixture() -> App {
+        let mut app = App::new(None).unwrap();
+        for record in fixture() {
+            app.push(record);
+        }
+        app
+    }
+
+    #[test]
+    fn renders_all_panes() {
+        let mut app = app_with_fixture();
+        let lines = render(&mut app);
+        assert!(lines[0].contains(" Packets (4/4) "));
+        assert!(lines[1].starts_with("│No.     Time         Source                   Destination"));
+        let rows = [
+            "│1       0.000000     10.0.0.2:50000           10.0.0.1:80              TCP    54     [SYN] Len=0",
+            "│2       0.250000     10.0.0.1:80              10.0.0.2:50000           TCP    54     [ACK|SYN] Len=0",
+            "│3       0.500000     10.0.0.2:50000           10.0.0.1:80              HTTP   101    Request line: GET /index.html HTTP/1.1",
+            "│4       1.000000     10.0.0.3:40000           10.0.0.2:9999            UDP    47     Len=5",
+        ];
+        for (line, row) in lines[2..].iter().zip(rows) {
+            assert!(line.starts_with(row), "{}", line);
+        }
+
+        let screen = lines.join("\n");
+        assert!(screen.contains(" Protocol tree "));
+        assert!(screen.contains(" Hex "));
+        // 追従中は最新のUDPパケットを表示する
+        assert!(screen.contains("│    Source port: 40000"));
+        assert!(screen.contains("│00000020  00 02 9c 40 27 0f 00 0d  e4 ad 68 65 6c 6c 6f"));
+        assert!(
+            lines[HEIGHT as usize - 1].starts_with("[CAPTURING] 4 packets | filter: none | q:quit")
+        );
+
+        app.finish();
+        assert!(screen_contains(&mut app, "[DONE] 4 packets"));
+    }
+
+    #[test]
+    fn pause_holds_new_packets() {
+        let mut records = fixture();
+        let later = records.split_off(2);
+        let mut app = App::new(None).unwrap();
+        for record in records {
+            app.push(record);
+        }
+
+        press(&mut app, KeyCode::Char(' '));
+        for record in later {
+            app.push(record);
+        }
+        let lines = render(&mut app);
+        assert!(lines[0].contains(" Packets (2/2) "));
+        assert!(lines[HEIGHT as usize - 1].starts_with("[PAUSED (+2)] 4 packets"));
+
+        press(&mut app, KeyCode::Char('p'));
+        let lines = render(&mut app);
+        assert!(lines[0].contains(" Packets (4/4) "));
+        assert!(lines[HEIGHT as usize - 1].starts_with("[CAPTURING] 4 packets"));
+    }
+
+    #[test]
+    fn scrolls_the_selection_and_detail() {
+        let mut app = app_with_fixture();
+        assert_eq!(selected(&app), 4);
+        press(&mut app, KeyCode::Char('k'));
+        assert_eq!(selected(&app), 3);
+        press(&mut app, KeyCode::Up);
+        assert_eq!(selected(&app), 2);
+        press(&mut app, KeyCode::Char('g'));
+        assert_eq!(selected(&app), 1);
+        press(&mut app, KeyCode::Down);
+        assert_eq!(selected(&app), 2);
+        press(&mut app, KeyCode::PageDown);
+        assert_eq!(selected(&app), 4);
+        press(&mut app, KeyCode::Home);
+        assert!(screen_contains(&mut app, "│    Destination port: 80"));
+
+        // 詳細とダンプは一緒にスクロールする
+        assert!(screen_contains(&mut app, "│00000000  "));
+        press(&mut app, KeyCode::Char('J'));
+        press(&mut app, KeyCode::Char('J'));
+        let scrolled = screen(&mut app);
+        assert!(!scrolled.contains("│00000000  "));
+        assert!(scrolled.contains("│00000020  "));
+        press(&mut app, KeyCode::Char('K'));
+        assert!(screen(&mut app).contains("│00000010  "));
+
+        // 末尾への追従に戻ると新しいパケットを選択し続ける
+        press(&mut app, KeyCode::Char('G'));
+        app.push(fixture().remove(0));
+        assert_eq!(selected(&app), 5);
+    }
+
+    #[test]
+    fn slash_edits_the_filter() {
+        let mut app = app_with_fixture();
+        press(&mut app, KeyCode::Char('/'));
+        type_text(&mut app, "tpc");
+        assert!(screen_contains(&mut app, "Filter: tpc_"));
+        press(&mut app, KeyCode::Backspace);
+        press(&mut app, KeyCode::Backspace);
+        type_text(&mut app, "cp");
+        press(&mut app, KeyCode::Enter);
+        let lines = render(&mut app);
+        assert!(lines[0].contains(" Packets (3/4) "));
+        assert!(lines[HEIGHT as usize - 1].contains("filter: tcp |"));
+
+        // 編集中のqはフィルタの文字として扱う
+        press(&mut app, KeyCode::Char('/'));
+        type_text(&mut app, " and port q");
+        assert!(!app.should_quit());
+        press(&mut app, KeyCode::Enter);
+        let lines = render(&mut app);
+        assert!(lines[0].contains(" Packets (3/4) "));
+        assert!(lines[HEIGHT as usize - 1].starts_with("Invalid port 'q' in filter"));
+
+        // 空にするとフィルタを外す。Escでは変えない
+        press(&mut app, KeyCode::Char('/'));
+        type_text(&mut app, "udp");
+        press(&mut app, KeyCode::Esc);
+        assert!(screen_contains(&mut app, " Packets (3/4) "));
+        press(&mut app, KeyCode::Char('/'));
+        for _ in 0.."tcp".len() {
+            press(&mut app, KeyCode::Backspace);
+        }
+        press(&mut app, KeyCode::Enter);
+        assert!(screen_contains(&mut app, " Packets (4/4) "));
+        assert!(screen_contains(&mut app, "filter: none"));
+    }
+
+    #[test]
+    fn tab_switches_to_flows() {
+        let mut app = app_with_fixture();
+        press(&mut app, KeyCode::Tab);
+        let lines = render(&mut app);
+        assert!(lines[0].contains(" Top flows (2 total) "));
+        assert!(lines[2].contains("TCP   10.0.0.1:80"));
+        assert!(lines[2].contains("ACK|PSH|SYN"));
+        press(&mut app, KeyCode::Tab);
+        assert!(screen_contains(&mut app, " Packets (4/4) "));
+        press(&mut app, KeyCode::Char('q'));
+        assert!(app.should_quit());
+    }
+
+    #[test]
+    fn caps_packets_held_while_paused() {
+        let record = fixture().remove(0);
+        let mut app = App::new(None).unwrap();
+        press(&mut app, KeyCode::Char(' '));
+        for _ in 0..=MAX_PACKETS {
+            app.push(record.clone());
+        }
+        assert_eq!(app.pending.len(), MAX_PACKETS + 1 - MAX_PACKETS / 10);
+        // 古いものから捨てる
+        assert_eq!(app.pending[0].number, MAX_PACKETS as u64 / 10 + 1);
+
+        press(&mut app, KeyCode::Char(' '));
+        assert!(app.pending.is_empty());
+        assert!(app.packets.len() <= MAX_PACKETS);
+        assert_eq!(app.received, MAX_PACKETS as u64 + 1);
+    }
+
+    #[test]
+    fn keeps_the_selection_when_dropping_old_packets() {
+        let record = fixture().remove(0);
+        let mut app = App::new(None).unwrap();
+        for _ in 0..MAX_PACKETS {
+            app.push(record.clone());
+        }
+        app.select(49_999);
+        assert_eq!(selected(&app), 50_000);
+
+        app.push(record);
+        assert_eq!(app.packets.len(), MAX_PACKETS + 1 - MAX_PACKETS / 10);
+        assert_eq!(selected(&app), 50_000);
+        assert!(!app.follow);
+    }
+
+    #[test]
+    fn caps_flows() {
+        let record = fixture().remove(3);
+        let mut app = App::new(None).unwrap();
+        for port in 1..=MAX_FLOWS as u16 + 1 {
+            let mut record = record.clone();
+            record.src_port = port;
+            record.dst_port = u16::MAX;
+            record.timestamp = port as f64;
+            app.push(record);
+        }
+        let flows = &app.flows;
+        assert_eq!(flows.flows.len(), MAX_FLOWS - MAX_FLOWS / 10);
+        assert_eq!(flows.ports.len(), MAX_FLOWS - MAX_FLOWS / 10);
+        // しばらく見ていないフローから捨てる
+        let oldest = flows
+            .flows
+            .values()
+            .map(|f| f.first_seen)
+            .fold(f64::MAX, f64::min);
+        assert_eq!(oldest, (MAX_FLOWS / 10 + 2) as f64);
+        assert_eq!(flows.hosts.len(), 2);
+    }
+}
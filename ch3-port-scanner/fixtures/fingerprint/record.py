@@ -0,0 +1,48 @@
+# 127.0.0.1の指定のポートへSYNを送り、返ってきたIPv4パケットをそのまま保存する
+# 使い方: sudo python3 record.py <ポート> <保存先> [SYNに付けるTCPオプション（16進数）]
+import random
+import socket
+import struct
+import sys
+
+
+def checksum(data):
+    if len(data) % 2:
+        data += b"\0"
+    total = sum(struct.unpack("!%dH" % (len(data) // 2), data))
+    while total >> 16:
+        total = (total & 0xFFFF) + (total >> 16)
+    return ~total & 0xFFFF
+
+
+def syn(sport, dport, options):
+    options += b"\0" * (-len(options) % 4)
+    offset = (20 + len(options)) // 4
+    header = struct.pack(
+        "!HHIIBBHHH", sport, dport, 0x12345678, 0, offset << 4, 0x02, 1024, 0, 0
+    )
+    header += options
+    pseudo = socket.inet_aton("127.0.0.1") * 2 + struct.pack("!BBH", 0, 6, len(header))
+    return header[:16] + struct.pack("!H", checksum(pseudo + header)) + header[18:]
+
+
+def main():
+    dport, path = int(sys.argv[1]), sys.argv[2]
+    options = bytes.fromhex(sys.argv[3]) if len(sys.argv) > 3 else b""
+    sport = random.randint(40000, 60000)
+    # IPv4のrawソケットは受信したパケットをIPヘッダごと渡す
+    sock = socket.socket(socket.AF_INET, socket.SOCK_RAW, socket.IPPROTO_TCP)
+    sock.settimeout(2)
+    sock.sendto(syn(sport, dport, options), ("127.0.0.1", 0))
+    while True:
+        packet = sock.recv(65535)
+        ihl = (packet[0] & 0xF) * 4
+        source, destination = struct.unpack("!HH", packet[ihl : ihl + 4])
+        if source == dport and destination == sport:
+            with open(path, "wb") as f:
+                f.write(packet)
+            print(path, packet.hex())
+            return
+
+
+main()
@@ -0,0 +1,124 @@
+use pnet::{
+    packet::{
+        ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+        ipv4::{Ipv4Flags, Ipv4Packet},
+        Packet,
+    },
+    transport::{
+        self, TransportChannelType, TransportProtocol, TransportReceiver, TransportSender,
+    },
+};
+use std::{io, net::IpAddr, time::Duration};
+
+/// 受信を待つ最大の時間。受信するスレッドはこの間隔で止めるかどうかを確かめる
+pub const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/**
+ * 受信したIPv4ヘッダのうち、TTLの記録とOSの推定に使うもの
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpHeader {
+    pub ttl: u8,
+    pub dont_fragment: bool,
+    pub id: u16,
+    /// IPオプションの長さ（オクテット）
+    pub options_len: u8,
+}
+
+impl IpHeader {
+    /**
+     * 受信したIPv4パケットのヘッダから取り出す
+     */
+    pub fn of(ip_packet: &Ipv4Packet) -> Self {
+        Self {
+            ttl: ip_packet.get_ttl(),
+            dont_fragment: ip_packet.get_flags() & Ipv4Flags::DontFragment != 0,
+            id: ip_packet.get_identification(),
+            options_len: (ip_packet.get_header_length() * 4).saturating_sub(20),
+        }
+    }
+}
+
+/**
+ * 受信したパケットの送信元、IPヘッダより後ろ、IPv4ヘッダ
+ */
+pub type Received = (IpAddr, Vec<u8>, Option<IpHeader>);
+
+/**
+ * IPv4かIPv6のrawソケットのチャネルを開く
+ * 送信はカーネルにIPヘッダを付けてもらう
+ * IPv4では返信のTTLを読むため、受信にはIPヘッダを含むレイヤ3のチャネルを使う
+ */
+pub fn open(
+    v6: bool,
+    protocol: IpNextHeaderProtocol,
+) -> io::Result<(TransportSender, TransportReceiver)> {
+    if v6 {
+        return transport::transport_channel(
+            1024,
+            TransportChannelType::Layer4(TransportProtocol::Ipv6(protocol)),
+        );
+    }
+    // 送信側のソケットで受信したものは使わない
+    let (ts, _) = transport::transport_channel(
+        1024,
+        TransportChannelType::Layer4(TransportProtocol::Ipv4(protocol)),
+    )?;
+    let (_, tr) = transport::transport_channel(1024, TransportChannelType::Layer3(protocol))?;
+    Ok((ts, tr))
+}
+
+/**
+ * openで開いたチャネルからパケットを受け取り、送信元、IPヘッダより後ろ、IPv4ヘッダを返す
+ * RECEIVE_TIMEOUTの間に何も届かなければNoneを返す
+ */
+pub fn next_packet(
+    tr: &mut TransportReceiver,
+    v6: bool,
+    protocol: IpNextHeaderProtocol,
+) -> io::Result<Option<Received>> {
+    if v6 {
+        next_ipv6(tr, protocol)
+    } else {
+        next_ipv4(tr)
+    }
+}
+
+/**
+ * IPv4のパケットをIPヘッダごと受け取る
+ */
+fn next_ipv4(tr: &mut TransportReceiver) -> io::Result<Option<Received>> {
+    let mut packet_iter = transport::ipv4_packet_iter(tr);
+    let (ip_packet, _) = match packet_iter.next_with_timeout(RECEIVE_TIMEOUT)? {
+        Some(received) => received,
+        None => return Ok(None),
+    };
+    Ok(Some((
+        IpAddr::V4(ip_packet.get_source()),
+        ip_packet.payload().to_vec(),
+        Some(IpHeader::of(&ip_packet)),
+    )))
+}
+
+/**
+ * IPv6のパケットを受け取る
+ * IPv6のrawソケットはIPヘッダを渡さないので、ホップリミットなどは分からない
+ * どのイテレータも受け取ったバイト列を包むだけなので、プロトコルに合うものを使って取り出す
+ */
+fn next_ipv6(
+    tr: &mut TransportReceiver,
+    protocol: IpNextHeaderProtocol,
+) -> io::Result<Option<Received>> {
+    let received = match protocol {
+        IpNextHeaderProtocols::Tcp => transport::tcp_packet_iter(tr)
+            .next_with_timeout(RECEIVE_TIMEOUT)?
+            .map(|(packet, host)| (packet.packet().to_vec(), host)),
+        IpNextHeaderProtocols::Udp => transport::udp_packet_iter(tr)
+            .next_with_timeout(RECEIVE_TIMEOUT)?
+            .map(|(packet, host)| (packet.packet().to_vec(), host)),
+        _ => transport::icmpv6_packet_iter(tr)
+            .next_with_timeout(RECEIVE_TIMEOUT)?
+            .map(|(packet, host)| (packet.packet().to_vec(), host)),
+    };
+    Ok(received.map(|(payload, host)| (host, payload, None)))
+}
@@ -0,0 +1,449 @@
+use crate::ports::{self, PortSelection};
+use crate::report::Format;
+use crate::scan::{ScanConfig, ScanType};
+use crate::timing::Timing;
+use anyhow::{anyhow, Context, Result};
+use pnet::packet::tcp::TcpFlags;
+use std::time::Duration;
+
+pub const USAGE: &str =
+    "Usage: ch3-port-scanner [-p PORTS | --top-ports N] [--exclude-ports PORTS] [-r] \
+[-T<0-5>] [--min-rate PPS] [--max-rate PPS] \
+[--timeout MS] [--retries N] [--max-time SECS] [--max-parallelism N] \
+[-sV [--version-db FILE]] [-O [--os-db FILE]] [-g PORT | --source-port-per-probe] \
+[--scanflags FLAGS] [-f text|json|xml|grepable] [-o FILE] [-Pn] [-iL FILE] [<target>...] \
+<sS|sF|sX|sN|sA|sW|sM|sT|sU>
+       ch3-port-scanner -sn [-T<0-5>] [--timeout MS] [--retries N] [-f FORMAT] [-o FILE] [-iL FILE] [<target>...]
+       ch3-port-scanner diff OLD.json NEW.json";
+
+/**
+ * コマンドライン引数
+ */
+#[derive(Debug)]
+pub struct Options {
+    /// IPアドレス、CIDR、ホスト名
+    pub targets: Vec<String>,
+    /// 対象を列挙したファイル
+    pub input_file: Option<String>,
+    /// -snでホストの発見だけを行う場合はNone
+    pub scan_type: Option<String>,
+    /// プローブのTCPフラグ。返信はスキャン方法に従って解釈する
+    pub scan_flags: Option<u16>,
+    /// ホストの発見をせず、すべてのホストをスキャンする
+    pub skip_discovery: bool,
+    pub ports: PortSelection,
+    pub config: ScanConfig,
+    /// 送信レートとタイムアウトの範囲
+    pub timing: Timing,
+    /// connectスキャンとバージョン検出で同時に接続を試みる数
+    pub max_parallelism: usize,
+    /// 開いているポートのサービスとバージョンを調べるか
+    pub version_detection: bool,
+    /// サービス検出のシグネチャファイル
+    pub version_db: Option<String>,
+    /// 返信からOSを推定するか
+    pub os_detection: bool,
+    /// OSの推定のシグネチャファイル
+    pub os_db: Option<String>,
+    /// rawソケットのスキャンで使う送信元ポート
+    pub source_port: Option<u16>,
+    /// プローブごとに送信元ポートを変えるか
+    pub per_probe_source_port: bool,
+    /// 結果の出力形式
+    pub format: Format,
+    /// 結果を書き出すファイル。なければ標準出力
+    pub output: Option<String>,
+}
+
+impl Options {
+    /**
+     * コマンドライン引数を解析する（先頭のプログラム名は含まない）
+     */
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self> {
+        let mut positional = vec![];
+        let mut ports = PortSelection::default();
+        let mut config = ScanConfig::default();
+        let mut template = None;
+        let mut min_rate = None;
+        let mut max_rate = None;
+        let mut timeout = None;
+        let mut input_file = None;
+        let mut max_parallelism = 100;
+        let mut version_detection = false;
+        let mut version_db = None;
+        let mut os_detection = false;
+        let mut os_db = None;
+        let mut source_port = None;
+        let mut scan_flags = None;
+        let mut per_probe_source_port = false;
+        let mut format = Format::Text;
+        let mut ping_only = false;
+        let mut skip_discovery = false;
+        let mut output = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-p" | "--ports" => {
+                    let value = args.next().context("Missing value for -p")?;
+                    ports.ports = Some(ports::parse_ports(&value)?);
+                }
+                // nmapと同じく"-p-"で全ポート
+                "-p-" => ports.ports = Some(ports::parse_ports("-")?),
+                "--top-ports" => {
+                    let value = args.next().context("Missing value for --top-ports")?;
+                    let top: usize = value.parse().context("invalid top ports count")?;
+                    if top == 0 || top > ports::TOP_PORTS.len() {
+                        return Err(anyhow!(
+                            "--top-ports must be between 1 and {}",
+                            ports::TOP_PORTS.len()
+                        ));
+                    }
+                    ports.top = Some(top);
+                }
+                "--exclude-ports" => {
+                    let value = args.next().context("Missing value for --exclude-ports")?;
+                    ports.exclude = ports::parse_ports(&value)?;
+                }
+                "-r" => ports.randomize = false,
+                "--max-parallelism" => {
+                    let value = args.next().context("Missing value for --max-parallelism")?;
+                    max_parallelism = value.parse().context("invalid parallelism")?;
+                    if max_parallelism == 0 {
+                        return Err(anyhow!("parallelism must be positive"));
+                    }
+                }
+                "-sV" => version_detection = true,
+                "-sn" => ping_only = true,
+                "-Pn" => skip_discovery = true,
+                "--version-db" => {
+                    version_db = Some(args.next().context("Missing value for --version-db")?);
+                }
+                "-O" => os_detection = true,
+                "--os-db" => {
+                    os_db = Some(args.next().context("Missing value for --os-db")?);
+                }
+                "-g" | "--source-port" => {
+                    let value = args.next().context("Missing value for -g")?;
+                    let port: u16 = value.parse().context("invalid source port")?;
+                    if port == 0 {
+                        return Err(anyhow!("source port must be positive"));
+                    }
+                    source_port = Some(port);
+                }
+                "--source-port-per-probe" => per_probe_source_port = true,
+                "--scanflags" => {
+                    let value = args.next().context("Missing value for --scanflags")?;
+                    scan_flags = Some(parse_scan_flags(&value)?);
+                }
+                "-f" | "--format" => {
+                    let value = args.next().context("Missing value for --format")?;
+                    format = value.parse()?;
+                }
+                "-o" | "--output" => {
+                    output = Some(args.next().context("Missing value for -o")?);
+                }
+                "-iL" => {
+                    input_file = Some(args.next().context("Missing value for -iL")?);
+                }
+                "--timeout" => {
+                    let value = args.next().context("Missing value for --timeout")?;
+                    let millis: u64 = value.parse().context("invalid timeout")?;
+                    if millis == 0 {
+                        return Err(anyhow!("timeout must be positive"));
+                    }
+                    timeout = Some(Duration::from_millis(millis));
+                }
+                // -T4のように続けても、-T aggressiveのように分けてもよい
+                "-T" => {
+                    let value = args.next().context("Missing value for -T")?;
+                    template = Some(Timing::template(&value)?);
+                }
+                _ if arg.starts_with("-T") => template = Some(Timing::template(&arg[2..])?),
+                "--min-rate" => {
+                    let value = args.next().context("Missing value for --min-rate")?;
+                    min_rate = Some(parse_rate(&value)?);
+                }
+                "--max-rate" => {
+                    let value = args.next().context("Missing value for --max-rate")?;
+                    max_rate = Some(parse_rate(&value)?);
+                }
+                "--retries" => {
+                    let value = args.next().context("Missing value for --retries")?;
+                    config.retries = value.parse().context("invalid retry count")?;
+                }
+                "--max-time" => {
+                    let value = args.next().context("Missing value for --max-time")?;
+                    let secs: u64 = value.parse().context("invalid max time")?;
+                    config.max_time = Some(Duration::from_secs(secs));
+                }
+                // スキャン方法のsSなどは-を付けずに指定する
+                _ if arg.starts_with('-') => {
+                    return Err(anyhow!("Unknown option {}\n{}", arg, USAGE));
+                }
+                _ => positional.push(arg),
+            }
+        }
+        // -snでなければ最後の引数がスキャン方法
+        let scan_type = if ping_only {
+            if positional
+                .last()
+                .is_some_and(|arg| ScanType::from_arg(arg).is_ok())
+            {
+                return Err(anyhow!("-sn cannot be used with a scan type\n{}", USAGE));
+            }
+            None
+        } else {
+            let scan_type = positional
+                .pop()
+                .with_context(|| format!("Please specify scan type\n{}", USAGE))?;
+            Some(scan_type)
+        };
+        let targets = positional;
+        if ping_only && (skip_discovery || version_detection || os_detection) {
+            return Err(anyhow!("-sn cannot be used with -Pn, -sV or -O\n{}", USAGE));
+        }
+        if targets.is_empty() && input_file.is_none() {
+            return Err(anyhow!("Please specify target\n{}", USAGE));
+        }
+        if ports.ports.is_some() && ports.top.is_some() {
+            return Err(anyhow!(
+                "-p and --top-ports cannot be used together\n{}",
+                USAGE
+            ));
+        }
+        if source_port.is_some() && per_probe_source_port {
+            return Err(anyhow!(
+                "-g and --source-port-per-probe cannot be used together\n{}",
+                USAGE
+            ));
+        }
+        let mut timing = template.unwrap_or_default();
+        if let Some(min_rate) = min_rate {
+            timing.min_rate = min_rate;
+            timing.max_rate = timing.max_rate.max(min_rate);
+        }
+        if let Some(max_rate) = max_rate {
+            timing.max_rate = max_rate;
+            timing.min_rate = timing.min_rate.min(max_rate);
+        }
+        if let (Some(min_rate), Some(max_rate)) = (min_rate, max_rate) {
+            if min_rate > max_rate {
+                return Err(anyhow!("--min-rate must not exceed --max-rate"));
+            }
+        }
+        timing.clamp_rate()?;
+        // --timeoutはRTTを測るまでのタイムアウトになる
+        config.timeout = timeout.unwrap_or(timing.initial_timeout);
+        Ok(Self {
+            targets,
+            input_file,
+            scan_type,
+            scan_flags,
+            skip_discovery,
+            ports,
+            config,
+            timing,
+            max_parallelism,
+            version_detection,
+            version_db,
+            os_detection,
+            os_db,
+            source_port,
+            per_probe_source_port,
+            format,
+            output,
+        })
+    }
+}
+
+/**
+ * 送信レート（パケット/秒）を読む
+ */
+fn parse_rate(value: &str) -> Result<f64> {
+    let rate: f64 = value.parse().context("invalid rate")?;
+    if !rate.is_finite() || rate <= 0.0 {
+        return Err(anyhow!("rate must be positive"));
+    }
+    Ok(rate)
+}
+
+/// --scanflagsで使えるフラグの名前
+const FLAG_NAMES: [(&str, u16); 9] = [
+    ("NS", TcpFlags::NS),
+    ("CWR", TcpFlags::CWR),
+    ("ECE", TcpFlags::ECE),
+    ("URG", TcpFlags::URG),
+    ("ACK", TcpFlags::ACK),
+    ("PSH", TcpFlags::PSH),
+    ("RST", TcpFlags::RST),
+    ("SYN", TcpFlags::SYN),
+    ("FIN", TcpFlags::FIN),
+];
+
+/**
+ * --scanflagsの値を読む
+ * nmapと同じく、URGACKのようにフラグの名前を順不同で続けるか、フラグのビットを数値で指定する
+ */
+fn parse_scan_flags(value: &str) -> Result<u16> {
+    let max = FLAG_NAMES.iter().fold(0, |all, (_, flag)| all | flag);
+    if let Ok(flags) = value.parse::<u16>() {
+        if flags > max {
+            return Err(anyhow!("scan flags must not exceed {}", max));
+        }
+        return Ok(flags);
+    }
+    let upper = value.to_ascii_uppercase();
+    let mut rest = upper.as_str();
+    let mut flags = 0;
+    while !rest.is_empty() {
+        let (name, flag) = FLAG_NAMES
+            .iter()
+            .find(|(name, _)| rest.starts_with(name))
+            .with_context(|| format!("invalid scan flags {}", value))?;
+        flags |= flag;
+        rest = &rest[name.len()..];
+    }
+    if flags == 0 {
+        return Err(anyhow!("invalid scan flags {}", value));
+    }
+    Ok(flags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options> {
+        Options::parse(args.split_whitespace().map(str::to_string))
+    }
+
+    fn error(args: &str) -> String {
+        format!("{:#}", parse(args).unwrap_err())
+    }
+
+    #[test]
+    fn port_options() {
+        let options = parse("-p 22,80-81 -r --exclude-ports 81 127.0.0.1 sS").unwrap();
+        assert_eq!(options.ports.ports, Some(vec![22, 80, 81]));
+        assert_eq!(options.ports.exclude, [81]);
+        assert!(!options.ports.randomize);
+        assert_eq!(options.ports.resolve(None).unwrap(), [22, 80]);
+
+        let options = parse("-p- 127.0.0.1 sS").unwrap();
+        assert_eq!(options.ports.ports.unwrap().len(), 65535);
+        assert!(error("-p 0 127.0.0.1 sS").contains("port 0 cannot be scanned"));
+        assert!(error("-p 22 --top-ports 10 127.0.0.1 sS")
+            .starts_with("-p and --top-ports cannot be used together"));
+    }
+
+    #[test]
+    fn top_ports_bounds() {
+        assert_eq!(
+            parse("--top-ports 1 127.0.0.1 sS").unwrap().ports.top,
+            Some(1)
+        );
+        assert_eq!(
+            parse("--top-ports 100 127.0.0.1 sS").unwrap().ports.top,
+            Some(100)
+        );
+        for count in ["0", "101"] {
+            assert_eq!(
+                error(&format!("--top-ports {} 127.0.0.1 sS", count)),
+                "--top-ports must be between 1 and 100"
+            );
+        }
+        assert!(error("--top-ports -1 127.0.0.1 sS").starts_with("invalid top ports count"));
+        assert!(error("127.0.0.1 sS --top-ports").starts_with("Missing value for --top-ports"));
+    }
+
+    #[test]
+    fn timing_templates() {
+        for args in ["-T4", "-T 4", "-Taggressive", "-T aggressive"] {
+            let options = parse(&format!("{} 127.0.0.1 sS", args)).unwrap();
+            assert_eq!(options.timing.initial_rate, 500.0);
+            // --timeoutがなければテンプレートのタイムアウトを使う
+            assert_eq!(options.config.timeout, Duration::from_millis(500));
+        }
+        let options = parse("-T5 --timeout 2000 127.0.0.1 sS").unwrap();
+        assert_eq!(options.timing.initial_rate, 1000.0);
+        assert_eq!(options.config.timeout, Duration::from_millis(2000));
+        assert_eq!(parse("127.0.0.1 sS").unwrap().timing.initial_rate, 200.0);
+
+        assert!(error("-T6 127.0.0.1 sS").starts_with("Unknown timing template 6"));
+        assert!(error("-T").starts_with("Missing value for -T"));
+    }
+
+    #[test]
+    fn rate_options() {
+        // 片方だけなら、もう片方をそれに合わせて広げる
+        let timing = parse("--min-rate 20000 127.0.0.1 sS").unwrap().timing;
+        assert_eq!(timing.min_rate, 20000.0);
+        assert_eq!(timing.max_rate, 20000.0);
+        assert_eq!(timing.initial_rate, 20000.0);
+
+        let timing = parse("-T2 --max-rate 0.5 127.0.0.1 sS").unwrap().timing;
+        assert_eq!(timing.min_rate, 0.5);
+        assert_eq!(timing.max_rate, 0.5);
+        assert_eq!(timing.initial_rate, 0.5);
+
+        let timing = parse("--min-rate 10 --max-rate 100 127.0.0.1 sS")
+            .unwrap()
+            .timing;
+        assert_eq!(timing.min_rate, 10.0);
+        assert_eq!(timing.max_rate, 100.0);
+        assert_eq!(timing.initial_rate, 100.0);
+
+        assert_eq!(
+            error("--min-rate 100 --max-rate 10 127.0.0.1 sS"),
+            "--min-rate must not exceed --max-rate"
+        );
+        assert_eq!(
+            error("--max-rate 10 --min-rate 100 127.0.0.1 sS"),
+            "--min-rate must not exceed --max-rate"
+        );
+        for rate in ["0", "-1", "inf", "NaN"] {
+            assert_eq!(
+                error(&format!("--min-rate {} 127.0.0.1 sS", rate)),
+                "rate must be positive"
+            );
+        }
+        assert!(error("--max-rate fast 127.0.0.1 sS").starts_with("invalid rate"));
+    }
+
+    #[test]
+    fn scan_flags() {
+        assert_eq!(
+            parse_scan_flags("URGACK").unwrap(),
+            TcpFlags::URG | TcpFlags::ACK
+        );
+        // 順不同で、小文字でもよい
+        assert_eq!(
+            parse_scan_flags("ackurg").unwrap(),
+            TcpFlags::URG | TcpFlags::ACK
+        );
+        assert_eq!(
+            parse_scan_flags("SYNFINNS").unwrap(),
+            TcpFlags::SYN | TcpFlags::FIN | TcpFlags::NS
+        );
+        assert_eq!(
+            parse_scan_flags("18").unwrap(),
+            TcpFlags::SYN | TcpFlags::ACK
+        );
+        assert_eq!(parse_scan_flags("0").unwrap(), 0);
+        assert_eq!(parse_scan_flags("511").unwrap(), 0x1ff);
+        assert_eq!(
+            format!("{:#}", parse_scan_flags("512").unwrap_err()),
+            "scan flags must not exceed 511"
+        );
+        for value in ["URGX", "FOO", "SYN,ACK", "", "-1"] {
+            assert_eq!(
+                format!("{:#}", parse_scan_flags(value).unwrap_err()),
+                format!("invalid scan flags {}", value)
+            );
+        }
+
+        let options = parse("--scanflags URGACK 127.0.0.1 sA").unwrap();
+        assert_eq!(options.scan_flags, Some(TcpFlags::URG | TcpFlags::ACK));
+        assert_eq!(options.scan_type.as_deref(), Some("sA"));
+        assert!(error("--scanflags").starts_with("Missing value for --scanflags"));
+    }
+}
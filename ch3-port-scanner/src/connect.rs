@@ -0,0 +1,274 @@
+use crate::scan::{Answer, PortState, Reason, Reply, Response, Scan};
+use crate::timing::{RttEstimator, Timing};
+use anyhow::Result;
+use log::debug;
+use mio::{net::TcpStream, Events, Poll, PollOpt, Ready, Token};
+use pnet::packet::tcp::TcpFlags;
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+/// 待つものがないときにpollする間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/**
+ * 接続中のソケット
+ */
+struct Pending {
+    host: IpAddr,
+    port: u16,
+    stream: TcpStream,
+    started: Instant,
+}
+
+/**
+ * ノンブロッキングのconnect()でスキャンする
+ * 同時に接続を試みる数をmax_parallelismまでに抑え、タイムアウトしたものは閉じて再送に回す
+ * タイムアウトは接続にかかった時間から計算し直す
+ */
+pub fn run(scan: &mut Scan, timing: &Timing, max_parallelism: usize) -> Result<()> {
+    let poll = Poll::new()?;
+    let mut events = Events::with_capacity(1024);
+    let mut pending: HashMap<Token, Pending> = HashMap::new();
+    let mut next_token = 0;
+    let mut rtt = RttEstimator::new(timing, scan.timeout());
+    let deadline = scan.deadline(Instant::now());
+
+    loop {
+        let now = Instant::now();
+        if deadline.is_some_and(|deadline| now >= deadline) {
+            debug!("scan reached the time limit");
+            scan.abort(now);
+            break;
+        }
+        // タイムアウトした接続を閉じる。再送するかどうかはScanが決める
+        let timeout = scan.timeout();
+        pending.retain(|_, p| now.duration_since(p.started) < timeout);
+
+        while pending.len() < max_parallelism {
+            let (host, port) = match scan.next_probe(now) {
+                Some(probe) => probe,
+                None => break,
+            };
+            let addr = SocketAddr::from((host, port));
+            match TcpStream::connect(&addr) {
+                Ok(stream) => {
+                    let token = Token(next_token);
+                    next_token += 1;
+                    poll.register(&stream, token, Ready::writable(), PollOpt::edge())?;
+                    pending.insert(
+                        token,
+                        Pending {
+                            host,
+                            port,
+                            stream,
+                            started: now,
+                        },
+                    );
+                }
+                Err(e) => {
+                    resolve_error(scan, host, port, &e, now);
+                }
+            }
+        }
+        if scan.finished() {
+            break;
+        }
+
+        let mut wait = scan.next_timeout(now).unwrap_or(POLL_INTERVAL);
+        if let Some(deadline) = deadline {
+            wait = wait.min(deadline.saturating_duration_since(now));
+        }
+        poll.poll(&mut events, Some(wait))?;
+        for event in &events {
+            let p = match pending.get(&event.token()) {
+                Some(p) => p,
+                None => continue,
+            };
+            // 書き込めるようになったら接続の結果が分かる
+            let result = match p.stream.take_error() {
+                Ok(Some(e)) | Err(e) => Err(e),
+                Ok(None) => p.stream.peer_addr().map(|_| ()),
+            };
+            let now = Instant::now();
+            let answer = match result {
+                Ok(()) => scan.on_reply(
+                    &Reply {
+                        host: p.host,
+                        port: p.port,
+                        response: Response::Tcp {
+                            flags: TcpFlags::SYN | TcpFlags::ACK,
+                            window: 0,
+                        },
+                        ttl: None,
+                        observation: None,
+                    },
+                    now,
+                ),
+                // まだ接続中
+                Err(e) if e.kind() == io::ErrorKind::NotConnected => continue,
+                Err(e) => resolve_error(scan, p.host, p.port, &e, now),
+            };
+            if let Some(Answer::Timely(sample)) = answer {
+                rtt.sample(sample);
+                scan.set_timeout(rtt.timeout());
+            }
+            pending.remove(&event.token());
+        }
+    }
+    Ok(())
+}
+
+/**
+ * 接続に失敗した理由からポートの状態を決める
+ * 接続拒否はRSTを受け取ったことを表し、それ以外（到達不能など）はフィルタされているとみなす
+ */
+fn resolve_error(
+    scan: &mut Scan,
+    host: IpAddr,
+    port: u16,
+    e: &io::Error,
+    now: Instant,
+) -> Option<Answer> {
+    if e.kind() == io::ErrorKind::ConnectionRefused {
+        return scan.on_reply(
+            &Reply {
+                host,
+                port,
+                response: Response::Tcp {
+                    flags: TcpFlags::RST,
+                    window: 0,
+                },
+                ttl: None,
+                observation: None,
+            },
+            now,
+        );
+    }
+    debug!("{}:{}: {}", host, port, e);
+    let reason = match e.kind() {
+        io::ErrorKind::HostUnreachable => Reason::HostUnreach,
+        io::ErrorKind::NetworkUnreachable => Reason::NetUnreach,
+        _ => Reason::Error,
+    };
+    scan.set_state(host, port, PortState::Filtered, reason, now);
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::{PortResult, ScanConfig, ScanType};
+    use net2::TcpBuilder;
+    use std::net::{self, Ipv4Addr};
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    /**
+     * 誰も待ち受けていないポート
+     */
+    fn closed_port() -> u16 {
+        let listener = net::TcpListener::bind((LOCALHOST, 0)).unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    /**
+     * SYNに応答しないポート
+     * バックログを0にして1つ接続しておくと、以降のSYNはacceptされるまで捨てられる
+     */
+    fn silent_port() -> (net::TcpListener, net::TcpStream) {
+        let listener = TcpBuilder::new_v4()
+            .unwrap()
+            .bind((LOCALHOST, 0))
+            .unwrap()
+            .listen(0)
+            .unwrap();
+        let stream = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (listener, stream)
+    }
+
+    fn scan(ports: &[u16], config: ScanConfig, max_parallelism: usize) -> Vec<PortResult> {
+        let mut scan = Scan::new(ScanType::Connect, config, &[LOCALHOST], ports);
+        run(&mut scan, &Timing::default(), max_parallelism).unwrap();
+        assert!(scan.finished());
+        scan.results().remove(0)
+    }
+
+    fn states(results: &[PortResult]) -> Vec<(u16, PortState, Reason)> {
+        results
+            .iter()
+            .map(|result| (result.port, result.state, result.reason))
+            .collect()
+    }
+
+    #[test]
+    fn listening_port_is_open_and_others_are_closed() {
+        let listener = net::TcpListener::bind((LOCALHOST, 0)).unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = closed_port();
+
+        let results = scan(&[open, closed], ScanConfig::default(), 10);
+        let mut expected = vec![
+            (open, PortState::Open, Reason::SynAck),
+            (closed, PortState::Closed, Reason::Reset),
+        ];
+        expected.sort_by_key(|&(port, _, _)| port);
+        assert_eq!(states(&results), expected);
+        assert!(results.iter().all(|result| result.rtt.is_some()));
+        assert!(results.iter().all(|result| result.ttl.is_none()));
+    }
+
+    #[test]
+    fn unanswered_connects_time_out_as_filtered() {
+        let silent: Vec<_> = (0..3).map(|_| silent_port()).collect();
+        let mut ports: Vec<u16> = silent
+            .iter()
+            .map(|(listener, _)| listener.local_addr().unwrap().port())
+            .collect();
+        let listener = net::TcpListener::bind((LOCALHOST, 0)).unwrap();
+        let open = listener.local_addr().unwrap().port();
+        ports.push(open);
+        let config = ScanConfig {
+            timeout: Duration::from_millis(100),
+            retries: 1,
+            max_time: None,
+        };
+
+        // 1つずつしか接続しないので、応答しないポートごとに2回タイムアウトを待つ
+        let started = Instant::now();
+        let results = scan(&ports, config, 1);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(600), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(5), "{:?}", elapsed);
+
+        let mut expected: Vec<_> = ports[..3]
+            .iter()
+            .map(|&port| (port, PortState::Filtered, Reason::NoResponse))
+            .collect();
+        expected.push((open, PortState::Open, Reason::SynAck));
+        expected.sort_by_key(|&(port, _, _)| port);
+        assert_eq!(states(&results), expected);
+    }
+
+    #[test]
+    fn time_limit_stops_the_scan() {
+        let (listener, _stream) = silent_port();
+        let port = listener.local_addr().unwrap().port();
+        let config = ScanConfig {
+            timeout: Duration::from_secs(10),
+            retries: 2,
+            max_time: Some(Duration::from_millis(200)),
+        };
+
+        let started = Instant::now();
+        let results = scan(&[port], config, 100);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            states(&results),
+            [(port, PortState::Filtered, Reason::NoResponse)]
+        );
+    }
+}
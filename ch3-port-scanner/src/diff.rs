@@ -0,0 +1,248 @@
+use crate::report::ScanReport;
+use crate::scan::PortState;
+use anyhow::{Context, Result};
+use std::{collections::BTreeMap, fs, io::Write, net::IpAddr};
+
+/**
+ * JSONで保存したスキャン結果を読み込む
+ */
+pub fn load(path: &str) -> Result<ScanReport> {
+    let contents = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    serde_json::from_str(&contents).with_context(|| format!("{} is not a JSON report", path))
+}
+
+/**
+ * ホストごとに、プロトコルとポート番号から状態を引けるようにする
+ */
+fn states(report: &ScanReport) -> BTreeMap<IpAddr, BTreeMap<(String, u16), PortState>> {
+    report
+        .hosts
+        .iter()
+        .map(|host| {
+            let ports = host
+                .ports
+                .iter()
+                .map(|port| ((port.protocol.clone(), port.port), port.state))
+                .collect();
+            (host.addr, ports)
+        })
+        .collect()
+}
+
+/**
+ * 2つのスキャン結果を比べ、状態が変わったポートを表示する
+ * 片方でしかスキャンしていないポートは状態を"-"として表示する
+ * 変化があればtrueを返す
+ */
+pub fn compare<W: Write>(old: &ScanReport, new: &ScanReport, w: &mut W) -> Result<bool> {
+    let old_states = states(old);
+    let new_states = states(new);
+    let mut changed = false;
+    for host in new
+        .hosts
+        .iter()
+        .filter(|host| !old_states.contains_key(&host.addr))
+    {
+        writeln!(w, "New host {}", host.label())?;
+        changed = true;
+    }
+    for host in old
+        .hosts
+        .iter()
+        .filter(|host| !new_states.contains_key(&host.addr))
+    {
+        writeln!(w, "Missing host {}", host.label())?;
+        changed = true;
+    }
+    for host in &new.hosts {
+        let (old_ports, new_ports) = match (old_states.get(&host.addr), new_states.get(&host.addr))
+        {
+            (Some(old_ports), Some(new_ports)) => (old_ports, new_ports),
+            _ => continue,
+        };
+        let mut keys: Vec<_> = old_ports.keys().chain(new_ports.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        let mut lines = vec![];
+        for key in keys {
+            let (old_state, new_state) = (old_ports.get(key), new_ports.get(key));
+            if old_state == new_state {
+                continue;
+            }
+            let label = |state: Option<&PortState>| match state {
+                Some(state) => state.to_string(),
+                None => "-".to_string(),
+            };
+            lines.push(format!(
+                "{:<9} {} -> {}",
+                format!("{}/{}", key.1, key.0),
+                label(old_state),
+                label(new_state)
+            ));
+        }
+        if lines.is_empty() {
+            continue;
+        }
+        writeln!(w, "Changes for {}", host.label())?;
+        for line in lines {
+            writeln!(w, "{}", line)?;
+        }
+        changed = true;
+    }
+    if !changed {
+        writeln!(w, "No changes")?;
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::HostReason;
+    use crate::report::{Format, HostReport, PortReport};
+    use crate::scan::Reason;
+    use std::{env, net::Ipv4Addr, process};
+
+    const A: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const B: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    fn host(addr: IpAddr, ports: &[(u16, PortState)]) -> HostReport {
+        HostReport {
+            addr,
+            name: None,
+            reason: HostReason::EchoReply,
+            reason_ttl: None,
+            elapsed: 1.0,
+            ports: ports
+                .iter()
+                .map(|&(port, state)| PortReport {
+                    port,
+                    protocol: "tcp".to_string(),
+                    state,
+                    reason: Reason::NoResponse,
+                    ttl: None,
+                    rtt_ms: None,
+                    service: None,
+                })
+                .collect(),
+            os: None,
+        }
+    }
+
+    fn report(hosts: Vec<HostReport>) -> ScanReport {
+        ScanReport {
+            scanner: "ch3-port-scanner".to_string(),
+            args: String::new(),
+            scan_type: Some("syn".to_string()),
+            start: 1_700_000_000,
+            elapsed: 1.0,
+            version_detection: false,
+            os_detection: false,
+            hosts,
+            down: 0,
+        }
+    }
+
+    fn diff(old: &ScanReport, new: &ScanReport) -> (bool, String) {
+        let mut buffer = vec![];
+        let changed = compare(old, new, &mut buffer).unwrap();
+        (changed, String::from_utf8(buffer).unwrap())
+    }
+
+    #[test]
+    fn no_changes() {
+        let old = report(vec![host(
+            A,
+            &[(22, PortState::Open), (23, PortState::Closed)],
+        )]);
+        let new = report(vec![host(
+            A,
+            &[(23, PortState::Closed), (22, PortState::Open)],
+        )]);
+        assert_eq!(diff(&old, &new), (false, "No changes\n".to_string()));
+    }
+
+    #[test]
+    fn new_and_missing_hosts() {
+        let old = report(vec![host(A, &[(22, PortState::Open)])]);
+        let mut b = host(B, &[(22, PortState::Open)]);
+        b.name = Some("b.example".to_string());
+        let new = report(vec![b]);
+        assert_eq!(
+            diff(&old, &new),
+            (
+                true,
+                "New host b.example (192.0.2.2)\nMissing host 192.0.2.1\n".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn state_changes_and_ports_scanned_on_one_side() {
+        let old = report(vec![host(
+            A,
+            &[
+                (22, PortState::Open),
+                (23, PortState::Closed),
+                (80, PortState::Filtered),
+            ],
+        )]);
+        let new = report(vec![host(
+            A,
+            &[
+                (22, PortState::Closed),
+                (80, PortState::Filtered),
+                (443, PortState::Open),
+            ],
+        )]);
+        assert_eq!(
+            diff(&old, &new),
+            (
+                true,
+                "Changes for 192.0.2.1\n\
+                 22/tcp    open -> closed\n\
+                 23/tcp    closed -> -\n\
+                 443/tcp   - -> open\n"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn same_port_on_other_protocols_is_another_port() {
+        let old = report(vec![host(A, &[(53, PortState::Open)])]);
+        let mut new = report(vec![host(A, &[(53, PortState::Open)])]);
+        new.hosts[0].ports[0].protocol = "udp".to_string();
+        assert_eq!(
+            diff(&old, &new),
+            (
+                true,
+                "Changes for 192.0.2.1\n53/tcp    open -> -\n53/udp    - -> open\n".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn loads_a_json_report() {
+        let old = report(vec![host(
+            A,
+            &[(22, PortState::Open), (23, PortState::Closed)],
+        )]);
+        let path = env::temp_dir().join(format!("ch3-port-scanner-diff-{}.json", process::id()));
+        let mut file = fs::File::create(&path).unwrap();
+        old.write(Format::Json, &mut file).unwrap();
+        drop(file);
+        let path = path.to_str().unwrap();
+        let loaded = load(path);
+        fs::remove_file(path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.hosts[0].addr, A);
+        assert_eq!(loaded.hosts[0].ports[1].state, PortState::Closed);
+        assert_eq!(diff(&old, &loaded), (false, "No changes\n".to_string()));
+
+        assert!(
+            format!("{:#}", load("/nonexistent/report.json").unwrap_err())
+                .starts_with("Failed to read /nonexistent/report.json")
+        );
+    }
+}
@@ -0,0 +1,772 @@
+use crate::channel::{self, RECEIVE_TIMEOUT};
+use crate::source::{self, Cookies};
+use anyhow::{anyhow, Context, Result};
+use ipnetwork::IpNetwork;
+use log::{debug, warn};
+use pnet::{
+    datalink::{
+        self, Channel::Ethernet, Config, DataLinkReceiver, DataLinkSender, NetworkInterface,
+    },
+    packet::{
+        arp::{ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket},
+        ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket},
+        icmp::{
+            echo_reply::EchoReplyPacket, echo_request::MutableEchoRequestPacket, IcmpPacket,
+            IcmpTypes,
+        },
+        icmpv6::{Icmpv6Packet, Icmpv6Types, MutableIcmpv6Packet},
+        ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+        tcp::{self, MutableTcpPacket, TcpFlags, TcpPacket},
+        util::checksum,
+        Packet,
+    },
+    transport::{TransportReceiver, TransportSender},
+    util::MacAddr,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/**
+ * ホストが動いているか調べるプローブ
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// ICMP（IPv6ではICMPv6）のEcho Request
+    Echo,
+    /// TCPのSYN。開いていればSYN/ACK、閉じていればRSTが返る
+    Syn(u16),
+    /// TCPのACK。状態を見ないファイアウォールならRSTが返る
+    Ack(u16),
+    /// 同じセグメントにあるIPv4のホストへのARPリクエスト
+    Arp,
+}
+
+/// nmapと同じく、ICMP Echo、443番へのSYN、80番へのACKを送る。同じセグメントのホストにはARPも送る
+pub const DEFAULT_PROBES: [Probe; 4] = [Probe::Echo, Probe::Syn(443), Probe::Ack(80), Probe::Arp];
+
+/// rawソケットを使えない場合にconnect()を試みるポート（nmapと同じ）
+const CONNECT_PORTS: [u16; 2] = [80, 443];
+
+/**
+ * ホストが動いていると判断した理由（nmapの--reasonと同じ名前）
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostReason {
+    EchoReply,
+    SynAck,
+    Reset,
+    ArpResponse,
+    ConnRefused,
+    /// -Pnで調べずに動いているとみなした
+    #[default]
+    UserSet,
+}
+
+impl HostReason {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HostReason::EchoReply => "echo-reply",
+            HostReason::SynAck => "syn-ack",
+            HostReason::Reset => "reset",
+            HostReason::ArpResponse => "arp-response",
+            HostReason::ConnRefused => "conn-refused",
+            HostReason::UserSet => "user-set",
+        }
+    }
+}
+
+/**
+ * 動いていると分かったホストの様子
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostStatus {
+    pub reason: HostReason,
+    /// 応答のTTL。IPv6、ARP、connect()では分からない
+    pub ttl: Option<u8>,
+}
+
+/**
+ * ホストの発見（ping sweep）
+ * スキャンとは独立していて、アドレスの一覧を渡せば応答したホストを返す
+ */
+pub struct Prober {
+    probes: Vec<Probe>,
+    timeout: Duration,
+    retries: u32,
+    /// プローブの送信間隔
+    interval: Duration,
+    /// connect()で調べる場合に同時に接続を試みる数
+    parallelism: usize,
+}
+
+impl Prober {
+    pub fn new(
+        probes: &[Probe],
+        timeout: Duration,
+        retries: u32,
+        rate: f64,
+        parallelism: usize,
+    ) -> Self {
+        Self {
+            probes: probes.to_vec(),
+            timeout,
+            retries,
+            interval: Duration::from_secs_f64(1.0 / rate),
+            parallelism,
+        }
+    }
+
+    /**
+     * ホストが動いているか調べ、応答があったホストとその理由を返す
+     * rawソケットを開けない（root権限がない）場合は、nmapと同じく80番と443番へのconnect()で調べる
+     */
+    pub fn discover(&self, addrs: &[IpAddr]) -> Result<HashMap<IpAddr, HostStatus>> {
+        let links = match Links::open(self, addrs) {
+            Ok(links) => links,
+            Err(e) if is_permission_denied(&e) => {
+                debug!("{:#}, discover hosts with connect()", e);
+                return Ok(self.discover_connect(addrs));
+            }
+            Err(e) => return Err(e),
+        };
+        self.discover_raw(links, addrs)
+    }
+
+    /**
+     * すべてのホストにプローブを送り、タイムアウトまで応答を待つ
+     * 応答のなかったホストには再送する
+     */
+    fn discover_raw(
+        &self,
+        mut links: Links,
+        addrs: &[IpAddr],
+    ) -> Result<HashMap<IpAddr, HostStatus>> {
+        let mut up = HashMap::new();
+        let mut pending = addrs.to_vec();
+        for round in 0..=self.retries {
+            for &host in &pending {
+                for reply in links.replies.try_iter() {
+                    up.entry(reply.0).or_insert(reply.1);
+                }
+                if up.contains_key(&host) {
+                    continue;
+                }
+                for probe in &self.probes {
+                    if links.send(*probe, host, round as u16)? {
+                        thread::sleep(self.interval);
+                    }
+                }
+            }
+            let deadline = Instant::now() + self.timeout;
+            while let Some(wait) = deadline.checked_duration_since(Instant::now()) {
+                match links.replies.recv_timeout(wait) {
+                    Ok((host, status)) => {
+                        up.entry(host).or_insert(status);
+                    }
+                    Err(RecvTimeoutError::Timeout) => break,
+                    Err(RecvTimeoutError::Disconnected) => {
+                        return Err(anyhow!("Receiver thread stopped"));
+                    }
+                }
+            }
+            pending.retain(|host| !up.contains_key(host));
+            if pending.is_empty() {
+                break;
+            }
+        }
+        Ok(up)
+    }
+
+    /**
+     * 80番と443番に接続を試み、接続できるか拒否されればホストは動いている
+     */
+    fn discover_connect(&self, addrs: &[IpAddr]) -> HashMap<IpAddr, HostStatus> {
+        let mut up = HashMap::new();
+        for chunk in addrs.chunks(self.parallelism) {
+            thread::scope(|s| {
+                let handles: Vec<_> = chunk
+                    .iter()
+                    .map(|&host| (host, s.spawn(move || self.connect(host))))
+                    .collect();
+                for (host, handle) in handles {
+                    if let Ok(Some(status)) = handle.join() {
+                        up.insert(host, status);
+                    }
+                }
+            });
+        }
+        up
+    }
+
+    fn connect(&self, host: IpAddr) -> Option<HostStatus> {
+        for _ in 0..=self.retries {
+            for port in CONNECT_PORTS {
+                let addr = SocketAddr::from((host, port));
+                let reason = match TcpStream::connect_timeout(&addr, self.timeout) {
+                    Ok(_) => HostReason::SynAck,
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                        HostReason::ConnRefused
+                    }
+                    Err(e) => {
+                        debug!("{}: {}", addr, e);
+                        continue;
+                    }
+                };
+                return Some(HostStatus { reason, ttl: None });
+            }
+        }
+        None
+    }
+}
+
+fn is_permission_denied(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied)
+}
+
+/**
+ * プローブへの応答を照合するための値
+ */
+#[derive(Clone)]
+struct Matcher {
+    hosts: Arc<HashSet<IpAddr>>,
+    cookies: Cookies,
+    my_port: u16,
+    /// Echo Requestの識別子
+    echo_id: u16,
+}
+
+impl Matcher {
+    /**
+     * TCPのプローブのシーケンス番号（ACKでは確認応答番号）
+     */
+    fn cookie(&self, host: IpAddr, port: u16) -> u32 {
+        self.cookies.seq(host, port, self.my_port)
+    }
+
+    /**
+     * IPヘッダより後ろを見て、どのプローブへの応答か調べる
+     */
+    fn parse(
+        &self,
+        host: IpAddr,
+        protocol: IpNextHeaderProtocol,
+        payload: &[u8],
+    ) -> Option<HostReason> {
+        match protocol {
+            IpNextHeaderProtocols::Tcp => self.parse_segment(host, payload),
+            IpNextHeaderProtocols::Icmp => {
+                let icmp_packet = IcmpPacket::new(payload)?;
+                if icmp_packet.get_icmp_type() != IcmpTypes::EchoReply {
+                    return None;
+                }
+                let reply = EchoReplyPacket::new(payload)?;
+                (reply.get_identifier() == self.echo_id).then_some(HostReason::EchoReply)
+            }
+            _ => {
+                let icmp_packet = Icmpv6Packet::new(payload)?;
+                if icmp_packet.get_icmpv6_type() != Icmpv6Types::EchoReply {
+                    return None;
+                }
+                // ICMPv6ヘッダの後に識別子とシーケンス番号が続く
+                let id = icmp_packet.payload().get(..2)?;
+                (u16::from_be_bytes([id[0], id[1]]) == self.echo_id)
+                    .then_some(HostReason::EchoReply)
+            }
+        }
+    }
+
+    /**
+     * SYNへの返信はSYN/ACKかRSTで、確認応答番号がシーケンス番号の次になる
+     * ACKへのRSTは、シーケンス番号がこちらの確認応答番号になる
+     */
+    fn parse_segment(&self, host: IpAddr, payload: &[u8]) -> Option<HostReason> {
+        let tcp_packet = TcpPacket::new(payload)?;
+        if tcp_packet.get_destination() != self.my_port {
+            return None;
+        }
+        let cookie = self.cookie(host, tcp_packet.get_source());
+        let flags = tcp_packet.get_flags();
+        let acked = flags & TcpFlags::ACK != 0
+            && tcp_packet.get_acknowledgement() == cookie.wrapping_add(1);
+        if flags & TcpFlags::RST != 0 && (acked || tcp_packet.get_sequence() == cookie) {
+            return Some(HostReason::Reset);
+        }
+        (acked && flags & TcpFlags::SYN != 0).then_some(HostReason::SynAck)
+    }
+}
+
+/**
+ * 同じセグメントにARPを送るためのインターフェイス
+ */
+struct ArpLink {
+    tx: Box<dyn DataLinkSender>,
+    mac: MacAddr,
+    ip: Ipv4Addr,
+    network: IpNetwork,
+}
+
+/**
+ * 応答を受信するスレッド
+ * 受信はRECEIVE_TIMEOUTごとに止めるかどうかを確かめるので、dropすると受信したチャネルとともに終わる
+ */
+#[derive(Default)]
+struct Receivers {
+    stop: Arc<AtomicBool>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Receivers {
+    fn spawn<F>(&mut self, receive: F)
+    where
+        F: FnOnce(&AtomicBool) + Send + 'static,
+    {
+        let stop = self.stop.clone();
+        self.handles.push(thread::spawn(move || receive(&stop)));
+    }
+}
+
+impl Drop for Receivers {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/**
+ * 発見に使うチャネルと、応答を受け取るチャネル
+ * 発見が終わってdropすると、受信するスレッドも止める
+ */
+struct Links {
+    /// IPv6かどうかとプロトコル（ICMP・ICMPv6・TCP）ごとの送信用のチャネル
+    senders: HashMap<(bool, IpNextHeaderProtocol), TransportSender>,
+    arp: Vec<ArpLink>,
+    /// チェックサムに使う宛先ごとの送信元アドレス
+    sources: HashMap<IpAddr, IpAddr>,
+    matcher: Matcher,
+    replies: mpsc::Receiver<(IpAddr, HostStatus)>,
+    /// dropしたときに受信するスレッドを止めるためだけに持つ
+    _receivers: Receivers,
+}
+
+impl Links {
+    /**
+     * 対象のアドレスファミリとプローブに必要なチャネルを開き、受信するスレッドを立ち上げる
+     */
+    fn open(prober: &Prober, addrs: &[IpAddr]) -> Result<Self> {
+        let matcher = Matcher {
+            hosts: Arc::new(addrs.iter().copied().collect()),
+            cookies: Cookies::default(),
+            my_port: source::random_port(),
+            echo_id: rand::thread_rng().gen(),
+        };
+        let (sender, replies) = mpsc::channel();
+        let mut senders = HashMap::new();
+        let mut receivers = Receivers::default();
+        for v6 in [false, true] {
+            if !addrs.iter().any(|addr| addr.is_ipv6() == v6) {
+                continue;
+            }
+            let mut protocols = vec![];
+            if prober.probes.contains(&Probe::Echo) {
+                protocols.push(if v6 {
+                    IpNextHeaderProtocols::Icmpv6
+                } else {
+                    IpNextHeaderProtocols::Icmp
+                });
+            }
+            if prober
+                .probes
+                .iter()
+                .any(|probe| matches!(probe, Probe::Syn(_) | Probe::Ack(_)))
+            {
+                protocols.push(IpNextHeaderProtocols::Tcp);
+            }
+            for protocol in protocols {
+                let (ts, tr) = channel::open(v6, protocol)
+                    .with_context(|| format!("Failed to open {} channel.", protocol))?;
+                senders.insert((v6, protocol), ts);
+                let matcher = matcher.clone();
+                let sender = sender.clone();
+                receivers
+                    .spawn(move |stop| receive_packets(tr, v6, protocol, &matcher, sender, stop));
+            }
+        }
+        let arp = if prober.probes.contains(&Probe::Arp) {
+            open_arp_links(addrs, &matcher, &sender, &mut receivers)
+        } else {
+            vec![]
+        };
+        let sources = addrs
+            .iter()
+            .map(|&host| Ok((host, source::route_source(host)?)))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            senders,
+            arp,
+            sources,
+            matcher,
+            replies,
+            _receivers: receivers,
+        })
+    }
+
+    /**
+     * ホストにプローブを送る。送るものがなければfalseを返す
+     */
+    fn send(&mut self, probe: Probe, host: IpAddr, seq: u16) -> Result<bool> {
+        match probe {
+            Probe::Echo => self.send_echo(host, seq)?,
+            Probe::Syn(port) => self.send_segment(host, port, TcpFlags::SYN)?,
+            Probe::Ack(port) => self.send_segment(host, port, TcpFlags::ACK)?,
+            Probe::Arp => return self.send_arp(host),
+        }
+        Ok(true)
+    }
+
+    fn sender(
+        &mut self,
+        host: IpAddr,
+        protocol: IpNextHeaderProtocol,
+    ) -> Result<&mut TransportSender> {
+        self.senders
+            .get_mut(&(host.is_ipv6(), protocol))
+            .with_context(|| format!("No channel for {}", host))
+    }
+
+    fn send_echo(&mut self, host: IpAddr, seq: u16) -> Result<()> {
+        let echo_id = self.matcher.echo_id;
+        let mut buffer = [0u8; 8];
+        match host {
+            IpAddr::V4(_) => {
+                let mut packet =
+                    MutableEchoRequestPacket::new(&mut buffer).context("invalid packet")?;
+                packet.set_icmp_type(IcmpTypes::EchoRequest);
+                packet.set_identifier(echo_id);
+                packet.set_sequence_number(seq);
+                let sum = checksum(packet.packet(), 1);
+                packet.set_checksum(sum);
+                self.sender(host, IpNextHeaderProtocols::Icmp)?
+                    .send_to(packet, host)?;
+            }
+            IpAddr::V6(_) => {
+                // ICMPv6のチェックサムはカーネルが計算する
+                let mut packet = MutableIcmpv6Packet::new(&mut buffer).context("invalid packet")?;
+                packet.set_icmpv6_type(Icmpv6Types::EchoRequest);
+                let mut payload = echo_id.to_be_bytes().to_vec();
+                payload.extend_from_slice(&seq.to_be_bytes());
+                packet.set_payload(&payload);
+                self.sender(host, IpNextHeaderProtocols::Icmpv6)?
+                    .send_to(packet, host)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_segment(&mut self, host: IpAddr, port: u16, flags: u16) -> Result<()> {
+        let cookie = self.matcher.cookie(host, port);
+        let source = self.sources[&host];
+        let mut buffer = [0u8; 20];
+        let mut packet = MutableTcpPacket::new(&mut buffer).context("invalid packet")?;
+        packet.set_source(self.matcher.my_port);
+        packet.set_destination(port);
+        packet.set_data_offset(5);
+        packet.set_flags(flags);
+        packet.set_window(1024);
+        if flags == TcpFlags::ACK {
+            packet.set_acknowledgement(cookie);
+        } else {
+            packet.set_sequence(cookie);
+        }
+        let sum = match (source, host) {
+            (IpAddr::V4(source), IpAddr::V4(host)) => {
+                tcp::ipv4_checksum(&packet.to_immutable(), &source, &host)
+            }
+            (IpAddr::V6(source), IpAddr::V6(host)) => {
+                tcp::ipv6_checksum(&packet.to_immutable(), &source, &host)
+            }
+            _ => return Err(anyhow!("{} and {} are different families", source, host)),
+        };
+        packet.set_checksum(sum);
+        self.sender(host, IpNextHeaderProtocols::Tcp)?
+            .send_to(packet, host)?;
+        Ok(())
+    }
+
+    /**
+     * 宛先と同じセグメントのインターフェイスからARPリクエストをブロードキャストする
+     */
+    fn send_arp(&mut self, host: IpAddr) -> Result<bool> {
+        let target = match host {
+            IpAddr::V4(target) => target,
+            IpAddr::V6(_) => return Ok(false),
+        };
+        let link = match self.arp.iter_mut().find(|link| link.network.contains(host)) {
+            Some(link) => link,
+            None => return Ok(false),
+        };
+        let mut arp_buffer = [0u8; 28];
+        let mut arp_packet = MutableArpPacket::new(&mut arp_buffer).context("invalid packet")?;
+        arp_packet.set_hardware_type(ArpHardwareTypes::Ethernet);
+        arp_packet.set_protocol_type(EtherTypes::Ipv4);
+        arp_packet.set_hw_addr_len(6);
+        arp_packet.set_proto_addr_len(4);
+        arp_packet.set_operation(ArpOperations::Request);
+        arp_packet.set_sender_hw_addr(link.mac);
+        arp_packet.set_sender_proto_addr(link.ip);
+        arp_packet.set_target_hw_addr(MacAddr::zero());
+        arp_packet.set_target_proto_addr(target);
+
+        let mut ethernet_buffer = [0u8; 42];
+        let mut ethernet_packet =
+            MutableEthernetPacket::new(&mut ethernet_buffer).context("invalid packet")?;
+        ethernet_packet.set_destination(MacAddr::broadcast());
+        ethernet_packet.set_source(link.mac);
+        ethernet_packet.set_ethertype(EtherTypes::Arp);
+        ethernet_packet.set_payload(arp_packet.packet());
+        link.tx
+            .send_to(ethernet_packet.packet(), None)
+            .context("Failed to send ARP request")??;
+        Ok(true)
+    }
+}
+
+/**
+ * 対象のホストを含むネットワークに繋がったインターフェイスごとに、ARPのチャネルを開く
+ * 開けなかったインターフェイスはARPを使わない
+ */
+fn open_arp_links(
+    addrs: &[IpAddr],
+    matcher: &Matcher,
+    sender: &Sender<(IpAddr, HostStatus)>,
+    receivers: &mut Receivers,
+) -> Vec<ArpLink> {
+    let mut links = vec![];
+    for interface in datalink::interfaces() {
+        if !interface.is_up() || interface.is_loopback() {
+            continue;
+        }
+        let mac = match interface.mac {
+            Some(mac) => mac,
+            None => continue,
+        };
+        let (ip, network) = match interface.ips.iter().find_map(|network| match network {
+            IpNetwork::V4(v4) if addrs.iter().any(|addr| network.contains(*addr)) => {
+                Some((v4.ip(), *network))
+            }
+            _ => None,
+        }) {
+            Some(found) => found,
+            None => continue,
+        };
+        let (tx, rx) = match open_datalink(&interface) {
+            Ok(channel) => channel,
+            Err(e) => {
+                warn!("{}: {:#}", interface.name, e);
+                continue;
+            }
+        };
+        let matcher = matcher.clone();
+        let sender = sender.clone();
+        receivers.spawn(move |stop| receive_arp(rx, &matcher, sender, stop));
+        links.push(ArpLink {
+            tx,
+            mac,
+            ip,
+            network,
+        });
+    }
+    links
+}
+
+fn open_datalink(
+    interface: &NetworkInterface,
+) -> Result<(Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>)> {
+    let config = Config {
+        read_timeout: Some(RECEIVE_TIMEOUT),
+        ..Default::default()
+    };
+    match datalink::channel(interface, config) {
+        Ok(Ethernet(tx, rx)) => Ok((tx, rx)),
+        Ok(_) => Err(anyhow!("Unhandled channel type")),
+        Err(e) => Err(anyhow!("Failed to create datalink channel {}", e)),
+    }
+}
+
+/**
+ * パケットを受信して、対象のホストからの応答をチャネルに送る
+ * 発見が終わっていたら受信もやめる
+ */
+fn receive_packets(
+    mut tr: TransportReceiver,
+    v6: bool,
+    protocol: IpNextHeaderProtocol,
+    matcher: &Matcher,
+    sender: Sender<(IpAddr, HostStatus)>,
+    stop: &AtomicBool,
+) {
+    while !stop.load(Ordering::Relaxed) {
+        let (host, payload, header) = match channel::next_packet(&mut tr, v6, protocol) {
+            Ok(Some(received)) => received,
+            Ok(None) => continue,
+            Err(e) => {
+                debug!("Failed to receive: {}", e);
+                continue;
+            }
+        };
+        if !matcher.hosts.contains(&host) {
+            continue;
+        }
+        let reason = match matcher.parse(host, protocol, &payload) {
+            Some(reason) => reason,
+            None => continue,
+        };
+        let ttl = header.map(|header| header.ttl);
+        if sender.send((host, HostStatus { reason, ttl })).is_err() {
+            return;
+        }
+    }
+}
+
+/**
+ * ARPのリプライを受信して、対象のホストからの応答をチャネルに送る
+ */
+fn receive_arp(
+    mut rx: Box<dyn DataLinkReceiver>,
+    matcher: &Matcher,
+    sender: Sender<(IpAddr, HostStatus)>,
+    stop: &AtomicBool,
+) {
+    while !stop.load(Ordering::Relaxed) {
+        let frame = match rx.next() {
+            Ok(frame) => frame,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => {
+                debug!("Failed to receive: {}", e);
+                continue;
+            }
+        };
+        let ethernet_packet = match EthernetPacket::new(frame) {
+            Some(packet) if packet.get_ethertype() == EtherTypes::Arp => packet,
+            _ => continue,
+        };
+        let arp_packet = match ArpPacket::new(ethernet_packet.payload()) {
+            Some(packet) if packet.get_operation() == ArpOperations::Reply => packet,
+            _ => continue,
+        };
+        let host = IpAddr::V4(arp_packet.get_sender_proto_addr());
+        if !matcher.hosts.contains(&host) {
+            continue;
+        }
+        let status = HostStatus {
+            reason: HostReason::ArpResponse,
+            ttl: None,
+        };
+        if sender.send((host, status)).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::Ipv6Addr, sync::atomic::AtomicUsize};
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn prober(probes: &[Probe]) -> Prober {
+        Prober::new(probes, Duration::from_millis(500), 1, 1000.0, 4)
+    }
+
+    /**
+     * rawソケットを開けるか
+     */
+    fn privileged() -> bool {
+        channel::open(false, IpNextHeaderProtocols::Icmp).is_ok()
+    }
+
+    /**
+     * connect()で調べた場合に、80番の様子から期待される理由
+     */
+    fn connect_reason(host: IpAddr) -> HostReason {
+        match TcpStream::connect((host, 80)) {
+            Ok(_) => HostReason::SynAck,
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => HostReason::ConnRefused,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn localhost_answers_echo_or_connect() {
+        let up = prober(&[Probe::Echo]).discover(&[LOCALHOST]).unwrap();
+        let status = up[&LOCALHOST];
+        if privileged() {
+            assert_eq!(status.reason, HostReason::EchoReply);
+            assert_eq!(status.ttl, Some(64));
+        } else {
+            assert_eq!(status.reason, connect_reason(LOCALHOST));
+            assert_eq!(status.ttl, None);
+        }
+        assert_eq!(up.len(), 1);
+    }
+
+    #[test]
+    fn connect_fallback_finds_localhost() {
+        let hosts = [LOCALHOST, IpAddr::V6(Ipv6Addr::LOCALHOST)];
+        let up = prober(&[]).discover_connect(&hosts);
+        assert_eq!(up.len(), 2);
+        for host in hosts {
+            assert_eq!(up[&host].reason, connect_reason(host));
+            assert_eq!(up[&host].ttl, None);
+        }
+    }
+
+    #[test]
+    fn receivers_stop_when_links_are_dropped() {
+        if !privileged() {
+            return;
+        }
+        let prober = prober(&[Probe::Echo, Probe::Syn(443)]);
+        let links = Links::open(&prober, &[LOCALHOST]).unwrap();
+        assert_eq!(links._receivers.handles.len(), 2);
+        let stop = links._receivers.stop.clone();
+
+        let started = Instant::now();
+        drop(links);
+        assert!(stop.load(Ordering::Relaxed));
+        assert!(started.elapsed() < RECEIVE_TIMEOUT * 5);
+    }
+
+    #[test]
+    fn receivers_are_joined_on_drop() {
+        let finished = Arc::new(AtomicUsize::new(0));
+        let mut receivers = Receivers::default();
+        for _ in 0..3 {
+            let finished = finished.clone();
+            receivers.spawn(move |stop| {
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                finished.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        drop(receivers);
+        assert_eq!(finished.load(Ordering::Relaxed), 3);
+    }
+}
@@ -0,0 +1,986 @@
+use crate::channel::IpHeader;
+use anyhow::{anyhow, Context, Result};
+use pnet::packet::tcp::{TcpFlags, TcpPacket};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, fs, net::IpAddr};
+
+/// 組み込みのシグネチャ。--os-dbで別のファイルを指定できる
+const DEFAULT_SIGNATURES: &str = include_str!("../os-signatures");
+/// ホストごと、返信の種類ごとに残す観測の数
+const MAX_OBSERVATIONS: usize = 8;
+/// 初期TTLから減ってよいホップ数（p0fと同じ）
+const MAX_DISTANCE: u8 = 35;
+/// 連番とみなすIP IDの増分の上限
+const MAX_ID_STEP: u16 = 1000;
+/// これより確信度の低い推定は表示しない
+const MIN_CONFIDENCE: u8 = 50;
+
+/// 確信度の内訳。足すと100になり、シグネチャが*の項目と観測できない項目は半分とする
+const LAYOUT_WEIGHT: u32 = 30;
+const WINDOW_WEIGHT: u32 = 20;
+const TTL_WEIGHT: u32 = 15;
+const QUIRKS_WEIGHT: u32 = 10;
+const MSS_WEIGHT: u32 = 10;
+const SCALE_WEIGHT: u32 = 5;
+const PAYLOAD_WEIGHT: u32 = 5;
+const IP_ID_WEIGHT: u32 = 5;
+
+/**
+ * OSの推定に使う返信の種類
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    SynAck,
+    Reset,
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::SynAck => "syn-ack",
+            Kind::Reset => "reset",
+        }
+    }
+}
+
+/**
+ * 複数の返信から分かるIP IDの付け方
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpId {
+    /// 常に0
+    Zero,
+    /// 返信ごとに少しずつ増える
+    Incremental,
+    Random,
+    /// IPv6か、IPv4の返信が1つしかない
+    Unknown,
+}
+
+impl IpId {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IpId::Zero => "zero",
+            IpId::Incremental => "incremental",
+            IpId::Random => "random",
+            IpId::Unknown => "unknown",
+        }
+    }
+
+    /**
+     * 受け取った順に並べたIP IDから付け方を判断する
+     */
+    fn classify(ids: &[u16]) -> Self {
+        if ids.len() < 2 {
+            return IpId::Unknown;
+        }
+        if ids.iter().all(|&id| id == 0) {
+            return IpId::Zero;
+        }
+        let incremental = ids.windows(2).all(|pair| {
+            let step = pair[1].wrapping_sub(pair[0]);
+            step > 0 && step <= MAX_ID_STEP
+        });
+        if incremental {
+            IpId::Incremental
+        } else {
+            IpId::Random
+        }
+    }
+
+    /**
+     * シグネチャでの表記（zero・incr・rand）を読む
+     */
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "zero" => Ok(IpId::Zero),
+            "incr" => Ok(IpId::Incremental),
+            "rand" => Ok(IpId::Random),
+            _ => Err(anyhow!("invalid ip id {}", value)),
+        }
+    }
+}
+
+/**
+ * TCPオプションの並び（p0fのolayoutの名前）
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TcpOption {
+    /// 後ろに続くパディングのオクテット数
+    Eol(u8),
+    Nop,
+    Mss,
+    Ws,
+    Sok,
+    Sack,
+    Ts,
+    Unknown(u8),
+}
+
+impl TcpOption {
+    fn parse(value: &str) -> Result<Self> {
+        let option = match value {
+            "nop" => TcpOption::Nop,
+            "mss" => TcpOption::Mss,
+            "ws" => TcpOption::Ws,
+            "sok" => TcpOption::Sok,
+            "sack" => TcpOption::Sack,
+            "ts" => TcpOption::Ts,
+            _ => {
+                if let Some(padding) = value.strip_prefix("eol+") {
+                    TcpOption::Eol(padding.parse().context("invalid eol padding")?)
+                } else if let Some(kind) = value.strip_prefix('?') {
+                    TcpOption::Unknown(kind.parse().context("invalid option kind")?)
+                } else {
+                    return Err(anyhow!("unknown option {}", value));
+                }
+            }
+        };
+        Ok(option)
+    }
+}
+
+impl fmt::Display for TcpOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TcpOption::Eol(padding) => write!(f, "eol+{}", padding),
+            TcpOption::Nop => write!(f, "nop"),
+            TcpOption::Mss => write!(f, "mss"),
+            TcpOption::Ws => write!(f, "ws"),
+            TcpOption::Sok => write!(f, "sok"),
+            TcpOption::Sack => write!(f, "sack"),
+            TcpOption::Ts => write!(f, "ts"),
+            TcpOption::Unknown(kind) => write!(f, "?{}", kind),
+        }
+    }
+}
+
+/**
+ * IPヘッダの特徴（p0fのquirksのうちIPに関するもの）
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quirk {
+    /// DFビットが立っている
+    Df,
+    /// DFビットが立っているのにIP IDが0でない
+    IdPlus,
+    /// DFビットが立っていないのにIP IDが0
+    IdMinus,
+}
+
+impl Quirk {
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "df" => Ok(Quirk::Df),
+            "id+" => Ok(Quirk::IdPlus),
+            "id-" => Ok(Quirk::IdMinus),
+            _ => Err(anyhow!("unsupported quirk {}", value)),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Quirk::Df => "df",
+            Quirk::IdPlus => "id+",
+            Quirk::IdMinus => "id-",
+        }
+    }
+
+    /**
+     * IPv4ヘッダから、p0fと同じ順に並べた特徴を取り出す
+     */
+    fn of(header: &IpHeader) -> Vec<Quirk> {
+        let mut quirks = vec![];
+        if header.dont_fragment {
+            quirks.push(Quirk::Df);
+            if header.id != 0 {
+                quirks.push(Quirk::IdPlus);
+            }
+        } else if header.id == 0 {
+            quirks.push(Quirk::IdMinus);
+        }
+        quirks
+    }
+}
+
+/**
+ * 1つの返信から読み取った特徴
+ */
+#[derive(Debug, Clone)]
+pub struct Observation {
+    kind: Kind,
+    /// IPv6ではNone
+    ip: Option<IpHeader>,
+    window: u16,
+    mss: Option<u16>,
+    /// ウィンドウスケールのオプションがなければ0（p0fと同じ）
+    scale: u8,
+    layout: Vec<TcpOption>,
+    payload: bool,
+}
+
+impl Observation {
+    /**
+     * SYN/ACKかRSTのセグメントから特徴を読み取る。それ以外のセグメントはNoneを返す
+     */
+    pub fn parse(ip: Option<IpHeader>, segment: &[u8]) -> Option<Self> {
+        let tcp_packet = TcpPacket::new(segment)?;
+        let flags = tcp_packet.get_flags();
+        let kind = if flags & TcpFlags::RST != 0 {
+            Kind::Reset
+        } else if flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN | TcpFlags::ACK {
+            Kind::SynAck
+        } else {
+            return None;
+        };
+        let header_len = tcp_packet.get_data_offset() as usize * 4;
+        let options = segment.get(20..header_len)?;
+        let mut observation = Observation {
+            kind,
+            ip,
+            window: tcp_packet.get_window(),
+            mss: None,
+            scale: 0,
+            layout: vec![],
+            payload: segment.len() > header_len,
+        };
+        observation.read_options(options);
+        Some(observation)
+    }
+
+    /**
+     * TCPオプションを順に読む。長さのおかしいオプションがあれば、そこまでとする
+     */
+    fn read_options(&mut self, options: &[u8]) {
+        let mut i = 0;
+        while i < options.len() {
+            let kind = options[i];
+            match kind {
+                0 => {
+                    self.layout
+                        .push(TcpOption::Eol((options.len() - i - 1) as u8));
+                    return;
+                }
+                1 => {
+                    self.layout.push(TcpOption::Nop);
+                    i += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            let len = match options.get(i + 1) {
+                Some(&len) if len >= 2 && i + len as usize <= options.len() => len as usize,
+                _ => return,
+            };
+            let data = &options[i + 2..i + len];
+            let option = match (kind, data.len()) {
+                (2, 2) => {
+                    self.mss = Some(u16::from_be_bytes([data[0], data[1]]));
+                    TcpOption::Mss
+                }
+                (3, 1) => {
+                    self.scale = data[0];
+                    TcpOption::Ws
+                }
+                (4, _) => TcpOption::Sok,
+                (5, _) => TcpOption::Sack,
+                (8, _) => TcpOption::Ts,
+                _ => TcpOption::Unknown(kind),
+            };
+            self.layout.push(option);
+            i += len;
+        }
+    }
+
+    /**
+     * IPv4では、TTLを初期値とそこからのホップ数に分ける
+     */
+    fn ttl(&self) -> Option<(u8, u8)> {
+        let ttl = self.ip?.ttl;
+        let initial = [32, 64, 128, 255]
+            .into_iter()
+            .find(|&initial| ttl <= initial)
+            .unwrap_or(255);
+        Some((initial, initial - ttl))
+    }
+}
+
+/**
+ * p0fのシグネチャの形式（ver:ittl:olen:mss:wsize,scale:olayout:quirks:pclass）で表す
+ * 初期TTLの後ろにはp0fと同じく+でホップ数を付ける
+ */
+impl fmt::Display for Observation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let version = if self.ip.is_some() { 4 } else { 6 };
+        let ttl = match self.ttl() {
+            Some((initial, distance)) => format!("{}+{}", initial, distance),
+            None => "*".to_string(),
+        };
+        let olen = self.ip.map_or(0, |ip| ip.options_len);
+        let mss = self.mss.map_or("*".to_string(), |mss| mss.to_string());
+        let layout: Vec<String> = self.layout.iter().map(TcpOption::to_string).collect();
+        let quirks: Vec<&str> = self
+            .ip
+            .map(|ip| Quirk::of(&ip))
+            .unwrap_or_default()
+            .iter()
+            .map(Quirk::name)
+            .collect();
+        write!(
+            f,
+            "{}:{}:{}:{}:{},{}:{}:{}:{}",
+            version,
+            ttl,
+            olen,
+            mss,
+            self.window,
+            self.scale,
+            layout.join(","),
+            quirks.join(","),
+            if self.payload { "+" } else { "0" }
+        )
+    }
+}
+
+/**
+ * シグネチャのウィンドウサイズ
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Window {
+    Any,
+    Value(u16),
+    /// MSSの倍数
+    Mss(u16),
+    /// MTU（MSSとIPとTCPのヘッダの長さの和）の倍数
+    Mtu(u16),
+    /// この値で割り切れる
+    Modulo(u16),
+}
+
+impl Window {
+    fn parse(value: &str) -> Result<Self> {
+        let window = if value == "*" {
+            Window::Any
+        } else if let Some(n) = value.strip_prefix("mss*") {
+            Window::Mss(n.parse()?)
+        } else if let Some(n) = value.strip_prefix("mtu*") {
+            Window::Mtu(n.parse()?)
+        } else if let Some(n) = value.strip_prefix('%') {
+            Window::Modulo(n.parse()?)
+        } else {
+            Window::Value(value.parse()?)
+        };
+        Ok(window)
+    }
+
+    /**
+     * 観測したウィンドウサイズに一致するか。MSSが分からず判断できなければNone
+     */
+    fn matches(&self, observation: &Observation) -> Option<bool> {
+        let window = observation.window as u32;
+        let headers = if observation.ip.is_some() { 40 } else { 60 };
+        match *self {
+            Window::Any => None,
+            Window::Value(value) => Some(window == value as u32),
+            Window::Mss(n) => observation.mss.map(|mss| window == mss as u32 * n as u32),
+            Window::Mtu(n) => observation
+                .mss
+                .map(|mss| window == (mss as u32 + headers) * n as u32),
+            Window::Modulo(n) => Some(n != 0 && window.is_multiple_of(n as u32)),
+        }
+    }
+}
+
+/**
+ * p0fのラベル（種類:分類:名前:詳細）
+ */
+#[derive(Debug, Clone)]
+struct Label {
+    /// s（特定のOS）でなくg（系統だけ）のもの
+    generic: bool,
+    class: String,
+    name: String,
+    flavor: String,
+}
+
+impl Label {
+    fn parse(value: &str) -> Result<Self> {
+        let fields: Vec<&str> = value.splitn(4, ':').collect();
+        let (generic, class, name, flavor) = match fields.as_slice() {
+            ["s", class, name, flavor] => (false, class, name, flavor),
+            ["g", class, name, flavor] => (true, class, name, flavor),
+            _ => return Err(anyhow!("invalid label {}", value)),
+        };
+        Ok(Self {
+            generic,
+            class: class.to_string(),
+            name: name.to_string(),
+            flavor: flavor.to_string(),
+        })
+    }
+}
+
+/**
+ * OSの返信の特徴を表す1行。Noneの項目は*（何でもよい）
+ */
+#[derive(Debug, Clone)]
+struct Signature {
+    kind: Kind,
+    label: Label,
+    version: Option<u8>,
+    ttl: Option<u8>,
+    olen: u8,
+    mss: Option<u16>,
+    window: Window,
+    scale: Option<u8>,
+    layout: Vec<TcpOption>,
+    quirks: Vec<Quirk>,
+    payload: Option<bool>,
+    ip_id: Option<IpId>,
+}
+
+/**
+ * 一致した項目の重みを返す。判断できない項目は半分とする
+ */
+fn weigh(weight: u32, matched: Option<bool>) -> u32 {
+    match matched {
+        Some(true) => weight,
+        Some(false) => 0,
+        None => weight / 2,
+    }
+}
+
+impl Signature {
+    /**
+     * 観測した返信がどれだけ一致するかを、IP ID以外の重みの和で返す
+     * 返信の種類、IPのバージョン、初期TTLが合わなければ比べられないのでNoneを返す
+     */
+    fn score(&self, observation: &Observation) -> Option<u32> {
+        if self.kind != observation.kind {
+            return None;
+        }
+        let version = if observation.ip.is_some() { 4 } else { 6 };
+        if self.version.is_some_and(|expected| expected != version) {
+            return None;
+        }
+        let ttl = match (self.ttl, observation.ip) {
+            (Some(initial), Some(ip)) => {
+                if ip.ttl > initial || initial - ip.ttl > MAX_DISTANCE {
+                    return None;
+                }
+                Some(true)
+            }
+            _ => None,
+        };
+        let quirks = observation
+            .ip
+            .map(|ip| Quirk::of(&ip) == self.quirks && ip.options_len == self.olen);
+        let mut score = weigh(LAYOUT_WEIGHT, Some(observation.layout == self.layout));
+        score += weigh(WINDOW_WEIGHT, self.window.matches(observation));
+        score += weigh(TTL_WEIGHT, ttl);
+        score += weigh(QUIRKS_WEIGHT, quirks);
+        score += weigh(MSS_WEIGHT, self.mss.map(|mss| observation.mss == Some(mss)));
+        score += weigh(
+            SCALE_WEIGHT,
+            self.scale.map(|scale| observation.scale == scale),
+        );
+        score += weigh(
+            PAYLOAD_WEIGHT,
+            self.payload.map(|payload| observation.payload == payload),
+        );
+        Some(score)
+    }
+}
+
+/**
+ * 推定したOS
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OsGuess {
+    /// "Linux 3.x"のような名前と詳細
+    pub name: String,
+    pub family: String,
+    /// unix・winなど（p0fの分類）
+    pub class: String,
+    /// 一致の度合い（0から100）
+    pub confidence: u8,
+}
+
+/**
+ * ホストごとのOSの推定の結果
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OsReport {
+    /// 推定に使った返信の種類と、その特徴をp0fのシグネチャの形式で表したもの
+    pub kind: Kind,
+    pub fingerprint: String,
+    pub ip_id: IpId,
+    /// 一致するシグネチャがなければNone
+    pub guess: Option<OsGuess>,
+}
+
+/**
+ * p0fの形式のシグネチャファイルの内容
+ * [tcp:response]にSYN/ACK、[tcp:rst]にRSTのシグネチャを、labelの行に続くsigの行で書く
+ * sigの行にはp0fの項目の後にIP IDの付け方（zero・incr・rand・*）を加えてもよい
+ * それ以外のセクションは読み飛ばす
+ */
+pub struct OsDb {
+    signatures: Vec<Signature>,
+}
+
+impl OsDb {
+    /**
+     * シグネチャファイルを読み込む。指定がなければ組み込みのものを使う
+     */
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        match path {
+            Some(path) => {
+                let contents =
+                    fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+                Self::parse(&contents).with_context(|| path.to_string())
+            }
+            None => Self::parse(DEFAULT_SIGNATURES),
+        }
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let mut signatures = vec![];
+        let mut kind = None;
+        let mut label = None;
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') {
+                kind = match line {
+                    "[tcp:response]" => Some(Kind::SynAck),
+                    "[tcp:rst]" => Some(Kind::Reset),
+                    _ => None,
+                };
+                label = None;
+                continue;
+            }
+            let kind = match kind {
+                Some(kind) => kind,
+                None => continue,
+            };
+            let context = || format!("line {}: {}", i + 1, line);
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .with_context(|| format!("{}: expected key = value", context()))?;
+            match key {
+                "label" => label = Some(Label::parse(value).with_context(context)?),
+                "sig" => {
+                    let label = label
+                        .clone()
+                        .with_context(|| format!("{}: sig without label", context()))?;
+                    signatures.push(parse_signature(kind, label, value).with_context(context)?);
+                }
+                // p0fのファイルにある、アプリケーション層で使う項目
+                "sys" => {}
+                _ => return Err(anyhow!("{}: unknown key {}", context(), key)),
+            }
+        }
+        Ok(Self { signatures })
+    }
+
+    /**
+     * ホストからの指定の種類の返信に最もよく一致するシグネチャを選ぶ
+     * 確信度は、返信の種類が同じ観測ごとの点数の平均に、IP IDの点数を足したもの
+     * 同じ点数なら、ファイルの上にあるものを選ぶ。ただし別のOSと同じ点数なら区別できないので推定しない
+     */
+    fn guess(&self, kind: Kind, observations: &[Observation], ip_id: IpId) -> Option<OsGuess> {
+        let mut best: Option<(&Signature, u32)> = None;
+        let mut tied = false;
+        for signature in self
+            .signatures
+            .iter()
+            .filter(|signature| signature.kind == kind)
+        {
+            let scores: Vec<Option<u32>> = observations
+                .iter()
+                .filter(|observation| observation.kind == signature.kind)
+                .map(|observation| signature.score(observation))
+                .collect();
+            if scores.iter().all(Option::is_none) {
+                continue;
+            }
+            // 比べられなかった返信は0点として平均する
+            let total: u32 = scores.iter().map(|score| score.unwrap_or_default()).sum();
+            let known = (ip_id != IpId::Unknown).then_some(ip_id);
+            let confidence = total / scores.len() as u32
+                + weigh(
+                    IP_ID_WEIGHT,
+                    signature
+                        .ip_id
+                        .zip(known)
+                        .map(|(expected, ip_id)| expected == ip_id),
+                );
+            match best {
+                Some((best, score)) if confidence == score => {
+                    tied |= best.label.name != signature.label.name;
+                }
+                Some((_, score)) if confidence < score => {}
+                _ => {
+                    best = Some((signature, confidence));
+                    tied = false;
+                }
+            }
+        }
+        let (signature, confidence) = best?;
+        let confidence = confidence.min(100) as u8;
+        if tied || confidence < MIN_CONFIDENCE {
+            return None;
+        }
+        let label = &signature.label;
+        let name = if label.generic || label.flavor.is_empty() {
+            label.name.clone()
+        } else {
+            format!("{} {}", label.name, label.flavor)
+        };
+        Some(OsGuess {
+            name,
+            family: label.name.clone(),
+            class: label.class.clone(),
+            confidence,
+        })
+    }
+}
+
+fn parse_signature(kind: Kind, label: Label, value: &str) -> Result<Signature> {
+    let fields: Vec<&str> = value.split(':').collect();
+    if fields.len() != 8 && fields.len() != 9 {
+        return Err(anyhow!("expected 8 or 9 fields"));
+    }
+    let wildcard = |value: &str| value == "*";
+    let version = match fields[0] {
+        "*" => None,
+        "4" => Some(4),
+        "6" => Some(6),
+        version => return Err(anyhow!("invalid version {}", version)),
+    };
+    // p0fでは"64-"のように、ホップ数が分からないものに-を付ける
+    let ttl = match fields[1].trim_end_matches('-') {
+        "*" => None,
+        ttl => Some(ttl.parse().context("invalid ttl")?),
+    };
+    let olen = fields[2].parse().context("invalid olen")?;
+    let mss = if wildcard(fields[3]) {
+        None
+    } else {
+        Some(fields[3].parse().context("invalid mss")?)
+    };
+    let (window, scale) = fields[4].split_once(',').context("expected wsize,scale")?;
+    let window = Window::parse(window).context("invalid window")?;
+    let scale = if wildcard(scale) {
+        None
+    } else {
+        Some(scale.parse().context("invalid scale")?)
+    };
+    let layout = fields[5]
+        .split(',')
+        .filter(|option| !option.is_empty())
+        .map(TcpOption::parse)
+        .collect::<Result<_>>()?;
+    let quirks = fields[6]
+        .split(',')
+        .filter(|quirk| !quirk.is_empty())
+        .map(Quirk::parse)
+        .collect::<Result<_>>()?;
+    let payload = match fields[7] {
+        "*" => None,
+        "0" => Some(false),
+        "+" => Some(true),
+        payload => return Err(anyhow!("invalid payload class {}", payload)),
+    };
+    let ip_id = match fields.get(8) {
+        None | Some(&"*") => None,
+        Some(ip_id) => Some(IpId::parse(ip_id)?),
+    };
+    Ok(Signature {
+        kind,
+        label,
+        version,
+        ttl,
+        olen,
+        mss,
+        window,
+        scale,
+        layout,
+        quirks,
+        payload,
+        ip_id,
+    })
+}
+
+/**
+ * スキャン中に受け取った返信の特徴を、ホストごとに集める
+ */
+#[derive(Default)]
+pub struct Fingerprints {
+    /// 受け取った順
+    hosts: HashMap<IpAddr, Vec<Observation>>,
+}
+
+impl Fingerprints {
+    pub fn add(&mut self, host: IpAddr, observation: Observation) {
+        let observations = self.hosts.entry(host).or_default();
+        let count = observations
+            .iter()
+            .filter(|other| other.kind == observation.kind)
+            .count();
+        if count < MAX_OBSERVATIONS {
+            observations.push(observation);
+        }
+    }
+
+    /**
+     * ホストごとにOSを推定する
+     * SYN/ACKのほうがOSごとの違いが大きいので先に試し、推定できなければRSTを使う
+     * 推定できなかった場合も、SYN/ACKを優先して最初の返信の特徴を残す
+     */
+    pub fn reports(&self, db: &OsDb) -> HashMap<IpAddr, OsReport> {
+        self.hosts
+            .iter()
+            .filter_map(|(&host, observations)| {
+                let ids: Vec<u16> = observations
+                    .iter()
+                    .filter_map(|observation| observation.ip.map(|ip| ip.id))
+                    .collect();
+                let ip_id = IpId::classify(&ids);
+                let guess = [Kind::SynAck, Kind::Reset].into_iter().find_map(|kind| {
+                    db.guess(kind, observations, ip_id)
+                        .map(|guess| (kind, guess))
+                });
+                let (kind, guess) = match guess {
+                    Some((kind, guess)) => (kind, Some(guess)),
+                    None if observations.iter().any(|o| o.kind == Kind::SynAck) => {
+                        (Kind::SynAck, None)
+                    }
+                    None => (Kind::Reset, None),
+                };
+                let observation = observations.iter().find(|o| o.kind == kind)?;
+                let report = OsReport {
+                    kind,
+                    fingerprint: observation.to_string(),
+                    ip_id,
+                    guess,
+                };
+                Some((host, report))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::{
+        ipv4::{self, Ipv4Packet},
+        Packet,
+    };
+
+    // 返信のIPv4パケット（fixtures/fingerprint/README.md）
+    const LINUX_SYN_ACK: &[u8] = include_bytes!("../fixtures/fingerprint/linux_syn_ack.bin");
+    const LINUX_SYN_ACK_OPTIONS: &[u8] =
+        include_bytes!("../fixtures/fingerprint/linux_syn_ack_options.bin");
+    const LINUX_RST: &[u8] = include_bytes!("../fixtures/fingerprint/linux_rst.bin");
+    const WINDOWS_SYN_ACK_135: &[u8] =
+        include_bytes!("../fixtures/fingerprint/windows_syn_ack_135.bin");
+    const WINDOWS_SYN_ACK_445: &[u8] =
+        include_bytes!("../fixtures/fingerprint/windows_syn_ack_445.bin");
+    const WINDOWS_RST: &[u8] = include_bytes!("../fixtures/fingerprint/windows_rst.bin");
+
+    /**
+     * 受信したときと同じく、IPv4ヘッダとTCPセグメントから特徴を読み取る
+     */
+    fn observe(packet: &[u8]) -> Observation {
+        let ip_packet = Ipv4Packet::new(packet).unwrap();
+        assert_eq!(ipv4::checksum(&ip_packet), ip_packet.get_checksum());
+        // ループバックで記録したSYN/ACKは、TCPのチェックサムの計算がNICに任されたままになっている
+        Observation::parse(Some(IpHeader::of(&ip_packet)), ip_packet.payload()).unwrap()
+    }
+
+    fn reports(packets: &[&[u8]]) -> OsReport {
+        let host: IpAddr = "192.0.2.10".parse().unwrap();
+        let mut fingerprints = Fingerprints::default();
+        for packet in packets {
+            fingerprints.add(host, observe(packet));
+        }
+        let db = OsDb::load(None).unwrap();
+        fingerprints.reports(&db).remove(&host).unwrap()
+    }
+
+    fn guess(report: &OsReport) -> Option<(&str, &str, &str, u8)> {
+        report.guess.as_ref().map(|guess| {
+            (
+                guess.name.as_str(),
+                guess.family.as_str(),
+                guess.class.as_str(),
+                guess.confidence,
+            )
+        })
+    }
+
+    #[test]
+    fn linux_syn_ack_features() {
+        let observation = observe(LINUX_SYN_ACK);
+        assert_eq!(observation.kind, Kind::SynAck);
+        assert_eq!(
+            observation.ip,
+            Some(IpHeader {
+                ttl: 64,
+                dont_fragment: true,
+                id: 0,
+                options_len: 0,
+            })
+        );
+        assert_eq!(observation.window, 64240);
+        assert_eq!(observation.mss, Some(1460));
+        assert_eq!(observation.scale, 0);
+        assert_eq!(observation.layout, [TcpOption::Mss]);
+        assert!(!observation.payload);
+        assert_eq!(observation.ttl(), Some((64, 0)));
+        assert_eq!(observation.to_string(), "4:64+0:0:1460:64240,0:mss:df:0");
+    }
+
+    #[test]
+    fn linux_syn_ack_option_order() {
+        let observation = observe(LINUX_SYN_ACK_OPTIONS);
+        assert_eq!(observation.kind, Kind::SynAck);
+        assert_eq!(observation.window, 65160);
+        assert_eq!(observation.mss, Some(1460));
+        assert_eq!(observation.scale, 10);
+        assert_eq!(
+            observation.layout,
+            [
+                TcpOption::Mss,
+                TcpOption::Sok,
+                TcpOption::Ts,
+                TcpOption::Nop,
+                TcpOption::Ws
+            ]
+        );
+        assert_eq!(
+            observation.to_string(),
+            "4:64+0:0:1460:65160,10:mss,sok,ts,nop,ws:df:0"
+        );
+    }
+
+    #[test]
+    fn linux_rst_features() {
+        let observation = observe(LINUX_RST);
+        assert_eq!(observation.kind, Kind::Reset);
+        assert_eq!(
+            observation.ip.map(|ip| (ip.dont_fragment, ip.id)),
+            Some((true, 0))
+        );
+        assert_eq!(observation.window, 0);
+        assert_eq!(observation.mss, None);
+        assert!(observation.layout.is_empty());
+        assert_eq!(observation.to_string(), "4:64+0:0:*:0,0::df:0");
+    }
+
+    #[test]
+    fn windows_features() {
+        let observation = observe(WINDOWS_SYN_ACK_135);
+        assert_eq!(observation.kind, Kind::SynAck);
+        assert_eq!(
+            observation.ip,
+            Some(IpHeader {
+                ttl: 121,
+                dont_fragment: true,
+                id: 0x0a5d,
+                options_len: 0,
+            })
+        );
+        assert_eq!(observation.window, 8192);
+        assert_eq!(observation.mss, Some(1460));
+        assert_eq!(observation.layout, [TcpOption::Mss]);
+        assert_eq!(observation.ttl(), Some((128, 7)));
+        assert_eq!(
+            observation.to_string(),
+            "4:128+7:0:1460:8192,0:mss:df,id+:0"
+        );
+
+        let observation = observe(WINDOWS_RST);
+        assert_eq!(observation.kind, Kind::Reset);
+        assert_eq!(
+            observation.ip.map(|ip| (ip.dont_fragment, ip.id)),
+            Some((false, 0x0a5f))
+        );
+        assert_eq!(observation.to_string(), "4:128+7:0:*:0,0:::0");
+    }
+
+    #[test]
+    fn guesses_linux() {
+        // SYN/ACKとRSTのIP IDがどちらも0なので、IP IDの付け方も一致する
+        let report = reports(&[LINUX_SYN_ACK, LINUX_RST]);
+        assert_eq!(report.kind, Kind::SynAck);
+        assert_eq!(report.fingerprint, "4:64+0:0:1460:64240,0:mss:df:0");
+        assert_eq!(report.ip_id, IpId::Zero);
+        assert_eq!(
+            guess(&report),
+            Some(("Linux 3.x and newer", "Linux", "unix", 85))
+        );
+
+        let report = reports(&[LINUX_SYN_ACK]);
+        assert_eq!(report.ip_id, IpId::Unknown);
+        assert_eq!(
+            guess(&report),
+            Some(("Linux 3.x and newer", "Linux", "unix", 82))
+        );
+
+        // SYN/ACKがなければRSTで推定する
+        let report = reports(&[LINUX_RST]);
+        assert_eq!(report.kind, Kind::Reset);
+        assert_eq!(guess(&report), Some(("Linux", "Linux", "unix", 92)));
+    }
+
+    #[test]
+    fn guesses_windows() {
+        let report = reports(&[WINDOWS_SYN_ACK_135, WINDOWS_SYN_ACK_445, WINDOWS_RST]);
+        assert_eq!(report.kind, Kind::SynAck);
+        assert_eq!(report.fingerprint, "4:128+7:0:1460:8192,0:mss:df,id+:0");
+        assert_eq!(report.ip_id, IpId::Incremental);
+        assert_eq!(
+            guess(&report),
+            Some(("Windows 7 or 8", "Windows", "win", 95))
+        );
+
+        let report = reports(&[WINDOWS_RST]);
+        assert_eq!(report.kind, Kind::Reset);
+        assert_eq!(guess(&report), Some(("Windows", "Windows", "win", 92)));
+    }
+
+    #[test]
+    fn replies_to_probes_with_options_are_not_guessed() {
+        // プローブにはオプションを付けないので、オプションの多い返信のシグネチャはない
+        let report = reports(&[LINUX_SYN_ACK_OPTIONS]);
+        assert_eq!(report.kind, Kind::SynAck);
+        assert_eq!(
+            report.fingerprint,
+            "4:64+0:0:1460:65160,10:mss,sok,ts,nop,ws:df:0"
+        );
+        assert_eq!(guess(&report), None);
+    }
+
+    #[test]
+    fn other_segments_are_not_observed() {
+        let ip_packet = Ipv4Packet::new(LINUX_SYN_ACK).unwrap();
+        let mut segment = ip_packet.payload().to_vec();
+        segment[13] = TcpFlags::SYN as u8;
+        assert!(Observation::parse(None, &segment).is_none());
+        segment[13] = TcpFlags::ACK as u8;
+        assert!(Observation::parse(None, &segment).is_none());
+        // データオフセットがセグメントより長い
+        segment[12] = 0xf0;
+        segment[13] = TcpFlags::RST as u8;
+        assert!(Observation::parse(None, &segment).is_none());
+    }
+}
@@ -0,0 +1,304 @@
+use crate::scan::{Response, Unreachable};
+use pnet::packet::{
+    icmp::{destination_unreachable::DestinationUnreachablePacket, IcmpPacket, IcmpTypes},
+    icmpv6::{Icmpv6Packet, Icmpv6Types},
+    ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+    ipv4::Ipv4Packet,
+    ipv6::Ipv6Packet,
+    Packet,
+};
+use std::net::IpAddr;
+
+/**
+ * ICMPのエラーに埋め込まれた元のプローブ
+ */
+pub struct Notification {
+    /// 元のパケットの宛先
+    pub host: IpAddr,
+    pub port: u16,
+    /// 元のパケットの送信元ポート
+    pub my_port: u16,
+    /// 元のパケットがTCPの場合のシーケンス番号
+    pub seq: Option<u32>,
+    pub response: Response,
+}
+
+/**
+ * ICMPのDestination UnreachableとSource Quenchに埋め込まれた元のパケットから、どのプローブへの返信かを調べる
+ * ICMPは途中のルータから返ってくることもあるので、送信元ではなく元のパケットの宛先をホストとする
+ * 自分が送ったプロトコルのものでなければNoneを返す
+ */
+pub fn parse_icmp(
+    icmp_packet: &IcmpPacket,
+    protocol: IpNextHeaderProtocol,
+) -> Option<Notification> {
+    let response = match icmp_packet.get_icmp_type() {
+        IcmpTypes::DestinationUnreachable => {
+            let unreachable = match icmp_packet.get_icmp_code().0 {
+                0 => Unreachable::Network,
+                1 => Unreachable::Host,
+                2 => Unreachable::Protocol,
+                3 => Unreachable::Port,
+                // 管理上の禁止（nmapと同じく、ネットワーク・ホスト宛てとフィルタによるもの）
+                9 | 10 | 13 => Unreachable::Prohibited,
+                _ => return None,
+            };
+            Response::Unreachable(unreachable)
+        }
+        IcmpTypes::SourceQuench => Response::SourceQuench,
+        _ => return None,
+    };
+    // Source QuenchもDestination Unreachableと同じく、4オクテットの未使用の後に元のパケットが続く
+    let packet = DestinationUnreachablePacket::new(icmp_packet.packet())?;
+    let original = Ipv4Packet::new(packet.payload())?;
+    if original.get_next_level_protocol() != protocol {
+        return None;
+    }
+    // 元のパケットはIPヘッダとその後の8オクテットしか含まれないことがある
+    let header_len = original.get_header_length() as usize * 4;
+    let transport = packet.payload().get(header_len..)?;
+    parse_original(
+        IpAddr::V4(original.get_destination()),
+        transport,
+        protocol,
+        response,
+    )
+}
+
+/**
+ * ICMPv6のDestination Unreachableに埋め込まれた元のパケットから、どのプローブへの返信かを調べる
+ * 拡張ヘッダの付いたパケットは送っていないので、IPv6ヘッダの直後がトランスポート層のものだけを扱う
+ */
+pub fn parse_icmpv6(
+    icmp_packet: &Icmpv6Packet,
+    protocol: IpNextHeaderProtocol,
+) -> Option<Notification> {
+    if icmp_packet.get_icmpv6_type() != Icmpv6Types::DestinationUnreachable {
+        return None;
+    }
+    let unreachable = match icmp_packet.get_icmpv6_code().0 {
+        // 経路なし
+        0 => Unreachable::Network,
+        1 => Unreachable::Prohibited,
+        // アドレス到達不能
+        3 => Unreachable::Host,
+        4 => Unreachable::Port,
+        _ => return None,
+    };
+    let response = Response::Unreachable(unreachable);
+    // ICMPv6ヘッダの後の4オクテットは未使用
+    let payload = icmp_packet.payload().get(4..)?;
+    let original = Ipv6Packet::new(payload)?;
+    if original.get_next_header() != protocol {
+        return None;
+    }
+    let transport = payload.get(Ipv6Packet::minimum_packet_size()..)?;
+    parse_original(
+        IpAddr::V6(original.get_destination()),
+        transport,
+        protocol,
+        response,
+    )
+}
+
+/**
+ * 元のパケットのトランスポート層のヘッダからポートを取り出す
+ * TCPもUDPも先頭の4オクテットが送信元ポートと宛先ポートで、TCPはその後の4オクテットがシーケンス番号
+ */
+fn parse_original(
+    host: IpAddr,
+    transport: &[u8],
+    protocol: IpNextHeaderProtocol,
+    response: Response,
+) -> Option<Notification> {
+    let ports = transport.get(..4)?;
+    let seq = match protocol {
+        IpNextHeaderProtocols::Tcp => {
+            let seq = transport.get(4..8)?;
+            Some(u32::from_be_bytes([seq[0], seq[1], seq[2], seq[3]]))
+        }
+        _ => None,
+    };
+    Some(Notification {
+        host,
+        port: u16::from_be_bytes([ports[2], ports[3]]),
+        my_port: u16::from_be_bytes([ports[0], ports[1]]),
+        seq,
+        response,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const HOST_V4: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 2);
+    const HOST_V6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2);
+
+    /**
+     * 送信元ポート40000から53番ポートへのトランスポート層のヘッダの先頭8オクテット
+     * TCPならシーケンス番号0x01020304、UDPなら長さとチェックサム
+     */
+    fn transport() -> Vec<u8> {
+        vec![0x9c, 0x40, 0x00, 0x35, 0x01, 0x02, 0x03, 0x04]
+    }
+
+    /**
+     * 元のIPv4パケットを埋め込んだICMPのエラー
+     */
+    fn icmp(icmp_type: u8, code: u8, protocol: IpNextHeaderProtocol, transport: &[u8]) -> Vec<u8> {
+        let mut packet = vec![icmp_type, code, 0, 0, 0, 0, 0, 0];
+        let mut header = vec![0x45, 0, 0, 28, 0, 0, 0, 0, 64, protocol.0, 0, 0];
+        header.extend_from_slice(&[192, 0, 2, 1]);
+        header.extend_from_slice(&HOST_V4.octets());
+        packet.extend_from_slice(&header);
+        packet.extend_from_slice(transport);
+        packet
+    }
+
+    /**
+     * 元のIPv6パケットを埋め込んだICMPv6のエラー
+     */
+    fn icmpv6(
+        icmp_type: u8,
+        code: u8,
+        protocol: IpNextHeaderProtocol,
+        transport: &[u8],
+    ) -> Vec<u8> {
+        let mut packet = vec![icmp_type, code, 0, 0, 0, 0, 0, 0];
+        packet.extend_from_slice(&[0x60, 0, 0, 0, 0, 8, protocol.0, 64]);
+        packet.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets());
+        packet.extend_from_slice(&HOST_V6.octets());
+        packet.extend_from_slice(transport);
+        packet
+    }
+
+    fn parse(packet: &[u8], protocol: IpNextHeaderProtocol) -> Option<Notification> {
+        parse_icmp(&IcmpPacket::new(packet).unwrap(), protocol)
+    }
+
+    fn parse_v6(packet: &[u8], protocol: IpNextHeaderProtocol) -> Option<Notification> {
+        parse_icmpv6(&Icmpv6Packet::new(packet).unwrap(), protocol)
+    }
+
+    #[test]
+    fn port_unreachable_for_udp() {
+        let udp = IpNextHeaderProtocols::Udp;
+        let notification = parse(&icmp(3, 3, udp, &transport()), udp).unwrap();
+        assert_eq!(notification.host, IpAddr::V4(HOST_V4));
+        assert_eq!(notification.port, 53);
+        assert_eq!(notification.my_port, 40000);
+        assert_eq!(notification.seq, None);
+        assert_eq!(
+            notification.response,
+            Response::Unreachable(Unreachable::Port)
+        );
+    }
+
+    #[test]
+    fn unreachable_for_tcp_has_the_sequence_number() {
+        let tcp = IpNextHeaderProtocols::Tcp;
+        let notification = parse(&icmp(3, 13, tcp, &transport()), tcp).unwrap();
+        assert_eq!(notification.port, 53);
+        assert_eq!(notification.my_port, 40000);
+        assert_eq!(notification.seq, Some(0x01020304));
+        assert_eq!(
+            notification.response,
+            Response::Unreachable(Unreachable::Prohibited)
+        );
+        // TCPはシーケンス番号まで埋め込まれていなければ照合できない
+        assert!(parse(&icmp(3, 3, tcp, &transport()[..4]), tcp).is_none());
+    }
+
+    #[test]
+    fn codes_of_icmp() {
+        let udp = IpNextHeaderProtocols::Udp;
+        let response = |icmp_type, code| {
+            parse(&icmp(icmp_type, code, udp, &transport()), udp).map(|n| n.response)
+        };
+        assert_eq!(
+            response(3, 0),
+            Some(Response::Unreachable(Unreachable::Network))
+        );
+        assert_eq!(
+            response(3, 1),
+            Some(Response::Unreachable(Unreachable::Host))
+        );
+        assert_eq!(
+            response(3, 2),
+            Some(Response::Unreachable(Unreachable::Protocol))
+        );
+        for code in [9, 10, 13] {
+            assert_eq!(
+                response(3, code),
+                Some(Response::Unreachable(Unreachable::Prohibited))
+            );
+        }
+        // フラグメント化が必要
+        assert_eq!(response(3, 4), None);
+        assert_eq!(response(4, 0), Some(Response::SourceQuench));
+        // エコー応答
+        assert_eq!(response(0, 0), None);
+    }
+
+    #[test]
+    fn other_protocols_and_truncated_packets_are_ignored() {
+        let udp = IpNextHeaderProtocols::Udp;
+        let tcp = IpNextHeaderProtocols::Tcp;
+        assert!(parse(&icmp(3, 3, tcp, &transport()), udp).is_none());
+        assert!(parse(&icmp(3, 3, udp, &transport()[..2]), udp).is_none());
+        assert!(parse(&icmp(3, 3, udp, &transport())[..20], udp).is_none());
+    }
+
+    #[test]
+    fn port_unreachable_for_udp_over_ipv6() {
+        let udp = IpNextHeaderProtocols::Udp;
+        let notification = parse_v6(&icmpv6(1, 4, udp, &transport()), udp).unwrap();
+        assert_eq!(notification.host, IpAddr::V6(HOST_V6));
+        assert_eq!(notification.port, 53);
+        assert_eq!(notification.my_port, 40000);
+        assert_eq!(notification.seq, None);
+        assert_eq!(
+            notification.response,
+            Response::Unreachable(Unreachable::Port)
+        );
+
+        let tcp = IpNextHeaderProtocols::Tcp;
+        let notification = parse_v6(&icmpv6(1, 4, tcp, &transport()), tcp).unwrap();
+        assert_eq!(notification.seq, Some(0x01020304));
+    }
+
+    #[test]
+    fn codes_of_icmpv6() {
+        let udp = IpNextHeaderProtocols::Udp;
+        let response = |icmp_type, code| {
+            parse_v6(&icmpv6(icmp_type, code, udp, &transport()), udp).map(|n| n.response)
+        };
+        assert_eq!(
+            response(1, 0),
+            Some(Response::Unreachable(Unreachable::Network))
+        );
+        assert_eq!(
+            response(1, 1),
+            Some(Response::Unreachable(Unreachable::Prohibited))
+        );
+        assert_eq!(
+            response(1, 3),
+            Some(Response::Unreachable(Unreachable::Host))
+        );
+        // 送信元アドレスがスコープ外
+        assert_eq!(response(1, 2), None);
+        // Packet Too Big
+        assert_eq!(response(2, 0), None);
+    }
+
+    #[test]
+    fn other_protocols_and_truncated_packets_are_ignored_over_ipv6() {
+        let udp = IpNextHeaderProtocols::Udp;
+        let tcp = IpNextHeaderProtocols::Tcp;
+        assert!(parse_v6(&icmpv6(1, 4, tcp, &transport()), udp).is_none());
+        assert!(parse_v6(&icmpv6(1, 4, udp, &transport()[..2]), udp).is_none());
+        assert!(parse_v6(&icmpv6(1, 4, udp, &transport())[..40], udp).is_none());
+    }
+}
@@ -1,182 +1,778 @@
 use anyhow::{anyhow, Context, Result};
-use env_logger;
+use log::{debug, warn};
 use pnet::{
     packet::{
-        ip::IpNextHeaderProtocols,
-        tcp::{self, MutableTcpPacket, TcpFlags},
-    },
-    transport::{
-        self, TransportChannelType, TransportProtocol, TransportReceiver, TransportSender,
+        icmp::IcmpPacket,
+        icmpv6::Icmpv6Packet,
+        ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+        tcp::{self, MutableTcpPacket, TcpFlags, TcpPacket},
+        udp::UdpPacket,
     },
+    transport::{TransportReceiver, TransportSender},
 };
 use std::{
-    collections::HashMap,
-    env, fs,
-    net::{IpAddr, Ipv4Addr},
-    str, thread,
-    time::Duration,
+    collections::{HashMap, HashSet},
+    env,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    net::IpAddr,
+    process, str,
+    sync::{
+        mpsc::{self, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
-#[derive(Clone, Copy)]
-enum ScanType {
-    Syn = TcpFlags::SYN as isize,
-    Fin = TcpFlags::FIN as isize,
-    Xmax = (TcpFlags::FIN | TcpFlags::URG | TcpFlags::PSH) as isize,
-    Null = 0,
-}
+mod channel;
+mod cli;
+mod connect;
+mod diff;
+mod discovery;
+mod fingerprint;
+mod icmp;
+mod ports;
+mod report;
+mod scan;
+mod service;
+mod source;
+mod targets;
+mod timing;
+mod udp;
+use cli::Options;
+use discovery::{HostStatus, Prober};
+use fingerprint::{Fingerprints, Observation, OsDb};
+use icmp::Notification;
+use report::ScanReport;
+use scan::{Answer, Interpretation, PortState, Reply, Response, Scan, ScanType};
+use service::SignatureDb;
+use source::{Cookies, SourcePort};
+use targets::Target;
+use timing::{RateControl, RttEstimator, Timing};
 
+/**
+ * プローブの送信元と、返信を照合するための情報
+ */
+#[derive(Clone)]
 struct PacketInfo {
-    my_ipaddr: Ipv4Addr,
-    target_ipaddr: Ipv4Addr,
-    my_port: u16,
-    maximum_port: u16,
+    /// .envのMY_IPADDRで指定した送信元アドレス。なければ宛先ごとに経路表から選ぶ
+    my_ipaddr: Option<IpAddr>,
+    source_port: SourcePort,
     scan_type: ScanType,
+    /// スキャン方法の解釈の表。--scanflagsを指定した場合は、プローブのフラグだけ置き換える
+    interpretation: Interpretation,
+    cookies: Cookies,
 }
 
 impl PacketInfo {
-    pub fn new(target_ipaddr: &str, scan_type: &str) -> Result<Self> {
-        let contents = fs::read_to_string(".env").context("Failed to read env file")?;
-        let lines: Vec<_> = contents.split('\n').collect();
-        let mut map = HashMap::new();
-        for line in lines {
-            let elm: Vec<_> = line.split('=').map(str::trim).collect();
-            if elm.len() == 2 {
-                map.insert(elm[0], elm[1]);
-            }
+    /**
+     * 送信元ポートは-g、.envのMY_PORT、スキャンごとにランダムの順に決める
+     */
+    pub fn new(
+        env: &HashMap<String, String>,
+        options: &Options,
+        scan_type: ScanType,
+    ) -> Result<Self> {
+        let my_ipaddr = env
+            .get("MY_IPADDR")
+            .map(|value| value.parse())
+            .transpose()
+            .context("invalid your ipaddr")?;
+        let env_port = env
+            .get("MY_PORT")
+            .map(|value| value.parse())
+            .transpose()
+            .context("invalid your port number")?;
+        let source_port = if options.per_probe_source_port {
+            SourcePort::PerProbe
+        } else {
+            SourcePort::Fixed(
+                options
+                    .source_port
+                    .or(env_port)
+                    .unwrap_or_else(source::random_port),
+            )
+        };
+        debug!("source port: {:?}", source_port);
+        let mut interpretation = scan_type.interpretation();
+        if let Some(flags) = options.scan_flags {
+            interpretation.flags = flags;
         }
+        Ok(Self {
+            my_ipaddr,
+            source_port,
+            scan_type,
+            interpretation,
+            cookies: Cookies::default(),
+        })
+    }
 
-        let res = Self {
-            my_ipaddr: map["MY_IPADDR"].parse().context("invalid your ipaddr")?,
-            target_ipaddr: target_ipaddr.parse().context("invalid target ipaddr")?,
-            my_port: map["MY_PORT"].parse().context("invalid your port number")?,
-            maximum_port: map["MAXIMUM_PORT_NUM"]
-                .parse()
-                .context("invalid maximum port num")?,
-            scan_type: match scan_type {
-                "sS" => ScanType::Syn,
-                "sF" => ScanType::Fin,
-                "sX" => ScanType::Xmax,
-                "sN" => ScanType::Null,
-                _ => return Err(anyhow!("Undefined scan method, only accept [sS|sF|sN|sX].")),
-            },
+    /**
+     * プローブの送信元ポート
+     * UDPではDHCPのように返信先のポートが決まっているサービスがあるので、それに合わせる
+     */
+    fn my_port(&self, host: IpAddr, port: u16) -> u16 {
+        let my_port = match self.source_port {
+            SourcePort::Fixed(my_port) => my_port,
+            SourcePort::PerProbe => self.cookies.source_port(host, port),
         };
-        Ok(res)
+        if self.scan_type == ScanType::Udp {
+            udp::source_port(port, my_port)
+        } else {
+            my_port
+        }
+    }
+
+    /**
+     * TCPのプローブのシーケンス番号
+     */
+    fn seq(&self, host: IpAddr, port: u16) -> u32 {
+        self.cookies.seq(host, port, self.my_port(host, port))
+    }
+
+    /**
+     * 返信の宛先ポートと確認応答番号が、自分のプローブに対するものか確かめる
+     * SYNとFINはシーケンス番号を1つ消費するので、確認応答番号はその分進む
+     * ACKを付けたプローブへのRSTはACKを持たず、プローブの確認応答番号をシーケンス番号とする
+     */
+    fn is_reply(
+        &self,
+        host: IpAddr,
+        port: u16,
+        my_port: u16,
+        ack: Option<u32>,
+        seq: Option<u32>,
+    ) -> bool {
+        if my_port != self.my_port(host, port) {
+            return false;
+        }
+        let flags = self.interpretation.flags;
+        let consumed = match flags & (TcpFlags::SYN | TcpFlags::FIN) {
+            0 => 0,
+            _ => 1,
+        };
+        match (ack, seq) {
+            (Some(ack), _) => ack == self.seq(host, port).wrapping_add(consumed),
+            (None, Some(seq)) if flags & TcpFlags::ACK != 0 => seq == self.seq(host, port),
+            _ => true,
+        }
+    }
+
+    /**
+     * 到達不能の通知に埋め込まれたプローブが、自分の送ったものか確かめる
+     */
+    fn is_notified(&self, notification: &Notification) -> bool {
+        let (host, port) = (notification.host, notification.port);
+        notification.my_port == self.my_port(host, port)
+            && notification
+                .seq
+                .is_none_or(|seq| seq == self.seq(host, port))
     }
 }
 
+/**
+ * .envファイルを読む
+ * connectスキャンでは使わないので、ファイルがなければ空とする
+ */
+fn read_env() -> Result<HashMap<String, String>> {
+    let contents = match fs::read_to_string(".env") {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e).context("Failed to read env file"),
+    };
+    let lines: Vec<_> = contents.split('\n').collect();
+    let mut map = HashMap::new();
+    for line in lines {
+        let elm: Vec<_> = line.split('=').map(str::trim).collect();
+        if elm.len() == 2 {
+            map.insert(elm[0].to_string(), elm[1].to_string());
+        }
+    }
+    Ok(map)
+}
+
 fn main() -> Result<()> {
     env::set_var("RUST_LOG", "debug");
     env_logger::init();
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        return Err(anyhow!("Bad number of arguments. [ipaddr] [scantype]"));
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("diff") {
+        return run_diff(args.skip(1).collect());
     }
+    let options = Options::parse(args)?;
 
-    let packet_info = PacketInfo::new(&args[1], &args[2])?;
+    let scan_type = options
+        .scan_type
+        .as_deref()
+        .map(ScanType::from_arg)
+        .transpose()?;
+    if options.version_detection && scan_type == Some(ScanType::Udp) {
+        return Err(anyhow!("-sV supports only TCP scans"));
+    }
+    let raw_tcp = scan_type.is_some_and(|scan_type| {
+        scan_type.is_raw() && scan_type.protocol() == IpNextHeaderProtocols::Tcp
+    });
+    if options.scan_flags.is_some() && !raw_tcp {
+        return Err(anyhow!("--scanflags supports only raw TCP scans"));
+    }
+    if options.os_detection && !raw_tcp {
+        return Err(anyhow!("-O requires a raw TCP scan"));
+    }
+    // スキャンの前にシグネチャファイルの誤りに気付けるよう、先に読み込む
+    let signatures = if options.version_detection {
+        Some(SignatureDb::load(options.version_db.as_deref())?)
+    } else {
+        None
+    };
+    let os_signatures = if options.os_detection {
+        Some(OsDb::load(options.os_db.as_deref())?)
+    } else {
+        None
+    };
+    let env = read_env()?;
+    // -pなどでポートを指定しなかった場合にスキャンする最大のポート
+    let maximum_port = env
+        .get("MAXIMUM_PORT_NUM")
+        .map(|value| value.parse())
+        .transpose()
+        .context("invalid maximum port num")?;
+    let ports = options.ports.resolve(maximum_port)?;
+    let mut specs = options.targets.clone();
+    if let Some(path) = &options.input_file {
+        specs.extend(targets::read_file(path)?);
+    }
+    let targets = targets::resolve(&specs)?;
+    let addrs: Vec<IpAddr> = targets.iter().map(|target| target.addr).collect();
 
-    // トランスポート層のチャネルを開く
-    // 内部的にはソケット
-    let (mut ts, mut tr) = transport::transport_channel(
-        1024,
-        TransportChannelType::Layer4(TransportProtocol::Ipv4(IpNextHeaderProtocols::Tcp)),
-    )
-    .context("Failed to opne channel.")?;
+    let start_time = SystemTime::now();
+    let started = Instant::now();
+    let statuses = discover_hosts(&options, &addrs)?;
+    let total = targets.len();
+    // 応答したホストだけをポートスキャンする
+    let targets: Vec<Target> = targets
+        .into_iter()
+        .filter(|target| statuses.contains_key(&target.addr))
+        .collect();
+    debug!("{} of {} hosts are up", targets.len(), total);
+    let addrs: Vec<IpAddr> = targets.iter().map(|target| target.addr).collect();
 
-    // パケットの送信と受信を並行に行う
-    rayon::join(
-        || send_packet(&mut ts, &packet_info).unwrap(),
-        || receive_packets(&mut tr, &packet_info).unwrap(),
+    let mut fingerprints = Fingerprints::default();
+    let scan = match scan_type {
+        Some(scan_type) => {
+            let mut scan = Scan::new(scan_type, options.config.clone(), &addrs, &ports);
+            if scan_type.is_raw() {
+                let packet_info = PacketInfo::new(&env, &options, scan_type)?;
+                fingerprints = raw_scan(&packet_info, &options.timing, &mut scan, &addrs)?;
+            } else {
+                connect::run(&mut scan, &options.timing, options.max_parallelism)?;
+            }
+            Some(scan)
+        }
+        None => None,
+    };
+    let services = match (&signatures, &scan) {
+        (Some(signatures), Some(scan)) => {
+            let open_ports = open_ports(scan);
+            service::detect_all(
+                signatures,
+                &open_ports,
+                scan.timeout(),
+                options.max_parallelism,
+            )
+        }
+        _ => HashMap::new(),
+    };
+    let mut report = ScanReport::new(
+        scan.as_ref(),
+        &targets,
+        &statuses,
+        total - targets.len(),
+        signatures.is_some().then_some(&services),
+        start_time,
+        started.elapsed(),
     );
+    if let Some(os_signatures) = &os_signatures {
+        report.set_os(fingerprints.reports(os_signatures));
+    }
+    match &options.output {
+        Some(path) => {
+            let file = File::create(path).with_context(|| format!("Failed to create {}", path))?;
+            let mut writer = BufWriter::new(file);
+            report.write(options.format, &mut writer)?;
+            writer.flush()?;
+        }
+        None => report.write(options.format, &mut io::stdout().lock())?,
+    }
     Ok(())
 }
 
 /**
- * 指定のレンジにパケットを送信する
+ * 動いているホストを調べる。-Pnの場合はすべてのホストが動いているとみなす
  */
-fn send_packet(ts: &mut TransportSender, packet_info: &PacketInfo) -> Result<()> {
-    let mut packet = build_packet(packet_info)?;
-    for i in 1..=packet_info.maximum_port {
-        let mut tcp_header = MutableTcpPacket::new(&mut packet).context("invalid packet")?;
-        register_destination_port(i, &mut tcp_header, packet_info);
-        thread::sleep(Duration::from_millis(5));
-        ts.send_to(tcp_header, IpAddr::V4(packet_info.target_ipaddr))?;
+fn discover_hosts(options: &Options, addrs: &[IpAddr]) -> Result<HashMap<IpAddr, HostStatus>> {
+    if options.skip_discovery {
+        return Ok(addrs
+            .iter()
+            .map(|&addr| (addr, HostStatus::default()))
+            .collect());
+    }
+    let prober = Prober::new(
+        &discovery::DEFAULT_PROBES,
+        options.config.timeout,
+        options.config.retries,
+        options.timing.initial_rate,
+        options.max_parallelism,
+    );
+    prober.discover(addrs)
+}
+
+/**
+ * 保存した2つのJSONのスキャン結果を比べる
+ * diff(1)と同じく、変化があれば終了コードを1にする
+ */
+fn run_diff(args: Vec<String>) -> Result<()> {
+    let (old, new) = match args.as_slice() {
+        [old, new] => (diff::load(old)?, diff::load(new)?),
+        _ => return Err(anyhow!("Usage: ch3-port-scanner diff OLD.json NEW.json")),
+    };
+    if diff::compare(&old, &new, &mut io::stdout().lock())? {
+        process::exit(1);
     }
     Ok(())
 }
 
 /**
- * TCPヘッダの宛先ポート情報を書き換える
- * チェックサムを計算し直す必要がある
+ * 開いていたホストとポートの一覧
  */
-fn register_destination_port(
-    target: u16,
-    tcp_header: &mut MutableTcpPacket,
+fn open_ports(scan: &Scan) -> Vec<(IpAddr, u16)> {
+    scan.hosts()
+        .iter()
+        .zip(scan.results())
+        .flat_map(|(host, results)| {
+            results
+                .into_iter()
+                .filter(|result| result.state == PortState::Open)
+                .map(move |result| (host.addr, result.port))
+        })
+        .collect()
+}
+
+/**
+ * rawソケットでプローブを送り、返信を受け取る
+ * OSの推定に使えるよう、返信の特徴をホストごとに集めて返す
+ */
+fn raw_scan(
     packet_info: &PacketInfo,
-) {
-    tcp_header.set_destination(target);
-    let checksum = tcp::ipv4_checksum(
-        &tcp_header.to_immutable(),
-        &packet_info.my_ipaddr,
-        &packet_info.target_ipaddr,
-    );
-    tcp_header.set_checksum(checksum);
+    timing: &Timing,
+    scan: &mut Scan,
+    addrs: &[IpAddr],
+) -> Result<Fingerprints> {
+    let sources = source_addresses(packet_info, addrs)?;
+
+    // 受信は別スレッドで行い、返信をチャネルで受け取る
+    let (sender, replies) = mpsc::channel();
+    let hosts: Arc<HashSet<IpAddr>> = Arc::new(addrs.iter().copied().collect());
+    let mut senders = Senders { v4: None, v6: None };
+    if addrs.iter().any(IpAddr::is_ipv4) {
+        senders.v4 = Some(open_channels(false, packet_info, &hosts, &sender)?);
+    }
+    if addrs.iter().any(IpAddr::is_ipv6) {
+        senders.v6 = Some(open_channels(true, packet_info, &hosts, &sender)?);
+    }
+    // 受信するスレッドがすべて止まったら分かるように、手元の送信側は閉じる
+    drop(sender);
+    run_scan(&mut senders, packet_info, timing, &sources, scan, &replies)
+}
+
+/**
+ * 宛先ごとの送信元アドレスを決める。チェックサムの疑似ヘッダに使う
+ * .envのMY_IPADDRが宛先と同じアドレスファミリならそれを使い、なければ経路表から選ぶ
+ */
+fn source_addresses(packet_info: &PacketInfo, addrs: &[IpAddr]) -> Result<HashMap<IpAddr, IpAddr>> {
+    addrs
+        .iter()
+        .map(|&host| {
+            let source = match packet_info.my_ipaddr {
+                Some(my_ipaddr) if my_ipaddr.is_ipv4() == host.is_ipv4() => my_ipaddr,
+                _ => source::route_source(host)?,
+            };
+            Ok((host, source))
+        })
+        .collect()
+}
+
+/**
+ * アドレスファミリごとの送信用のチャネル
+ */
+struct Senders {
+    v4: Option<TransportSender>,
+    v6: Option<TransportSender>,
+}
+
+impl Senders {
+    fn get(&mut self, host: IpAddr) -> Result<&mut TransportSender> {
+        match host {
+            IpAddr::V4(_) => self.v4.as_mut(),
+            IpAddr::V6(_) => self.v6.as_mut(),
+        }
+        .with_context(|| format!("No channel for {}", host))
+    }
 }
 
 /**
- * パケットを受信してスキャン情報を出力する
+ * IPv4かIPv6のトランスポート層とICMPのチャネルを開き、受信するスレッドを立ち上げる
+ * 内部的にはソケット
  */
-fn receive_packets(tr: &mut TransportReceiver, packet_info: &PacketInfo) -> Result<()> {
-    let mut reply_ports = vec![];
-    let mut packet_iter = transport::tcp_packet_iter(tr);
+fn open_channels(
+    v6: bool,
+    packet_info: &PacketInfo,
+    hosts: &Arc<HashSet<IpAddr>>,
+    sender: &Sender<Reply>,
+) -> Result<TransportSender> {
+    let protocol = packet_info.scan_type.protocol();
+    let parse: Parser = match packet_info.scan_type {
+        ScanType::Udp => parse_datagram,
+        _ => parse_segment,
+    };
+    let (icmp_protocol, parse_icmp): (_, Parser) = if v6 {
+        (IpNextHeaderProtocols::Icmpv6, parse_icmpv6)
+    } else {
+        (IpNextHeaderProtocols::Icmp, parse_icmpv4)
+    };
+    let (ts, tr) = channel::open(v6, protocol).context("Failed to opne channel.")?;
+    // 到達不能などの通知を受け取るためのICMPのチャネル
+    let (_, icmp_tr) = channel::open(v6, icmp_protocol).context("Failed to open ICMP channel.")?;
+    for (tr, protocol, parse) in [(tr, protocol, parse), (icmp_tr, icmp_protocol, parse_icmp)] {
+        let packet_info = packet_info.clone();
+        let hosts = hosts.clone();
+        let sender = sender.clone();
+        thread::spawn(move || {
+            if let Err(e) = receive_packets(tr, v6, protocol, parse, &packet_info, &hosts, sender) {
+                warn!("{:#}", e);
+            }
+        });
+    }
+    Ok(ts)
+}
 
+/// 送るものも待つタイムアウトもないときに返信を待つ時間
+const IDLE_WAIT: Duration = Duration::from_millis(5);
+/// プローブの送信がこの回数続けて失敗したら、一時的なものではないとみなしてスキャンをやめる
+const MAX_SEND_FAILURES: u32 = 100;
+
+/**
+ * プローブの送信と返信の処理を、すべてのポートの状態が決まるか制限時間を過ぎるまで繰り返す
+ * 返信がないプローブは再送し、再送し尽くしたらスキャン方法に応じた返信なしの状態とする
+ * 送信間隔は返信の様子に合わせて調整し、タイムアウトは測ったRTTから決める
+ */
+fn run_scan(
+    senders: &mut Senders,
+    packet_info: &PacketInfo,
+    timing: &Timing,
+    sources: &HashMap<IpAddr, IpAddr>,
+    scan: &mut Scan,
+    replies: &mpsc::Receiver<Reply>,
+) -> Result<Fingerprints> {
+    let mut packet = build_packet(packet_info)?;
+    let mut fingerprints = Fingerprints::default();
+    let deadline = scan.deadline(Instant::now());
+    let mut next_send = Instant::now();
+    let mut rate = RateControl::new(timing);
+    let mut rtt = RttEstimator::new(timing, scan.timeout());
+    let mut send_failures = 0;
     loop {
-        // ターゲットからの通信パケット
-        let tcp_packet = match packet_iter.next() {
-            Ok((tcp_packet, _)) => {
-                if tcp_packet.get_destination() == packet_info.my_port {
-                    tcp_packet
-                } else {
-                    continue;
+        let now = Instant::now();
+        if deadline.is_some_and(|deadline| now >= deadline) {
+            debug!("scan reached the time limit");
+            scan.abort(now);
+            break;
+        }
+        if now >= next_send {
+            if let Some((host, port)) = scan.next_probe(now) {
+                let ts = senders.get(host)?;
+                // 送れなかったプローブは送ったものとして扱い、タイムアウトして再送させる
+                // バッファ不足などの一時的な失敗が多いので、送信レートも下げる
+                match send_probe(ts, &mut packet, host, port, sources[&host], packet_info) {
+                    Ok(()) => send_failures = 0,
+                    Err(e) => {
+                        warn!("Failed to send probe to {}:{}: {:#}", host, port, e);
+                        send_failures += 1;
+                        if send_failures >= MAX_SEND_FAILURES {
+                            return Err(e.context(format!(
+                                "Sending probes failed {} times in a row",
+                                send_failures
+                            )));
+                        }
+                        rate.on_congestion(now, rtt.timeout());
+                    }
                 }
+                next_send = now + rate.interval();
             }
-            Err(_) => {
-                continue;
-            }
-        };
+        }
+        if scan.finished() {
+            break;
+        }
 
-        let target_port = tcp_packet.get_source();
-        match packet_info.scan_type {
-            ScanType::Syn => {
-                if tcp_packet.get_flags() == TcpFlags::SYN | TcpFlags::ACK {
-                    println!("port {} is open", target_port);
+        // 次の送信かタイムアウトまで返信を待つ
+        let mut wait = if scan.has_unsent() {
+            next_send.saturating_duration_since(now)
+        } else {
+            scan.next_timeout(now).unwrap_or(IDLE_WAIT)
+        };
+        if let Some(deadline) = deadline {
+            wait = wait.min(deadline.saturating_duration_since(now));
+        }
+        match replies.recv_timeout(wait) {
+            Ok(reply) => {
+                on_reply(scan, &mut rate, &mut rtt, &mut fingerprints, reply);
+                // 溜まっている返信をまとめて処理する
+                for reply in replies.try_iter() {
+                    on_reply(scan, &mut rate, &mut rtt, &mut fingerprints, reply);
                 }
             }
-            // SYNスキャン以外はレスポンスが返ってきたポート（=閉じているポート）を記録
-            ScanType::Fin | ScanType::Xmax | ScanType::Null => {
-                reply_ports.push(target_port);
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(anyhow!("Receiver thread stopped"));
             }
         }
+    }
+    debug!(
+        "finished at {:.1} packets/sec, timeout {:?}",
+        rate.rate(),
+        rtt.timeout()
+    );
+    Ok(fingerprints)
+}
 
-        // 手抜き：スキャン対象の最後のポートに対する返信が返ってこれば終了
-        // フロー制御や再送制御などのTCPの機能を実装していないため
-        if target_port != packet_info.maximum_port {
-            continue;
+/**
+ * 返信でポートの状態を決め、送信レートとタイムアウトを調整する
+ * 再送したプローブへの返信は、それまでのプローブか返信が失われたとみなしてレートを下げる
+ * UDPのスキャンではICMPのレート制限で返信が落ちることが多いが、同じように扱われる
+ */
+fn on_reply(
+    scan: &mut Scan,
+    rate: &mut RateControl,
+    rtt: &mut RttEstimator,
+    fingerprints: &mut Fingerprints,
+    mut reply: Reply,
+) {
+    if let Some(observation) = reply.observation.take() {
+        fingerprints.add(reply.host, observation);
+    }
+    let now = Instant::now();
+    if reply.response == Response::SourceQuench {
+        rate.on_congestion(now, rtt.timeout());
+        return;
+    }
+    match scan.on_reply(&reply, now) {
+        Some(Answer::Timely(sample)) => {
+            rtt.sample(sample);
+            scan.set_timeout(rtt.timeout());
+            rate.on_reply();
         }
-        match packet_info.scan_type {
-            ScanType::Fin | ScanType::Xmax | ScanType::Null => {
-                for i in 1..=packet_info.maximum_port {
-                    // 返信のないポートを開いているものと判断
-                    if reply_ports.iter().find(|&&x| x == i).is_none() {
-                        println!("port {} is open", i);
-                    }
-                }
+        Some(Answer::AfterRetransmission) => rate.on_congestion(now, rtt.timeout()),
+        None => {}
+    }
+}
+
+/**
+ * 指定のホストのポートにプローブを送信する
+ * TCPは使い回すヘッダの宛先を書き換え、UDPはポートごとにペイロードが違うので毎回作る
+ */
+fn send_probe(
+    ts: &mut TransportSender,
+    packet: &mut [u8],
+    host: IpAddr,
+    port: u16,
+    source: IpAddr,
+    packet_info: &PacketInfo,
+) -> Result<()> {
+    if packet_info.scan_type != ScanType::Udp {
+        return send_packet(ts, packet, host, port, source, packet_info);
+    }
+    let my_port = packet_info.my_port(host, port);
+    let probe = udp::build_probe(source, my_port, host, port)?;
+    let udp_packet = UdpPacket::new(&probe).context("invalid packet")?;
+    ts.send_to(udp_packet, host)?;
+    Ok(())
+}
+
+/**
+ * 指定のホストのポートにパケットを送信する
+ */
+fn send_packet(
+    ts: &mut TransportSender,
+    packet: &mut [u8],
+    host: IpAddr,
+    port: u16,
+    source: IpAddr,
+    packet_info: &PacketInfo,
+) -> Result<()> {
+    let mut tcp_header = MutableTcpPacket::new(packet).context("invalid packet")?;
+    register_destination(host, port, source, &mut tcp_header, packet_info)?;
+    ts.send_to(tcp_header, host)?;
+    Ok(())
+}
+
+/**
+ * TCPヘッダの宛先ポート情報を書き換える
+ * 送信元ポートとシーケンス番号（ACKを付ける場合は確認応答番号も）もプローブごとに決まる
+ * チェックサムは疑似ヘッダの宛先アドレスも含むので計算し直す必要がある
+ */
+fn register_destination(
+    host: IpAddr,
+    port: u16,
+    source: IpAddr,
+    tcp_header: &mut MutableTcpPacket,
+    packet_info: &PacketInfo,
+) -> Result<()> {
+    tcp_header.set_source(packet_info.my_port(host, port));
+    tcp_header.set_destination(port);
+    tcp_header.set_sequence(packet_info.seq(host, port));
+    if packet_info.interpretation.flags & TcpFlags::ACK != 0 {
+        tcp_header.set_acknowledgement(packet_info.seq(host, port));
+    }
+    let checksum = match (source, host) {
+        (IpAddr::V4(source), IpAddr::V4(host)) => {
+            tcp::ipv4_checksum(&tcp_header.to_immutable(), &source, &host)
+        }
+        (IpAddr::V6(source), IpAddr::V6(host)) => {
+            tcp::ipv6_checksum(&tcp_header.to_immutable(), &source, &host)
+        }
+        _ => return Err(anyhow!("{} and {} are different families", source, host)),
+    };
+    tcp_header.set_checksum(checksum);
+    Ok(())
+}
+
+/**
+ * 受信したIPパケットのペイロードから、自分のプローブへの返信を取り出す関数
+ * 引数は送信元アドレスとIPヘッダより後ろのバイト列
+ */
+type Parser = fn(&PacketInfo, IpAddr, &[u8]) -> Option<Reply>;
+
+/**
+ * パケットを受信して、ターゲットからの返信をチャネルに送る
+ */
+fn receive_packets(
+    mut tr: TransportReceiver,
+    v6: bool,
+    protocol: IpNextHeaderProtocol,
+    parse: Parser,
+    packet_info: &PacketInfo,
+    hosts: &HashSet<IpAddr>,
+    sender: Sender<Reply>,
+) -> Result<()> {
+    loop {
+        let (host, payload, header) = match channel::next_packet(&mut tr, v6, protocol) {
+            Ok(Some(received)) => received,
+            Ok(None) => continue,
+            Err(e) => {
+                debug!("Failed to receive: {}", e);
+                continue;
             }
-            _ => {}
+        };
+        if !hosts.contains(&host) {
+            continue;
+        }
+        let reply = match parse(packet_info, host, &payload) {
+            // ICMPのエラーは元のパケットの宛先で照合する
+            Some(reply) if hosts.contains(&reply.host) => reply,
+            _ => continue,
+        };
+        let observation = match protocol {
+            IpNextHeaderProtocols::Tcp => Observation::parse(header, &payload),
+            _ => None,
+        };
+        let reply = Reply {
+            ttl: header.map(|header| header.ttl),
+            observation,
+            ..reply
+        };
+        // スキャンが終わっていたら受信もやめる
+        if sender.send(reply).is_err() {
+            return Ok(());
         }
-        return Ok(());
     }
 }
 
+/**
+ * ターゲットからのTCPセグメントが、自分のプローブへの返信か調べる
+ */
+fn parse_segment(packet_info: &PacketInfo, host: IpAddr, payload: &[u8]) -> Option<Reply> {
+    let tcp_packet = TcpPacket::new(payload)?;
+    let port = tcp_packet.get_source();
+    let flags = tcp_packet.get_flags();
+    let ack = (flags & TcpFlags::ACK != 0).then(|| tcp_packet.get_acknowledgement());
+    let seq = Some(tcp_packet.get_sequence());
+    if !packet_info.is_reply(host, port, tcp_packet.get_destination(), ack, seq) {
+        return None;
+    }
+    Some(Reply {
+        host,
+        port,
+        response: Response::Tcp {
+            flags,
+            window: tcp_packet.get_window(),
+        },
+        ttl: None,
+        observation: None,
+    })
+}
+
+/**
+ * ターゲットからのUDPのデータグラムが、自分のプローブへの返信か調べる
+ * 中身に関わらず、返信があればポートは開いている
+ */
+fn parse_datagram(packet_info: &PacketInfo, host: IpAddr, payload: &[u8]) -> Option<Reply> {
+    let udp_packet = UdpPacket::new(payload)?;
+    let port = udp_packet.get_source();
+    if !packet_info.is_reply(host, port, udp_packet.get_destination(), None, None) {
+        return None;
+    }
+    Some(Reply {
+        host,
+        port,
+        response: Response::Udp,
+        ttl: None,
+        observation: None,
+    })
+}
+
+/**
+ * ICMPのDestination UnreachableとSource Quenchが、どのプローブへの通知か調べる
+ */
+fn parse_icmpv4(packet_info: &PacketInfo, _: IpAddr, payload: &[u8]) -> Option<Reply> {
+    let icmp_packet = IcmpPacket::new(payload)?;
+    let notification = icmp::parse_icmp(&icmp_packet, packet_info.scan_type.protocol())?;
+    notified_reply(notification, packet_info)
+}
+
+/**
+ * ICMPv6のDestination Unreachableが、どのプローブへの通知か調べる
+ */
+fn parse_icmpv6(packet_info: &PacketInfo, _: IpAddr, payload: &[u8]) -> Option<Reply> {
+    let icmp_packet = Icmpv6Packet::new(payload)?;
+    let notification = icmp::parse_icmpv6(&icmp_packet, packet_info.scan_type.protocol())?;
+    notified_reply(notification, packet_info)
+}
+
+/**
+ * 自分のプローブへのICMPのエラーを返信とする
+ */
+fn notified_reply(notification: Notification, packet_info: &PacketInfo) -> Option<Reply> {
+    if !packet_info.is_notified(&notification) {
+        return None;
+    }
+    Some(Reply {
+        host: notification.host,
+        port: notification.port,
+        response: notification.response,
+        ttl: None,
+        observation: None,
+    })
+}
+
 const TCP_SIZE: usize = 20;
 
 /**
@@ -188,16 +784,10 @@ fn build_packet(packet_info: &PacketInfo) -> Result<[u8; TCP_SIZE]> {
     let mut tcp_header =
         MutableTcpPacket::new(&mut tcp_buffer[..]).context("Failed to make MutableTcpPacket")?;
 
-    tcp_header.set_source(packet_info.my_port);
     // オプションを含まないので、20オクテットまでがTCPヘッダ。4オクテット単位で指定する。
     tcp_header.set_data_offset(5);
-    tcp_header.set_flags(packet_info.scan_type as u16);
-    let checksum = tcp::ipv4_checksum(
-        &tcp_header.to_immutable(),
-        &packet_info.my_ipaddr,
-        &packet_info.target_ipaddr,
-    );
-    tcp_header.set_checksum(checksum);
+    tcp_header.set_flags(packet_info.interpretation.flags);
+    // 送信元ポート、シーケンス番号、確認応答番号、チェックサムは宛先ごとにregister_destinationで決める
 
     Ok(tcp_buffer)
 }
@@ -0,0 +1,230 @@
+use anyhow::{anyhow, Context, Result};
+use rand::seq::SliceRandom;
+use std::collections::BTreeSet;
+
+/**
+ * よく使われるTCPポート（nmapのtop portsの上位100個を頻度順に並べたもの）
+ */
+pub const TOP_PORTS: [u16; 100] = [
+    80, 23, 443, 21, 22, 25, 3389, 110, 445, 139, 143, 53, 135, 3306, 8080, 1723, 111, 995, 993,
+    5900, 1025, 587, 8888, 199, 1720, 465, 548, 113, 81, 6001, 10000, 514, 5060, 179, 1026, 2000,
+    8443, 8000, 32768, 554, 26, 1433, 49152, 2001, 515, 8008, 49154, 1027, 5666, 646, 5000, 5631,
+    631, 49153, 8081, 2049, 88, 79, 5800, 106, 2121, 1110, 49155, 6000, 513, 990, 5357, 427, 49156,
+    543, 544, 5101, 144, 7, 389, 8009, 3128, 444, 9999, 5009, 7070, 5190, 3000, 5432, 1900, 3986,
+    13, 1029, 9, 5051, 6646, 49157, 1028, 873, 1755, 2717, 4899, 9100, 119, 37,
+];
+
+/**
+ * スキャンするポートの選び方
+ */
+#[derive(Debug, Clone)]
+pub struct PortSelection {
+    /// -pで指定したポート
+    pub ports: Option<Vec<u16>>,
+    /// よく使われるポートの上位N個
+    pub top: Option<usize>,
+    pub exclude: Vec<u16>,
+    /// 連番で送らないように順番をランダムにする
+    pub randomize: bool,
+}
+
+impl Default for PortSelection {
+    fn default() -> Self {
+        Self {
+            ports: None,
+            top: None,
+            exclude: vec![],
+            randomize: true,
+        }
+    }
+}
+
+impl PortSelection {
+    /**
+     * プローブを送るポートを送信順に並べる
+     * どちらも指定されていない場合は、maximum_portがあれば1からそこまで、なければよく使われる100ポート
+     */
+    pub fn resolve(&self, maximum_port: Option<u16>) -> Result<Vec<u16>> {
+        let candidates: Vec<u16> = match (&self.ports, self.top, maximum_port) {
+            (Some(ports), _, _) => ports.clone(),
+            (None, Some(top), _) => TOP_PORTS[..top.min(TOP_PORTS.len())].to_vec(),
+            (None, None, Some(maximum_port)) => (1..=maximum_port).collect(),
+            (None, None, None) => TOP_PORTS.to_vec(),
+        };
+        let exclude: BTreeSet<u16> = self.exclude.iter().copied().collect();
+        let mut seen = BTreeSet::new();
+        let mut ports: Vec<u16> = candidates
+            .into_iter()
+            .filter(|port| !exclude.contains(port) && seen.insert(*port))
+            .collect();
+        if ports.is_empty() {
+            return Err(anyhow!("No port to scan"));
+        }
+        if self.randomize {
+            ports.shuffle(&mut rand::thread_rng());
+        } else {
+            ports.sort_unstable();
+        }
+        Ok(ports)
+    }
+}
+
+/**
+ * "22,80,443,8000-8100"のようなポートの指定を解析する
+ * 範囲の片側を省略すると1か65535までとみなし、"-"だけなら全ポートになる
+ */
+pub fn parse_ports(spec: &str) -> Result<Vec<u16>> {
+    let mut ports = vec![];
+    for item in spec.split(',').map(str::trim) {
+        if item.is_empty() {
+            return Err(anyhow!("Empty item in port list {:?}", spec));
+        }
+        let (start, end) = match item.split_once('-') {
+            Some((start, end)) => (
+                parse_port(start, 1).with_context(|| format!("invalid port range {}", item))?,
+                parse_port(end, u16::MAX)
+                    .with_context(|| format!("invalid port range {}", item))?,
+            ),
+            None => {
+                let port = parse_port(item, 0).with_context(|| format!("invalid port {}", item))?;
+                (port, port)
+            }
+        };
+        if start > end {
+            return Err(anyhow!("Reversed port range {}", item));
+        }
+        ports.extend(start..=end);
+    }
+    Ok(ports)
+}
+
+/**
+ * ポート番号を1つ解析する。空文字列ならdefaultを返す
+ */
+fn parse_port(s: &str, default: u16) -> Result<u16> {
+    let port = if s.is_empty() {
+        default
+    } else {
+        s.trim().parse()?
+    };
+    if port == 0 {
+        return Err(anyhow!("port 0 cannot be scanned"));
+    }
+    Ok(port)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ordered(ports: Option<Vec<u16>>, top: Option<usize>, exclude: Vec<u16>) -> PortSelection {
+        PortSelection {
+            ports,
+            top,
+            exclude,
+            randomize: false,
+        }
+    }
+
+    #[test]
+    fn parses_lists_and_ranges() {
+        assert_eq!(parse_ports("22").unwrap(), [22]);
+        assert_eq!(parse_ports(" 22 , 80,443").unwrap(), [22, 80, 443]);
+        assert_eq!(
+            parse_ports("8000-8003,22").unwrap(),
+            [8000, 8001, 8002, 8003, 22]
+        );
+        assert_eq!(parse_ports("7-7").unwrap(), [7]);
+        // 重複は解決するときに取り除く
+        assert_eq!(parse_ports("80,79-81").unwrap(), [80, 79, 80, 81]);
+    }
+
+    #[test]
+    fn open_ended_ranges() {
+        assert_eq!(parse_ports("-3").unwrap(), [1, 2, 3]);
+        assert_eq!(parse_ports("65533-").unwrap(), [65533, 65534, 65535]);
+        let all = parse_ports("-").unwrap();
+        assert_eq!(all.len(), 65535);
+        assert_eq!((all[0], all[65534]), (1, 65535));
+    }
+
+    #[test]
+    fn rejects_invalid_specs() {
+        for spec in [
+            "", "22,", ",22", "22,,80", "0", "0-10", "-0", "100-10", "65536", "1-65536", "http",
+            "1-2-3", "-1-",
+        ] {
+            assert!(parse_ports(spec).is_err(), "{:?}", spec);
+        }
+        let e = parse_ports("100-10").unwrap_err();
+        assert_eq!(e.to_string(), "Reversed port range 100-10");
+        let e = parse_ports("0").unwrap_err();
+        assert_eq!(
+            format!("{:#}", e),
+            "invalid port 0: port 0 cannot be scanned"
+        );
+    }
+
+    #[test]
+    fn resolve_dedups_and_excludes() {
+        let selection = ordered(Some(vec![443, 22, 80, 22, 443]), None, vec![80]);
+        assert_eq!(selection.resolve(None).unwrap(), [22, 443]);
+
+        let selection = ordered(Some(vec![22, 80]), None, vec![80, 22, 9999]);
+        let e = selection.resolve(None).unwrap_err();
+        assert_eq!(e.to_string(), "No port to scan");
+    }
+
+    #[test]
+    fn resolve_default_ports() {
+        let ports = ordered(None, None, vec![]).resolve(None).unwrap();
+        assert_eq!(ports.len(), TOP_PORTS.len());
+        assert_eq!(ports[..3], [7, 9, 13]);
+        // 最大のポート番号が決まっている場合はそこまでの全ポート
+        let ports = ordered(None, None, vec![2]).resolve(Some(4)).unwrap();
+        assert_eq!(ports, [1, 3, 4]);
+        // -pが優先される
+        let ports = ordered(Some(vec![8080]), None, vec![]).resolve(Some(4));
+        assert_eq!(ports.unwrap(), [8080]);
+    }
+
+    #[test]
+    fn resolve_top_ports() {
+        let ports = ordered(None, Some(3), vec![]).resolve(Some(1024)).unwrap();
+        assert_eq!(ports, [23, 80, 443]);
+        let ports = ordered(None, Some(3), vec![23]).resolve(None).unwrap();
+        assert_eq!(ports, [80, 443]);
+        // 一覧より多く指定しても一覧の分だけ
+        let ports = ordered(None, Some(1000), vec![]).resolve(None).unwrap();
+        assert_eq!(ports.len(), TOP_PORTS.len());
+    }
+
+    #[test]
+    fn randomize_keeps_the_same_ports() {
+        let selection = PortSelection {
+            ports: Some((1..=1000).collect()),
+            exclude: vec![500],
+            ..Default::default()
+        };
+        assert!(selection.randomize);
+        let ports = selection.resolve(None).unwrap();
+        assert_eq!(ports.len(), 999);
+        // 1000個が昇順のまま残る確率は無視できる
+        assert!(ports.windows(2).any(|w| w[0] > w[1]));
+        let mut sorted = ports.clone();
+        sorted.sort_unstable();
+        assert_eq!(
+            sorted,
+            ordered(Some((1..=1000).collect()), None, vec![500])
+                .resolve(None)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn top_ports_are_unique() {
+        let unique: BTreeSet<u16> = TOP_PORTS.iter().copied().collect();
+        assert_eq!(unique.len(), TOP_PORTS.len());
+        assert!(!unique.contains(&0));
+    }
+}
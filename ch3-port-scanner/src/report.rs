@@ -0,0 +1,786 @@
+use crate::discovery::{HostReason, HostStatus};
+use crate::fingerprint::OsReport;
+use crate::scan::{PortState, Reason, Scan};
+use crate::service::Service;
+use crate::targets::Target;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::IpAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const SCANNER: &str = env!("CARGO_PKG_NAME");
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/**
+ * 出力形式
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Xml,
+    Grepable,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "xml" => Ok(Format::Xml),
+            "grepable" => Ok(Format::Grepable),
+            _ => Err(anyhow!(
+                "Undefined output format, only accept [text|json|xml|grepable]."
+            )),
+        }
+    }
+}
+
+/**
+ * スキャン全体の結果
+ * JSONで保存したものをdiffで読み込むので、書き出すものはすべて読み戻せるようにする
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScanReport {
+    pub scanner: String,
+    /// 実行したときのコマンドライン
+    pub args: String,
+    /// nmapの名前でのスキャン方法（syn、connectなど）。-snではNone
+    pub scan_type: Option<String>,
+    /// 開始時刻（UNIX時間の秒）
+    pub start: u64,
+    /// かかった時間（秒）
+    pub elapsed: f64,
+    /// -sVでサービスを調べたか
+    pub version_detection: bool,
+    /// -OでOSを推定したか
+    #[serde(default)]
+    pub os_detection: bool,
+    /// 動いているホストだけを含む
+    pub hosts: Vec<HostReport>,
+    /// 応答がなかったホストの数
+    #[serde(default)]
+    pub down: usize,
+}
+
+/**
+ * ホストごとの結果
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HostReport {
+    pub addr: IpAddr,
+    /// ホスト名で指定した場合の名前
+    pub name: Option<String>,
+    /// 動いていると判断した理由
+    #[serde(default)]
+    pub reason: HostReason,
+    #[serde(default)]
+    pub reason_ttl: Option<u8>,
+    /// このホストのスキャンにかかった時間（秒）
+    pub elapsed: f64,
+    /// 閉じているものも含めた、スキャンしたすべてのポート
+    pub ports: Vec<PortReport>,
+    /// SYN/ACKもRSTも受け取れなかった場合はNone
+    #[serde(default)]
+    pub os: Option<OsReport>,
+}
+
+impl HostReport {
+    /**
+     * "host.example (10.0.0.1)"のような表示用の名前
+     */
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} ({})", name, self.addr),
+            None => self.addr.to_string(),
+        }
+    }
+
+    fn closed(&self) -> usize {
+        self.ports
+            .iter()
+            .filter(|port| port.state == PortState::Closed)
+            .count()
+    }
+}
+
+/**
+ * ポートごとの結果
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PortReport {
+    pub port: u16,
+    pub protocol: String,
+    pub state: PortState,
+    pub reason: Reason,
+    /// 状態を決めた返信のTTL。IPv6とconnect()では分からない
+    pub ttl: Option<u8>,
+    /// 再送せずに返信があった場合のRTT（ミリ秒）
+    pub rtt_ms: Option<f64>,
+    pub service: Option<Service>,
+}
+
+impl ScanReport {
+    /**
+     * ホストの発見とスキャンの結果、検出したサービスをまとめる
+     * targetsは動いているホストで、-snの場合はscanがNone
+     */
+    pub fn new(
+        scan: Option<&Scan>,
+        targets: &[Target],
+        statuses: &HashMap<IpAddr, HostStatus>,
+        down: usize,
+        services: Option<&HashMap<(IpAddr, u16), Service>>,
+        started: SystemTime,
+        elapsed: Duration,
+    ) -> Self {
+        let results = match scan {
+            Some(scan) => scan
+                .hosts()
+                .iter()
+                .zip(scan.results())
+                .map(|(host, results)| (host.duration().as_secs_f64(), results))
+                .collect(),
+            None => vec![(0.0, vec![]); targets.len()],
+        };
+        let protocol = scan.map_or("tcp", |scan| scan.scan_type().protocol_name());
+        let hosts = targets
+            .iter()
+            .zip(results)
+            .map(|(target, (elapsed, results))| {
+                let status = statuses.get(&target.addr).copied().unwrap_or_default();
+                HostReport {
+                    addr: target.addr,
+                    name: target.name.clone(),
+                    reason: status.reason,
+                    reason_ttl: status.ttl,
+                    elapsed,
+                    ports: results
+                        .into_iter()
+                        .map(|result| PortReport {
+                            port: result.port,
+                            protocol: protocol.to_string(),
+                            state: result.state,
+                            reason: result.reason,
+                            ttl: result.ttl,
+                            rtt_ms: result.rtt.map(|rtt| rtt.as_secs_f64() * 1000.0),
+                            service: services
+                                .and_then(|services| services.get(&(target.addr, result.port)))
+                                .cloned(),
+                        })
+                        .collect(),
+                    os: None,
+                }
+            })
+            .collect();
+        Self {
+            scanner: SCANNER.to_string(),
+            args: std::env::args().collect::<Vec<_>>().join(" "),
+            scan_type: scan.map(|scan| scan.scan_type().name().to_string()),
+            start: started
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            elapsed: elapsed.as_secs_f64(),
+            version_detection: services.is_some(),
+            os_detection: false,
+            hosts,
+            down,
+        }
+    }
+
+    /**
+     * ホストごとのOSの推定の結果を加える
+     */
+    pub fn set_os(&mut self, mut reports: HashMap<IpAddr, OsReport>) {
+        self.os_detection = true;
+        for host in &mut self.hosts {
+            host.os = reports.remove(&host.addr);
+        }
+    }
+
+    /**
+     * 指定の形式で書き出す
+     */
+    pub fn write<W: Write>(&self, format: Format, w: &mut W) -> Result<()> {
+        match format {
+            Format::Text => self.write_text(w),
+            Format::Json => {
+                serde_json::to_writer_pretty(&mut *w, self)?;
+                writeln!(w)?;
+                Ok(())
+            }
+            Format::Xml => self.write_xml(w),
+            Format::Grepable => self.write_grepable(w),
+        }
+    }
+
+    /**
+     * ホストごとにnmap風に表示する
+     * 閉じているポートは数だけ表示する
+     * バージョン検出をした場合はサービスとバージョンの列を、OSを推定した場合はその結果を加える
+     */
+    fn write_text<W: Write>(&self, w: &mut W) -> Result<()> {
+        for host in &self.hosts {
+            writeln!(w, "Scan report for {}", host.label())?;
+            writeln!(w, "Host is up ({}).", host.reason.name())?;
+            if self.scan_type.is_none() {
+                writeln!(w)?;
+                continue;
+            }
+            let closed = host.closed();
+            if closed > 0 {
+                writeln!(w, "Not shown: {} closed ports", closed)?;
+            }
+            if self.version_detection {
+                writeln!(w, "PORT      STATE         SERVICE  VERSION")?;
+            } else {
+                writeln!(w, "PORT      STATE")?;
+            }
+            for port in host
+                .ports
+                .iter()
+                .filter(|port| port.state != PortState::Closed)
+            {
+                let port_label = format!("{}/{}", port.port, port.protocol);
+                if !self.version_detection {
+                    writeln!(w, "{:<9} {}", port_label, port.state)?;
+                    continue;
+                }
+                let (name, version) = match &port.service {
+                    Some(service) => (service.name.as_str(), service.version.as_deref()),
+                    None => ("", None),
+                };
+                let line = format!(
+                    "{:<9} {:<13} {:<8} {}",
+                    port_label,
+                    port.state,
+                    name,
+                    version.unwrap_or_default()
+                );
+                writeln!(w, "{}", line.trim_end())?;
+            }
+            if self.os_detection {
+                write_os_text(host.os.as_ref(), w)?;
+            }
+            writeln!(
+                w,
+                "Scanned {} ports in {:.2} seconds",
+                host.ports.len(),
+                host.elapsed
+            )?;
+            writeln!(w)?;
+        }
+        writeln!(
+            w,
+            "{} hosts scanned ({} up) in {:.2} seconds",
+            self.hosts.len() + self.down,
+            self.hosts.len(),
+            self.elapsed
+        )?;
+        Ok(())
+    }
+
+    /**
+     * nmapの-oXの一部の要素と属性だけを書き出す
+     * 応答がなかったホストは数だけをrunstatsに書く
+     * 閉じているポートはnmapと同じくextraportsにまとめる
+     * OSの推定はosmatchに、推定に使った特徴はosfingerprintに書く
+     */
+    fn write_xml<W: Write>(&self, w: &mut W) -> Result<()> {
+        let end = self.start + self.elapsed as u64;
+        writeln!(w, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(w, "<!DOCTYPE nmaprun>")?;
+        writeln!(
+            w,
+            "<nmaprun scanner=\"{}\" args=\"{}\" start=\"{}\" version=\"{}\" xmloutputversion=\"1.05\">",
+            SCANNER,
+            escape(&self.args),
+            self.start,
+            VERSION
+        )?;
+        let protocol = self
+            .hosts
+            .iter()
+            .flat_map(|host| host.ports.first())
+            .map(|port| port.protocol.as_str())
+            .next()
+            .unwrap_or("tcp");
+        let mut ports: Vec<u16> = self
+            .hosts
+            .iter()
+            .flat_map(|host| host.ports.iter().map(|port| port.port))
+            .collect();
+        ports.sort_unstable();
+        ports.dedup();
+        if let Some(scan_type) = &self.scan_type {
+            writeln!(
+                w,
+                "<scaninfo type=\"{}\" protocol=\"{}\" numservices=\"{}\" services=\"{}\"/>",
+                scan_type,
+                protocol,
+                ports.len(),
+                port_ranges(&ports)
+            )?;
+        }
+        for host in &self.hosts {
+            let end = self.start + host.elapsed as u64;
+            writeln!(w, "<host starttime=\"{}\" endtime=\"{}\">", self.start, end)?;
+            writeln!(
+                w,
+                "<status state=\"up\" reason=\"{}\" reason_ttl=\"{}\"/>",
+                host.reason.name(),
+                host.reason_ttl.unwrap_or_default()
+            )?;
+            let addrtype = match host.addr {
+                IpAddr::V4(_) => "ipv4",
+                IpAddr::V6(_) => "ipv6",
+            };
+            writeln!(
+                w,
+                "<address addr=\"{}\" addrtype=\"{}\"/>",
+                host.addr, addrtype
+            )?;
+            match &host.name {
+                Some(name) => writeln!(
+                    w,
+                    "<hostnames>\n<hostname name=\"{}\" type=\"user\"/>\n</hostnames>",
+                    escape(name)
+                )?,
+                None => writeln!(w, "<hostnames>\n</hostnames>")?,
+            }
+            writeln!(w, "<ports>")?;
+            let closed = host.closed();
+            if closed > 0 {
+                writeln!(w, "<extraports state=\"closed\" count=\"{}\">", closed)?;
+                let mut reasons: Vec<(Reason, usize)> = vec![];
+                for port in host
+                    .ports
+                    .iter()
+                    .filter(|port| port.state == PortState::Closed)
+                {
+                    match reasons
+                        .iter_mut()
+                        .find(|(reason, _)| *reason == port.reason)
+                    {
+                        Some((_, count)) => *count += 1,
+                        None => reasons.push((port.reason, 1)),
+                    }
+                }
+                for (reason, count) in reasons {
+                    writeln!(
+                        w,
+                        "<extrareasons reason=\"{}\" count=\"{}\"/>",
+                        reason.name(),
+                        count
+                    )?;
+                }
+                writeln!(w, "</extraports>")?;
+            }
+            for port in host
+                .ports
+                .iter()
+                .filter(|port| port.state != PortState::Closed)
+            {
+                writeln!(
+                    w,
+                    "<port protocol=\"{}\" portid=\"{}\"><state state=\"{}\" reason=\"{}\" reason_ttl=\"{}\"/>",
+                    port.protocol,
+                    port.port,
+                    port.state,
+                    port.reason.name(),
+                    port.ttl.unwrap_or_default()
+                )?;
+                if let Some(service) = &port.service {
+                    match &service.version {
+                        Some(version) => writeln!(
+                            w,
+                            "<service name=\"{}\" product=\"{}\" method=\"probed\" conf=\"10\"/>",
+                            escape(&service.name),
+                            escape(version)
+                        )?,
+                        None => writeln!(
+                            w,
+                            "<service name=\"{}\" method=\"probed\" conf=\"10\"/>",
+                            escape(&service.name)
+                        )?,
+                    }
+                }
+                writeln!(w, "</port>")?;
+            }
+            writeln!(w, "</ports>")?;
+            if let Some(os) = &host.os {
+                writeln!(w, "<os>")?;
+                if let Some(guess) = &os.guess {
+                    writeln!(
+                        w,
+                        "<osmatch name=\"{}\" accuracy=\"{}\">\n<osclass osfamily=\"{}\" accuracy=\"{}\"/>\n</osmatch>",
+                        escape(&guess.name),
+                        guess.confidence,
+                        escape(&guess.family),
+                        guess.confidence
+                    )?;
+                }
+                writeln!(
+                    w,
+                    "<osfingerprint fingerprint=\"{} {}\"/>",
+                    os.kind.name(),
+                    escape(&os.fingerprint)
+                )?;
+                writeln!(w, "</os>")?;
+            }
+            writeln!(w, "</host>")?;
+        }
+        writeln!(w, "<runstats>")?;
+        writeln!(
+            w,
+            "<finished time=\"{}\" elapsed=\"{:.2}\" exit=\"success\"/>",
+            end, self.elapsed
+        )?;
+        writeln!(
+            w,
+            "<hosts up=\"{}\" down=\"{}\" total=\"{}\"/>",
+            self.hosts.len(),
+            self.down,
+            self.hosts.len() + self.down
+        )?;
+        writeln!(w, "</runstats>")?;
+        writeln!(w, "</nmaprun>")?;
+        Ok(())
+    }
+
+    /**
+     * nmapの-oGと同じく、1ホスト1行で書き出す
+     * ポートは"番号/状態/プロトコル/所有者/サービス/RPC情報/バージョン/"の形式で、閉じているものは数だけ書く
+     */
+    fn write_grepable<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(
+            w,
+            "# {} {} scan initiated at {} as: {}",
+            SCANNER, VERSION, self.start, self.args
+        )?;
+        for host in &self.hosts {
+            let label = match &host.name {
+                Some(name) => format!("{} ({})", host.addr, name),
+                None => format!("{} ()", host.addr),
+            };
+            writeln!(w, "Host: {}\tStatus: Up", label)?;
+            if self.scan_type.is_none() {
+                continue;
+            }
+            let ports: Vec<String> = host
+                .ports
+                .iter()
+                .filter(|port| port.state != PortState::Closed)
+                .map(|port| {
+                    let (name, version) = match &port.service {
+                        Some(service) => (
+                            grepable_field(&service.name),
+                            service
+                                .version
+                                .as_deref()
+                                .map(grepable_field)
+                                .unwrap_or_default(),
+                        ),
+                        None => (String::new(), String::new()),
+                    };
+                    format!(
+                        "{}/{}/{}//{}//{}/",
+                        port.port, port.state, port.protocol, name, version
+                    )
+                })
+                .collect();
+            let mut line = format!("Host: {}\tPorts: {}", label, ports.join(", "));
+            let closed = host.closed();
+            if closed > 0 {
+                line.push_str(&format!("\tIgnored State: closed ({})", closed));
+            }
+            if let Some(guess) = host.os.as_ref().and_then(|os| os.guess.as_ref()) {
+                line.push_str(&format!("\tOS: {}", guess.name));
+            }
+            writeln!(w, "{}", line)?;
+        }
+        writeln!(
+            w,
+            "# {} done at {} -- {} IP addresses ({} hosts up) scanned in {:.2} seconds",
+            SCANNER,
+            self.start + self.elapsed as u64,
+            self.hosts.len() + self.down,
+            self.hosts.len(),
+            self.elapsed
+        )?;
+        Ok(())
+    }
+}
+
+/**
+ * OSの推定の結果を表示する
+ */
+fn write_os_text<W: Write>(os: Option<&OsReport>, w: &mut W) -> Result<()> {
+    let os = match os {
+        Some(os) => os,
+        None => {
+            writeln!(w, "OS guess: no SYN/ACK or RST received")?;
+            return Ok(());
+        }
+    };
+    match &os.guess {
+        Some(guess) => writeln!(
+            w,
+            "OS guess: {} ({}% confidence)",
+            guess.name, guess.confidence
+        )?,
+        None => writeln!(w, "OS guess: no match")?,
+    }
+    writeln!(
+        w,
+        "OS fingerprint: {} {} (IP ID {})",
+        os.kind.name(),
+        os.fingerprint,
+        os.ip_id.name()
+    )?;
+    Ok(())
+}
+
+/**
+ * XMLの属性値に使えない文字を実体参照にする
+ */
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/**
+ * grepableの区切り文字と重ならないよう、nmapと同じく/を|に、,を空白にする
+ */
+fn grepable_field(s: &str) -> String {
+    s.replace('/', "|").replace(',', " ")
+}
+
+/**
+ * 整列済みのポートを"1-3,80"のような範囲の表記にする
+ */
+fn port_ranges(ports: &[u16]) -> String {
+    let mut ranges: Vec<String> = vec![];
+    let mut i = 0;
+    while i < ports.len() {
+        let start = ports[i];
+        let mut end = start;
+        while i + 1 < ports.len() && ports[i + 1] == end + 1 {
+            end += 1;
+            i += 1;
+        }
+        if start == end {
+            ranges.push(start.to_string());
+        } else {
+            ranges.push(format!("{}-{}", start, end));
+        }
+        i += 1;
+    }
+    ranges.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprint::{IpId, Kind, OsGuess};
+    use std::net::Ipv4Addr;
+
+    fn port(port: u16, state: PortState, reason: Reason) -> PortReport {
+        PortReport {
+            port,
+            protocol: "tcp".to_string(),
+            state,
+            reason,
+            ttl: Some(64),
+            rtt_ms: Some(1.5),
+            service: None,
+        }
+    }
+
+    /**
+     * 開いているポートと閉じているポート、フィルタされたポートを持つホストが1つと、応答のなかったホストが1つ
+     */
+    fn report() -> ScanReport {
+        let mut ssh = port(22, PortState::Open, Reason::SynAck);
+        ssh.service = Some(Service {
+            name: "ssh".to_string(),
+            version: Some("OpenSSH <8.9p1> \"a&b\", x/y".to_string()),
+        });
+        ScanReport {
+            scanner: SCANNER.to_string(),
+            args: "ch3-port-scanner -sV -p 22-24,80 host.example sS".to_string(),
+            scan_type: Some("syn".to_string()),
+            start: 1_700_000_000,
+            elapsed: 2.5,
+            version_detection: true,
+            os_detection: false,
+            hosts: vec![HostReport {
+                addr: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                name: Some("host.example".to_string()),
+                reason: HostReason::EchoReply,
+                reason_ttl: Some(64),
+                elapsed: 1.25,
+                ports: vec![
+                    ssh,
+                    port(23, PortState::Closed, Reason::Reset),
+                    port(24, PortState::Closed, Reason::Reset),
+                    port(80, PortState::Filtered, Reason::NoResponse),
+                ],
+                os: None,
+            }],
+            down: 1,
+        }
+    }
+
+    fn output(report: &ScanReport, format: Format) -> String {
+        let mut buffer = vec![];
+        report.write(format, &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    fn with_os(mut report: ScanReport) -> ScanReport {
+        let os = OsReport {
+            kind: Kind::SynAck,
+            fingerprint: "4:64+0:0:1460:mss*20,10:mss,sok,ts,nop,ws:df,id+:0".to_string(),
+            ip_id: IpId::Zero,
+            guess: Some(OsGuess {
+                name: "Linux 3.x".to_string(),
+                family: "Linux".to_string(),
+                class: "unix".to_string(),
+                confidence: 90,
+            }),
+        };
+        report.set_os(HashMap::from([(report.hosts[0].addr, os)]));
+        report
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(
+            output(&report(), Format::Text),
+            "Scan report for host.example (192.0.2.1)\n\
+             Host is up (echo-reply).\n\
+             Not shown: 2 closed ports\n\
+             PORT      STATE         SERVICE  VERSION\n\
+             22/tcp    open          ssh      OpenSSH <8.9p1> \"a&b\", x/y\n\
+             80/tcp    filtered\n\
+             Scanned 4 ports in 1.25 seconds\n\
+             \n\
+             2 hosts scanned (1 up) in 2.50 seconds\n"
+        );
+
+        let text = output(&with_os(report()), Format::Text);
+        assert!(text.contains(
+            "80/tcp    filtered\n\
+             OS guess: Linux 3.x (90% confidence)\n\
+             OS fingerprint: syn-ack 4:64+0:0:1460:mss*20,10:mss,sok,ts,nop,ws:df,id+:0 (IP ID zero)\n\
+             Scanned 4 ports"
+        ));
+
+        // -snではポートの表を出さない
+        let mut ping = report();
+        ping.scan_type = None;
+        ping.version_detection = false;
+        assert_eq!(
+            output(&ping, Format::Text),
+            "Scan report for host.example (192.0.2.1)\n\
+             Host is up (echo-reply).\n\
+             \n\
+             2 hosts scanned (1 up) in 2.50 seconds\n"
+        );
+    }
+
+    #[test]
+    fn xml() {
+        let xml = output(&with_os(report()), Format::Xml);
+        for line in [
+            "<scaninfo type=\"syn\" protocol=\"tcp\" numservices=\"4\" services=\"22-24,80\"/>",
+            "<host starttime=\"1700000000\" endtime=\"1700000001\">",
+            "<status state=\"up\" reason=\"echo-reply\" reason_ttl=\"64\"/>",
+            "<address addr=\"192.0.2.1\" addrtype=\"ipv4\"/>",
+            "<hostname name=\"host.example\" type=\"user\"/>",
+            "<extraports state=\"closed\" count=\"2\">\n<extrareasons reason=\"reset\" count=\"2\"/>\n</extraports>",
+            "<port protocol=\"tcp\" portid=\"22\"><state state=\"open\" reason=\"syn-ack\" reason_ttl=\"64\"/>",
+            "<service name=\"ssh\" product=\"OpenSSH &lt;8.9p1&gt; &quot;a&amp;b&quot;, x/y\" method=\"probed\" conf=\"10\"/>",
+            "<port protocol=\"tcp\" portid=\"80\"><state state=\"filtered\" reason=\"no-response\" reason_ttl=\"64\"/>\n</port>",
+            "<osmatch name=\"Linux 3.x\" accuracy=\"90\">\n<osclass osfamily=\"Linux\" accuracy=\"90\"/>\n</osmatch>",
+            "<finished time=\"1700000002\" elapsed=\"2.50\" exit=\"success\"/>",
+            "<hosts up=\"1\" down=\"1\" total=\"2\"/>",
+        ] {
+            assert!(xml.contains(line), "{} not in\n{}", line, xml);
+        }
+        // 閉じているポートはextraportsにまとめる
+        assert!(!xml.contains("portid=\"23\""));
+        assert!(xml.ends_with("</runstats>\n</nmaprun>\n"));
+    }
+
+    #[test]
+    fn escape_attribute_values() {
+        assert_eq!(
+            escape("<html> \"Tom & Jerry's\""),
+            "&lt;html&gt; &quot;Tom &amp; Jerry&apos;s&quot;"
+        );
+        assert_eq!(escape("OpenSSH_8.9p1"), "OpenSSH_8.9p1");
+    }
+
+    #[test]
+    fn grepable() {
+        assert_eq!(
+            output(&with_os(report()), Format::Grepable),
+            format!(
+                "# {scanner} {version} scan initiated at 1700000000 as: \
+                 ch3-port-scanner -sV -p 22-24,80 host.example sS\n\
+                 Host: 192.0.2.1 (host.example)\tStatus: Up\n\
+                 Host: 192.0.2.1 (host.example)\tPorts: \
+                 22/open/tcp//ssh//OpenSSH <8.9p1> \"a&b\"  x|y/, 80/filtered/tcp/////\t\
+                 Ignored State: closed (2)\tOS: Linux 3.x\n\
+                 # {scanner} done at 1700000002 -- 2 IP addresses (1 hosts up) scanned in 2.50 seconds\n",
+                scanner = SCANNER,
+                version = VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let json = output(&with_os(report()), Format::Json);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["scan_type"], "syn");
+        assert_eq!(value["hosts"][0]["reason"], "echo-reply");
+        assert_eq!(value["hosts"][0]["ports"][0]["service"]["name"], "ssh");
+        assert_eq!(value["hosts"][0]["ports"][3]["state"], "filtered");
+        assert_eq!(value["hosts"][0]["ports"][3]["reason"], "no-response");
+        assert_eq!(value["hosts"][0]["os"]["guess"]["confidence"], 90);
+
+        let read: ScanReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(output(&read, Format::Json), json);
+        assert_eq!(
+            output(&read, Format::Text),
+            output(&with_os(report()), Format::Text)
+        );
+    }
+
+    #[test]
+    fn port_ranges_merge_consecutive_ports() {
+        assert_eq!(port_ranges(&[]), "");
+        assert_eq!(port_ranges(&[1, 2, 3, 80, 443, 444]), "1-3,80,443-444");
+        assert_eq!(port_ranges(&[65534, 65535]), "65534-65535");
+    }
+}
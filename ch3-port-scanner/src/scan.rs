@@ -0,0 +1,1040 @@
+use crate::fingerprint::Observation;
+use anyhow::{anyhow, Result};
+use pnet::packet::{
+    ip::{IpNextHeaderProtocol, IpNextHeaderProtocols},
+    tcp::TcpFlags,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanType {
+    Syn,
+    Fin,
+    Xmax,
+    Null,
+    /// ACKだけを送り、RSTが返るかでフィルタされているかを調べる
+    Ack,
+    /// ACKスキャンと同じプローブで、RSTのウィンドウサイズから開いているかを判断する
+    Window,
+    /// FIN/ACKを送る。BSD系のOSは開いているポートではRSTを返さない
+    Maimon,
+    /// OSのconnect()で3ウェイハンドシェイクを行う。rawソケットを使わないので権限が要らない
+    Connect,
+    Udp,
+}
+
+/**
+ * プローブに対するターゲットからの返信
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response {
+    /// TCPの返信のフラグとウィンドウサイズ
+    Tcp {
+        flags: u16,
+        window: u16,
+    },
+    Udp,
+    /// ICMPかICMPv6のDestination Unreachable
+    Unreachable(Unreachable),
+    /// ICMPのSource Quench。ポートの状態には使わず、送信レートを下げる
+    SourceQuench,
+}
+
+/**
+ * 到達不能の種類
+ * ICMPとICMPv6でコードが違うので、受信したときにこの分類に直す
+ * ポート以外の到達不能は、フィルタされているとみなす
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unreachable {
+    Network,
+    Host,
+    Protocol,
+    Port,
+    /// 管理上の禁止
+    Prohibited,
+}
+
+/**
+ * ターゲットからの返信
+ * ICMPの場合は、埋め込まれた元のパケットの宛先
+ */
+pub struct Reply {
+    pub host: IpAddr,
+    pub port: u16,
+    pub response: Response,
+    /// 返信のTTL。IPv6とconnect()では分からない
+    pub ttl: Option<u8>,
+    /// OSの推定に使う、SYN/ACKかRSTの特徴。rawソケットで受け取った場合だけ分かる
+    pub observation: Option<Observation>,
+}
+
+/**
+ * ポートの状態を決めた理由（nmapの--reasonと同じ名前）
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    SynAck,
+    Reset,
+    UdpResponse,
+    NetUnreach,
+    HostUnreach,
+    ProtoUnreach,
+    PortUnreach,
+    AdminProhibited,
+    SourceQuench,
+    NoResponse,
+    /// connect()がタイムアウトと接続拒否以外の理由で失敗した
+    Error,
+}
+
+impl Reason {
+    fn from_response(response: Response) -> Self {
+        match response {
+            Response::Tcp { flags, .. } if flags & TcpFlags::RST != 0 => Reason::Reset,
+            Response::Tcp { .. } => Reason::SynAck,
+            Response::Udp => Reason::UdpResponse,
+            Response::Unreachable(Unreachable::Network) => Reason::NetUnreach,
+            Response::Unreachable(Unreachable::Host) => Reason::HostUnreach,
+            Response::Unreachable(Unreachable::Protocol) => Reason::ProtoUnreach,
+            Response::Unreachable(Unreachable::Port) => Reason::PortUnreach,
+            Response::Unreachable(Unreachable::Prohibited) => Reason::AdminProhibited,
+            Response::SourceQuench => Reason::SourceQuench,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Reason::SynAck => "syn-ack",
+            Reason::Reset => "reset",
+            Reason::UdpResponse => "udp-response",
+            Reason::NetUnreach => "net-unreach",
+            Reason::HostUnreach => "host-unreach",
+            Reason::ProtoUnreach => "proto-unreach",
+            Reason::PortUnreach => "port-unreach",
+            Reason::AdminProhibited => "admin-prohibited",
+            Reason::SourceQuench => "source-quench",
+            Reason::NoResponse => "no-response",
+            Reason::Error => "error",
+        }
+    }
+}
+
+/**
+ * RSTを受け取ったときのポートの状態の決め方
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnReset {
+    State(PortState),
+    /// ウィンドウサイズが0でなければ開いている。RSTに0以外を入れるOSでしか区別できない
+    Window,
+}
+
+/**
+ * スキャン方法ごとの、プローブのフラグと返信の解釈の表
+ * build_packetはフラグでプローブを作り、受信した返信はstateでポートの状態に直す
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interpretation {
+    /// プローブのTCPフラグ
+    pub flags: u16,
+    /// SYN/ACKを受け取ったときの状態。Noneなら判断に使わない
+    pub syn_ack: Option<PortState>,
+    pub rst: OnReset,
+    /// UDPの返信を受け取ったときの状態
+    pub udp: Option<PortState>,
+    /// Port Unreachableを受け取ったときの状態。それ以外の到達不能はフィルタされているとみなす
+    pub port_unreachable: PortState,
+    /// 再送しても返信がなかったときの状態
+    pub no_response: PortState,
+}
+
+impl Interpretation {
+    /**
+     * 返信からポートの状態を判断する
+     * 判断に使えない返信はNoneを返す
+     */
+    pub fn state(&self, response: Response) -> Option<PortState> {
+        match response {
+            Response::Unreachable(Unreachable::Port) => Some(self.port_unreachable),
+            Response::Unreachable(_) => Some(PortState::Filtered),
+            Response::Udp => self.udp,
+            Response::Tcp { flags, window } if flags & TcpFlags::RST != 0 => match self.rst {
+                OnReset::State(state) => Some(state),
+                OnReset::Window if window > 0 => Some(PortState::Open),
+                OnReset::Window => Some(PortState::Closed),
+            },
+            Response::Tcp { flags, .. }
+                if flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN | TcpFlags::ACK =>
+            {
+                self.syn_ack
+            }
+            Response::Tcp { .. } | Response::SourceQuench => None,
+        }
+    }
+}
+
+impl ScanType {
+    pub fn from_arg(arg: &str) -> Result<Self> {
+        match arg {
+            "sS" => Ok(ScanType::Syn),
+            "sF" => Ok(ScanType::Fin),
+            "sX" => Ok(ScanType::Xmax),
+            "sN" => Ok(ScanType::Null),
+            "sA" => Ok(ScanType::Ack),
+            "sW" => Ok(ScanType::Window),
+            "sM" => Ok(ScanType::Maimon),
+            "sT" => Ok(ScanType::Connect),
+            "sU" => Ok(ScanType::Udp),
+            _ => Err(anyhow!(
+                "Undefined scan method, only accept [sS|sF|sN|sX|sA|sW|sM|sT|sU]."
+            )),
+        }
+    }
+
+    /**
+     * プローブのフラグと返信の解釈
+     * connect()の場合は、成功をSYN/ACK、接続拒否をRSTを受け取ったものとして扱う
+     * FIN/Xmas/Null/MaimonとUDPのスキャンでは、開いているポートもフィルタされたポートも返信しないことがある
+     */
+    pub fn interpretation(&self) -> Interpretation {
+        let (flags, syn_ack, rst, no_response) = match self {
+            ScanType::Syn | ScanType::Connect => (
+                TcpFlags::SYN,
+                Some(PortState::Open),
+                OnReset::State(PortState::Closed),
+                PortState::Filtered,
+            ),
+            ScanType::Fin => (
+                TcpFlags::FIN,
+                None,
+                OnReset::State(PortState::Closed),
+                PortState::OpenFiltered,
+            ),
+            ScanType::Xmax => (
+                TcpFlags::FIN | TcpFlags::URG | TcpFlags::PSH,
+                None,
+                OnReset::State(PortState::Closed),
+                PortState::OpenFiltered,
+            ),
+            ScanType::Null => (
+                0,
+                None,
+                OnReset::State(PortState::Closed),
+                PortState::OpenFiltered,
+            ),
+            ScanType::Ack => (
+                TcpFlags::ACK,
+                None,
+                OnReset::State(PortState::Unfiltered),
+                PortState::Filtered,
+            ),
+            ScanType::Window => (TcpFlags::ACK, None, OnReset::Window, PortState::Filtered),
+            ScanType::Maimon => (
+                TcpFlags::FIN | TcpFlags::ACK,
+                None,
+                OnReset::State(PortState::Closed),
+                PortState::OpenFiltered,
+            ),
+            ScanType::Udp => {
+                return Interpretation {
+                    flags: 0,
+                    syn_ack: None,
+                    rst: OnReset::State(PortState::Closed),
+                    udp: Some(PortState::Open),
+                    port_unreachable: PortState::Closed,
+                    no_response: PortState::OpenFiltered,
+                }
+            }
+        };
+        Interpretation {
+            flags,
+            syn_ack,
+            rst,
+            udp: None,
+            port_unreachable: PortState::Filtered,
+            no_response,
+        }
+    }
+
+    /**
+     * プローブのトランスポート層のプロトコル
+     */
+    pub fn protocol(&self) -> IpNextHeaderProtocol {
+        match self {
+            ScanType::Udp => IpNextHeaderProtocols::Udp,
+            _ => IpNextHeaderProtocols::Tcp,
+        }
+    }
+
+    /**
+     * nmapのXML出力のscaninfoで使う名前
+     */
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScanType::Syn => "syn",
+            ScanType::Fin => "fin",
+            ScanType::Xmax => "xmas",
+            ScanType::Null => "null",
+            ScanType::Ack => "ack",
+            ScanType::Window => "window",
+            ScanType::Maimon => "maimon",
+            ScanType::Connect => "connect",
+            ScanType::Udp => "udp",
+        }
+    }
+
+    /**
+     * 結果に表示するプロトコル名
+     */
+    pub fn protocol_name(&self) -> &'static str {
+        match self {
+            ScanType::Udp => "udp",
+            _ => "tcp",
+        }
+    }
+
+    /**
+     * rawソケットを使うか
+     */
+    pub fn is_raw(&self) -> bool {
+        *self != ScanType::Connect
+    }
+}
+
+/**
+ * 返信でポートの状態が決まったプローブの送受信の様子
+ */
+#[derive(Debug, Clone, Copy)]
+pub enum Answer {
+    /// 最初のプローブへの返信と、そのRTT
+    Timely(Duration),
+    /// 再送したプローブへの返信。それまでのプローブか返信が失われている
+    /// どのプローブへの返信か分からないので、RTTは測らない（Karnのアルゴリズム）
+    AfterRetransmission,
+}
+
+/**
+ * ポートの状態（nmapの分類に合わせる）
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortState {
+    Open,
+    Closed,
+    Filtered,
+    /// ACKスキャンで、届いてはいるが開いているかは分からない
+    Unfiltered,
+    /// 返信がないので、開いているかフィルタされているか区別できない
+    #[serde(rename = "open|filtered")]
+    OpenFiltered,
+}
+
+impl PortState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PortState::Open => "open",
+            PortState::Closed => "closed",
+            PortState::Filtered => "filtered",
+            PortState::Unfiltered => "unfiltered",
+            PortState::OpenFiltered => "open|filtered",
+        }
+    }
+}
+
+impl fmt::Display for PortState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/**
+ * 1つのポートのスキャン結果
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct PortResult {
+    pub port: u16,
+    pub state: PortState,
+    pub reason: Reason,
+    /// 状態を決めた返信のTTL
+    pub ttl: Option<u8>,
+    /// 再送せずに返信があった場合のRTT
+    pub rtt: Option<Duration>,
+}
+
+/**
+ * タイムアウトと再送の設定
+ */
+#[derive(Debug, Clone)]
+pub struct ScanConfig {
+    /// 1回のプローブの返信を待つ時間。スキャン中はRTTから計算し直す
+    pub timeout: Duration,
+    /// 返信がない場合に送り直す回数
+    pub retries: u32,
+    /// スキャン全体の制限時間。過ぎたら返信のないポートは返信なしとして扱う
+    pub max_time: Option<Duration>,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(1000),
+            retries: 2,
+            max_time: None,
+        }
+    }
+}
+
+/**
+ * 1つのホストのポートに対するプローブの状態
+ */
+struct Probe {
+    /// hostsの添字
+    host: usize,
+    port: u16,
+    /// 送信した回数
+    tries: u32,
+    last_sent: Option<Instant>,
+    result: Option<PortResult>,
+}
+
+/**
+ * ホストごとの進み具合
+ */
+pub struct HostProgress {
+    pub addr: IpAddr,
+    /// 状態が決まっていないプローブの数
+    remaining: usize,
+    /// 最初のプローブを送った時刻
+    pub started: Option<Instant>,
+    /// すべてのポートの状態が決まった時刻
+    pub finished: Option<Instant>,
+}
+
+impl HostProgress {
+    /**
+     * スキャンにかかった時間
+     */
+    pub fn duration(&self) -> Duration {
+        match (self.started, self.finished) {
+            (Some(started), Some(finished)) => finished.saturating_duration_since(started),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/**
+ * スキャン中の全プローブの状態
+ */
+pub struct Scan {
+    scan_type: ScanType,
+    config: ScanConfig,
+    hosts: Vec<HostProgress>,
+    probes: Vec<Probe>,
+    /// ホストとポートからprobesの添字
+    index: HashMap<(IpAddr, u16), usize>,
+    /// まだ一度も送っていない最初のプローブ
+    next_new: usize,
+    /// 返信待ちのプローブを送信した順に並べたもの
+    /// タイムアウトは全プローブで共通なので、変わっても先頭から順にタイムアウトする
+    in_flight: VecDeque<usize>,
+    /// 状態が決まっていないプローブの数
+    remaining: usize,
+}
+
+impl Scan {
+    /**
+     * 同じホストに続けて送らないよう、ポートごとに全ホストへ順番に送る
+     */
+    pub fn new(scan_type: ScanType, config: ScanConfig, hosts: &[IpAddr], ports: &[u16]) -> Self {
+        let probes: Vec<Probe> = ports
+            .iter()
+            .flat_map(|&port| {
+                (0..hosts.len()).map(move |host| Probe {
+                    host,
+                    port,
+                    tries: 0,
+                    last_sent: None,
+                    result: None,
+                })
+            })
+            .collect();
+        let index = probes
+            .iter()
+            .enumerate()
+            .map(|(i, probe)| ((hosts[probe.host], probe.port), i))
+            .collect();
+        let remaining = probes.len();
+        Self {
+            scan_type,
+            config,
+            hosts: hosts
+                .iter()
+                .map(|&addr| HostProgress {
+                    addr,
+                    remaining: ports.len(),
+                    started: None,
+                    finished: None,
+                })
+                .collect(),
+            probes,
+            index,
+            next_new: 0,
+            in_flight: VecDeque::new(),
+            remaining,
+        }
+    }
+
+    /**
+     * 次に送るべきホストとポートを返し、送信したものとして記録する
+     * 再送を新しいプローブより優先し、再送し尽くしたプローブは返信なしとして確定する
+     */
+    pub fn next_probe(&mut self, now: Instant) -> Option<(IpAddr, u16)> {
+        let max_tries = self.config.retries + 1;
+        while let Some(&i) = self.in_flight.front() {
+            let probe = &self.probes[i];
+            if probe.result.is_some() {
+                self.in_flight.pop_front();
+                continue;
+            }
+            let due = probe
+                .last_sent
+                .is_some_and(|sent| now.duration_since(sent) >= self.config.timeout);
+            if !due {
+                break;
+            }
+            self.in_flight.pop_front();
+            if probe.tries < max_tries {
+                return Some(self.send(i, now));
+            }
+            self.resolve_silent(i, now);
+        }
+        if self.next_new < self.probes.len() {
+            self.next_new += 1;
+            return Some(self.send(self.next_new - 1, now));
+        }
+        None
+    }
+
+    fn send(&mut self, i: usize, now: Instant) -> (IpAddr, u16) {
+        let probe = &mut self.probes[i];
+        probe.tries += 1;
+        probe.last_sent = Some(now);
+        self.in_flight.push_back(i);
+        let host = &mut self.hosts[probe.host];
+        host.started.get_or_insert(now);
+        (host.addr, probe.port)
+    }
+
+    /**
+     * プローブの状態を確定し、ホストごとの進み具合を更新する
+     */
+    fn resolve(
+        &mut self,
+        i: usize,
+        state: PortState,
+        reason: Reason,
+        ttl: Option<u8>,
+        rtt: Option<Duration>,
+        now: Instant,
+    ) {
+        let probe = &mut self.probes[i];
+        if probe.result.is_some() {
+            return;
+        }
+        probe.result = Some(PortResult {
+            port: probe.port,
+            state,
+            reason,
+            ttl,
+            rtt,
+        });
+        self.remaining -= 1;
+        let host = &mut self.hosts[probe.host];
+        host.remaining -= 1;
+        if host.remaining == 0 {
+            host.finished = Some(now);
+        }
+    }
+
+    /**
+     * nowに受け取った返信からポートの状態を決める
+     * 状態が決まった場合は、送信レートやタイムアウトの調整に使うプローブの送受信の様子を返す
+     */
+    pub fn on_reply(&mut self, reply: &Reply, now: Instant) -> Option<Answer> {
+        let state = self.scan_type.interpretation().state(reply.response)?;
+        let i = *self.index.get(&(reply.host, reply.port))?;
+        let probe = &self.probes[i];
+        if probe.result.is_some() {
+            return None;
+        }
+        let answer = match (probe.tries, probe.last_sent) {
+            (1, Some(sent)) => Answer::Timely(now.saturating_duration_since(sent)),
+            _ => Answer::AfterRetransmission,
+        };
+        let rtt = match answer {
+            Answer::Timely(rtt) => Some(rtt),
+            Answer::AfterRetransmission => None,
+        };
+        let reason = Reason::from_response(reply.response);
+        self.resolve(i, state, reason, reply.ttl, rtt, now);
+        Some(answer)
+    }
+
+    /**
+     * 返信以外の理由で分かったポートの状態を記録する
+     */
+    pub fn set_state(
+        &mut self,
+        host: IpAddr,
+        port: u16,
+        state: PortState,
+        reason: Reason,
+        now: Instant,
+    ) {
+        if let Some(&i) = self.index.get(&(host, port)) {
+            self.resolve(i, state, reason, None, None, now);
+        }
+    }
+
+    /**
+     * 返信がなかったプローブの状態を確定する
+     */
+    fn resolve_silent(&mut self, i: usize, now: Instant) {
+        let state = self.scan_type.interpretation().no_response;
+        self.resolve(i, state, Reason::NoResponse, None, None, now);
+    }
+
+    pub fn scan_type(&self) -> ScanType {
+        self.scan_type
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.config.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.config.timeout = timeout;
+    }
+
+    /**
+     * 制限時間を過ぎた場合などに、状態の決まっていないプローブをすべて返信なしとする
+     */
+    pub fn abort(&mut self, now: Instant) {
+        for i in 0..self.probes.len() {
+            self.resolve_silent(i, now);
+        }
+        // 以降は何も送らない
+        self.next_new = self.probes.len();
+        self.in_flight.clear();
+    }
+
+    pub fn finished(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /**
+     * 次の再送かタイムアウトまでの時間
+     */
+    pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        self.in_flight
+            .iter()
+            .map(|&i| &self.probes[i])
+            .find(|probe| probe.result.is_none())
+            .and_then(|probe| probe.last_sent)
+            .map(|sent| (sent + self.config.timeout).saturating_duration_since(now))
+    }
+
+    pub fn has_unsent(&self) -> bool {
+        self.next_new < self.probes.len()
+    }
+
+    pub fn deadline(&self, start: Instant) -> Option<Instant> {
+        self.config.max_time.map(|max_time| start + max_time)
+    }
+
+    pub fn hosts(&self) -> &[HostProgress] {
+        &self.hosts
+    }
+
+    /**
+     * ホストごとの、ポート番号順の結果（hosts()と同じ順番）
+     */
+    pub fn results(&self) -> Vec<Vec<PortResult>> {
+        let mut results = vec![vec![]; self.hosts.len()];
+        for probe in &self.probes {
+            if let Some(result) = probe.result {
+                results[probe.host].push(result);
+            }
+        }
+        for host in &mut results {
+            host.sort_by_key(|result| result.port);
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::tcp::TcpFlags::{ACK, FIN, RST, SYN};
+
+    const A: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+    const B: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn config(retries: u32) -> ScanConfig {
+        ScanConfig {
+            timeout: TIMEOUT,
+            retries,
+            max_time: None,
+        }
+    }
+
+    fn reply(host: IpAddr, port: u16, response: Response) -> Reply {
+        Reply {
+            host,
+            port,
+            response,
+            ttl: Some(64),
+            observation: None,
+        }
+    }
+
+    fn tcp(flags: u16) -> Response {
+        Response::Tcp { flags, window: 0 }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /**
+     * 1つのポートにプローブを送り、responseを受け取ったときの結果
+     * responseがNoneなら再送し尽くすまで返信がない
+     */
+    fn resolve_one(scan_type: ScanType, response: Option<Response>) -> PortResult {
+        let t0 = Instant::now();
+        let mut scan = Scan::new(scan_type, config(1), &[A], &[80]);
+        assert_eq!(scan.next_probe(t0), Some((A, 80)));
+        match response {
+            Some(response) => {
+                scan.on_reply(&reply(A, 80, response), t0 + ms(10));
+            }
+            None => {
+                assert_eq!(scan.next_probe(t0 + TIMEOUT), Some((A, 80)));
+                assert_eq!(scan.next_probe(t0 + TIMEOUT * 2), None);
+            }
+        }
+        assert!(scan.finished());
+        scan.results()[0][0]
+    }
+
+    #[test]
+    fn sends_each_port_to_all_hosts_in_turn() {
+        let t0 = Instant::now();
+        let mut scan = Scan::new(ScanType::Syn, config(0), &[A, B], &[80, 443]);
+        let sent: Vec<_> = (0..4).map_while(|_| scan.next_probe(t0)).collect();
+        assert_eq!(sent, [(A, 80), (B, 80), (A, 443), (B, 443)]);
+        assert!(!scan.has_unsent());
+        // タイムアウトするまでは送るものがない
+        assert_eq!(scan.next_probe(t0 + ms(999)), None);
+        assert_eq!(scan.next_timeout(t0 + ms(400)), Some(ms(600)));
+        assert!(!scan.finished());
+        assert_eq!(scan.hosts()[0].started, Some(t0));
+    }
+
+    #[test]
+    fn retransmits_until_retries_run_out() {
+        let t0 = Instant::now();
+        let mut scan = Scan::new(ScanType::Syn, config(2), &[A], &[80]);
+        assert_eq!(scan.next_probe(t0), Some((A, 80)));
+        assert_eq!(scan.next_probe(t0 + TIMEOUT), Some((A, 80)));
+        assert_eq!(scan.next_timeout(t0 + TIMEOUT), Some(TIMEOUT));
+        assert_eq!(scan.next_probe(t0 + TIMEOUT * 2), Some((A, 80)));
+        assert!(!scan.finished());
+        // 3回送っても返信がないので返信なしとする
+        assert_eq!(scan.next_probe(t0 + TIMEOUT * 3), None);
+        assert!(scan.finished());
+        assert_eq!(scan.next_timeout(t0 + TIMEOUT * 3), None);
+
+        let result = scan.results()[0][0];
+        assert_eq!(result.state, PortState::Filtered);
+        assert_eq!(result.reason, Reason::NoResponse);
+        assert_eq!(result.rtt, None);
+        let host = &scan.hosts()[0];
+        assert_eq!(host.finished, Some(t0 + TIMEOUT * 3));
+        assert_eq!(host.duration(), TIMEOUT * 3);
+    }
+
+    #[test]
+    fn retransmissions_come_before_new_probes() {
+        let t0 = Instant::now();
+        let mut scan = Scan::new(ScanType::Syn, config(1), &[A], &[80, 443]);
+        assert_eq!(scan.next_probe(t0), Some((A, 80)));
+        assert_eq!(scan.next_probe(t0 + TIMEOUT), Some((A, 80)));
+        assert_eq!(scan.next_probe(t0 + TIMEOUT), Some((A, 443)));
+    }
+
+    #[test]
+    fn timely_reply_measures_rtt() {
+        let t0 = Instant::now();
+        let mut scan = Scan::new(ScanType::Syn, config(2), &[A], &[80]);
+        scan.next_probe(t0);
+        let answer = scan.on_reply(&reply(A, 80, tcp(SYN | ACK)), t0 + ms(30));
+        assert!(matches!(answer, Some(Answer::Timely(rtt)) if rtt == ms(30)));
+        let result = scan.results()[0][0];
+        assert_eq!(result.rtt, Some(ms(30)));
+        assert_eq!(result.ttl, Some(64));
+        assert!(scan.finished());
+        // 状態が決まった後の返信は使わない
+        assert!(scan
+            .on_reply(&reply(A, 80, tcp(RST)), t0 + ms(40))
+            .is_none());
+        assert_eq!(scan.results()[0][0].state, PortState::Open);
+    }
+
+    #[test]
+    fn reply_after_retransmission_has_no_rtt() {
+        let t0 = Instant::now();
+        let mut scan = Scan::new(ScanType::Syn, config(2), &[A], &[80]);
+        scan.next_probe(t0);
+        scan.next_probe(t0 + TIMEOUT);
+        let answer = scan.on_reply(&reply(A, 80, tcp(RST | ACK)), t0 + TIMEOUT + ms(5));
+        assert!(matches!(answer, Some(Answer::AfterRetransmission)));
+        assert_eq!(scan.results()[0][0].rtt, None);
+        assert_eq!(scan.results()[0][0].state, PortState::Closed);
+    }
+
+    #[test]
+    fn ignores_unrelated_replies() {
+        let t0 = Instant::now();
+        let mut scan = Scan::new(ScanType::Syn, config(0), &[A], &[80]);
+        scan.next_probe(t0);
+        assert!(scan.on_reply(&reply(B, 80, tcp(SYN | ACK)), t0).is_none());
+        assert!(scan.on_reply(&reply(A, 81, tcp(SYN | ACK)), t0).is_none());
+        // SYNスキャンではSYN/ACKとRST以外のTCPは判断に使わない
+        assert!(scan.on_reply(&reply(A, 80, tcp(ACK)), t0).is_none());
+        assert!(scan
+            .on_reply(&reply(A, 80, Response::SourceQuench), t0)
+            .is_none());
+        assert!(!scan.finished());
+    }
+
+    #[test]
+    fn classifies_port_states() {
+        let unreachable = |kind| Some(Response::Unreachable(kind));
+        let cases = [
+            (
+                ScanType::Syn,
+                Some(tcp(SYN | ACK)),
+                PortState::Open,
+                Reason::SynAck,
+            ),
+            (
+                ScanType::Syn,
+                Some(tcp(RST | ACK)),
+                PortState::Closed,
+                Reason::Reset,
+            ),
+            (ScanType::Syn, None, PortState::Filtered, Reason::NoResponse),
+            (
+                ScanType::Syn,
+                unreachable(Unreachable::Prohibited),
+                PortState::Filtered,
+                Reason::AdminProhibited,
+            ),
+            (
+                ScanType::Fin,
+                Some(tcp(RST)),
+                PortState::Closed,
+                Reason::Reset,
+            ),
+            (
+                ScanType::Fin,
+                None,
+                PortState::OpenFiltered,
+                Reason::NoResponse,
+            ),
+            (
+                ScanType::Udp,
+                Some(Response::Udp),
+                PortState::Open,
+                Reason::UdpResponse,
+            ),
+            (
+                ScanType::Udp,
+                unreachable(Unreachable::Port),
+                PortState::Closed,
+                Reason::PortUnreach,
+            ),
+            (
+                ScanType::Udp,
+                unreachable(Unreachable::Host),
+                PortState::Filtered,
+                Reason::HostUnreach,
+            ),
+            (
+                ScanType::Udp,
+                None,
+                PortState::OpenFiltered,
+                Reason::NoResponse,
+            ),
+        ];
+        for (scan_type, response, state, reason) in cases {
+            let result = resolve_one(scan_type, response);
+            assert_eq!(
+                (result.state, result.reason),
+                (state, reason),
+                "{:?} {:?}",
+                scan_type,
+                response
+            );
+        }
+    }
+
+    #[test]
+    fn classifies_ack_window_and_maimon() {
+        let rst = |window| Some(Response::Tcp { flags: RST, window });
+        let unreachable = Some(Response::Unreachable(Unreachable::Port));
+        let cases = [
+            (ScanType::Ack, rst(0), PortState::Unfiltered, Reason::Reset),
+            (
+                ScanType::Ack,
+                rst(1024),
+                PortState::Unfiltered,
+                Reason::Reset,
+            ),
+            (ScanType::Ack, None, PortState::Filtered, Reason::NoResponse),
+            (
+                ScanType::Ack,
+                unreachable,
+                PortState::Filtered,
+                Reason::PortUnreach,
+            ),
+            // Windowスキャンでは、RSTのウィンドウサイズが0でなければ開いているとみなす
+            (ScanType::Window, rst(0), PortState::Closed, Reason::Reset),
+            (ScanType::Window, rst(1024), PortState::Open, Reason::Reset),
+            (
+                ScanType::Window,
+                None,
+                PortState::Filtered,
+                Reason::NoResponse,
+            ),
+            (
+                ScanType::Window,
+                unreachable,
+                PortState::Filtered,
+                Reason::PortUnreach,
+            ),
+            (ScanType::Maimon, rst(0), PortState::Closed, Reason::Reset),
+            (
+                ScanType::Maimon,
+                rst(1024),
+                PortState::Closed,
+                Reason::Reset,
+            ),
+            (
+                ScanType::Maimon,
+                None,
+                PortState::OpenFiltered,
+                Reason::NoResponse,
+            ),
+            (
+                ScanType::Maimon,
+                unreachable,
+                PortState::Filtered,
+                Reason::PortUnreach,
+            ),
+        ];
+        for (scan_type, response, state, reason) in cases {
+            let result = resolve_one(scan_type, response);
+            assert_eq!(
+                (result.state, result.reason),
+                (state, reason),
+                "{:?} {:?}",
+                scan_type,
+                response
+            );
+        }
+    }
+
+    #[test]
+    fn probe_flags_and_unusable_replies() {
+        assert_eq!(ScanType::Ack.interpretation().flags, ACK);
+        assert_eq!(ScanType::Window.interpretation().flags, ACK);
+        assert_eq!(ScanType::Maimon.interpretation().flags, FIN | ACK);
+        // SYN/ACKはACK・Window・Maimonの判断には使わない
+        for scan_type in [ScanType::Ack, ScanType::Window, ScanType::Maimon] {
+            assert_eq!(scan_type.interpretation().state(tcp(SYN | ACK)), None);
+        }
+        // --scanflagsで変えるのはフラグだけで、返信の解釈はスキャン方法のまま
+        let mut interpretation = ScanType::Window.interpretation();
+        interpretation.flags = SYN | FIN;
+        let state = |window| interpretation.state(Response::Tcp { flags: RST, window });
+        assert_eq!(state(0), Some(PortState::Closed));
+        assert_eq!(state(1024), Some(PortState::Open));
+    }
+
+    #[test]
+    fn abort_resolves_the_rest_as_silent() {
+        let t0 = Instant::now();
+        let mut scan = Scan::new(ScanType::Fin, config(2), &[A, B], &[22]);
+        scan.next_probe(t0);
+        scan.on_reply(&reply(A, 22, tcp(RST | ACK)), t0 + ms(1));
+        // Bにはまだ送っていない
+        scan.abort(t0 + ms(2));
+        assert!(scan.finished());
+        assert_eq!(scan.next_probe(t0 + TIMEOUT), None);
+        let results = scan.results();
+        assert_eq!(results[0][0].state, PortState::Closed);
+        assert_eq!(results[1][0].state, PortState::OpenFiltered);
+        assert_eq!(results[1][0].reason, Reason::NoResponse);
+        assert_eq!(scan.hosts()[1].finished, Some(t0 + ms(2)));
+    }
+
+    #[test]
+    fn deadline_comes_from_max_time() {
+        let t0 = Instant::now();
+        let scan = Scan::new(ScanType::Syn, config(0), &[A], &[80]);
+        assert_eq!(scan.deadline(t0), None);
+        let config = ScanConfig {
+            max_time: Some(Duration::from_secs(5)),
+            ..config(0)
+        };
+        let scan = Scan::new(ScanType::Syn, config, &[A], &[80]);
+        assert_eq!(scan.deadline(t0), Some(t0 + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn new_timeout_applies_to_probes_in_flight() {
+        let t0 = Instant::now();
+        let mut scan = Scan::new(ScanType::Syn, config(1), &[A], &[80]);
+        scan.next_probe(t0);
+        scan.set_timeout(ms(200));
+        assert_eq!(scan.timeout(), ms(200));
+        assert_eq!(scan.next_timeout(t0 + ms(50)), Some(ms(150)));
+        assert_eq!(scan.next_probe(t0 + ms(200)), Some((A, 80)));
+    }
+
+    #[test]
+    fn set_state_resolves_without_a_reply() {
+        let t0 = Instant::now();
+        let mut scan = Scan::new(ScanType::Connect, config(1), &[A], &[80, 81]);
+        scan.next_probe(t0);
+        scan.set_state(A, 80, PortState::Filtered, Reason::Error, t0 + ms(1));
+        // スキャンしていないホストとポートは無視する
+        scan.set_state(B, 80, PortState::Open, Reason::SynAck, t0 + ms(1));
+        let results = scan.results();
+        assert_eq!(results[0].len(), 1);
+        assert_eq!(results[0][0].reason, Reason::Error);
+        assert!(!scan.finished());
+    }
+}
@@ -0,0 +1,683 @@
+use anyhow::{anyhow, Context, Result};
+use log::debug;
+use regex::bytes::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{ErrorKind, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// 組み込みのシグネチャ。--version-dbで別のファイルを指定できる
+const DEFAULT_SIGNATURES: &str = include_str!("../service-signatures");
+/// 読み込む応答の最大サイズ
+const MAX_RESPONSE: usize = 4096;
+/// 応答の一部を受け取った後、続きを待つ時間
+const REST_TIMEOUT: Duration = Duration::from_millis(200);
+/// バナーとして表示する最大の文字数
+const MAX_BANNER: usize = 40;
+
+/**
+ * バージョン検出で送るプローブ
+ */
+struct Probe {
+    name: &'static str,
+    /// 空の場合は何も送らずにサーバからの挨拶を待つ
+    payload: Vec<u8>,
+}
+
+/**
+ * プローブを送る順番に並べたもの
+ * 挨拶を送ってくるサービス（SSH・SMTP・FTPなど）はNULLで分かるので、先に試す
+ */
+fn probes() -> Vec<Probe> {
+    vec![
+        Probe {
+            name: "NULL",
+            payload: vec![],
+        },
+        Probe {
+            name: "HTTP",
+            payload: b"HEAD / HTTP/1.0\r\n\r\n".to_vec(),
+        },
+        Probe {
+            name: "SSH",
+            payload: b"SSH-2.0-ch3-port-scanner\r\n".to_vec(),
+        },
+        Probe {
+            name: "TLS",
+            payload: tls_client_hello(),
+        },
+    ]
+}
+
+/**
+ * TLS 1.2のClientHello
+ * サーバがServerHelloかアラートを返せばTLSだと分かるので、拡張は付けない
+ */
+fn tls_client_hello() -> Vec<u8> {
+    let cipher_suites: [u16; 6] = [0xc02f, 0xc030, 0xc02b, 0xc02c, 0x009c, 0x002f];
+    let mut hello = vec![0x03, 0x03]; // バージョン（TLS 1.2）
+    hello.extend_from_slice(&[0x5a; 32]); // 乱数
+    hello.push(0); // セッションIDの長さ
+    hello.extend_from_slice(&((cipher_suites.len() * 2) as u16).to_be_bytes());
+    for suite in cipher_suites {
+        hello.extend_from_slice(&suite.to_be_bytes());
+    }
+    hello.extend_from_slice(&[1, 0]); // 圧縮方式（なし）
+
+    let mut handshake = vec![0x01]; // ClientHello
+    handshake.extend_from_slice(&(hello.len() as u32).to_be_bytes()[1..]);
+    handshake.extend_from_slice(&hello);
+
+    let mut record = vec![0x16, 0x03, 0x01]; // ハンドシェイクのレコード
+    record.extend_from_slice(&(handshake.len() as u16).to_be_bytes());
+    record.extend_from_slice(&handshake);
+    record
+}
+
+/**
+ * 応答とサービスを対応付ける1行
+ */
+struct Signature {
+    /// どのプローブの応答に使うか。"*"はすべてのプローブ
+    probe: String,
+    service: String,
+    pattern: Regex,
+    /// $1などをキャプチャで置き換えてバージョンとする
+    version: Option<String>,
+}
+
+/**
+ * シグネチャファイルの内容
+ * 1行に1つ、次の形式で書く。上にあるものから順に試す
+ *   match <プローブ|*> <サービス> m|正規表現|[is] [v|バージョン|]
+ * 区切り文字は|以外の記号でもよい
+ */
+pub struct SignatureDb {
+    signatures: Vec<Signature>,
+}
+
+impl SignatureDb {
+    /**
+     * シグネチャファイルを読み込む。指定がなければ組み込みのものを使う
+     */
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        match path {
+            Some(path) => {
+                let contents =
+                    fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+                Self::parse(&contents).with_context(|| path.to_string())
+            }
+            None => Self::parse(DEFAULT_SIGNATURES),
+        }
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let mut signatures = vec![];
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let signature =
+                parse_signature(line).with_context(|| format!("line {}: {}", i + 1, line))?;
+            signatures.push(signature);
+        }
+        Ok(Self { signatures })
+    }
+
+    /**
+     * プローブへの応答に一致する最初のシグネチャからサービスを決める
+     */
+    fn identify(&self, probe: &str, response: &[u8]) -> Option<Service> {
+        self.signatures
+            .iter()
+            .filter(|signature| signature.probe == "*" || signature.probe == probe)
+            .find_map(|signature| {
+                let captures = signature.pattern.captures(response)?;
+                let version = signature
+                    .version
+                    .as_deref()
+                    .map(|template| expand(template, &captures))
+                    .filter(|version| !version.is_empty());
+                Some(Service {
+                    name: signature.service.clone(),
+                    version,
+                })
+            })
+    }
+}
+
+fn parse_signature(line: &str) -> Result<Signature> {
+    let mut fields = line.splitn(4, char::is_whitespace);
+    if fields.next() != Some("match") {
+        return Err(anyhow!("expected match"));
+    }
+    let probe = fields.next().context("missing probe")?.to_string();
+    let service = fields.next().context("missing service")?.to_string();
+    let rest = fields.next().context("missing pattern")?.trim_start();
+
+    let (pattern, rest) = delimited(rest, 'm').context("invalid pattern")?;
+    let flags: String = rest.chars().take_while(|c| !c.is_whitespace()).collect();
+    let rest = rest[flags.len()..].trim_start();
+    let mut builder = RegexBuilder::new(pattern);
+    // 応答はバイナリのこともあるので、バイト単位で一致させる
+    builder.unicode(false);
+    for flag in flags.chars() {
+        match flag {
+            'i' => builder.case_insensitive(true),
+            's' => builder.dot_matches_new_line(true),
+            _ => return Err(anyhow!("unknown flag {}", flag)),
+        };
+    }
+    let pattern = builder.build()?;
+
+    let version = if rest.is_empty() {
+        None
+    } else {
+        let (version, rest) = delimited(rest, 'v').context("invalid version")?;
+        if !rest.trim().is_empty() {
+            return Err(anyhow!("unexpected {}", rest.trim()));
+        }
+        Some(version.to_string())
+    };
+    Ok(Signature {
+        probe,
+        service,
+        pattern,
+        version,
+    })
+}
+
+/**
+ * "m|...|"のような、先頭の文字と区切り文字で囲まれた部分を取り出す
+ * 囲まれた部分と、閉じた区切り文字より後ろを返す
+ */
+fn delimited(s: &str, prefix: char) -> Option<(&str, &str)> {
+    let s = s.strip_prefix(prefix)?;
+    let delimiter = s.chars().next()?;
+    let s = &s[delimiter.len_utf8()..];
+    let end = s.find(delimiter)?;
+    Some((&s[..end], &s[end + delimiter.len_utf8()..]))
+}
+
+/**
+ * テンプレートの$1から$9をキャプチャで置き換える
+ */
+fn expand(template: &str, captures: &regex::bytes::Captures) -> String {
+    let mut expanded = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        let group = match chars.peek().and_then(|next| next.to_digit(10)) {
+            Some(group) if c == '$' => group,
+            _ => {
+                expanded.push(c);
+                continue;
+            }
+        };
+        chars.next();
+        if let Some(capture) = captures.get(group as usize) {
+            expanded.push_str(&printable(capture.as_bytes()));
+        }
+    }
+    expanded.trim().to_string()
+}
+
+/**
+ * 表示できない文字を\xNNにする
+ */
+fn printable(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b' '..=b'~' => (b as char).to_string(),
+            b'\r' => "\\r".to_string(),
+            b'\n' => "\\n".to_string(),
+            _ => format!("\\x{:02x}", b),
+        })
+        .collect()
+}
+
+/**
+ * 検出したサービス
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Service {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+/**
+ * 開いているポートに接続し、プローブを順に送ってサービスを調べる
+ * どのシグネチャにも一致しない場合は、最初に受け取った応答をバナーとして表示する
+ */
+pub fn detect(db: &SignatureDb, addr: SocketAddr, timeout: Duration) -> Option<Service> {
+    let mut banner: Option<Vec<u8>> = None;
+    for probe in probes() {
+        let response = match exchange(addr, &probe.payload, timeout) {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("{}: {} probe failed: {}", addr, probe.name, e);
+                continue;
+            }
+        };
+        if response.is_empty() {
+            continue;
+        }
+        // 送ったものをそのまま返してくる
+        if response == probe.payload {
+            return Some(Service {
+                name: "echo".to_string(),
+                version: None,
+            });
+        }
+        if let Some(service) = db.identify(probe.name, &response) {
+            return Some(service);
+        }
+        banner.get_or_insert(response);
+    }
+    banner.map(|banner| {
+        let mut banner = printable(&banner);
+        if banner.len() > MAX_BANNER {
+            banner.truncate(MAX_BANNER);
+            banner.push_str("...");
+        }
+        Service {
+            name: "unknown".to_string(),
+            version: Some(format!("banner \"{}\"", banner)),
+        }
+    })
+}
+
+/**
+ * 接続してプローブを送り、応答を読む
+ * 一部を受け取ったら、続きは短い時間だけ待つ
+ */
+fn exchange(addr: SocketAddr, payload: &[u8], timeout: Duration) -> Result<Vec<u8>> {
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    if !payload.is_empty() {
+        stream.write_all(payload)?;
+    }
+    let deadline = Instant::now() + timeout;
+    let mut response = vec![];
+    let mut buffer = [0u8; 1024];
+    while response.len() < MAX_RESPONSE {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        let wait = if response.is_empty() {
+            deadline - now
+        } else {
+            REST_TIMEOUT.min(deadline - now)
+        };
+        stream.set_read_timeout(Some(wait))?;
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buffer[..n]),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => break,
+            // 応答の後に切断された場合は受け取った分を使う
+            Err(e) if !response.is_empty() => {
+                debug!("{}: {}", addr, e);
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(response)
+}
+
+/**
+ * 開いているポートのサービスを、最大parallelism個ずつ並行して調べる
+ */
+pub fn detect_all(
+    db: &SignatureDb,
+    open_ports: &[(IpAddr, u16)],
+    timeout: Duration,
+    parallelism: usize,
+) -> HashMap<(IpAddr, u16), Service> {
+    let mut services = HashMap::new();
+    for chunk in open_ports.chunks(parallelism) {
+        thread::scope(|s| {
+            let handles: Vec<_> = chunk
+                .iter()
+                .map(|&(host, port)| {
+                    let handle =
+                        s.spawn(move || detect(db, SocketAddr::from((host, port)), timeout));
+                    ((host, port), handle)
+                })
+                .collect();
+            for (key, handle) in handles {
+                if let Ok(Some(service)) = handle.join() {
+                    services.insert(key, service);
+                }
+            }
+        });
+    }
+    services
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        net::{Ipv4Addr, TcpListener},
+        path::Path,
+        process::{Child, Command, Stdio},
+    };
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+    /// 別の章のサーバをビルドして起動するまで待つ時間
+    const SERVER_STARTUP: Duration = Duration::from_secs(120);
+
+    fn db(contents: &str) -> SignatureDb {
+        SignatureDb::parse(contents).unwrap()
+    }
+
+    fn identify(probe: &str, response: &[u8]) -> Option<(String, Option<String>)> {
+        SignatureDb::load(None)
+            .unwrap()
+            .identify(probe, response)
+            .map(|service| (service.name, service.version))
+    }
+
+    fn service(name: &str, version: Option<&str>) -> Option<(String, Option<String>)> {
+        Some((name.to_string(), version.map(str::to_string)))
+    }
+
+    fn parse_error(line: &str) -> String {
+        format!("{:#}", SignatureDb::parse(line).err().unwrap())
+    }
+
+    #[test]
+    fn builtin_signatures_are_parsed() {
+        let db = SignatureDb::load(None).unwrap();
+        assert_eq!(db.signatures.len(), 12);
+        let ssh = &db.signatures[0];
+        assert_eq!(ssh.probe, "*");
+        assert_eq!(ssh.service, "ssh");
+        assert_eq!(ssh.pattern.as_str(), r"^SSH-([\d.]+)-([^\s]+)");
+        assert_eq!(ssh.version.as_deref(), Some("$2 (protocol $1)"));
+        let ssl = db.signatures.last().unwrap();
+        assert_eq!(ssl.probe, "TLS");
+        assert_eq!(ssl.version, None);
+    }
+
+    #[test]
+    fn signature_syntax() {
+        let db = db("# comment\n\n  match HTTP web m#^a|b#i v#x $1#  \nmatch NULL x m/./s\n");
+        assert_eq!(db.signatures.len(), 2);
+        assert_eq!(db.signatures[0].pattern.as_str(), "^a|b");
+        assert_eq!(db.signatures[0].version.as_deref(), Some("x $1"));
+        assert!(db.signatures[0].pattern.is_match(b"B"));
+        assert!(db.signatures[1].pattern.is_match(b"\n"));
+
+        assert_eq!(
+            parse_error("# comment\nmatches * x m|a|"),
+            "line 2: matches * x m|a|: expected match"
+        );
+        assert_eq!(parse_error("match"), "line 1: match: missing probe");
+        assert_eq!(parse_error("match *"), "line 1: match *: missing service");
+        assert_eq!(
+            parse_error("match * x"),
+            "line 1: match * x: missing pattern"
+        );
+        assert_eq!(
+            parse_error("match * x |a|"),
+            "line 1: match * x |a|: invalid pattern"
+        );
+        assert_eq!(
+            parse_error("match * x m|a"),
+            "line 1: match * x m|a: invalid pattern"
+        );
+        assert_eq!(
+            parse_error("match * x m|a|g"),
+            "line 1: match * x m|a|g: unknown flag g"
+        );
+        assert_eq!(
+            parse_error("match * x m|a| w|1|"),
+            "line 1: match * x m|a| w|1|: invalid version"
+        );
+        assert_eq!(
+            parse_error("match * x m|a| v|1| extra"),
+            "line 1: match * x m|a| v|1| extra: unexpected extra"
+        );
+        assert!(parse_error("match * x m|(a|").starts_with("line 1: match * x m|(a|: "));
+    }
+
+    #[test]
+    fn signature_file_errors_name_the_file() {
+        let error = SignatureDb::load(Some("/nonexistent/signatures"))
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "Failed to read /nonexistent/signatures");
+    }
+
+    #[test]
+    fn builtin_signatures_identify_services() {
+        assert_eq!(
+            identify("NULL", b"SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.1\r\n"),
+            service("ssh", Some("OpenSSH_8.9p1 (protocol 2.0)"))
+        );
+        // SSHのシグネチャはすべてのプローブの応答に使う
+        assert_eq!(
+            identify("HTTP", b"SSH-1.99-Cisco-1.25\n"),
+            service("ssh", Some("Cisco-1.25 (protocol 1.99)"))
+        );
+        assert_eq!(
+            identify("NULL", b"220 mail.example.com ESMTP Postfix\r\n"),
+            service("smtp", Some("mail.example.com ESMTP Postfix"))
+        );
+        assert_eq!(
+            identify("NULL", b"220 (vsFTPd 3.0.3)\r\n"),
+            service("ftp", Some("(vsFTPd 3.0.3)"))
+        );
+        // smtpもftpも含まない挨拶は、汎用のftpのシグネチャに一致する
+        assert_eq!(
+            identify("NULL", b"220 Welcome\r\n"),
+            service("ftp", Some("Welcome"))
+        );
+        assert_eq!(
+            identify("NULL", b"+OK Dovecot ready.\r\n"),
+            service("pop3", Some("Dovecot ready."))
+        );
+        assert_eq!(
+            identify("NULL", b"* OK [CAPABILITY IMAP4rev1] ready\r\n"),
+            service("imap", Some("[CAPABILITY IMAP4rev1] ready"))
+        );
+        assert_eq!(
+            identify("NULL", b"J\x00\x00\x00\x0a5.7.33-log\x00\x0b\x00\x00\x00"),
+            service("mysql", Some("5.7.33-log"))
+        );
+        assert_eq!(
+            identify("NULL", b"RFB 003.008\n"),
+            service("vnc", Some("protocol 003.008"))
+        );
+        // 挨拶のシグネチャは、NULL以外のプローブの応答には使わない
+        assert_eq!(identify("HTTP", b"220 Welcome\r\n"), None);
+        assert_eq!(identify("NULL", b"hello\r\n"), None);
+    }
+
+    #[test]
+    fn builtin_signatures_identify_http_and_tls() {
+        assert_eq!(
+            identify(
+                "HTTP",
+                b"HTTP/1.1 200 OK\r\nDate: Sun, 18 Oct 2026 00:00:00 GMT\r\nSERVER:  nginx/1.18.0\r\n\r\n"
+            ),
+            service("http", Some("nginx/1.18.0"))
+        );
+        assert_eq!(
+            identify(
+                "TLS",
+                b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n"
+            ),
+            service("http", None)
+        );
+        assert_eq!(identify("HTTP", b"HTTP/2 200\r\n"), None);
+
+        assert_eq!(
+            identify("TLS", b"\x16\x03\x03\x00\x51\x02\x00\x00\x4d\x03\x03"),
+            service("ssl", None)
+        );
+        assert_eq!(
+            identify("TLS", b"\x15\x03\x03\x00\x02\x02\x28"),
+            service("ssl", None)
+        );
+        assert_eq!(identify("HTTP", b"\x15\x03\x03\x00\x02\x02\x28"), None);
+    }
+
+    #[test]
+    fn first_matching_signature_wins() {
+        let db = db("match NULL a m|^x(y)?| v|$1|\nmatch * b m|^x|\nmatch HTTP c m|.|");
+        let name = |probe, response: &[u8]| {
+            db.identify(probe, response)
+                .map(|service| (service.name, service.version))
+        };
+        // キャプチャが空ならバージョンは付けない
+        assert_eq!(name("NULL", b"x"), service("a", None));
+        assert_eq!(name("NULL", b"xy"), service("a", Some("y")));
+        assert_eq!(name("HTTP", b"x"), service("b", None));
+        assert_eq!(name("HTTP", b"z"), service("c", None));
+        assert_eq!(name("NULL", b"z"), None);
+    }
+
+    #[test]
+    fn expand_replaces_captures() {
+        let regex = Regex::new(r"(?s-u)^(\w+) (\w+)?(.*)").unwrap();
+        let captures = regex.captures(b"one  \x01\r\n").unwrap();
+        assert_eq!(expand("$1", &captures), "one");
+        assert_eq!(expand("v$1-$1", &captures), "vone-one");
+        // 一致しなかったグループと存在しないグループは空になる
+        assert_eq!(expand("[$2][$9]", &captures), "[][]");
+        assert_eq!(expand("$3", &captures), "\\x01\\r\\n");
+        // $の後に数字がなければそのまま
+        assert_eq!(expand("$ $x $$1", &captures), "$ $x $one");
+        assert_eq!(expand("  $1 $2 ", &captures), "one");
+        assert_eq!(expand("", &captures), "");
+    }
+
+    #[test]
+    fn delimited_takes_any_delimiter() {
+        assert_eq!(delimited("m|a b| rest", 'm'), Some(("a b", " rest")));
+        assert_eq!(delimited("m=a|b=i", 'm'), Some(("a|b", "i")));
+        assert_eq!(delimited("v§x§", 'v'), Some(("x", "")));
+        assert_eq!(delimited("v|x", 'v'), None);
+        assert_eq!(delimited("m", 'm'), None);
+        assert_eq!(delimited("x|a|", 'm'), None);
+    }
+
+    /**
+     * 他の章のサーバをcargo runで起動し、テストが終わったら止める
+     */
+    struct Server(Child);
+
+    impl Server {
+        fn start(chapter: &str, args: &[&str], addr: SocketAddr) -> Self {
+            let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("..")
+                .join(chapter);
+            let child = Command::new(env!("CARGO"))
+                .args(["run", "--quiet", "--manifest-path"])
+                .arg(dir.join("Cargo.toml"))
+                .arg("--")
+                .args(args)
+                .current_dir(&dir)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap();
+            let mut server = Self(child);
+            let started = Instant::now();
+            while TcpStream::connect(addr).is_err() {
+                if let Some(status) = server.0.try_wait().unwrap() {
+                    panic!("{} exited with {}", chapter, status);
+                }
+                assert!(
+                    started.elapsed() < SERVER_STARTUP,
+                    "{} did not start",
+                    chapter
+                );
+                thread::sleep(Duration::from_millis(100));
+            }
+            server
+        }
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn free_addr() -> SocketAddr {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[test]
+    fn detects_ch4_web_server() {
+        let addr = free_addr();
+        let _server = Server::start("ch4-mio-webserver", &[&addr.to_string()], addr);
+        let db = SignatureDb::load(None).unwrap();
+        let found = detect(&db, addr, TIMEOUT).unwrap();
+        assert_eq!(found.name, "http");
+        assert_eq!(found.version.as_deref(), Some("mio webserver"));
+    }
+
+    #[test]
+    fn detects_ch1_echo_server() {
+        let addr = free_addr();
+        let _server = Server::start(
+            "ch1-socket-programming",
+            &["tcp", "server", &addr.to_string()],
+            addr,
+        );
+        let db = SignatureDb::load(None).unwrap();
+        let found = detect(&db, addr, TIMEOUT).unwrap();
+        assert_eq!(found.name, "echo");
+        assert_eq!(found.version, None);
+    }
+
+    #[test]
+    fn unknown_greeting_is_shown_as_banner() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                stream
+                    .write_all(b"hello\x01 from a service nobody has heard of\r\n")
+                    .unwrap();
+            }
+        });
+        let db = SignatureDb::load(None).unwrap();
+        let found = detect(&db, addr, TIMEOUT).unwrap();
+        assert_eq!(found.name, "unknown");
+        assert_eq!(
+            found.version.as_deref(),
+            Some("banner \"hello\\x01 from a service nobody has hear...\"")
+        );
+    }
+
+    #[test]
+    fn silent_and_closed_ports_are_not_detected() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let silent = listener.local_addr().unwrap();
+        let closed = free_addr();
+        let db = SignatureDb::load(None).unwrap();
+        let services = detect_all(
+            &db,
+            &[(silent.ip(), silent.port()), (closed.ip(), closed.port())],
+            Duration::from_millis(100),
+            2,
+        );
+        assert!(services.is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use rand::Rng;
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket},
+    ops::RangeInclusive,
+};
+
+/// 送信元ポートに使う範囲（Linuxのエフェメラルポートと同じ）
+const EPHEMERAL_PORTS: RangeInclusive<u16> = 32768..=60999;
+
+/**
+ * 宛先への経路で使われる送信元アドレスを経路表から選ぶ
+ * UDPソケットをconnectするとカーネルが経路を決めるので、パケットは送らずにそのアドレスを読む
+ */
+pub fn route_source(destination: IpAddr) -> Result<IpAddr> {
+    let unspecified = match destination {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind((unspecified, 0))?;
+    // ポート番号は経路の選択に関係しない
+    socket
+        .connect((destination, 9))
+        .with_context(|| format!("No route to {}", destination))?;
+    Ok(socket.local_addr()?.ip())
+}
+
+/**
+ * スキャン全体で使う送信元ポートをランダムに選ぶ
+ */
+pub fn random_port() -> u16 {
+    rand::thread_rng().gen_range(EPHEMERAL_PORTS)
+}
+
+/**
+ * 送信元ポートの選び方
+ */
+#[derive(Debug, Clone, Copy)]
+pub enum SourcePort {
+    /// すべてのプローブで同じポートを使う
+    Fixed(u16),
+    /// プローブごとにポートを変える。ポートは宛先から計算するので、覚えておかなくても返信を照合できる
+    PerProbe,
+}
+
+/**
+ * プローブごとの値を秘密の鍵から計算する
+ * 送ったプローブを覚えておかなくても、返信の値を計算し直せば自分のプローブへの返信か分かる
+ */
+#[derive(Clone, Default)]
+pub struct Cookies {
+    /// プロセスごとにランダムな鍵を持つハッシュ関数
+    key: RandomState,
+}
+
+impl Cookies {
+    fn hash(&self, host: IpAddr, port: u16, my_port: Option<u16>) -> u64 {
+        self.key.hash_one((host, port, my_port))
+    }
+
+    /**
+     * TCPのプローブのシーケンス番号
+     */
+    pub fn seq(&self, host: IpAddr, port: u16, my_port: u16) -> u32 {
+        self.hash(host, port, Some(my_port)) as u32
+    }
+
+    /**
+     * プローブごとに変える場合の送信元ポート
+     */
+    pub fn source_port(&self, host: IpAddr, port: u16) -> u16 {
+        let start = *EPHEMERAL_PORTS.start();
+        let len = (EPHEMERAL_PORTS.end() - start) as u64 + 1;
+        start + (self.hash(host, port, None) % len) as u16
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::{anyhow, Context, Result};
+use ipnetwork::IpNetwork;
+use std::{
+    collections::HashSet,
+    fs,
+    net::{IpAddr, ToSocketAddrs},
+};
+
+/// 1回のスキャンで対象にできるホスト数の上限
+const MAX_HOSTS: usize = 65536;
+/// MAX_HOSTSに収まるネットワークの最小のプレフィックス長
+const MIN_IPV4_PREFIX: u8 = 16;
+const MIN_IPV6_PREFIX: u8 = 112;
+
+/**
+ * スキャン対象のホスト
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub addr: IpAddr,
+    /// ホスト名で指定した場合の名前
+    pub name: Option<String>,
+}
+
+/**
+ * 対象の指定を解析してホストの一覧にする
+ * IPアドレス、CIDR（10.0.0.0/24、2001:db8::/120）、ホスト名をカンマ区切りで複数指定できる
+ * 同じアドレスは最初の1つだけ残す
+ */
+pub fn resolve(specs: &[String]) -> Result<Vec<Target>> {
+    let mut targets = vec![];
+    let mut seen = HashSet::new();
+    for spec in specs.iter().flat_map(|spec| spec.split(',')) {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            continue;
+        }
+        for target in parse_target(spec)? {
+            if seen.insert(target.addr) {
+                targets.push(target);
+            }
+            if targets.len() > MAX_HOSTS {
+                return Err(anyhow!("Too many targets (max {})", MAX_HOSTS));
+            }
+        }
+    }
+    if targets.is_empty() {
+        return Err(anyhow!("No target to scan"));
+    }
+    Ok(targets)
+}
+
+/**
+ * ファイルから対象の指定を読む
+ * 空白か改行で区切り、#以降はコメントとして扱う
+ */
+pub fn read_file(path: &str) -> Result<Vec<String>> {
+    let contents = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    Ok(contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(str::split_whitespace)
+        .map(str::to_string)
+        .collect())
+}
+
+fn parse_target(spec: &str) -> Result<Vec<Target>> {
+    if let Ok(addr) = spec.parse::<IpAddr>() {
+        return Ok(vec![Target { addr, name: None }]);
+    }
+    if spec.contains('/') {
+        let network: IpNetwork = spec
+            .parse()
+            .with_context(|| format!("invalid network {}", spec))?;
+        // size()は/0でオーバーフローするので、プレフィックス長で判断する
+        let min_prefix = if network.is_ipv4() {
+            MIN_IPV4_PREFIX
+        } else {
+            MIN_IPV6_PREFIX
+        };
+        if network.prefix() < min_prefix {
+            return Err(anyhow!("Too many targets in {} (max {})", spec, MAX_HOSTS));
+        }
+        // IPv4の/30以下ではネットワークアドレスとブロードキャストアドレスを除く
+        let skip_edges = network.is_ipv4() && network.prefix() < 31;
+        return Ok(network
+            .iter()
+            .filter(|addr| {
+                !skip_edges || (*addr != network.network() && *addr != network.broadcast())
+            })
+            .map(|addr| Target { addr, name: None })
+            .collect());
+    }
+    // IPv4のアドレスがあればそれを、なければIPv6のアドレスを使う
+    let addrs: Vec<IpAddr> = (spec, 0)
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve {}", spec))?
+        .map(|addr| addr.ip())
+        .collect();
+    let addr = addrs
+        .iter()
+        .find(|addr| addr.is_ipv4())
+        .or_else(|| addrs.first())
+        .copied()
+        .with_context(|| format!("No address for {}", spec))?;
+    Ok(vec![Target {
+        addr,
+        name: Some(spec.to_string()),
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    fn addrs(targets: &[Target]) -> Vec<String> {
+        targets.iter().map(|t| t.addr.to_string()).collect()
+    }
+
+    fn specs(specs: &[&str]) -> Vec<String> {
+        specs.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn single_addresses() {
+        let targets = resolve(&specs(&["192.0.2.1", "2001:db8::1"])).unwrap();
+        assert_eq!(addrs(&targets), ["192.0.2.1", "2001:db8::1"]);
+        assert!(targets.iter().all(|t| t.name.is_none()));
+    }
+
+    #[test]
+    fn ipv4_networks_skip_network_and_broadcast() {
+        let targets = resolve(&specs(&["192.0.2.0/30"])).unwrap();
+        assert_eq!(addrs(&targets), ["192.0.2.1", "192.0.2.2"]);
+        // /31と/32には除くアドレスがない
+        let targets = resolve(&specs(&["192.0.2.0/31", "198.51.100.7/32"])).unwrap();
+        assert_eq!(addrs(&targets), ["192.0.2.0", "192.0.2.1", "198.51.100.7"]);
+    }
+
+    #[test]
+    fn ipv6_networks_include_every_address() {
+        let targets = resolve(&specs(&["2001:db8::/126"])).unwrap();
+        assert_eq!(
+            addrs(&targets),
+            ["2001:db8::", "2001:db8::1", "2001:db8::2", "2001:db8::3"]
+        );
+    }
+
+    #[test]
+    fn largest_networks_are_accepted() {
+        assert_eq!(resolve(&specs(&["10.0.0.0/16"])).unwrap().len(), 65534);
+        assert_eq!(resolve(&specs(&["2001:db8::/112"])).unwrap().len(), 65536);
+    }
+
+    #[test]
+    fn too_large_networks_are_rejected() {
+        for spec in [
+            "0.0.0.0/0",
+            "10.0.0.0/8",
+            "10.0.0.0/15",
+            "::/0",
+            "2001:db8::/111",
+        ] {
+            let error = resolve(&specs(&[spec])).unwrap_err().to_string();
+            assert!(error.starts_with("Too many targets"), "{}: {}", spec, error);
+        }
+        // 1つずつは上限以内でも、合わせて超えたら拒否する
+        let error = resolve(&specs(&["10.0.0.0/16", "10.1.0.0/16"])).unwrap_err();
+        assert_eq!(error.to_string(), "Too many targets (max 65536)");
+    }
+
+    #[test]
+    fn invalid_networks() {
+        for spec in ["10.0.0.0/33", "2001:db8::/129", "10.0.0.0/x"] {
+            let error = resolve(&specs(&[spec])).unwrap_err().to_string();
+            assert_eq!(error, format!("invalid network {}", spec));
+        }
+    }
+
+    #[test]
+    fn comma_separated_lists_are_deduplicated() {
+        let targets = resolve(&specs(&[
+            "192.0.2.1, 192.0.2.2,,",
+            "192.0.2.0/30,192.0.2.3",
+        ]))
+        .unwrap();
+        assert_eq!(addrs(&targets), ["192.0.2.1", "192.0.2.2", "192.0.2.3"]);
+
+        let error = resolve(&specs(&[" , "])).unwrap_err();
+        assert_eq!(error.to_string(), "No target to scan");
+    }
+
+    #[test]
+    fn hostnames_keep_their_name() {
+        let targets = resolve(&specs(&["localhost"])).unwrap();
+        assert_eq!(targets.len(), 1);
+        assert!(targets[0].addr.is_loopback());
+        assert_eq!(targets[0].name.as_deref(), Some("localhost"));
+
+        let error = resolve(&specs(&["no-such-host.invalid"])).unwrap_err();
+        assert_eq!(error.to_string(), "Failed to resolve no-such-host.invalid");
+    }
+
+    #[test]
+    fn target_files_allow_comments_and_whitespace() {
+        let path = env::temp_dir().join(format!("ch3-targets-{}.txt", process::id()));
+        fs::write(
+            &path,
+            "# scan targets\n192.0.2.1 192.0.2.2\n\n  2001:db8::/127  # lab\n192.0.2.1\n",
+        )
+        .unwrap();
+        let specs = read_file(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            specs,
+            ["192.0.2.1", "192.0.2.2", "2001:db8::/127", "192.0.2.1"]
+        );
+
+        let targets = resolve(&specs).unwrap();
+        assert_eq!(
+            addrs(&targets),
+            ["192.0.2.1", "192.0.2.2", "2001:db8::", "2001:db8::1"]
+        );
+
+        let error = read_file("/nonexistent/targets.txt").unwrap_err();
+        assert_eq!(error.to_string(), "Failed to read /nonexistent/targets.txt");
+    }
+}
@@ -0,0 +1,290 @@
+use anyhow::{anyhow, Result};
+use log::debug;
+use std::time::{Duration, Instant};
+
+/// 返信が時間内に届くたびに送信レートに掛ける値
+const RATE_INCREASE: f64 = 1.02;
+/// 取りこぼしや輻輳の通知があったときに送信レートに掛ける値
+const RATE_DECREASE: f64 = 0.5;
+
+/**
+ * 送信レートとタイムアウトの設定
+ * nmapのタイミングテンプレート（-T0から-T5）に合わせた既定値を持つ
+ */
+#[derive(Debug, Clone)]
+pub struct Timing {
+    /// 最初の送信レート（パケット/秒）
+    pub initial_rate: f64,
+    pub min_rate: f64,
+    pub max_rate: f64,
+    /// RTTを測るまで使うタイムアウト
+    pub initial_timeout: Duration,
+    pub min_timeout: Duration,
+    pub max_timeout: Duration,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self::template("3").unwrap()
+    }
+}
+
+impl Timing {
+    /**
+     * 番号（0から5）か名前でタイミングテンプレートを選ぶ
+     * 0から2はレートを固定し、3以降は返信の様子に合わせてレートを変える
+     */
+    pub fn template(name: &str) -> Result<Self> {
+        let (rate, min_rate, max_rate, initial, min, max) = match name {
+            // 5分に1つ
+            "0" | "paranoid" => (1.0 / 300.0, 1.0 / 300.0, 1.0 / 300.0, 5000, 1000, 10000),
+            // 15秒に1つ
+            "1" | "sneaky" => (1.0 / 15.0, 1.0 / 15.0, 1.0 / 15.0, 5000, 1000, 10000),
+            // 0.4秒に1つ
+            "2" | "polite" => (2.5, 2.5, 2.5, 1000, 100, 10000),
+            "3" | "normal" => (200.0, 1.0, 10000.0, 1000, 100, 10000),
+            "4" | "aggressive" => (500.0, 10.0, 50000.0, 500, 100, 1250),
+            "5" | "insane" => (1000.0, 100.0, 100000.0, 250, 50, 300),
+            _ => {
+                return Err(anyhow!(
+                    "Unknown timing template {}, only accept 0-5 or \
+                     paranoid|sneaky|polite|normal|aggressive|insane",
+                    name
+                ))
+            }
+        };
+        Ok(Self {
+            initial_rate: rate,
+            min_rate,
+            max_rate,
+            initial_timeout: Duration::from_millis(initial),
+            min_timeout: Duration::from_millis(min),
+            max_timeout: Duration::from_millis(max),
+        })
+    }
+
+    /**
+     * --min-rateや--max-rateで範囲を変えたときに、最初のレートを範囲に収める
+     */
+    pub fn clamp_rate(&mut self) -> Result<()> {
+        if self.min_rate > self.max_rate {
+            return Err(anyhow!(
+                "min rate {} is larger than max rate {}",
+                self.min_rate,
+                self.max_rate
+            ));
+        }
+        self.initial_rate = self.initial_rate.clamp(self.min_rate, self.max_rate);
+        Ok(())
+    }
+}
+
+/**
+ * 送信レートの調整
+ * 時間内に返信が届くたびに少しずつ上げ、取りこぼしや輻輳の通知があれば半分に下げる
+ */
+pub struct RateControl {
+    rate: f64,
+    min_rate: f64,
+    max_rate: f64,
+    /// 最後にレートを下げた時刻
+    /// 同じ時期に送ったプローブの取りこぼしで何度も下げないよう、RTOの間は下げない
+    last_decrease: Option<Instant>,
+}
+
+impl RateControl {
+    pub fn new(timing: &Timing) -> Self {
+        Self {
+            rate: timing.initial_rate,
+            min_rate: timing.min_rate,
+            max_rate: timing.max_rate,
+            last_decrease: None,
+        }
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /**
+     * 今のレートでの送信間隔
+     */
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.rate)
+    }
+
+    pub fn on_reply(&mut self) {
+        self.rate = (self.rate * RATE_INCREASE).min(self.max_rate);
+    }
+
+    /**
+     * 取りこぼしや輻輳の通知があったときにレートを下げる
+     */
+    pub fn on_congestion(&mut self, now: Instant, hold: Duration) {
+        if self
+            .last_decrease
+            .is_some_and(|last| now.duration_since(last) < hold)
+        {
+            return;
+        }
+        self.last_decrease = Some(now);
+        let rate = (self.rate * RATE_DECREASE).max(self.min_rate);
+        if rate < self.rate {
+            debug!("slow down to {:.1} packets/sec", rate);
+        }
+        self.rate = rate;
+    }
+}
+
+/**
+ * RTTからタイムアウト（RTO）を計算する（RFC 6298）
+ */
+pub struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    timeout: Duration,
+    min_timeout: Duration,
+    max_timeout: Duration,
+}
+
+impl RttEstimator {
+    pub fn new(timing: &Timing, initial_timeout: Duration) -> Self {
+        Self {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            timeout: initial_timeout,
+            min_timeout: timing.min_timeout,
+            max_timeout: timing.max_timeout,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /**
+     * 再送していないプローブの返信から測ったRTTを加える
+     */
+    pub fn sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let diff = srtt.abs_diff(rtt);
+                self.rttvar = self.rttvar * 3 / 4 + diff / 4;
+                self.srtt = Some(srtt * 7 / 8 + rtt / 8);
+            }
+        }
+        let timeout = self.srtt.unwrap_or_default() + self.rttvar * 4;
+        self.timeout = timeout.clamp(self.min_timeout, self.max_timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn templates_by_number_and_name() {
+        let aggressive = Timing::template("aggressive").unwrap();
+        let four = Timing::template("4").unwrap();
+        assert_eq!(aggressive.initial_rate, four.initial_rate);
+        assert_eq!(aggressive.initial_rate, 500.0);
+        assert_eq!(aggressive.min_rate, 10.0);
+        assert_eq!(aggressive.max_rate, 50000.0);
+        assert_eq!(aggressive.initial_timeout, MS * 500);
+        assert_eq!(aggressive.min_timeout, MS * 100);
+        assert_eq!(aggressive.max_timeout, MS * 1250);
+
+        // 0から2はレートを固定する
+        let polite = Timing::template("polite").unwrap();
+        assert_eq!(polite.min_rate, polite.max_rate);
+        assert_eq!(Timing::template("0").unwrap().initial_rate, 1.0 / 300.0);
+
+        let normal = Timing::default();
+        assert_eq!(normal.initial_rate, 200.0);
+        assert_eq!(normal.initial_timeout, MS * 1000);
+
+        assert!(Timing::template("6").is_err());
+        assert!(Timing::template("fast").is_err());
+    }
+
+    #[test]
+    fn clamp_rate_keeps_the_initial_rate_in_range() {
+        let mut timing = Timing {
+            min_rate: 300.0,
+            ..Timing::default()
+        };
+        timing.clamp_rate().unwrap();
+        assert_eq!(timing.initial_rate, 300.0);
+
+        timing.max_rate = 100.0;
+        assert!(timing.clamp_rate().is_err());
+        timing.min_rate = 10.0;
+        timing.clamp_rate().unwrap();
+        assert_eq!(timing.initial_rate, 100.0);
+    }
+
+    #[test]
+    fn rate_speeds_up_on_timely_replies() {
+        let mut rate = RateControl::new(&Timing::default());
+        assert_eq!(rate.rate(), 200.0);
+        assert_eq!(rate.interval(), MS * 5);
+        rate.on_reply();
+        assert_eq!(rate.rate(), 200.0 * RATE_INCREASE);
+        for _ in 0..1000 {
+            rate.on_reply();
+        }
+        assert_eq!(rate.rate(), 10000.0);
+    }
+
+    #[test]
+    fn rate_backs_off_once_per_hold() {
+        let mut rate = RateControl::new(&Timing::default());
+        let now = Instant::now();
+        let hold = MS * 1000;
+        rate.on_congestion(now, hold);
+        assert_eq!(rate.rate(), 100.0);
+        // 同じ時期に送ったプローブの取りこぼしでは下げない
+        rate.on_congestion(now + MS * 500, hold);
+        assert_eq!(rate.rate(), 100.0);
+        rate.on_congestion(now + hold, hold);
+        assert_eq!(rate.rate(), 50.0);
+
+        let mut now = now + hold;
+        for _ in 0..10 {
+            now += hold;
+            rate.on_congestion(now, hold);
+        }
+        assert_eq!(rate.rate(), 1.0);
+    }
+
+    #[test]
+    fn rto_follows_rtt() {
+        let timing = Timing::default();
+        let mut rtt = RttEstimator::new(&timing, MS * 2000);
+        assert_eq!(rtt.timeout(), MS * 2000);
+        // SRTT=100ms、RTTVAR=50ms
+        rtt.sample(MS * 100);
+        assert_eq!(rtt.timeout(), MS * 300);
+        // RTTVAR=3/4*50+1/4*100=62.5ms、SRTT=7/8*100+1/8*200=112.5ms
+        rtt.sample(MS * 200);
+        assert_eq!(rtt.timeout(), Duration::from_micros(362_500));
+    }
+
+    #[test]
+    fn rto_is_bounded() {
+        let timing = Timing::default();
+        let mut rtt = RttEstimator::new(&timing, timing.initial_timeout);
+        rtt.sample(MS);
+        assert_eq!(rtt.timeout(), timing.min_timeout);
+
+        let mut rtt = RttEstimator::new(&timing, timing.initial_timeout);
+        rtt.sample(MS * 5000);
+        assert_eq!(rtt.timeout(), timing.max_timeout);
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::{anyhow, Context, Result};
+use pnet::packet::udp::{self, MutableUdpPacket};
+use std::net::IpAddr;
+
+const UDP_HEADER_SIZE: usize = 8;
+/// DHCPのサーバとクライアントのポート
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+
+/**
+ * プローブの送信元ポート
+ * DHCPのサーバは要求の送信元ポートではなく68番ポートに返信するので、67番へのプローブだけは68番から送る
+ */
+pub fn source_port(port: u16, my_port: u16) -> u16 {
+    if port == DHCP_SERVER_PORT {
+        DHCP_CLIENT_PORT
+    } else {
+        my_port
+    }
+}
+
+/**
+ * UDPのプローブを生成する
+ * よく知られたサービスには、実際に返信してもらえるようにそのプロトコルの要求を載せる
+ */
+pub fn build_probe(my_ipaddr: IpAddr, my_port: u16, host: IpAddr, port: u16) -> Result<Vec<u8>> {
+    let payload = payload(port);
+    let mut buffer = vec![0u8; UDP_HEADER_SIZE + payload.len()];
+    let mut udp_header =
+        MutableUdpPacket::new(&mut buffer).context("Failed to make MutableUdpPacket")?;
+    udp_header.set_source(my_port);
+    udp_header.set_destination(port);
+    udp_header.set_length((UDP_HEADER_SIZE + payload.len()) as u16);
+    udp_header.set_payload(&payload);
+    // チェックサムは疑似ヘッダの宛先アドレスも含むので宛先ごとに計算する
+    let checksum = match (my_ipaddr, host) {
+        (IpAddr::V4(my_ipaddr), IpAddr::V4(host)) => {
+            udp::ipv4_checksum(&udp_header.to_immutable(), &my_ipaddr, &host)
+        }
+        (IpAddr::V6(my_ipaddr), IpAddr::V6(host)) => {
+            udp::ipv6_checksum(&udp_header.to_immutable(), &my_ipaddr, &host)
+        }
+        _ => return Err(anyhow!("{} and {} are different families", my_ipaddr, host)),
+    };
+    udp_header.set_checksum(checksum);
+    Ok(buffer)
+}
+
+/**
+ * ポートごとのペイロード
+ * 知らないポートには空のデータグラムを送る
+ */
+fn payload(port: u16) -> Vec<u8> {
+    match port {
+        53 => dns_query(),
+        DHCP_SERVER_PORT => dhcp_discover(),
+        123 => ntp_request(),
+        161 => snmp_get_request(),
+        _ => vec![],
+    }
+}
+
+/**
+ * ルートのNSレコードを問い合わせるDNSのクエリ
+ */
+fn dns_query() -> Vec<u8> {
+    vec![
+        0x12, 0x34, // ID
+        0x01, 0x00, // フラグ（再帰要求）
+        0x00, 0x01, // 質問の数
+        0x00, 0x00, // 回答の数
+        0x00, 0x00, // 権威の数
+        0x00, 0x00, // 追加情報の数
+        0x00, // 名前（ルート）
+        0x00, 0x02, // タイプ（NS）
+        0x00, 0x01, // クラス（IN）
+    ]
+}
+
+/**
+ * DHCPDISCOVER
+ * アドレスを持たないクライアントとして、ブロードキャストフラグを立ててOFFERをブロードキャストで返してもらう
+ */
+fn dhcp_discover() -> Vec<u8> {
+    let mut packet = vec![0u8; 236];
+    packet[0] = 1; // op（要求）
+    packet[1] = 1; // ハードウェアの種類（イーサネット）
+    packet[2] = 6; // ハードウェアアドレスの長さ
+    packet[4..8].copy_from_slice(&[0x12, 0x34, 0x56, 0x78]); // トランザクションID
+    packet[10] = 0x80; // フラグ（ブロードキャスト）
+    packet[28..34].copy_from_slice(&[0x02, 0x00, 0x00, 0x00, 0x00, 0x01]); // クライアントのMACアドレス
+    packet.extend_from_slice(&[0x63, 0x82, 0x53, 0x63]); // マジッククッキー
+    packet.extend_from_slice(&[53, 1, 1]); // メッセージタイプ（DHCPDISCOVER）
+    packet.push(255); // 終わり
+    packet
+}
+
+/**
+ * NTPv4のクライアントの要求
+ */
+fn ntp_request() -> Vec<u8> {
+    let mut packet = vec![0u8; 48];
+    // LI=3（未同期）、バージョン4、モード3（クライアント）
+    packet[0] = 0xe3;
+    packet
+}
+
+/**
+ * コミュニティ名publicでsysDescr.0を取得するSNMPv1のGetRequest
+ */
+fn snmp_get_request() -> Vec<u8> {
+    vec![
+        0x30, 0x29, // SEQUENCE
+        0x02, 0x01, 0x00, // バージョン（v1）
+        0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', // コミュニティ名
+        0xa0, 0x1c, // GetRequest
+        0x02, 0x04, 0x00, 0x00, 0x00, 0x01, // リクエストID
+        0x02, 0x01, 0x00, // エラーステータス
+        0x02, 0x01, 0x00, // エラーインデックス
+        0x30, 0x0e, // 変数のリスト
+        0x30, 0x0c, // 変数
+        0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, // 1.3.6.1.2.1.1.1.0
+        0x05, 0x00, // NULL
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::{udp::UdpPacket, Packet};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const MY_V4: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const HOST_V4: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+    const MY_V6: IpAddr = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+    const HOST_V6: IpAddr = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2));
+
+    #[test]
+    fn builds_a_header_with_a_valid_checksum() {
+        for (my_ipaddr, host) in [(MY_V4, HOST_V4), (MY_V6, HOST_V6)] {
+            let buffer = build_probe(my_ipaddr, 40000, host, 123).unwrap();
+            let packet = UdpPacket::new(&buffer).unwrap();
+            assert_eq!(packet.get_source(), 40000);
+            assert_eq!(packet.get_destination(), 123);
+            assert_eq!(packet.get_length() as usize, buffer.len());
+            assert_eq!(packet.payload(), ntp_request().as_slice());
+            let checksum = match (my_ipaddr, host) {
+                (IpAddr::V4(my_ipaddr), IpAddr::V4(host)) => {
+                    udp::ipv4_checksum(&packet, &my_ipaddr, &host)
+                }
+                (IpAddr::V6(my_ipaddr), IpAddr::V6(host)) => {
+                    udp::ipv6_checksum(&packet, &my_ipaddr, &host)
+                }
+                _ => unreachable!(),
+            };
+            assert_ne!(packet.get_checksum(), 0);
+            assert_eq!(packet.get_checksum(), checksum);
+        }
+    }
+
+    #[test]
+    fn different_families_are_an_error() {
+        assert!(build_probe(MY_V4, 40000, HOST_V6, 53).is_err());
+        assert!(build_probe(MY_V6, 40000, HOST_V4, 53).is_err());
+    }
+
+    #[test]
+    fn payload_per_port() {
+        let dns = payload(53);
+        assert_eq!(dns.len(), 17);
+        assert_eq!(&dns[4..6], &[0x00, 0x01]);
+
+        let ntp = payload(123);
+        assert_eq!(ntp.len(), 48);
+        assert_eq!(ntp[0], 0xe3);
+
+        let snmp = payload(161);
+        assert_eq!(snmp[0], 0x30);
+        assert_eq!(snmp[1] as usize, snmp.len() - 2);
+
+        assert!(payload(9).is_empty());
+    }
+
+    #[test]
+    fn dhcp_discover_asks_for_a_broadcast_reply() {
+        let dhcp = payload(67);
+        assert_eq!(dhcp[0], 1);
+        assert_eq!(&dhcp[10..12], &[0x80, 0x00]);
+        assert_eq!(&dhcp[236..240], &[0x63, 0x82, 0x53, 0x63]);
+        assert_eq!(&dhcp[240..], &[53, 1, 1, 255]);
+    }
+
+    #[test]
+    fn dhcp_probes_are_sent_from_the_client_port() {
+        assert_eq!(source_port(67, 40000), 68);
+        assert_eq!(source_port(53, 40000), 40000);
+        assert_eq!(source_port(68, 40000), 40000);
+
+        let buffer = build_probe(MY_V4, source_port(67, 40000), HOST_V4, 67).unwrap();
+        let packet = UdpPacket::new(&buffer).unwrap();
+        assert_eq!(packet.get_source(), 68);
+        assert_eq!(packet.get_destination(), 67);
+    }
+}